@@ -0,0 +1,156 @@
+//! AppView と PDS への XRPC 呼び出し
+//!
+//! 接続先は起動時に 1 度だけ決める（`AppConfig::bsky_api_url`）。各フィードのクレートはこれを受け取って使うので、
+//! 統合テストのモックサーバーやセルフホストの AppView にもそのまま向けられる
+
+use crate::http::HttpClient;
+use crate::xrpc::XrpcError;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+pub const DEFAULT_APPVIEW_URL: &str = "https://api.bsky.app";
+pub const DEFAULT_PDS_URL: &str = "https://bsky.social";
+
+/// `com.atproto.server.createSession` のレスポンス
+#[derive(Debug, Clone, Deserialize)]
+pub struct Session {
+    pub did: String,
+    #[serde(rename = "accessJwt")]
+    pub access_jwt: String,
+    #[serde(rename = "refreshJwt")]
+    pub refresh_jwt: String,
+}
+
+/// clone しても同じコネクションプール（とホストごとのレート制限の状態）を共有する
+#[derive(Clone)]
+pub struct AppViewClient {
+    http: HttpClient,
+    appview_url: String,
+    pds_url: String,
+}
+
+impl AppViewClient {
+    pub fn new(http: impl Into<HttpClient>, appview_url: impl Into<String>) -> Self {
+        Self {
+            http: http.into(),
+            appview_url: trim_url(appview_url.into()),
+            pds_url: DEFAULT_PDS_URL.to_string(),
+        }
+    }
+
+    /// ログインに使う PDS（既定は bsky.social）
+    pub fn with_pds_url(mut self, pds_url: impl Into<String>) -> Self {
+        self.pds_url = trim_url(pds_url.into());
+        self
+    }
+
+    pub fn appview_url(&self) -> &str {
+        &self.appview_url
+    }
+
+    pub fn pds_url(&self) -> &str {
+        &self.pds_url
+    }
+
+    pub fn http(&self) -> &HttpClient {
+        &self.http
+    }
+
+    /// AppView のクエリ（GET）。`token` は `Bearer ` の有無どちらでもよい
+    ///
+    /// ステータスの確認は呼び出し側に任せる（フィードごとに扱いが違うので）
+    pub async fn get<Q>(
+        &self,
+        nsid: &'static str,
+        token: Option<&str>,
+        query: &Q,
+    ) -> Result<reqwest::Response, XrpcError>
+    where
+        Q: Serialize + ?Sized,
+    {
+        let mut request = self
+            .http
+            .get(format!("{}/xrpc/{}", self.appview_url, nsid))
+            .query(query);
+        if let Some(token) = token {
+            request = request.header("Authorization", bearer(token));
+        }
+        crate::metrics::appview()
+            .observe(nsid, self.http.send(request))
+            .await
+    }
+
+    /// AppView のクエリを呼び、2xx ならレスポンスを `T` として読む
+    pub async fn query<T, Q>(
+        &self,
+        nsid: &'static str,
+        token: Option<&str>,
+        query: &Q,
+    ) -> Result<T, XrpcError>
+    where
+        T: DeserializeOwned,
+        Q: Serialize + ?Sized,
+    {
+        let res = self.get(nsid, token, query).await?;
+        read_json(res).await
+    }
+
+    /// PDS にアプリパスワードでログインする
+    pub async fn create_session(
+        &self,
+        identifier: &str,
+        password: &str,
+    ) -> Result<Session, XrpcError> {
+        let request = self
+            .http
+            .post(format!(
+                "{}/xrpc/com.atproto.server.createSession",
+                self.pds_url
+            ))
+            .json(&serde_json::json!({
+                "identifier": identifier,
+                "password": password,
+            }));
+        let res = crate::metrics::appview()
+            .observe("com.atproto.server.createSession", self.http.send(request))
+            .await?;
+        read_json(res).await
+    }
+
+    /// リフレッシュトークンで新しいセッションを得る（リフレッシュトークンも新しくなる）
+    pub async fn refresh_session(&self, refresh_jwt: &str) -> Result<Session, XrpcError> {
+        let request = self
+            .http
+            .post(format!(
+                "{}/xrpc/com.atproto.server.refreshSession",
+                self.pds_url
+            ))
+            .header("Authorization", bearer(refresh_jwt));
+        let res = crate::metrics::appview()
+            .observe("com.atproto.server.refreshSession", self.http.send(request))
+            .await?;
+        read_json(res).await
+    }
+}
+
+async fn read_json<T: DeserializeOwned>(res: reqwest::Response) -> Result<T, XrpcError> {
+    if !res.status().is_success() {
+        return Err(XrpcError::from_response(res).await);
+    }
+    let bytes = res.bytes().await?;
+    serde_json::from_slice(&bytes).map_err(|e| XrpcError::Decode(e.to_string()))
+}
+
+fn trim_url(mut url: String) -> String {
+    while url.ends_with('/') {
+        url.pop();
+    }
+    url
+}
+
+fn bearer(token: &str) -> String {
+    if token.starts_with("Bearer ") {
+        token.to_string()
+    } else {
+        format!("Bearer {}", token)
+    }
+}
@@ -0,0 +1,339 @@
+//! 外部 API への HTTP 呼び出しの共通部分
+//!
+//! - 接続先のホストごとに同時に送る数を制限する
+//! - `ratelimit-remaining` が 0 になったら、`ratelimit-reset` まで同じホストへは送らない
+//! - 冪等な GET / HEAD は、送信エラー・5xx・429 のときにジッター付きの間隔をあけてやり直す
+//! - やり直しや待ち時間も含めて、1 リクエストの締め切りを超えたら諦める
+
+use crate::metrics::Counter;
+use crate::xrpc::XrpcError;
+use rand::Rng;
+use reqwest::header::HeaderMap;
+use reqwest::{Method, Request, RequestBuilder, Response, StatusCode};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone)]
+pub struct HttpOptions {
+    /// やり直しも含めた 1 リクエストの締め切り
+    pub deadline: Duration,
+    /// 最初の 1 回に加えて、やり直す回数の上限
+    pub max_retries: u32,
+    /// やり直しの間隔は `base_backoff * 2^n` を上限にジッターをかける
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+    /// 同じホストへ同時に送るリクエストの上限
+    pub per_host_concurrency: usize,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        Self {
+            deadline: Duration::from_secs(10),
+            max_retries: 2,
+            base_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(2),
+            per_host_concurrency: 8,
+        }
+    }
+}
+
+/// どれもホストのラベル付き。`retries` はさらに理由（`status` / `error`）で分ける
+#[derive(Clone, Default)]
+pub struct HttpMetrics {
+    pub retries: Counter,
+    /// レート制限が解けるまで待った回数
+    pub rate_limit_waits: Counter,
+    /// 同時リクエスト数の上限で待たされた回数
+    pub concurrency_waits: Counter,
+    pub deadline_exceeded: Counter,
+}
+
+/// ホストごとの状態
+struct Host {
+    permits: Arc<Semaphore>,
+    /// この UNIX 時刻（秒）までは送らない
+    blocked_until: Mutex<Option<i64>>,
+}
+
+impl Host {
+    fn blocked_until(&self, now: i64) -> Option<i64> {
+        let mut blocked_until = self.blocked_until.lock().unwrap();
+        if blocked_until.is_some_and(|until| until <= now) {
+            *blocked_until = None;
+        }
+        *blocked_until
+    }
+
+    fn block_until(&self, until: i64) {
+        let mut blocked_until = self.blocked_until.lock().unwrap();
+        *blocked_until = Some(blocked_until.map_or(until, |current| current.max(until)));
+    }
+}
+
+/// clone しても、コネクションプール・ホストごとの状態・メトリクスを共有する
+#[derive(Clone)]
+pub struct HttpClient {
+    inner: reqwest::Client,
+    options: HttpOptions,
+    hosts: Arc<Mutex<HashMap<String, Arc<Host>>>>,
+    metrics: HttpMetrics,
+}
+
+impl From<reqwest::Client> for HttpClient {
+    fn from(inner: reqwest::Client) -> Self {
+        Self::new(inner)
+    }
+}
+
+impl HttpClient {
+    pub fn new(inner: reqwest::Client) -> Self {
+        Self {
+            inner,
+            options: HttpOptions::default(),
+            hosts: Arc::default(),
+            metrics: HttpMetrics::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: HttpOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// 組み立てたら `send` で送る
+    pub fn get(&self, url: impl reqwest::IntoUrl) -> RequestBuilder {
+        self.inner.get(url)
+    }
+
+    /// 組み立てたら `send` で送る（やり直しはしない）
+    pub fn post(&self, url: impl reqwest::IntoUrl) -> RequestBuilder {
+        self.inner.post(url)
+    }
+
+    pub fn metrics(&self) -> &HttpMetrics {
+        &self.metrics
+    }
+
+    /// 送る。2xx 以外のレスポンスもそのまま返す（やり直しても駄目だった最後のもの）
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, XrpcError> {
+        let request = request.build()?;
+        let host_name = request.url().host_str().unwrap_or("unknown").to_string();
+        let host = self.host(&host_name);
+        let labels = [("host", host_name.as_str())];
+        let deadline = Instant::now() + self.options.deadline;
+        let idempotent = matches!(*request.method(), Method::GET | Method::HEAD);
+
+        let mut original = Some(request);
+        let mut attempt = 0;
+        loop {
+            if let Some(until) = host.blocked_until(now()) {
+                if instant_at(until) > deadline {
+                    self.metrics.deadline_exceeded.inc(&labels);
+                    return Err(XrpcError::RateLimited {
+                        reset: Some(until),
+                        message: format!("Rate limit for {} is exhausted", host_name),
+                    });
+                }
+                self.metrics.rate_limit_waits.inc(&labels);
+                tokio::time::sleep_until(instant_at(until)).await;
+            }
+
+            // ボディがストリームでなければ複製できる。できなければやり直さない
+            let mut request = match original.as_ref().and_then(Request::try_clone) {
+                Some(request) if idempotent => request,
+                _ => original
+                    .take()
+                    .expect("non-idempotent request is sent once"),
+            };
+            let retryable = original.is_some() && attempt < self.options.max_retries;
+
+            let permit = match host.permits.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    self.metrics.concurrency_waits.inc(&labels);
+                    match tokio::time::timeout_at(deadline, host.permits.clone().acquire_owned())
+                        .await
+                    {
+                        Ok(permit) => permit.expect("semaphore is never closed"),
+                        Err(_) => return Err(self.deadline_exceeded(&host_name)),
+                    }
+                }
+            };
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(self.deadline_exceeded(&host_name));
+            }
+            *request.timeout_mut() = Some(remaining);
+
+            let result = self.inner.execute(request).await;
+            drop(permit);
+
+            let backoff = backoff(&self.options, attempt);
+            match result {
+                Ok(res) => {
+                    let status = res.status();
+                    if let Some(until) = rate_limited_until(status, res.headers(), now()) {
+                        host.block_until(until);
+                    }
+                    if !retryable || !should_retry(status) {
+                        return Ok(res);
+                    }
+                    // 429 はレート制限が解けるまで待つ。締め切りに間に合わなければ 429 を返す
+                    let resume = match host.blocked_until(now()) {
+                        Some(until) => instant_at(until),
+                        None => Instant::now() + backoff,
+                    };
+                    if resume > deadline {
+                        return Ok(res);
+                    }
+                    tracing::debug!("Retrying {} after {}", host_name, status);
+                    self.metrics
+                        .retries
+                        .inc(&[("host", host_name.as_str()), ("reason", "status")]);
+                    tokio::time::sleep_until(resume).await;
+                }
+                Err(e) if e.is_timeout() && Instant::now() >= deadline => {
+                    return Err(self.deadline_exceeded(&host_name));
+                }
+                Err(e) if retryable && (e.is_connect() || e.is_timeout() || e.is_request()) => {
+                    if Instant::now() + backoff > deadline {
+                        return Err(e.into());
+                    }
+                    tracing::debug!("Retrying {} after error: {}", host_name, e);
+                    self.metrics
+                        .retries
+                        .inc(&[("host", host_name.as_str()), ("reason", "error")]);
+                    tokio::time::sleep(backoff).await;
+                }
+                Err(e) => return Err(e.into()),
+            }
+            attempt += 1;
+        }
+    }
+
+    fn host(&self, name: &str) -> Arc<Host> {
+        self.hosts
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| {
+                Arc::new(Host {
+                    permits: Arc::new(Semaphore::new(self.options.per_host_concurrency.max(1))),
+                    blocked_until: Mutex::new(None),
+                })
+            })
+            .clone()
+    }
+
+    fn deadline_exceeded(&self, host: &str) -> XrpcError {
+        self.metrics.deadline_exceeded.inc(&[("host", host)]);
+        XrpcError::Transport(format!(
+            "Request to {} did not complete within {:?}",
+            host, self.options.deadline
+        ))
+    }
+}
+
+fn should_retry(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// 使い切った（`ratelimit-remaining: 0`）か 429 なら、解ける UNIX 時刻（秒）
+///
+/// `ratelimit-reset` がなければ `retry-after`（秒）を見る。どちらもなければ None
+fn rate_limited_until(status: StatusCode, headers: &HeaderMap, now: i64) -> Option<i64> {
+    let header =
+        |name: &str| -> Option<i64> { headers.get(name)?.to_str().ok()?.trim().parse().ok() };
+    let exhausted = header("ratelimit-remaining") == Some(0);
+    if !exhausted && status != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+    header("ratelimit-reset")
+        .or_else(|| header("retry-after").map(|seconds| now + seconds))
+        .filter(|until| *until > now)
+}
+
+/// `base_backoff * 2^attempt`（`max_backoff` まで）の半分から全部までのどこか
+fn backoff(options: &HttpOptions, attempt: u32) -> Duration {
+    let ceiling = options
+        .base_backoff
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(options.max_backoff);
+    let half = ceiling / 2;
+    half + half.mul_f64(rand::thread_rng().gen::<f64>())
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// UNIX 時刻（秒）を `Instant` に置き換える
+fn instant_at(unix_secs: i64) -> Instant {
+    let wait = (unix_secs - now()).max(0) as u64;
+    Instant::now() + Duration::from_secs(wait)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    /// 残りが 0 か 429 のときだけ、リセット時刻まで止めるか
+    #[test]
+    fn test_rate_limited_until() {
+        let mut headers = HeaderMap::new();
+        headers.insert("ratelimit-remaining", HeaderValue::from_static("3"));
+        headers.insert("ratelimit-reset", HeaderValue::from_static("1060"));
+        assert_eq!(rate_limited_until(StatusCode::OK, &headers, 1000), None);
+        assert_eq!(
+            rate_limited_until(StatusCode::TOO_MANY_REQUESTS, &headers, 1000),
+            Some(1060)
+        );
+
+        headers.insert("ratelimit-remaining", HeaderValue::from_static("0"));
+        assert_eq!(
+            rate_limited_until(StatusCode::OK, &headers, 1000),
+            Some(1060)
+        );
+        // すでに過ぎていれば止めない
+        assert_eq!(rate_limited_until(StatusCode::OK, &headers, 1060), None);
+
+        let mut retry_after = HeaderMap::new();
+        retry_after.insert("retry-after", HeaderValue::from_static("5"));
+        assert_eq!(
+            rate_limited_until(StatusCode::TOO_MANY_REQUESTS, &retry_after, 1000),
+            Some(1005)
+        );
+    }
+
+    /// やり直しの間隔が、回数に応じて伸び、上限を超えないか
+    #[test]
+    fn test_backoff() {
+        let options = HttpOptions {
+            base_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+            ..Default::default()
+        };
+        for _ in 0..20 {
+            let first = backoff(&options, 0);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+            let capped = backoff(&options, 5);
+            assert!(capped >= Duration::from_millis(150) && capped <= Duration::from_millis(300));
+        }
+    }
+}
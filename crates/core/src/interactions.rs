@@ -0,0 +1,331 @@
+//! `app.bsky.feed.sendInteractions` で受け取ったシグナルの保存と参照
+//!
+//! テーブル: `feed_interactions`
+//!   - feed          : TEXT NOT NULL    (フィードの rkey)
+//!   - requester_did : TEXT NOT NULL
+//!   - item          : TEXT NOT NULL    (投稿の AT-URI)
+//!   - event         : TEXT NOT NULL    (lexicon の token 名 例: `app.bsky.feed.defs#requestLess`)
+//!   - feed_context  : TEXT             (スケルトンで返した feedContext)
+//!   - created_at    : INTEGER NOT NULL (UNIX タイムスタンプ秒)
+
+use anyhow::Result;
+use serde::Deserialize;
+use sqlx::{Row, SqlitePool};
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// これより古いシグナルは `prune` で消す
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(30 * 24 * 3600);
+
+/// 必要なテーブルを作成する（冪等）
+pub async fn migrate(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS feed_interactions (
+            id            INTEGER PRIMARY KEY AUTOINCREMENT,
+            feed          TEXT    NOT NULL,
+            requester_did TEXT    NOT NULL,
+            item          TEXT    NOT NULL,
+            event         TEXT    NOT NULL,
+            feed_context  TEXT,
+            created_at    INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_feed_interactions_requester
+            ON feed_interactions(feed, requester_did, created_at);
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// 保存対象のイベント（それ以外の clickthrough などは捨てる）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InteractionEvent {
+    /// "Show more like this"
+    RequestMore,
+    /// "Show less like this"
+    RequestLess,
+    Seen,
+}
+
+impl InteractionEvent {
+    pub fn from_lexicon(event: &str) -> Option<Self> {
+        match event {
+            "app.bsky.feed.defs#requestMore" => Some(Self::RequestMore),
+            "app.bsky.feed.defs#requestLess" => Some(Self::RequestLess),
+            "app.bsky.feed.defs#interactionSeen" => Some(Self::Seen),
+            _ => None,
+        }
+    }
+
+    pub fn as_lexicon(&self) -> &'static str {
+        match self {
+            Self::RequestMore => "app.bsky.feed.defs#requestMore",
+            Self::RequestLess => "app.bsky.feed.defs#requestLess",
+            Self::Seen => "app.bsky.feed.defs#interactionSeen",
+        }
+    }
+}
+
+/// `app.bsky.feed.defs#interaction`
+#[derive(Debug, Clone, Deserialize)]
+pub struct Interaction {
+    pub item: Option<String>,
+    pub event: Option<String>,
+    #[serde(rename = "feedContext")]
+    pub feed_context: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct InteractionStore {
+    pool: SqlitePool,
+}
+
+impl InteractionStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    /// 保存対象のイベントだけを記録し、保存した件数を返す
+    pub async fn record(
+        &self,
+        feed: &str,
+        requester_did: &str,
+        interactions: &[Interaction],
+    ) -> Result<u64> {
+        let now = unix_now();
+        let mut tx = self.pool.begin().await?;
+        let mut stored = 0;
+
+        for interaction in interactions {
+            let (Some(item), Some(event)) = (
+                interaction.item.as_deref(),
+                interaction
+                    .event
+                    .as_deref()
+                    .and_then(InteractionEvent::from_lexicon),
+            ) else {
+                continue;
+            };
+
+            sqlx::query(
+                "INSERT INTO feed_interactions (feed, requester_did, item, event, feed_context, created_at) VALUES (?, ?, ?, ?, ?, ?)",
+            )
+            .bind(feed)
+            .bind(requester_did)
+            .bind(item)
+            .bind(event.as_lexicon())
+            .bind(interaction.feed_context.as_deref())
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+            stored += 1;
+        }
+
+        tx.commit().await?;
+        Ok(stored)
+    }
+
+    /// "Show less" が最後の意思表示になっている投稿
+    ///
+    /// 後から "Show more" された投稿は含まない
+    pub async fn requested_less(&self, feed: &str, requester_did: &str) -> Result<HashSet<String>> {
+        let rows = sqlx::query(
+            "SELECT item, event FROM feed_interactions WHERE feed = ? AND requester_did = ? AND event IN (?, ?) ORDER BY id",
+        )
+        .bind(feed)
+        .bind(requester_did)
+        .bind(InteractionEvent::RequestLess.as_lexicon())
+        .bind(InteractionEvent::RequestMore.as_lexicon())
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut items = HashSet::new();
+        for row in rows {
+            let item: String = row.get("item");
+            let event: String = row.get("event");
+            if event == InteractionEvent::RequestLess.as_lexicon() {
+                items.insert(item);
+            } else {
+                items.remove(&item);
+            }
+        }
+
+        Ok(items)
+    }
+
+    /// `since`（UNIX 秒）以降に表示された投稿
+    pub async fn seen_since(
+        &self,
+        feed: &str,
+        requester_did: &str,
+        since: i64,
+    ) -> Result<HashSet<String>> {
+        let rows = sqlx::query(
+            "SELECT DISTINCT item FROM feed_interactions WHERE feed = ? AND requester_did = ? AND event = ? AND created_at >= ?",
+        )
+        .bind(feed)
+        .bind(requester_did)
+        .bind(InteractionEvent::Seen.as_lexicon())
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| row.get("item")).collect())
+    }
+
+    /// `retention` より前に受け取ったシグナルを消し、消した件数を返す
+    pub async fn prune(&self, retention: Duration) -> Result<u64> {
+        self.prune_before(unix_now() - retention.as_secs() as i64)
+            .await
+    }
+
+    async fn prune_before(&self, cutoff: i64) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM feed_interactions WHERE created_at < ?")
+            .bind(cutoff)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn in_memory_store() -> InteractionStore {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate(&pool).await.unwrap();
+        InteractionStore::new(pool)
+    }
+
+    fn interaction(item: &str, event: &str) -> Interaction {
+        Interaction {
+            item: Some(item.to_string()),
+            event: Some(event.to_string()),
+            feed_context: None,
+        }
+    }
+
+    /// 保存対象外のイベントや item のないものは捨てられるか
+    #[tokio::test]
+    async fn test_record_skips_unsupported_events() {
+        let store = in_memory_store().await;
+        let stored = store
+            .record(
+                "oneyearago",
+                "did:plc:alice",
+                &[
+                    interaction("at://post/1", "app.bsky.feed.defs#requestLess"),
+                    interaction("at://post/2", "app.bsky.feed.defs#clickthroughItem"),
+                    Interaction {
+                        item: None,
+                        event: Some("app.bsky.feed.defs#interactionSeen".to_string()),
+                        feed_context: None,
+                    },
+                ],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(stored, 1);
+    }
+
+    /// 最後の "Show less" / "Show more" が優先され、フィード・ユーザーごとに分離されるか
+    #[tokio::test]
+    async fn test_requested_less() {
+        let store = in_memory_store().await;
+        store
+            .record(
+                "oneyearago",
+                "did:plc:alice",
+                &[
+                    interaction("at://post/1", "app.bsky.feed.defs#requestLess"),
+                    interaction("at://post/2", "app.bsky.feed.defs#requestLess"),
+                    interaction("at://post/2", "app.bsky.feed.defs#requestMore"),
+                ],
+            )
+            .await
+            .unwrap();
+        store
+            .record(
+                "fakebluesky",
+                "did:plc:alice",
+                &[interaction("at://post/3", "app.bsky.feed.defs#requestLess")],
+            )
+            .await
+            .unwrap();
+
+        let less = store
+            .requested_less("oneyearago", "did:plc:alice")
+            .await
+            .unwrap();
+        assert_eq!(less, HashSet::from(["at://post/1".to_string()]));
+
+        let other_user = store
+            .requested_less("oneyearago", "did:plc:bob")
+            .await
+            .unwrap();
+        assert!(other_user.is_empty());
+    }
+
+    /// 保存期間を過ぎたシグナルだけが消えるか
+    #[tokio::test]
+    async fn test_prune() {
+        let store = in_memory_store().await;
+        store
+            .record(
+                "oneyearago",
+                "did:plc:alice",
+                &[interaction("at://post/1", "app.bsky.feed.defs#requestLess")],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(store.prune(DEFAULT_RETENTION).await.unwrap(), 0);
+        assert_eq!(store.prune_before(unix_now() + 1).await.unwrap(), 1);
+        assert!(store
+            .requested_less("oneyearago", "did:plc:alice")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    /// 表示済みの投稿を期間で絞り込めるか
+    #[tokio::test]
+    async fn test_seen_since() {
+        let store = in_memory_store().await;
+        store
+            .record(
+                "fakebluesky",
+                "did:plc:alice",
+                &[interaction(
+                    "at://post/1",
+                    "app.bsky.feed.defs#interactionSeen",
+                )],
+            )
+            .await
+            .unwrap();
+
+        let seen = store
+            .seen_since("fakebluesky", "did:plc:alice", 0)
+            .await
+            .unwrap();
+        assert_eq!(seen, HashSet::from(["at://post/1".to_string()]));
+
+        let future = store
+            .seen_since("fakebluesky", "did:plc:alice", unix_now() + 60)
+            .await
+            .unwrap();
+        assert!(future.is_empty());
+    }
+}
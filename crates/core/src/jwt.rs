@@ -0,0 +1,425 @@
+//! サービス間認証 (inter-service auth) JWT の検証
+//!
+//! AppView から届く `Authorization: Bearer <jwt>` は、リクエストしたユーザーの
+//! `#atproto` 鍵で署名されている。`iss` の DID ドキュメントから公開鍵を引き
+//! （[`crate::resolver::DidResolver`]）、署名・`aud`・`exp`・`lxm` を検証して初めてその DID を信用する。
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine as _};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Multikey のコーデック接頭辞 (varint エンコード済み)
+const SECP256K1_MULTICODEC: [u8; 2] = [0xe7, 0x01];
+const P256_MULTICODEC: [u8; 2] = [0x80, 0x24];
+
+/// 署名が合わなかったときに鍵を引き直す間隔（秒）。偽造トークンで DID ドキュメントを取りに行かされ続けないようにする
+const KEY_REFRESH_INTERVAL_SECS: i64 = 60;
+
+/// `#atproto` 検証鍵
+#[derive(Debug, Clone)]
+pub enum PublicKey {
+    Secp256k1(k256::ecdsa::VerifyingKey),
+    P256(p256::ecdsa::VerifyingKey),
+}
+
+impl PublicKey {
+    /// DID ドキュメントの `publicKeyMultibase` (base58btc, `z` 始まり) をパースする
+    pub fn from_multibase(value: &str) -> Result<Self> {
+        let encoded = value
+            .strip_prefix('z')
+            .context("Unsupported multibase encoding")?;
+        let bytes = bs58::decode(encoded)
+            .into_vec()
+            .context("Failed to decode base58btc key")?;
+
+        if let Some(key) = bytes.strip_prefix(&SECP256K1_MULTICODEC) {
+            let key = k256::ecdsa::VerifyingKey::from_sec1_bytes(key)
+                .context("Invalid secp256k1 public key")?;
+            Ok(Self::Secp256k1(key))
+        } else if let Some(key) = bytes.strip_prefix(&P256_MULTICODEC) {
+            let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(key)
+                .context("Invalid P-256 public key")?;
+            Ok(Self::P256(key))
+        } else {
+            bail!("Unsupported key type in multikey")
+        }
+    }
+
+    /// JWT の `alg` ヘッダー値
+    pub fn jwt_alg(&self) -> &'static str {
+        match self {
+            Self::Secp256k1(_) => "ES256K",
+            Self::P256(_) => "ES256",
+        }
+    }
+
+    /// 64 バイトの compact 署名 (r || s) を検証する
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<()> {
+        match self {
+            Self::Secp256k1(key) => {
+                use k256::ecdsa::signature::Verifier;
+                let sig = k256::ecdsa::Signature::from_slice(signature)
+                    .context("Malformed ES256K signature")?;
+                key.verify(message, &sig)
+                    .map_err(|_| anyhow::anyhow!("Invalid JWT signature"))
+            }
+            Self::P256(key) => {
+                use p256::ecdsa::signature::Verifier;
+                let sig = p256::ecdsa::Signature::from_slice(signature)
+                    .context("Malformed ES256 signature")?;
+                key.verify(message, &sig)
+                    .map_err(|_| anyhow::anyhow!("Invalid JWT signature"))
+            }
+        }
+    }
+}
+
+/// DID から `#atproto` 検証鍵を引くための抽象
+#[async_trait]
+pub trait SigningKeyResolver: Send + Sync {
+    async fn resolve_signing_key(&self, did: &str) -> Result<PublicKey>;
+
+    /// キャッシュを無視して鍵を引き直す（鍵ローテーション対策）
+    async fn refresh_signing_key(&self, did: &str) -> Result<PublicKey> {
+        self.resolve_signing_key(did).await
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JwtHeader {
+    alg: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwtClaims {
+    iss: String,
+    aud: String,
+    exp: i64,
+    #[serde(default)]
+    lxm: Option<String>,
+}
+
+/// サービス間 JWT の検証器
+#[derive(Clone)]
+pub struct JwtVerifier {
+    service_did: String,
+    resolver: Arc<dyn SigningKeyResolver>,
+    /// DID ごとに最後に鍵を引き直した時刻（UNIX 秒）
+    refreshed_at: Arc<Mutex<HashMap<String, i64>>>,
+}
+
+impl JwtVerifier {
+    /// `service_did` はこのフィードジェネレーター自身の DID（`aud` と照合する）
+    pub fn new(service_did: String, resolver: Arc<dyn SigningKeyResolver>) -> Self {
+        Self {
+            service_did,
+            resolver,
+            refreshed_at: Arc::default(),
+        }
+    }
+
+    pub fn service_did(&self) -> &str {
+        &self.service_did
+    }
+
+    /// Authorization ヘッダーを検証し、リクエスト元の DID を返す
+    ///
+    /// `lxm` を指定した場合、トークンに `lxm` クレームがあればそれと一致することも確認する。
+    pub async fn verify(&self, header: Option<&str>, lxm: Option<&str>) -> Result<String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        self.verify_at(header, lxm, now).await
+    }
+
+    async fn verify_at(&self, header: Option<&str>, lxm: Option<&str>, now: i64) -> Result<String> {
+        let header = header.context("Missing Authorization header")?;
+
+        let parts: Vec<&str> = header.split_whitespace().collect();
+        if parts.len() != 2 || !parts[0].eq_ignore_ascii_case("Bearer") {
+            bail!("Invalid Authorization header format");
+        }
+        let jwt = parts[1];
+        let components: Vec<&str> = jwt.split('.').collect();
+        if components.len() != 3 {
+            bail!("Invalid JWT format");
+        }
+
+        let jwt_header: JwtHeader =
+            serde_json::from_slice(&decode_segment(components[0]).context("Invalid JWT header")?)
+                .context("Failed to parse JWT header")?;
+        let claims: JwtClaims =
+            serde_json::from_slice(&decode_segment(components[1]).context("Invalid JWT payload")?)
+                .context("Failed to parse JWT payload")?;
+        let signature = decode_segment(components[2]).context("Invalid JWT signature encoding")?;
+
+        if claims.aud != self.service_did {
+            bail!("JWT audience mismatch: {}", claims.aud);
+        }
+        if claims.exp <= now {
+            bail!("JWT expired");
+        }
+        if let (Some(expected), Some(actual)) = (lxm, claims.lxm.as_deref()) {
+            if expected != actual {
+                bail!("JWT lexicon method mismatch: {}", actual);
+            }
+        }
+
+        // `iss` はサービス ID 付き (did:plc:xxx#atproto_labeler) の場合がある
+        let did = claims.iss.split('#').next().unwrap_or_default();
+        if !did.starts_with("did:") {
+            bail!("Invalid JWT issuer: {}", claims.iss);
+        }
+
+        let key = self
+            .resolver
+            .resolve_signing_key(did)
+            .await
+            .with_context(|| format!("Failed to resolve signing key for {}", did))?;
+
+        let signed_part = &jwt[..components[0].len() + 1 + components[1].len()];
+        if let Err(e) = check_signature(&key, &jwt_header.alg, signed_part, &signature) {
+            // キャッシュ済みの鍵が古い可能性があるので、一度だけ引き直して再検証する
+            if !self.may_refresh(did, now) {
+                return Err(e);
+            }
+            let key = self
+                .resolver
+                .refresh_signing_key(did)
+                .await
+                .with_context(|| format!("Failed to resolve signing key for {}", did))?;
+            check_signature(&key, &jwt_header.alg, signed_part, &signature)?;
+        }
+
+        Ok(did.to_string())
+    }
+
+    /// `did` の鍵を最後に引き直してから `KEY_REFRESH_INTERVAL_SECS` 経っていれば、引き直す時刻として記録する
+    fn may_refresh(&self, did: &str, now: i64) -> bool {
+        let mut refreshed_at = self.refreshed_at.lock().unwrap();
+        if refreshed_at
+            .get(did)
+            .is_some_and(|at| now - at < KEY_REFRESH_INTERVAL_SECS)
+        {
+            return false;
+        }
+        refreshed_at.retain(|_, at| now - *at < KEY_REFRESH_INTERVAL_SECS);
+        refreshed_at.insert(did.to_string(), now);
+        true
+    }
+}
+
+fn check_signature(key: &PublicKey, alg: &str, signed_part: &str, signature: &[u8]) -> Result<()> {
+    if alg != key.jwt_alg() {
+        bail!(
+            "JWT algorithm {} does not match key type {}",
+            alg,
+            key.jwt_alg()
+        );
+    }
+    key.verify(signed_part.as_bytes(), signature)
+}
+
+pub(crate) fn decode_segment(segment: &str) -> Result<Vec<u8>, base64::DecodeError> {
+    general_purpose::URL_SAFE_NO_PAD
+        .decode(segment)
+        .or_else(|_| general_purpose::URL_SAFE.decode(segment))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SERVICE_DID: &str = "did:web:feeds.example.com";
+    const NOW: i64 = 1_700_000_000;
+
+    struct StaticResolver(PublicKey);
+
+    #[async_trait]
+    impl SigningKeyResolver for StaticResolver {
+        async fn resolve_signing_key(&self, _did: &str) -> Result<PublicKey> {
+            Ok(self.0.clone())
+        }
+    }
+
+    fn k256_key() -> k256::ecdsa::SigningKey {
+        k256::ecdsa::SigningKey::from_slice(&[7u8; 32]).unwrap()
+    }
+
+    fn sign_k256(key: &k256::ecdsa::SigningKey, claims: serde_json::Value) -> String {
+        use k256::ecdsa::signature::Signer;
+        let header = general_purpose::URL_SAFE_NO_PAD.encode(json!({"alg": "ES256K"}).to_string());
+        let payload = general_purpose::URL_SAFE_NO_PAD.encode(claims.to_string());
+        let signed = format!("{}.{}", header, payload);
+        let sig: k256::ecdsa::Signature = key.sign(signed.as_bytes());
+        format!(
+            "Bearer {}.{}",
+            signed,
+            general_purpose::URL_SAFE_NO_PAD.encode(sig.to_bytes())
+        )
+    }
+
+    fn verifier_for(key: &k256::ecdsa::SigningKey) -> JwtVerifier {
+        let public = PublicKey::Secp256k1(*key.verifying_key());
+        JwtVerifier::new(SERVICE_DID.to_string(), Arc::new(StaticResolver(public)))
+    }
+
+    fn claims(aud: &str, exp: i64) -> serde_json::Value {
+        json!({
+            "iss": "did:plc:alice",
+            "aud": aud,
+            "exp": exp,
+            "lxm": "app.bsky.feed.getFeedSkeleton",
+        })
+    }
+
+    /// 正しく署名されたトークンから DID を取り出せる
+    #[tokio::test]
+    async fn test_verify_valid_token() {
+        let key = k256_key();
+        let token = sign_k256(&key, claims(SERVICE_DID, NOW + 60));
+        let did = verifier_for(&key)
+            .verify_at(Some(&token), Some("app.bsky.feed.getFeedSkeleton"), NOW)
+            .await
+            .unwrap();
+        assert_eq!(did, "did:plc:alice");
+    }
+
+    /// 別の鍵で署名されたトークンは拒否される
+    #[tokio::test]
+    async fn test_verify_rejects_forged_signature() {
+        let attacker = k256::ecdsa::SigningKey::from_slice(&[9u8; 32]).unwrap();
+        let token = sign_k256(&attacker, claims(SERVICE_DID, NOW + 60));
+        assert!(verifier_for(&k256_key())
+            .verify_at(Some(&token), None, NOW)
+            .await
+            .is_err());
+    }
+
+    /// aud / exp / lxm の不一致は拒否される
+    #[tokio::test]
+    async fn test_verify_rejects_invalid_claims() {
+        let key = k256_key();
+        let verifier = verifier_for(&key);
+
+        let wrong_aud = sign_k256(&key, claims("did:web:other.example.com", NOW + 60));
+        assert!(verifier
+            .verify_at(Some(&wrong_aud), None, NOW)
+            .await
+            .is_err());
+
+        let expired = sign_k256(&key, claims(SERVICE_DID, NOW - 1));
+        assert!(verifier.verify_at(Some(&expired), None, NOW).await.is_err());
+
+        let token = sign_k256(&key, claims(SERVICE_DID, NOW + 60));
+        assert!(verifier
+            .verify_at(Some(&token), Some("app.bsky.feed.sendInteractions"), NOW)
+            .await
+            .is_err());
+    }
+
+    /// キャッシュ済みの鍵が古くても、引き直した鍵で検証できる
+    #[tokio::test]
+    async fn test_verify_retries_with_refreshed_key() {
+        struct RotatedResolver {
+            stale: PublicKey,
+            current: PublicKey,
+        }
+
+        #[async_trait]
+        impl SigningKeyResolver for RotatedResolver {
+            async fn resolve_signing_key(&self, _did: &str) -> Result<PublicKey> {
+                Ok(self.stale.clone())
+            }
+            async fn refresh_signing_key(&self, _did: &str) -> Result<PublicKey> {
+                Ok(self.current.clone())
+            }
+        }
+
+        let stale = k256::ecdsa::SigningKey::from_slice(&[3u8; 32]).unwrap();
+        let current = k256_key();
+        let verifier = JwtVerifier::new(
+            SERVICE_DID.to_string(),
+            Arc::new(RotatedResolver {
+                stale: PublicKey::Secp256k1(*stale.verifying_key()),
+                current: PublicKey::Secp256k1(*current.verifying_key()),
+            }),
+        );
+
+        let token = sign_k256(&current, claims(SERVICE_DID, NOW + 60));
+        assert!(verifier.verify_at(Some(&token), None, NOW).await.is_ok());
+    }
+
+    /// 偽造トークンが続いても、鍵の引き直しは DID ごとに間隔を空ける
+    #[tokio::test]
+    async fn test_verify_limits_key_refresh() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        struct CountingResolver {
+            key: PublicKey,
+            refreshes: AtomicUsize,
+        }
+
+        #[async_trait]
+        impl SigningKeyResolver for CountingResolver {
+            async fn resolve_signing_key(&self, _did: &str) -> Result<PublicKey> {
+                Ok(self.key.clone())
+            }
+            async fn refresh_signing_key(&self, _did: &str) -> Result<PublicKey> {
+                self.refreshes.fetch_add(1, Ordering::SeqCst);
+                Ok(self.key.clone())
+            }
+        }
+
+        let resolver = Arc::new(CountingResolver {
+            key: PublicKey::Secp256k1(*k256_key().verifying_key()),
+            refreshes: AtomicUsize::new(0),
+        });
+        let verifier = JwtVerifier::new(SERVICE_DID.to_string(), resolver.clone());
+        let attacker = k256::ecdsa::SigningKey::from_slice(&[9u8; 32]).unwrap();
+        let forged = sign_k256(&attacker, claims(SERVICE_DID, NOW + 600));
+
+        for now in [NOW, NOW + 1, NOW + 59] {
+            assert!(verifier.verify_at(Some(&forged), None, now).await.is_err());
+        }
+        assert_eq!(resolver.refreshes.load(Ordering::SeqCst), 1);
+
+        assert!(verifier
+            .verify_at(Some(&forged), None, NOW + KEY_REFRESH_INTERVAL_SECS)
+            .await
+            .is_err());
+        assert_eq!(resolver.refreshes.load(Ordering::SeqCst), 2);
+    }
+
+    /// ヘッダー形式の不備は拒否される
+    #[tokio::test]
+    async fn test_verify_rejects_malformed_header() {
+        let verifier = verifier_for(&k256_key());
+        assert!(verifier.verify_at(None, None, NOW).await.is_err());
+        assert!(verifier
+            .verify_at(Some("Bearer invalid.token.structure"), None, NOW)
+            .await
+            .is_err());
+    }
+
+    /// did:key 形式の multikey をパースできる
+    #[test]
+    fn test_public_key_from_multibase() {
+        // https://atproto.com/specs/cryptography の例
+        let k256 =
+            PublicKey::from_multibase("zQ3shqwJEJyMBsBXCWyCBpUBMqxcon9oHB7mCvx4sSpMdLJwc").unwrap();
+        assert_eq!(k256.jwt_alg(), "ES256K");
+
+        let p256 =
+            PublicKey::from_multibase("zDnaembgSGUhZULN2Caob4HLJPaxBh92N7rtH21TErzqf8HQo").unwrap();
+        assert_eq!(p256.jwt_alg(), "ES256");
+
+        assert!(PublicKey::from_multibase("mAAAA").is_err());
+    }
+}
@@ -1,53 +1,98 @@
+pub mod appview;
+pub mod http;
+pub mod interactions;
+pub mod jwt;
+pub mod metrics;
+pub mod resolver;
+pub mod session;
+pub mod xrpc;
+
 use serde::{Deserialize, Serialize};
 
 /// フィードスケルトンのレスポンス型
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FeedSkeletonResult {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cursor: Option<String>,
     pub feed: Vec<FeedItem>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct FeedItem {
-    pub post: String,
+/// 取り込んだイベントで、フィードの索引がどう変わったか
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeedChange {
+    pub added: Option<&'static str>,
+    pub removed: Vec<&'static str>,
 }
 
-/// フィードサービス名の列挙型
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum FeedService {
-    Helloworld,
-    Todoapp,
-    Oneyearago,
-    Fakebluesky,
-    Realbluesky,
-    Privatelist,
-}
+impl FeedChange {
+    pub fn added(feed: &'static str) -> Self {
+        Self {
+            added: Some(feed),
+            removed: Vec::new(),
+        }
+    }
 
-impl FeedService {
-    #[allow(clippy::should_implement_trait)]
-    pub fn from_str(s: &str) -> Option<Self> {
-        match s {
-            "helloworld" => Some(Self::Helloworld),
-            "todoapp" => Some(Self::Todoapp),
-            "oneyearago" => Some(Self::Oneyearago),
-            "fakebluesky" => Some(Self::Fakebluesky),
-            "realbluesky" => Some(Self::Realbluesky),
-            "privatelist" => Some(Self::Privatelist),
-            _ => None,
+    pub fn removed(feed: &'static str) -> Self {
+        Self {
+            added: None,
+            removed: vec![feed],
         }
     }
 
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            Self::Helloworld => "helloworld",
-            Self::Todoapp => "todoapp",
-            Self::Oneyearago => "oneyearago",
-            Self::Fakebluesky => "fakebluesky",
-            Self::Realbluesky => "realbluesky",
-            Self::Privatelist => "privatelist",
+    /// 追加か削除のあったフィード（キャッシュの破棄用）
+    pub fn affected(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.added.into_iter().chain(self.removed.iter().copied())
+    }
+}
+
+/// `app.bsky.feed.defs#skeletonFeedPost`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeedItem {
+    pub post: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<SkeletonReason>,
+    /// sendInteractions でクライアントからそのまま送り返される任意の文字列
+    #[serde(rename = "feedContext", skip_serializing_if = "Option::is_none")]
+    pub feed_context: Option<String>,
+}
+
+impl FeedItem {
+    pub fn new(post: impl Into<String>) -> Self {
+        Self {
+            post: post.into(),
+            reason: None,
+            feed_context: None,
         }
     }
+
+    /// フィード先頭に固定表示する投稿としてマークする
+    pub fn pinned(mut self) -> Self {
+        self.reason = Some(SkeletonReason::Pin);
+        self
+    }
+
+    /// `repost` はリポストレコードの AT-URI
+    pub fn reposted_by(mut self, repost: impl Into<String>) -> Self {
+        self.reason = Some(SkeletonReason::Repost {
+            repost: repost.into(),
+        });
+        self
+    }
+
+    pub fn with_context(mut self, feed_context: impl Into<String>) -> Self {
+        self.feed_context = Some(feed_context.into());
+        self
+    }
+}
+
+/// スケルトン項目の表示理由 (`$type` で判別される union)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "$type")]
+pub enum SkeletonReason {
+    #[serde(rename = "app.bsky.feed.defs#skeletonReasonRepost")]
+    Repost { repost: String },
+    #[serde(rename = "app.bsky.feed.defs#skeletonReasonPin")]
+    Pin,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -61,38 +106,6 @@ pub struct FeedUri {
     pub uri: String,
 }
 
-#[derive(Debug, Deserialize)]
-struct JwtPayload {
-    iss: String,
-}
-
-pub fn extract_did_from_jwt(header: Option<&str>) -> anyhow::Result<String> {
-    use anyhow::Context;
-    use base64::{engine::general_purpose, Engine as _};
-
-    let header = header.context("Missing Authorization header")?;
-
-    let parts: Vec<&str> = header.split_whitespace().collect();
-    if parts.len() != 2 || !parts[0].eq_ignore_ascii_case("Bearer") {
-        anyhow::bail!("Invalid Authorization header format");
-    }
-    let jwt = parts[1];
-    let components: Vec<&str> = jwt.split('.').collect();
-    if components.len() != 3 {
-        anyhow::bail!("Invalid JWT format");
-    }
-    let payload_part = components[1];
-
-    let decoded = general_purpose::URL_SAFE_NO_PAD
-        .decode(payload_part)
-        .or_else(|_| general_purpose::URL_SAFE.decode(payload_part))
-        .context("Failed to decode JWT payload")?;
-
-    let payload: JwtPayload =
-        serde_json::from_slice(&decoded).context("Failed to parse JWT payload")?;
-    Ok(payload.iss)
-}
-
 pub fn get_user_language(header: Option<&str>) -> Option<String> {
     let header = header?;
     let mut languages: Vec<(&str, f32)> = header
@@ -118,36 +131,86 @@ pub fn get_user_language(header: Option<&str>) -> Option<String> {
     languages.first().map(|(lang, _)| lang.to_string())
 }
 
+/// `uri` を主キーに持つ投稿テーブルから、指定したアカウントの投稿を取り除き、取り除いた件数を返す
+pub async fn delete_posts_by_author(
+    pool: &sqlx::SqlitePool,
+    table: &str,
+    did: &str,
+) -> Result<u64, sqlx::Error> {
+    // `at://{did}/` で始まる URI を主キーの範囲で探す（'0' は '/' の次の文字）
+    let query = format!("DELETE FROM {} WHERE uri >= ? AND uri < ?", table);
+    let result = sqlx::query(&query)
+        .bind(format!("at://{}/", did))
+        .bind(format!("at://{}0", did))
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    /// JWTからDIDを抽出できているかを各種検証する
+    /// 指定したアカウントの投稿だけが消え、DID が前方一致する別アカウントの投稿は残るか
+    #[tokio::test]
+    async fn test_delete_posts_by_author() {
+        let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE posts (uri TEXT PRIMARY KEY)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        for uri in [
+            "at://did:plc:alice/app.bsky.feed.post/1",
+            "at://did:plc:alice/app.bsky.feed.post/2",
+            "at://did:plc:alice2/app.bsky.feed.post/1",
+            "at://did:plc:bob/app.bsky.feed.post/1",
+        ] {
+            sqlx::query("INSERT INTO posts (uri) VALUES (?)")
+                .bind(uri)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let purged = delete_posts_by_author(&pool, "posts", "did:plc:alice")
+            .await
+            .unwrap();
+        assert_eq!(purged, 2);
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM posts")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 2);
+    }
+
+    /// reason と feedContext が lexicon どおりにシリアライズされるか
     #[test]
-    fn test_extract_did_from_jwt() {
-        // Basic happy path
-        let valid_jwt = "Bearer eyJhbGciOiJIUzI1NiJ9.eyJpc3MiOiJkaWQ6cGxjOmV4YW1wbGUifQ.signature";
+    fn test_feed_item_serialization() {
+        let plain = serde_json::to_value(FeedItem::new("at://post/1")).unwrap();
+        assert_eq!(plain, serde_json::json!({ "post": "at://post/1" }));
+
+        let pinned =
+            serde_json::to_value(FeedItem::new("at://post/2").pinned().with_context("ctx"))
+                .unwrap();
         assert_eq!(
-            extract_did_from_jwt(Some(valid_jwt)).unwrap(),
-            "did:plc:example"
+            pinned,
+            serde_json::json!({
+                "post": "at://post/2",
+                "reason": { "$type": "app.bsky.feed.defs#skeletonReasonPin" },
+                "feedContext": "ctx"
+            })
         );
 
-        // Missing header
-        assert!(extract_did_from_jwt(None).is_err());
-
-        // Case-insensitive Bearer
-        let lowercase_bearer =
-            "bearer eyJhbGciOiJIUzI1NiJ9.eyJpc3MiOiJkaWQ6cGxjOmV4YW1wbGUifQ.signature";
+        let reposted = FeedItem::new("at://post/3").reposted_by("at://repost/1");
+        let value = serde_json::to_value(&reposted).unwrap();
         assert_eq!(
-            extract_did_from_jwt(Some(lowercase_bearer)).unwrap(),
-            "did:plc:example"
+            value["reason"],
+            serde_json::json!({
+                "$type": "app.bsky.feed.defs#skeletonReasonRepost",
+                "repost": "at://repost/1"
+            })
         );
-
-        // Invalid format (missing Bearer)
-        assert!(extract_did_from_jwt(Some("InvalidToken")).is_err());
-
-        // Invalid JWT (not enought parts)
-        assert!(extract_did_from_jwt(Some("Bearer invalid.jwt")).is_err());
+        assert_eq!(serde_json::from_value::<FeedItem>(value).unwrap(), reposted);
     }
 
     /// ヘッダーから最も優先度が高い言語を取得する
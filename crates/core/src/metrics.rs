@@ -0,0 +1,342 @@
+//! Prometheus のテキスト形式で出力するための、最小限のメトリクス
+//!
+//! カウンターとヒストグラムはラベルの組み合わせごとに値を持つ。clone しても同じ値を共有する。
+//! イベントごとに呼ぶところでは `with_labels` で先にラベルを解決しておくと、毎回ラベルを組み立てずに済む
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// ラベルの組（名前順ではなく、記録したときの順に出力する）
+pub type Labels = Vec<(&'static str, String)>;
+
+fn labels(pairs: &[(&'static str, &str)]) -> Labels {
+    pairs
+        .iter()
+        .map(|(name, value)| (*name, value.to_string()))
+        .collect()
+}
+
+/// 単調増加するカウンター
+#[derive(Clone, Default)]
+pub struct Counter {
+    values: Arc<Mutex<BTreeMap<Labels, Arc<AtomicU64>>>>,
+}
+
+impl Counter {
+    pub fn inc(&self, pairs: &[(&'static str, &str)]) {
+        self.add(pairs, 1);
+    }
+
+    pub fn add(&self, pairs: &[(&'static str, &str)], value: u64) {
+        self.with_labels(pairs).add(value);
+    }
+
+    /// ラベルを解決したカウンター。同じラベルの値を共有する
+    pub fn with_labels(&self, pairs: &[(&'static str, &str)]) -> BoundCounter {
+        BoundCounter(
+            self.values
+                .lock()
+                .unwrap()
+                .entry(labels(pairs))
+                .or_default()
+                .clone(),
+        )
+    }
+
+    pub fn get(&self, pairs: &[(&'static str, &str)]) -> u64 {
+        self.values
+            .lock()
+            .unwrap()
+            .get(&labels(pairs))
+            .map_or(0, |value| value.load(Ordering::Relaxed))
+    }
+}
+
+/// `Counter::with_labels` で作る、ラベルが決まったカウンター
+#[derive(Clone)]
+pub struct BoundCounter(Arc<AtomicU64>);
+
+impl BoundCounter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+}
+
+/// 処理時間などの分布（秒）
+#[derive(Clone)]
+pub struct Histogram {
+    buckets: &'static [f64],
+    values: Arc<Mutex<BTreeMap<Labels, Arc<Mutex<HistogramValue>>>>>,
+}
+
+#[derive(Default)]
+struct HistogramValue {
+    /// バケットごとの（累積ではない）件数。最後は +Inf
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+/// 数ミリ秒から数十秒までを想定した既定のバケット
+pub const DEFAULT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUCKETS)
+    }
+}
+
+impl Histogram {
+    /// `buckets` は昇順の上限値
+    pub fn new(buckets: &'static [f64]) -> Self {
+        Self {
+            buckets,
+            values: Arc::default(),
+        }
+    }
+
+    pub fn observe(&self, pairs: &[(&'static str, &str)], value: f64) {
+        self.with_labels(pairs).observe(value);
+    }
+
+    /// ラベルを解決したヒストグラム。同じラベルの値を共有する
+    pub fn with_labels(&self, pairs: &[(&'static str, &str)]) -> BoundHistogram {
+        let value = self
+            .values
+            .lock()
+            .unwrap()
+            .entry(labels(pairs))
+            .or_insert_with(|| {
+                Arc::new(Mutex::new(HistogramValue {
+                    counts: vec![0; self.buckets.len() + 1],
+                    ..Default::default()
+                }))
+            })
+            .clone();
+        BoundHistogram {
+            buckets: self.buckets,
+            value,
+        }
+    }
+
+    pub fn count(&self, pairs: &[(&'static str, &str)]) -> u64 {
+        self.values
+            .lock()
+            .unwrap()
+            .get(&labels(pairs))
+            .map_or(0, |value| value.lock().unwrap().count)
+    }
+}
+
+/// `Histogram::with_labels` で作る、ラベルが決まったヒストグラム
+#[derive(Clone)]
+pub struct BoundHistogram {
+    buckets: &'static [f64],
+    value: Arc<Mutex<HistogramValue>>,
+}
+
+impl BoundHistogram {
+    pub fn observe(&self, value: f64) {
+        let bucket = self
+            .buckets
+            .iter()
+            .position(|upper| value <= *upper)
+            .unwrap_or(self.buckets.len());
+        let mut entry = self.value.lock().unwrap();
+        entry.counts[bucket] += 1;
+        entry.sum += value;
+        entry.count += 1;
+    }
+}
+
+/// AppView など外部 API への呼び出し
+///
+/// 各フィードのクレートから直接呼ばれるので、プロセス全体で 1 つだけ持つ（`appview()`）
+#[derive(Clone, Default)]
+pub struct OutboundMetrics {
+    /// メソッド（NSID）ごとの呼び出し回数
+    pub requests: Counter,
+    /// 送信エラーか、2xx 以外のレスポンス
+    pub failures: Counter,
+    pub duration: Histogram,
+}
+
+impl OutboundMetrics {
+    /// `send`（リクエストを送る future）の結果を `method` のラベルで記録する
+    pub async fn observe<F, E>(&self, method: &'static str, send: F) -> Result<reqwest::Response, E>
+    where
+        F: std::future::Future<Output = Result<reqwest::Response, E>>,
+    {
+        let started = std::time::Instant::now();
+        let result = send.await;
+        self.requests.inc(&[("method", method)]);
+        self.duration
+            .observe(&[("method", method)], started.elapsed().as_secs_f64());
+        match &result {
+            Ok(res) if res.status().is_success() => {}
+            Ok(res) => self
+                .failures
+                .inc(&[("method", method), ("status", res.status().as_str())]),
+            Err(_) => self
+                .failures
+                .inc(&[("method", method), ("status", "error")]),
+        }
+        result
+    }
+}
+
+/// AppView への呼び出しのメトリクス
+pub fn appview() -> &'static OutboundMetrics {
+    static APPVIEW: std::sync::OnceLock<OutboundMetrics> = std::sync::OnceLock::new();
+    APPVIEW.get_or_init(OutboundMetrics::default)
+}
+
+/// Prometheus のテキスト形式（`text/plain; version=0.0.4`）を組み立てる
+#[derive(Default)]
+pub struct TextEncoder {
+    out: String,
+}
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+impl TextEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn counter(&mut self, name: &str, help: &str, counter: &Counter) {
+        self.header(name, help, "counter");
+        for (labels, value) in counter.values.lock().unwrap().iter() {
+            self.sample(name, labels, None, value.load(Ordering::Relaxed) as f64);
+        }
+    }
+
+    /// `Counter` の外で数えている単調増加の値（出力する時点で読む）
+    pub fn counter_values<I>(&mut self, name: &str, help: &str, samples: I)
+    where
+        I: IntoIterator<Item = (Labels, u64)>,
+    {
+        self.header(name, help, "counter");
+        for (labels, value) in samples {
+            self.sample(name, &labels, None, value as f64);
+        }
+    }
+
+    /// 出力する時点で計算する値
+    pub fn gauge<I>(&mut self, name: &str, help: &str, samples: I)
+    where
+        I: IntoIterator<Item = (Labels, f64)>,
+    {
+        self.header(name, help, "gauge");
+        for (labels, value) in samples {
+            self.sample(name, &labels, None, value);
+        }
+    }
+
+    pub fn histogram(&mut self, name: &str, help: &str, histogram: &Histogram) {
+        self.header(name, help, "histogram");
+        let bucket_name = format!("{}_bucket", name);
+        for (labels, value) in histogram.values.lock().unwrap().iter() {
+            let value = value.lock().unwrap();
+            let mut cumulative = 0;
+            for (i, count) in value.counts.iter().enumerate() {
+                cumulative += count;
+                let le = match histogram.buckets.get(i) {
+                    Some(upper) => upper.to_string(),
+                    None => "+Inf".to_string(),
+                };
+                self.sample(&bucket_name, labels, Some(&le), cumulative as f64);
+            }
+            self.sample(&format!("{}_sum", name), labels, None, value.sum);
+            self.sample(&format!("{}_count", name), labels, None, value.count as f64);
+        }
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+
+    fn header(&mut self, name: &str, help: &str, kind: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &Labels, le: Option<&str>, value: f64) {
+        let mut pairs: Vec<String> = labels
+            .iter()
+            .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+            .collect();
+        if let Some(le) = le {
+            pairs.push(format!("le=\"{}\"", le));
+        }
+
+        if pairs.is_empty() {
+            let _ = writeln!(self.out, "{} {}", name, value);
+        } else {
+            let _ = writeln!(self.out, "{}{{{}}} {}", name, pairs.join(","), value);
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// カウンター・ゲージ・ヒストグラムが Prometheus のテキスト形式で出力されるか
+    #[test]
+    fn test_text_encoder() {
+        let requests = Counter::default();
+        requests.inc(&[("feed", "helloworld")]);
+        requests.add(&[("feed", "helloworld")], 2);
+        requests.inc(&[("feed", "say \"hi\"")]);
+        requests.with_labels(&[("feed", "helloworld")]).inc();
+        assert_eq!(requests.get(&[("feed", "helloworld")]), 4);
+
+        let latency = Histogram::new(&[0.1, 1.0]);
+        latency.observe(&[("feed", "helloworld")], 0.05);
+        latency.with_labels(&[("feed", "helloworld")]).observe(0.5);
+        latency.observe(&[("feed", "helloworld")], 3.0);
+
+        let mut encoder = TextEncoder::new();
+        encoder.counter("requests_total", "Requests.", &requests);
+        encoder.gauge("up", "Up.", vec![(vec![], 1.0)]);
+        encoder.histogram("latency_seconds", "Latency.", &latency);
+
+        assert_eq!(
+            encoder.finish(),
+            [
+                "# HELP requests_total Requests.",
+                "# TYPE requests_total counter",
+                "requests_total{feed=\"helloworld\"} 4",
+                "requests_total{feed=\"say \\\"hi\\\"\"} 1",
+                "# HELP up Up.",
+                "# TYPE up gauge",
+                "up 1",
+                "# HELP latency_seconds Latency.",
+                "# TYPE latency_seconds histogram",
+                "latency_seconds_bucket{feed=\"helloworld\",le=\"0.1\"} 1",
+                "latency_seconds_bucket{feed=\"helloworld\",le=\"1\"} 2",
+                "latency_seconds_bucket{feed=\"helloworld\",le=\"+Inf\"} 3",
+                "latency_seconds_sum{feed=\"helloworld\"} 3.55",
+                "latency_seconds_count{feed=\"helloworld\"} 3",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+}
@@ -0,0 +1,361 @@
+//! DID 解決モジュール
+//!
+//! `did:plc` は PLC ディレクトリ、`did:web` は `/.well-known/did.json` から DID ドキュメントを取得し、
+//! 署名鍵・PDS エンドポイント・ハンドルを取り出す。
+//!
+//! テーブル: `did_cache`
+//!   - did        : TEXT PRIMARY KEY
+//!   - document   : TEXT NOT NULL       (DID ドキュメントの JSON)
+//!   - expires_at : INTEGER NOT NULL    (UNIX タイムスタンプ秒)
+
+use crate::http::HttpClient;
+use crate::jwt::{PublicKey, SigningKeyResolver};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqlitePool};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_PLC_DIRECTORY_URL: &str = "https://plc.directory";
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// `did_cache` テーブルを作成する（冪等）
+pub async fn migrate(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS did_cache (
+            did        TEXT    PRIMARY KEY,
+            document   TEXT    NOT NULL,
+            expires_at INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_did_cache_expires_at ON did_cache(expires_at);
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DidDocument {
+    pub id: String,
+    #[serde(rename = "alsoKnownAs", default)]
+    pub also_known_as: Vec<String>,
+    #[serde(rename = "verificationMethod", default)]
+    pub verification_method: Vec<VerificationMethod>,
+    #[serde(default)]
+    pub service: Vec<DidService>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerificationMethod {
+    pub id: String,
+    #[serde(rename = "publicKeyMultibase")]
+    pub public_key_multibase: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DidService {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub service_type: String,
+    #[serde(rename = "serviceEndpoint")]
+    pub service_endpoint: String,
+}
+
+/// DID ドキュメントから取り出した、アプリが使う情報
+#[derive(Debug, Clone)]
+pub struct ResolvedDid {
+    pub did: String,
+    /// `#atproto` 検証鍵（ない DID もありうる）
+    pub signing_key: Option<PublicKey>,
+    /// `#atproto_pds` のエンドポイント
+    pub pds_endpoint: Option<String>,
+    /// `alsoKnownAs` の `at://` ハンドル（先頭が主ハンドル）
+    pub handles: Vec<String>,
+}
+
+impl ResolvedDid {
+    pub fn from_document(doc: &DidDocument) -> Result<Self> {
+        let signing_key = doc
+            .verification_method
+            .iter()
+            .find(|m| is_fragment(&m.id, &doc.id, "atproto"))
+            .and_then(|m| m.public_key_multibase.as_deref())
+            .map(PublicKey::from_multibase)
+            .transpose()?;
+
+        let pds_endpoint = doc
+            .service
+            .iter()
+            .find(|s| {
+                is_fragment(&s.id, &doc.id, "atproto_pds")
+                    && s.service_type == "AtprotoPersonalDataServer"
+            })
+            .map(|s| s.service_endpoint.trim_end_matches('/').to_string());
+
+        let handles = doc
+            .also_known_as
+            .iter()
+            .filter_map(|aka| aka.strip_prefix("at://"))
+            .map(|h| h.to_string())
+            .collect();
+
+        Ok(Self {
+            did: doc.id.clone(),
+            signing_key,
+            pds_endpoint,
+            handles,
+        })
+    }
+
+    /// 主ハンドル（`alsoKnownAs` の先頭）
+    pub fn handle(&self) -> Option<&str> {
+        self.handles.first().map(|h| h.as_str())
+    }
+}
+
+/// `#atproto` と `did:plc:xxx#atproto` の両方の書き方を許容する
+fn is_fragment(id: &str, did: &str, fragment: &str) -> bool {
+    match id.split_once('#') {
+        Some(("", f)) => f == fragment,
+        Some((prefix, f)) => prefix == did && f == fragment,
+        None => false,
+    }
+}
+
+/// SQLite キャッシュ付きの DID リゾルバー
+#[derive(Clone)]
+pub struct DidResolver {
+    client: HttpClient,
+    pool: SqlitePool,
+    plc_directory_url: String,
+    ttl: Duration,
+}
+
+impl DidResolver {
+    pub fn new(client: impl Into<HttpClient>, pool: SqlitePool) -> Self {
+        Self {
+            client: client.into(),
+            pool,
+            plc_directory_url: DEFAULT_PLC_DIRECTORY_URL.to_string(),
+            ttl: DEFAULT_CACHE_TTL,
+        }
+    }
+
+    pub fn with_plc_directory_url(mut self, url: impl Into<String>) -> Self {
+        self.plc_directory_url = url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// キャッシュ優先で DID を解決する
+    pub async fn resolve(&self, did: &str) -> Result<ResolvedDid> {
+        match self.get_cached(did).await {
+            Ok(Some(doc)) => return ResolvedDid::from_document(&doc),
+            Ok(None) => {}
+            Err(e) => tracing::warn!("[did] Cache read failed for {}: {:#}", did, e),
+        }
+        self.resolve_fresh(did).await
+    }
+
+    /// キャッシュを使わずに DID を解決し、結果をキャッシュし直す
+    pub async fn resolve_fresh(&self, did: &str) -> Result<ResolvedDid> {
+        let doc = self.fetch_document(did).await?;
+        if let Err(e) = self.set_cached(&doc).await {
+            tracing::warn!("[did] Cache write failed for {}: {:#}", did, e);
+        }
+        ResolvedDid::from_document(&doc)
+    }
+
+    /// キャッシュを破棄する（ハンドル変更・鍵ローテーション時など）
+    pub async fn invalidate(&self, did: &str) -> Result<()> {
+        sqlx::query("DELETE FROM did_cache WHERE did = ?")
+            .bind(did)
+            .execute(&self.pool)
+            .await
+            .context("did_cache: invalidate query failed")?;
+        Ok(())
+    }
+
+    fn document_url(&self, did: &str) -> Result<String> {
+        if did.starts_with("did:plc:") {
+            Ok(format!("{}/{}", self.plc_directory_url, did))
+        } else if let Some(host) = did.strip_prefix("did:web:") {
+            // atproto の did:web はホスト名のみ（パスは使われない）。ポートは %3A でエンコードされる
+            if host.contains(':') {
+                bail!("Unsupported did:web with path: {}", did);
+            }
+            let host = host.replace("%3A", ":").replace("%3a", ":");
+            Ok(format!("https://{}/.well-known/did.json", host))
+        } else {
+            bail!("Unsupported DID method: {}", did)
+        }
+    }
+
+    async fn fetch_document(&self, did: &str) -> Result<DidDocument> {
+        let url = self.document_url(did)?;
+        let res = self
+            .client
+            .send(self.client.get(&url))
+            .await
+            .context("Failed to fetch DID document")?;
+
+        if !res.status().is_success() {
+            bail!("DID document request failed: {}", res.status());
+        }
+
+        let doc: DidDocument = res.json().await.context("Failed to parse DID document")?;
+        if doc.id != did {
+            bail!("DID document id mismatch: expected {}, got {}", did, doc.id);
+        }
+        Ok(doc)
+    }
+
+    async fn get_cached(&self, did: &str) -> Result<Option<DidDocument>> {
+        let row = sqlx::query("SELECT document FROM did_cache WHERE did = ? AND expires_at > ?")
+            .bind(did)
+            .bind(unix_now())
+            .fetch_optional(&self.pool)
+            .await
+            .context("did_cache: get query failed")?;
+
+        row.map(|r| {
+            serde_json::from_str(&r.get::<String, _>(0)).context("did_cache: invalid document")
+        })
+        .transpose()
+    }
+
+    async fn set_cached(&self, doc: &DidDocument) -> Result<()> {
+        let expires_at = unix_now() + self.ttl.as_secs() as i64;
+        sqlx::query(
+            "INSERT OR REPLACE INTO did_cache (did, document, expires_at) VALUES (?, ?, ?)",
+        )
+        .bind(&doc.id)
+        .bind(serde_json::to_string(doc)?)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await
+        .context("did_cache: set query failed")?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SigningKeyResolver for DidResolver {
+    async fn resolve_signing_key(&self, did: &str) -> Result<PublicKey> {
+        self.resolve(did)
+            .await?
+            .signing_key
+            .context("DID document has no #atproto verification method")
+    }
+
+    async fn refresh_signing_key(&self, did: &str) -> Result<PublicKey> {
+        self.resolve_fresh(did)
+            .await?
+            .signing_key
+            .context("DID document has no #atproto verification method")
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn sample_document() -> DidDocument {
+        serde_json::from_value(json!({
+            "@context": ["https://www.w3.org/ns/did/v1"],
+            "id": "did:plc:alice",
+            "alsoKnownAs": ["at://alice.example.com", "https://example.com"],
+            "verificationMethod": [{
+                "id": "did:plc:alice#atproto",
+                "type": "Multikey",
+                "controller": "did:plc:alice",
+                "publicKeyMultibase": "zQ3shqwJEJyMBsBXCWyCBpUBMqxcon9oHB7mCvx4sSpMdLJwc"
+            }],
+            "service": [{
+                "id": "#atproto_pds",
+                "type": "AtprotoPersonalDataServer",
+                "serviceEndpoint": "https://pds.example.com/"
+            }]
+        }))
+        .unwrap()
+    }
+
+    async fn resolver() -> DidResolver {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate(&pool).await.unwrap();
+        // ネットワークに出ないよう、到達不能な PLC ディレクトリを指定する
+        DidResolver::new(reqwest::Client::new(), pool)
+            .with_plc_directory_url("http://127.0.0.1:9/plc")
+    }
+
+    /// DID ドキュメントから署名鍵・PDS・ハンドルを取り出せる
+    #[test]
+    fn test_resolved_did_from_document() {
+        let resolved = ResolvedDid::from_document(&sample_document()).unwrap();
+        assert_eq!(resolved.did, "did:plc:alice");
+        assert_eq!(resolved.signing_key.unwrap().jwt_alg(), "ES256K");
+        assert_eq!(
+            resolved.pds_endpoint.as_deref(),
+            Some("https://pds.example.com")
+        );
+        assert_eq!(resolved.handles, vec!["alice.example.com"]);
+    }
+
+    /// 各 DID メソッドのドキュメント URL
+    #[tokio::test]
+    async fn test_document_url() {
+        let resolver = resolver().await;
+        assert_eq!(
+            resolver.document_url("did:plc:alice").unwrap(),
+            "http://127.0.0.1:9/plc/did:plc:alice"
+        );
+        assert_eq!(
+            resolver.document_url("did:web:example.com").unwrap(),
+            "https://example.com/.well-known/did.json"
+        );
+        assert_eq!(
+            resolver.document_url("did:web:localhost%3A8080").unwrap(),
+            "https://localhost:8080/.well-known/did.json"
+        );
+        assert!(resolver.document_url("did:web:example.com:user").is_err());
+        assert!(resolver.document_url("did:key:z123").is_err());
+    }
+
+    /// キャッシュが有効な間はネットワークに出ずに解決できる
+    #[tokio::test]
+    async fn test_resolve_cache_hit() {
+        let resolver = resolver().await;
+        resolver.set_cached(&sample_document()).await.unwrap();
+
+        let resolved = resolver.resolve("did:plc:alice").await.unwrap();
+        assert_eq!(resolved.handle(), Some("alice.example.com"));
+    }
+
+    /// 期限切れ・破棄済みのキャッシュは使われない
+    #[tokio::test]
+    async fn test_resolve_cache_expired_and_invalidated() {
+        let resolver = resolver().await.with_ttl(Duration::ZERO);
+        resolver.set_cached(&sample_document()).await.unwrap();
+        assert!(resolver.resolve("did:plc:alice").await.is_err());
+
+        let resolver = resolver.with_ttl(DEFAULT_CACHE_TTL);
+        resolver.set_cached(&sample_document()).await.unwrap();
+        resolver.invalidate("did:plc:alice").await.unwrap();
+        assert!(resolver.resolve("did:plc:alice").await.is_err());
+    }
+}
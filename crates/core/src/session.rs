@@ -0,0 +1,301 @@
+//! フィードが AppView を呼ぶときに使う、サービスアカウントのセッション
+//!
+//! - アクセストークンの `exp` が近づいたら、使う前に `refreshSession` で更新する
+//! - リフレッシュトークンも切れていたり更新に失敗したりしたら、アプリパスワードで `createSession` し直す
+//! - 同時に何件更新が必要になっても、実際の更新は 1 回にまとめる
+
+use crate::appview::{AppViewClient, Session};
+use crate::xrpc::XrpcError;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::future::Future;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, RwLock};
+
+/// `exp` のこの秒数前になったら先回りして更新する
+const DEFAULT_REFRESH_MARGIN_SECS: i64 = 60;
+
+/// 期限切れ・無効なトークンで拒否されたことを表すエラーか
+///
+/// `ServiceSession::with_token` は、これが true のときだけ更新してやり直す
+pub trait AuthFailure {
+    fn is_auth_failure(&self) -> bool;
+}
+
+impl AuthFailure for XrpcError {
+    fn is_auth_failure(&self) -> bool {
+        XrpcError::is_auth_failure(self)
+    }
+}
+
+/// `context` で包まれていても、元の `XrpcError` を見て判定する
+impl AuthFailure for anyhow::Error {
+    fn is_auth_failure(&self) -> bool {
+        self.chain()
+            .filter_map(|e| e.downcast_ref::<XrpcError>())
+            .any(XrpcError::is_auth_failure)
+    }
+}
+
+#[derive(Clone)]
+struct Tokens {
+    did: String,
+    access_jwt: String,
+    refresh_jwt: String,
+    /// JWT として読めなければ None（拒否されるまで使う）
+    access_exp: Option<i64>,
+    refresh_exp: Option<i64>,
+}
+
+impl Tokens {
+    fn new(session: Session) -> Self {
+        Self {
+            access_exp: jwt_expiry(&session.access_jwt),
+            refresh_exp: jwt_expiry(&session.refresh_jwt),
+            did: session.did,
+            access_jwt: session.access_jwt,
+            refresh_jwt: session.refresh_jwt,
+        }
+    }
+
+    fn access_expiring(&self, now: i64, margin: i64) -> bool {
+        self.access_exp.is_some_and(|exp| exp - margin <= now)
+    }
+
+    fn refresh_usable(&self, now: i64) -> bool {
+        !self.refresh_jwt.is_empty() && self.refresh_exp.is_none_or(|exp| exp > now)
+    }
+}
+
+pub struct ServiceSession {
+    appview: AppViewClient,
+    identifier: String,
+    password: String,
+    refresh_margin: i64,
+    current: RwLock<Option<Tokens>>,
+    /// 更新を 1 つずつにする（待っていた側は更新後のトークンを使う）
+    refreshing: Mutex<()>,
+}
+
+impl ServiceSession {
+    pub fn new(
+        appview: AppViewClient,
+        identifier: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        Self {
+            appview,
+            identifier: identifier.into(),
+            password: password.into(),
+            refresh_margin: DEFAULT_REFRESH_MARGIN_SECS,
+            current: RwLock::new(None),
+            refreshing: Mutex::new(()),
+        }
+    }
+
+    /// ログイン済みのセッションから始める
+    pub fn with_session(mut self, session: Session) -> Self {
+        *self.current.get_mut() = Some(Tokens::new(session));
+        self
+    }
+
+    pub fn with_refresh_margin(mut self, seconds: i64) -> Self {
+        self.refresh_margin = seconds;
+        self
+    }
+
+    /// アプリパスワードでログインし、アカウントの DID を返す
+    pub async fn login(&self) -> Result<String> {
+        let _guard = self.refreshing.lock().await;
+        let tokens = self.create_session().await?;
+        let did = tokens.did.clone();
+        *self.current.write().await = Some(tokens);
+        Ok(did)
+    }
+
+    /// ログインしていればアカウントの DID
+    pub async fn did(&self) -> Option<String> {
+        self.current.read().await.as_ref().map(|t| t.did.clone())
+    }
+
+    /// 使えるアクセストークン。期限が近ければ更新してから返す
+    pub async fn access_token(&self) -> Result<String> {
+        let stale = {
+            let current = self.current.read().await;
+            match current.as_ref() {
+                Some(tokens) if !tokens.access_expiring(now(), self.refresh_margin) => {
+                    return Ok(tokens.access_jwt.clone());
+                }
+                Some(tokens) => Some(tokens.access_jwt.clone()),
+                None => None,
+            }
+        };
+        self.refresh(stale.as_deref()).await
+    }
+
+    /// `rejected` が拒否されたので更新する。すでに他で更新済みなら、そのトークンを返す
+    pub async fn refresh_rejected(&self, rejected: &str) -> Result<String> {
+        self.refresh(Some(rejected)).await
+    }
+
+    /// トークンを渡して `f` を呼ぶ。期限切れで拒否されたら、1 度だけ更新してやり直す
+    pub async fn with_token<T, E, F, Fut>(&self, f: F) -> Result<T, E>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: AuthFailure + From<anyhow::Error>,
+    {
+        let token = self.access_token().await?;
+        match f(token.clone()).await {
+            Err(e) if e.is_auth_failure() => {
+                tracing::warn!("Service token was rejected, refreshing session");
+                let token = self.refresh_rejected(&token).await?;
+                f(token).await
+            }
+            result => result,
+        }
+    }
+
+    async fn refresh(&self, stale: Option<&str>) -> Result<String> {
+        let _guard = self.refreshing.lock().await;
+
+        // 待っている間に他のタスクが更新していれば、それを使う
+        let current = self.current.read().await.clone();
+        if let Some(tokens) = &current {
+            if Some(tokens.access_jwt.as_str()) != stale
+                && !tokens.access_expiring(now(), self.refresh_margin)
+            {
+                return Ok(tokens.access_jwt.clone());
+            }
+        }
+
+        let refreshed = match current.filter(|t| t.refresh_usable(now())) {
+            Some(tokens) => match self.refresh_session(&tokens.refresh_jwt).await {
+                Ok(refreshed) => refreshed,
+                Err(e) => {
+                    tracing::warn!("refreshSession failed, logging in again: {:#}", e);
+                    self.create_session().await?
+                }
+            },
+            None => self.create_session().await?,
+        };
+
+        tracing::info!("Service session refreshed (DID: {})", refreshed.did);
+        let access_jwt = refreshed.access_jwt.clone();
+        *self.current.write().await = Some(refreshed);
+        Ok(access_jwt)
+    }
+
+    async fn refresh_session(&self, refresh_jwt: &str) -> Result<Tokens> {
+        let session = self.appview.refresh_session(refresh_jwt).await?;
+        Ok(Tokens::new(session))
+    }
+
+    async fn create_session(&self) -> Result<Tokens> {
+        if self.identifier.is_empty() || self.password.is_empty() {
+            bail!("Cannot log in: credentials missing");
+        }
+        let session = self
+            .appview
+            .create_session(&self.identifier, &self.password)
+            .await
+            .context("createSession failed")?;
+        Ok(Tokens::new(session))
+    }
+}
+
+#[derive(Deserialize)]
+struct ExpiryClaim {
+    exp: Option<i64>,
+}
+
+/// JWT の `exp`（署名は検証しない。自分のトークンの期限を知りたいだけなので）
+fn jwt_expiry(token: &str) -> Option<i64> {
+    let payload = token.split('.').nth(1)?;
+    let bytes = crate::jwt::decode_segment(payload).ok()?;
+    serde_json::from_slice::<ExpiryClaim>(&bytes).ok()?.exp
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose, Engine as _};
+
+    fn jwt(exp: i64) -> String {
+        let encode = |value: serde_json::Value| {
+            general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(&value).unwrap())
+        };
+        format!(
+            "{}.{}.sig",
+            encode(serde_json::json!({ "alg": "ES256K" })),
+            encode(serde_json::json!({ "exp": exp }))
+        )
+    }
+
+    /// `exp` を読み、期限の手前で更新が必要と判定するか
+    #[test]
+    fn test_token_expiry() {
+        let tokens = Tokens::new(Session {
+            did: "did:plc:service".to_string(),
+            access_jwt: jwt(1_000),
+            refresh_jwt: jwt(2_000),
+        });
+        assert_eq!(tokens.access_exp, Some(1_000));
+        assert!(!tokens.access_expiring(900, 60));
+        assert!(tokens.access_expiring(940, 60));
+        assert!(tokens.refresh_usable(1_999));
+        assert!(!tokens.refresh_usable(2_000));
+
+        // JWT でなければ期限はわからないので、拒否されるまで使う
+        let opaque = Tokens::new(Session {
+            did: "did:plc:service".to_string(),
+            access_jwt: "opaque".to_string(),
+            refresh_jwt: String::new(),
+        });
+        assert!(!opaque.access_expiring(i64::MAX, 60));
+        assert!(!opaque.refresh_usable(0));
+    }
+
+    /// `context` で包まれた XrpcError も判定でき、メッセージの文字列には左右されないか
+    #[test]
+    fn test_auth_failure_detection() {
+        let expired = anyhow::Error::from(XrpcError::ExpiredToken {
+            message: "Token has expired".to_string(),
+        })
+        .context("Failed to fetch TODOs");
+        assert!(expired.is_auth_failure());
+
+        let upstream = anyhow::Error::from(XrpcError::Upstream5xx {
+            status: 503,
+            message: "401 Unauthorized from proxy".to_string(),
+        });
+        assert!(!upstream.is_auth_failure());
+        assert!(!anyhow::anyhow!("ExpiredToken").is_auth_failure());
+    }
+
+    /// 期限内のトークンは、ネットワークに出ずにそのまま返すか
+    #[tokio::test]
+    async fn test_access_token_without_refresh() {
+        let access = jwt(now() + 3600);
+        let session = ServiceSession::new(
+            AppViewClient::new(reqwest::Client::new(), "http://127.0.0.1:9"),
+            "",
+            "",
+        )
+        .with_session(Session {
+            did: "did:plc:service".to_string(),
+            access_jwt: access.clone(),
+            refresh_jwt: jwt(now() + 7200),
+        });
+
+        assert_eq!(session.access_token().await.unwrap(), access);
+        assert_eq!(session.did().await.as_deref(), Some("did:plc:service"));
+    }
+}
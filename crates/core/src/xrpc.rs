@@ -0,0 +1,174 @@
+//! AppView / PDS の XRPC 呼び出しの失敗
+//!
+//! レスポンスのステータスと `{"error", "message"}` から種類を決める。
+//! 呼び出し側はメッセージの文字列ではなく、この種類で扱いを分ける
+
+use serde::Deserialize;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum XrpcError {
+    /// アクセストークンの期限切れ（更新すれば通る）
+    ExpiredToken {
+        message: String,
+    },
+    /// トークンが無効（署名違い・失効など）
+    InvalidToken {
+        message: String,
+    },
+    /// レート制限。`reset` は制限が解ける UNIX 時刻（秒）
+    RateLimited {
+        reset: Option<i64>,
+        message: String,
+    },
+    NotFound {
+        message: String,
+    },
+    /// 上流の 5xx
+    Upstream5xx {
+        status: u16,
+        message: String,
+    },
+    /// レスポンスが期待した形でない
+    Decode(String),
+    /// 接続できなかった・タイムアウトした
+    Transport(String),
+    /// その他の 4xx（`error` は XRPC のエラー名）
+    Request {
+        status: u16,
+        error: String,
+        message: String,
+    },
+}
+
+#[derive(Deserialize, Default)]
+struct ErrorBody {
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    message: Option<String>,
+}
+
+impl XrpcError {
+    /// 2xx 以外のレスポンスから作る
+    pub async fn from_response(res: reqwest::Response) -> Self {
+        let status = res.status().as_u16();
+        let reset = res
+            .headers()
+            .get("ratelimit-reset")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse().ok());
+        let text = res.text().await.unwrap_or_default();
+        Self::from_parts(status, reset, &text)
+    }
+
+    fn from_parts(status: u16, reset: Option<i64>, text: &str) -> Self {
+        let body: ErrorBody = serde_json::from_str(text).unwrap_or_default();
+        let error = body.error.unwrap_or_default();
+        let message = body.message.unwrap_or_else(|| text.to_string());
+
+        match (status, error.as_str()) {
+            (_, "ExpiredToken") => Self::ExpiredToken { message },
+            (_, "InvalidToken") | (401, _) => Self::InvalidToken { message },
+            (429, _) | (_, "RateLimitExceeded") => Self::RateLimited { reset, message },
+            (404, _) | (_, "NotFound") => Self::NotFound { message },
+            (500..=599, _) => Self::Upstream5xx { status, message },
+            _ => Self::Request {
+                status,
+                error,
+                message,
+            },
+        }
+    }
+
+    /// 更新したトークンでやり直せば通る可能性があるか
+    pub fn is_auth_failure(&self) -> bool {
+        matches!(self, Self::ExpiredToken { .. } | Self::InvalidToken { .. })
+    }
+
+    /// 時間をおけば通る可能性があるか
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::RateLimited { .. } | Self::Upstream5xx { .. } | Self::Transport(_)
+        )
+    }
+}
+
+impl fmt::Display for XrpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ExpiredToken { message } => write!(f, "ExpiredToken: {}", message),
+            Self::InvalidToken { message } => write!(f, "InvalidToken: {}", message),
+            Self::RateLimited { reset, message } => match reset {
+                Some(reset) => write!(f, "RateLimited until {}: {}", reset, message),
+                None => write!(f, "RateLimited: {}", message),
+            },
+            Self::NotFound { message } => write!(f, "NotFound: {}", message),
+            Self::Upstream5xx { status, message } => write!(f, "Upstream {}: {}", status, message),
+            Self::Decode(message) => write!(f, "Failed to decode response: {}", message),
+            Self::Transport(message) => write!(f, "Request failed: {}", message),
+            Self::Request {
+                status,
+                error,
+                message,
+            } => write!(f, "{} {}: {}", status, error, message),
+        }
+    }
+}
+
+impl std::error::Error for XrpcError {}
+
+impl From<reqwest::Error> for XrpcError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_decode() {
+            Self::Decode(err.to_string())
+        } else {
+            Self::Transport(err.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ステータスと XRPC のエラー名から種類を決めるか
+    #[test]
+    fn test_from_parts() {
+        let expired = XrpcError::from_parts(
+            400,
+            None,
+            r#"{"error":"ExpiredToken","message":"Token has expired"}"#,
+        );
+        assert!(
+            matches!(&expired, XrpcError::ExpiredToken { message } if message == "Token has expired")
+        );
+        assert!(expired.is_auth_failure());
+
+        assert!(matches!(
+            XrpcError::from_parts(401, None, ""),
+            XrpcError::InvalidToken { .. }
+        ));
+        assert!(matches!(
+            XrpcError::from_parts(429, Some(1_700_000_000), "{}"),
+            XrpcError::RateLimited {
+                reset: Some(1_700_000_000),
+                ..
+            }
+        ));
+        assert!(matches!(
+            XrpcError::from_parts(404, None, r#"{"error":"NotFound"}"#),
+            XrpcError::NotFound { .. }
+        ));
+        let upstream = XrpcError::from_parts(503, None, "Service Unavailable");
+        assert!(
+            matches!(&upstream, XrpcError::Upstream5xx { status: 503, message } if message == "Service Unavailable")
+        );
+        assert!(upstream.is_transient());
+        assert!(matches!(
+            XrpcError::from_parts(400, None, r#"{"error":"InvalidRequest","message":"bad"}"#),
+            XrpcError::Request { status: 400, ref error, .. } if error == "InvalidRequest"
+        ));
+    }
+}
@@ -1,10 +1,20 @@
 use atrium_api::record::KnownRecord;
-use bsky_core::{FeedItem, FeedSkeletonResult};
-use jetstream_oxide::events::commit::CommitEvent;
+use bsky_core::{FeedChange, FeedItem, FeedSkeletonResult};
+use jetstream_oxide::events::commit::{CommitEvent, CommitType};
 use regex::Regex;
 use sqlx::{Row, SqlitePool};
 use std::sync::OnceLock;
 
+/// 1ページ目の先頭に固定表示する投稿
+pub const PINNED_POST_URI: &str =
+    "at://did:plc:tsvcmd72oxp47wtixs4qllyi/app.bsky.feed.post/3ldy6oad3vk27";
+
+/// 固定表示した投稿に付ける feedContext
+pub const PINNED_CONTEXT: &str = "pinned";
+
+/// `process_event` が返すフィード名
+const FEED: &str = "helloworld";
+
 static HELLO_REGEX: OnceLock<Regex> = OnceLock::new();
 
 #[derive(Debug, Default, Clone)]
@@ -20,20 +30,29 @@ pub fn matches_hello_world(text: &str) -> bool {
     regex.is_match(text)
 }
 
-pub async fn process_event(pool: &SqlitePool, event: &CommitEvent) {
-    if let CommitEvent::Create { info, commit } = event {
-        if let KnownRecord::AppBskyFeedPost(post) = &commit.record {
+/// 投稿の作成・編集・削除をフィードに反映する
+///
+/// - 作成: マッチすれば追加
+/// - 編集: マッチすれば追加（既にあれば位置はそのまま）、マッチしなくなったら取り除く
+/// - 削除: 取り除く
+///
+/// 追加・削除したら、そのフィード名を返す（取り込みのメトリクスとキャッシュの破棄用）
+pub async fn process_event(pool: &SqlitePool, event: &CommitEvent) -> FeedChange {
+    match event {
+        CommitEvent::Create { info, commit } | CommitEvent::Update { info, commit } => {
             let collection = commit.info.collection.as_str();
             if collection != "app.bsky.feed.post" {
-                return;
+                return FeedChange::default();
             }
+            let KnownRecord::AppBskyFeedPost(post) = &commit.record else {
+                return FeedChange::default();
+            };
 
-            let text = &post.text;
+            let rkey = commit.info.rkey.as_str();
+            let did = info.did.as_str();
+            let post_uri = format!("at://{}/{}/{}", did, collection, rkey);
 
-            if matches_hello_world(text) {
-                let rkey = commit.info.rkey.as_str();
-                let did = info.did.as_str();
-                let post_uri = format!("at://{}/{}/{}", did, collection, rkey);
+            if matches_hello_world(&post.text) {
                 tracing::info!("Found hello world post: {}", post_uri);
 
                 let indexed_at = chrono::Utc::now().timestamp_micros();
@@ -48,14 +67,59 @@ pub async fn process_event(pool: &SqlitePool, event: &CommitEvent) {
                 .execute(pool)
                 .await;
 
-                if let Err(e) = result {
-                    tracing::error!("Failed to insert post: {}", e);
+                match result {
+                    Ok(_) => return FeedChange::added(FEED),
+                    Err(e) => tracing::error!("Failed to insert post: {}", e),
                 }
+            } else if matches!(commit.info.operation, CommitType::Update) {
+                // CommitEvent は untagged なので、編集も Create として届く。操作の種類は operation で見分ける
+                return removed(delete_post(pool, &post_uri).await);
             }
+            FeedChange::default()
+        }
+        CommitEvent::Delete { info, commit } => {
+            let collection = commit.collection.as_str();
+            if collection != "app.bsky.feed.post" {
+                return FeedChange::default();
+            }
+            let post_uri = format!("at://{}/{}/{}", info.did.as_str(), collection, commit.rkey);
+            removed(delete_post(pool, &post_uri).await)
+        }
+    }
+}
+
+fn removed(deleted: bool) -> FeedChange {
+    if deleted {
+        FeedChange::removed(FEED)
+    } else {
+        FeedChange::default()
+    }
+}
+
+/// 取り除いたら true（もともと無ければ false）
+async fn delete_post(pool: &SqlitePool, post_uri: &str) -> bool {
+    match sqlx::query("DELETE FROM helloworld_posts WHERE uri = ?")
+        .bind(post_uri)
+        .execute(pool)
+        .await
+    {
+        Ok(result) if result.rows_affected() > 0 => {
+            tracing::info!("Removed hello world post: {}", post_uri);
+            true
+        }
+        Ok(_) => false,
+        Err(e) => {
+            tracing::error!("Failed to delete post: {}", e);
+            false
         }
     }
 }
 
+/// 指定したアカウントの投稿をすべて取り除き、取り除いた件数を返す
+pub async fn purge_author(pool: &SqlitePool, did: &str) -> Result<u64, sqlx::Error> {
+    bsky_core::delete_posts_by_author(pool, "helloworld_posts", did).await
+}
+
 pub async fn get_feed_skeleton(
     pool: &SqlitePool,
     cursor: Option<String>,
@@ -66,10 +130,11 @@ pub async fn get_feed_skeleton(
 
     // Fixed pinned post at the top for first page
     if cursor.is_none() {
-        feed.push(FeedItem {
-            post: "at://did:plc:tsvcmd72oxp47wtixs4qllyi/app.bsky.feed.post/3ldy6oad3vk27"
-                .to_string(),
-        });
+        feed.push(
+            FeedItem::new(PINNED_POST_URI)
+                .pinned()
+                .with_context(PINNED_CONTEXT),
+        );
         tracing::info!("Added pinned post to feed (first page)");
     }
 
@@ -104,7 +169,7 @@ pub async fn get_feed_skeleton(
 
             for row in rows {
                 let uri: String = row.get("uri");
-                feed.push(FeedItem { post: uri });
+                feed.push(FeedItem::new(uri));
             }
         }
         Err(e) => {
@@ -191,4 +256,71 @@ mod tests {
         // Should not error on second run
         assert!(result.is_ok());
     }
+
+    /// 指定したアカウントの投稿だけが取り除かれるか検証（DID が前方一致するだけの別アカウントは残す）
+    #[tokio::test]
+    async fn test_purge_author() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate(&pool).await.unwrap();
+
+        for uri in [
+            "at://did:plc:alice/app.bsky.feed.post/1",
+            "at://did:plc:alice/app.bsky.feed.post/2",
+            "at://did:plc:alice2/app.bsky.feed.post/1",
+        ] {
+            sqlx::query("INSERT INTO helloworld_posts (uri, cid, indexed_at) VALUES (?, 'cid', 1)")
+                .bind(uri)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(purge_author(&pool, "did:plc:alice").await.unwrap(), 2);
+
+        let uris: Vec<String> = sqlx::query_scalar("SELECT uri FROM helloworld_posts")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(uris, vec!["at://did:plc:alice2/app.bsky.feed.post/1"]);
+    }
+
+    /// 記録した Jetstream のイベントを再生し、作成・編集・削除がフィードに反映され、取り除いたことを返すか検証
+    #[tokio::test]
+    async fn test_process_event_replay() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate(&pool).await.unwrap();
+
+        let fixture = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../tests/fixtures/jetstream/posts.ndjson"
+        );
+        let removed = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let replayed =
+            jetstream::replay::replay_file(fixture, jetstream::ReplaySpeed::Unthrottled, |event| {
+                let pool = pool.clone();
+                let removed = removed.clone();
+                async move {
+                    if let jetstream::ReplayEvent::Commit(event) = event {
+                        let change = process_event(&pool, &event).await;
+                        removed.lock().unwrap().extend(change.removed);
+                    }
+                }
+            })
+            .await
+            .unwrap();
+        assert_eq!(replayed, 11);
+        // 索引していた投稿の削除と、マッチしなくなった編集の 2 件だけが取り除いたと返す
+        assert_eq!(*removed.lock().unwrap(), vec![FEED, FEED]);
+
+        // マッチするように編集された投稿だけが残る
+        // （削除された投稿・マッチしなくなるよう編集された投稿は取り除かれる）
+        let uris: Vec<String> = sqlx::query_scalar("SELECT uri FROM helloworld_posts ORDER BY uri")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            uris,
+            vec!["at://did:plc:4xzwd7j3mzmuxkj6sc3k4ypq/app.bsky.feed.post/3l3qo2wigz22a"]
+        );
+    }
 }
@@ -0,0 +1,215 @@
+//! 保存済みカーソルからの追いつき（バックフィル）
+//!
+//! - カーソルが `max_window` 以内なら、そこから再生して取りこぼしを埋める
+//! - それより古い（Jetstream が保持していない）場合だけ、ライブに切り替える
+//! - 追いつくまでは平均レートを `max_events_per_sec` に抑え、進捗をログに出す
+
+use chrono::{DateTime, Duration, Utc};
+
+/// Jetstream がイベントを保持している期間の目安
+const DEFAULT_MAX_WINDOW_HOURS: i64 = 24;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BackfillConfig {
+    /// これより古いカーソルは再生せず、ライブから始める
+    pub max_window: Duration,
+    /// 追いつき中の平均レートの上限。0 なら無制限
+    pub max_events_per_sec: u32,
+    /// 現在時刻との差がこれ以内になったら追いついたとみなす
+    pub live_threshold: Duration,
+    pub progress_interval: Duration,
+}
+
+impl Default for BackfillConfig {
+    fn default() -> Self {
+        Self {
+            max_window: Duration::hours(DEFAULT_MAX_WINDOW_HOURS),
+            max_events_per_sec: 2000,
+            live_threshold: Duration::seconds(10),
+            progress_interval: Duration::seconds(30),
+        }
+    }
+}
+
+/// 再接続時に使うカーソルを決める。`max_window` より古ければ None（ライブ）
+pub fn resolve_start_cursor(
+    cursor: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+    max_window: Duration,
+) -> Option<DateTime<Utc>> {
+    let cursor = cursor?;
+    if now - cursor > max_window {
+        tracing::warn!(
+            "Cursor {} is older than the backfill window ({}h). Resetting to Live Tail.",
+            cursor,
+            max_window.num_hours()
+        );
+        return None;
+    }
+    Some(cursor)
+}
+
+/// 1 回の接続中の追いつき状況
+pub(crate) struct CatchUp {
+    config: BackfillConfig,
+    live: bool,
+    started_at: DateTime<Utc>,
+    last_progress: DateTime<Utc>,
+    replayed: u64,
+}
+
+impl CatchUp {
+    pub(crate) fn new(
+        config: BackfillConfig,
+        start_cursor: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let live = start_cursor.is_none_or(|cursor| now - cursor <= config.live_threshold);
+        if let Some(cursor) = start_cursor.filter(|_| !live) {
+            tracing::info!(
+                "Backfill started from {} ({}s behind)",
+                cursor,
+                (now - cursor).num_seconds()
+            );
+        }
+
+        Self {
+            config,
+            live,
+            started_at: now,
+            last_progress: now,
+            replayed: 0,
+        }
+    }
+
+    /// カーソルの時刻が分からないまま再開する（ファイアホースの seq など）。最初のイベントの時刻で追いついたか判断する
+    pub(crate) fn resuming(config: BackfillConfig, now: DateTime<Utc>) -> Self {
+        Self {
+            config,
+            live: false,
+            started_at: now,
+            last_progress: now,
+            replayed: 0,
+        }
+    }
+
+    /// イベントごとに呼び、レート上限を超えていれば待つべき時間を返す
+    pub(crate) fn on_event(
+        &mut self,
+        event_time: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Option<std::time::Duration> {
+        if self.live {
+            return None;
+        }
+
+        let elapsed = now - self.started_at;
+        if now - event_time <= self.config.live_threshold {
+            self.live = true;
+            tracing::info!(
+                "Backfill caught up: {} events in {}s",
+                self.replayed,
+                elapsed.num_seconds()
+            );
+            return None;
+        }
+
+        self.replayed += 1;
+
+        if now - self.last_progress >= self.config.progress_interval {
+            self.last_progress = now;
+            tracing::info!(
+                "Backfill progress: at {} ({}s behind), {} events, {:.1}/s",
+                event_time,
+                (now - event_time).num_seconds(),
+                self.replayed,
+                self.replayed as f64 / elapsed.num_milliseconds().max(1) as f64 * 1000.0
+            );
+        }
+
+        if self.config.max_events_per_sec == 0 {
+            return None;
+        }
+
+        // ここまでの件数を上限レートで流した場合にかかるはずの時間との差だけ待つ
+        let budget_ms = self.replayed * 1000 / self.config.max_events_per_sec as u64;
+        let ahead_ms = budget_ms as i64 - elapsed.num_milliseconds();
+        (ahead_ms > 0).then(|| std::time::Duration::from_millis(ahead_ms as u64))
+    }
+
+    #[cfg(test)]
+    fn is_live(&self) -> bool {
+        self.live
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 観点1: ウィンドウ以内のカーソルはそのまま再生に使われること
+    #[test]
+    fn test_resolve_start_cursor_within_window() {
+        let now = Utc::now();
+        // 6時間前のカーソル（以前は5分で切り捨てていた）
+        let cursor = Some(now - Duration::hours(6));
+
+        assert_eq!(
+            resolve_start_cursor(cursor, now, Duration::hours(24)),
+            cursor
+        );
+    }
+
+    /// 観点2: ウィンドウより古いカーソルはリセットされ、ライブから始まること
+    #[test]
+    fn test_resolve_start_cursor_too_old() {
+        let now = Utc::now();
+        let cursor = Some(now - Duration::hours(25));
+
+        assert_eq!(resolve_start_cursor(cursor, now, Duration::hours(24)), None);
+        assert_eq!(resolve_start_cursor(None, now, Duration::hours(24)), None);
+    }
+
+    /// 観点3: 追いつき中は平均レートを超えた分だけ待たされ、追いついたら制限がなくなること
+    #[test]
+    fn test_catch_up_rate_limit() {
+        let now = Utc::now();
+        let config = BackfillConfig {
+            max_events_per_sec: 10,
+            ..Default::default()
+        };
+        let mut catch_up = CatchUp::new(config, Some(now - Duration::hours(1)), now);
+        assert!(!catch_up.is_live());
+
+        let old_event = now - Duration::minutes(30);
+        // 10件/秒なので、同時刻に流すと1件ごとに 100ms ずつ先行する
+        assert_eq!(
+            catch_up.on_event(old_event, now),
+            Some(std::time::Duration::from_millis(100))
+        );
+        assert_eq!(
+            catch_up.on_event(old_event, now),
+            Some(std::time::Duration::from_millis(200))
+        );
+        // 十分に時間が経っていれば待たない
+        assert_eq!(
+            catch_up.on_event(old_event, now + Duration::seconds(1)),
+            None
+        );
+
+        // 現在時刻に近いイベントが来たら追いついたとみなす
+        let later = now + Duration::seconds(2);
+        assert_eq!(catch_up.on_event(later, later), None);
+        assert!(catch_up.is_live());
+        assert_eq!(catch_up.on_event(old_event, later), None);
+    }
+
+    /// 観点4: ライブから始めた場合は最初から制限しないこと
+    #[test]
+    fn test_catch_up_live_start() {
+        let now = Utc::now();
+        let mut catch_up = CatchUp::new(BackfillConfig::default(), None, now);
+        assert!(catch_up.is_live());
+        assert_eq!(catch_up.on_event(now - Duration::hours(1), now), None);
+    }
+}
@@ -0,0 +1,168 @@
+//! Jetstream カーソルの永続化
+//!
+//! テーブル: `jetstream_cursors`
+//!   - name       : TEXT PRIMARY KEY   (コンシューマー名)
+//!   - cursor_us  : INTEGER NOT NULL   (全購読者が処理し終えたイベントの time_us)
+//!   - updated_at : INTEGER NOT NULL   (UNIX タイムスタンプ秒)
+//!
+//! コンシューマーごとに名前付きのカーソルを持つので、購読するコレクションが違っても独立して再開できる
+
+use anyhow::{Context, Result};
+use sqlx::SqlitePool;
+
+/// `jetstream.db` に必要なテーブルを作成する（冪等）
+pub async fn migrate(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS jetstream_cursors (
+            name       TEXT    PRIMARY KEY,
+            cursor_us  INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL
+        );
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Clone)]
+pub struct CursorStore {
+    pool: SqlitePool,
+    name: String,
+}
+
+impl CursorStore {
+    pub fn new(pool: SqlitePool, name: impl Into<String>) -> Self {
+        Self {
+            pool,
+            name: name.into(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 保存済みのカーソル（マイクロ秒）
+    pub async fn load(&self) -> Result<Option<i64>> {
+        let cursor_us =
+            sqlx::query_scalar("SELECT cursor_us FROM jetstream_cursors WHERE name = ?")
+                .bind(&self.name)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(cursor_us)
+    }
+
+    pub async fn save(&self, cursor_us: i64) -> Result<()> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO jetstream_cursors (name, cursor_us, updated_at) VALUES (?, ?, ?)",
+        )
+        .bind(&self.name)
+        .bind(cursor_us)
+        .bind(chrono::Utc::now().timestamp())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// 以前 realfakebluesky.db に置いていた単一カーソル（`jetstream_cursor` テーブル）を引き継ぐ
+    ///
+    /// 自分のカーソルが未保存の場合のみ取り込む。取り込んだら true
+    pub async fn import_legacy(&self, legacy_pool: &SqlitePool) -> Result<bool> {
+        if self.load().await?.is_some() {
+            return Ok(false);
+        }
+
+        // テーブルがなければ引き継ぐものはない。読めないときは黙って最新から始めず、エラーにする
+        let exists: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'jetstream_cursor')",
+        )
+        .fetch_one(legacy_pool)
+        .await
+        .context("Failed to look up the legacy jetstream_cursor table")?;
+        if !exists {
+            return Ok(false);
+        }
+        let legacy: Option<i64> =
+            sqlx::query_scalar("SELECT cursor_us FROM jetstream_cursor WHERE id = 1")
+                .fetch_optional(legacy_pool)
+                .await
+                .context("Failed to read the legacy Jetstream cursor")?;
+
+        match legacy {
+            Some(cursor_us) => {
+                self.save(cursor_us).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn in_memory_pool() -> SqlitePool {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate(&pool).await.unwrap();
+        pool
+    }
+
+    /// 名前ごとに独立して保存・上書きされるか
+    #[tokio::test]
+    async fn test_named_cursors_are_independent() {
+        let pool = in_memory_pool().await;
+        let posts = CursorStore::new(pool.clone(), "posts");
+        let likes = CursorStore::new(pool.clone(), "likes");
+
+        assert_eq!(posts.load().await.unwrap(), None);
+
+        posts.save(100).await.unwrap();
+        likes.save(200).await.unwrap();
+        posts.save(300).await.unwrap();
+
+        assert_eq!(posts.load().await.unwrap(), Some(300));
+        assert_eq!(likes.load().await.unwrap(), Some(200));
+    }
+
+    /// 旧テーブルのカーソルを、未保存の場合だけ引き継ぐか。旧テーブルが読めなければエラーにするか
+    #[tokio::test]
+    async fn test_import_legacy() {
+        let pool = in_memory_pool().await;
+        let legacy = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let store = CursorStore::new(pool, "default");
+
+        // 旧テーブルがなくてもエラーにならない
+        assert!(!store.import_legacy(&legacy).await.unwrap());
+
+        sqlx::query(
+            "CREATE TABLE jetstream_cursor (id INTEGER PRIMARY KEY CHECK (id = 1), cursor_us INTEGER NOT NULL)",
+        )
+        .execute(&legacy)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO jetstream_cursor (id, cursor_us) VALUES (1, 1000000)")
+            .execute(&legacy)
+            .await
+            .unwrap();
+
+        assert!(store.import_legacy(&legacy).await.unwrap());
+        assert_eq!(store.load().await.unwrap(), Some(1000000));
+
+        store.save(2000000).await.unwrap();
+        assert!(!store.import_legacy(&legacy).await.unwrap());
+        assert_eq!(store.load().await.unwrap(), Some(2000000));
+
+        // 形の違う旧テーブルは、無いものとして扱わない
+        let broken = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE jetstream_cursor (id INTEGER PRIMARY KEY)")
+            .execute(&broken)
+            .await
+            .unwrap();
+        let fresh = CursorStore::new(in_memory_pool().await, "default");
+        assert!(fresh.import_legacy(&broken).await.is_err());
+    }
+}
@@ -0,0 +1,758 @@
+//! CommitEvent を購読者ごとのキューへ配るディスパッチャー
+//!
+//! - 購読者ごとに有界キュー・並列度・遅延メトリクスを持つので、遅い購読者が他を止めない
+//! - 永続化するカーソルは、全購読者が処理し終えた（ack した）イベントまでしか進めない
+//! - identity / account イベントはキューを通さず、`dispatch_lifecycle` でその場で処理する。
+//!   アカウントが無効になったら、それより前に配信されてまだキューにあるその DID のイベントは捨てる
+//!   （取り除いた投稿が後から入り直さないように）
+
+use crate::lifecycle::LifecycleEvent;
+use crate::metrics::{IngestMetrics, Outcome};
+use bsky_core::metrics::TextEncoder;
+use jetstream_oxide::events::commit::CommitEvent;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, Semaphore};
+
+type Handler = Arc<
+    dyn Fn(Arc<CommitEvent>) -> Pin<Box<dyn Future<Output = Option<&'static str>> + Send>>
+        + Send
+        + Sync,
+>;
+type LifecycleHandler =
+    Arc<dyn Fn(Arc<LifecycleEvent>) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+/// キューが満杯のときの挙動
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// 空くまで待つ（全体が詰まるが取りこぼさない）
+    Block,
+    /// そのイベントを捨てて処理済み扱いにする
+    Drop,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SubscriberOptions {
+    pub queue_capacity: usize,
+    /// 同時に処理するイベント数の上限
+    pub concurrency: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for SubscriberOptions {
+    fn default() -> Self {
+        Self {
+            queue_capacity: 256,
+            concurrency: 1,
+            overflow: OverflowPolicy::Block,
+        }
+    }
+}
+
+/// 購読者ごとの統計（ログ出力用のスナップショット）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubscriberStats {
+    pub name: &'static str,
+    /// キューに積まれて未着手のイベント数
+    pub queued: usize,
+    pub processed: u64,
+    pub dropped: u64,
+    /// 最後に配信したイベントと、最後に処理し終えたイベントの time_us の差
+    pub lag_us: i64,
+}
+
+#[derive(Default)]
+struct SubscriberMetrics {
+    processed: AtomicU64,
+    dropped: AtomicU64,
+    last_processed_us: AtomicI64,
+}
+
+struct Subscriber {
+    name: &'static str,
+    overflow: OverflowPolicy,
+    sender: mpsc::Sender<Job>,
+    metrics: Arc<SubscriberMetrics>,
+}
+
+struct Job {
+    event: Arc<CommitEvent>,
+    time_us: i64,
+    _ack: Ack,
+}
+
+/// drop 時に ack する。ハンドラーが panic したり、キューから捨てられても必ず ack される
+struct Ack {
+    tracker: Arc<AckTracker>,
+    seq: u64,
+}
+
+impl Drop for Ack {
+    fn drop(&mut self) {
+        self.tracker.ack(self.seq);
+    }
+}
+
+struct Pending {
+    cursor: i64,
+    time_us: i64,
+    remaining: usize,
+}
+
+/// 配信順に並んだ未完了イベントを管理し、先頭から連続して完了した分だけカーソルを進める
+#[derive(Default)]
+struct AckTracker {
+    state: Mutex<TrackerState>,
+}
+
+#[derive(Default)]
+struct TrackerState {
+    next_seq: u64,
+    pending: BTreeMap<u64, Pending>,
+    committed: Option<i64>,
+    /// `committed` のイベントの time_us
+    committed_time_us: Option<i64>,
+}
+
+impl AckTracker {
+    fn begin(&self, cursor: i64, time_us: i64, subscribers: usize) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.pending.insert(
+            seq,
+            Pending {
+                cursor,
+                time_us,
+                remaining: subscribers,
+            },
+        );
+        state.advance();
+        seq
+    }
+
+    fn ack(&self, seq: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(pending) = state.pending.get_mut(&seq) {
+            pending.remaining = pending.remaining.saturating_sub(1);
+        }
+        state.advance();
+    }
+
+    /// 次に配信するイベントの seq
+    fn next_seq(&self) -> u64 {
+        self.state.lock().unwrap().next_seq
+    }
+
+    /// `seq` より前に配信したイベントに、まだ ack されていないものがあるか
+    fn has_pending_before(&self, seq: u64) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .pending
+            .first_key_value()
+            .is_some_and(|(first, _)| *first < seq)
+    }
+
+    fn committed(&self) -> Option<i64> {
+        self.state.lock().unwrap().committed
+    }
+
+    fn committed_time_us(&self) -> Option<i64> {
+        self.state.lock().unwrap().committed_time_us
+    }
+}
+
+impl TrackerState {
+    fn advance(&mut self) {
+        while let Some(entry) = self.pending.first_entry() {
+            if entry.get().remaining > 0 {
+                break;
+            }
+            let pending = entry.remove();
+            self.committed = Some(pending.cursor);
+            self.committed_time_us = Some(pending.time_us);
+        }
+    }
+}
+
+/// 無効になったアカウントの DID と、無効になった時点の `AckTracker::next_seq`
+///
+/// それより前に配信したイベントがすべて ack されたら、もう捨てるものはないので消す
+#[derive(Default)]
+struct InactiveDids {
+    dids: Mutex<HashMap<String, u64>>,
+}
+
+impl InactiveDids {
+    fn contains(&self, did: &str) -> bool {
+        self.dids.lock().unwrap().contains_key(did)
+    }
+
+    fn update(&self, event: &LifecycleEvent, tracker: &AckTracker) {
+        let mut dids = self.dids.lock().unwrap();
+        dids.retain(|_, boundary| tracker.has_pending_before(*boundary));
+        match event {
+            LifecycleEvent::Account { did, active, .. } if !active => {
+                dids.insert(did.clone(), tracker.next_seq());
+            }
+            LifecycleEvent::Account { did, .. } => {
+                dids.remove(did);
+            }
+            LifecycleEvent::Identity { .. } => {}
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Dispatcher {
+    subscribers: Arc<Vec<Subscriber>>,
+    lifecycle_handlers: Arc<Vec<LifecycleHandler>>,
+    tracker: Arc<AckTracker>,
+    inactive: Arc<InactiveDids>,
+    last_dispatched_us: Arc<AtomicI64>,
+    metrics: IngestMetrics,
+}
+
+impl Dispatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 購読者を登録し、専用のワーカーを起動する
+    ///
+    /// 配信を始める（`Dispatcher` を clone する）前に呼ぶこと
+    pub fn subscribe<F, Fut>(
+        mut self,
+        name: &'static str,
+        options: SubscriberOptions,
+        handler: F,
+    ) -> Self
+    where
+        F: Fn(Arc<CommitEvent>) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: Outcome,
+    {
+        let (sender, receiver) = mpsc::channel(options.queue_capacity.max(1));
+        let metrics = Arc::new(SubscriberMetrics::default());
+        let handler: Handler = Arc::new(move |event| {
+            let future = handler(event);
+            Box::pin(async move { future.await.matched_feed() })
+        });
+
+        tokio::spawn(run_worker(
+            name,
+            receiver,
+            handler,
+            self.inactive.clone(),
+            options.concurrency.max(1),
+            metrics.clone(),
+            self.metrics.clone(),
+        ));
+
+        Arc::get_mut(&mut self.subscribers)
+            .expect("subscribe must be called before the dispatcher is shared")
+            .push(Subscriber {
+                name,
+                overflow: options.overflow,
+                sender,
+                metrics,
+            });
+        self
+    }
+
+    /// identity / account イベントのハンドラーを登録する
+    ///
+    /// `subscribe` と同じく、配信を始める前に呼ぶこと
+    pub fn on_lifecycle<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(Arc<LifecycleEvent>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let handler: LifecycleHandler = Arc::new(move |event| Box::pin(handler(event)));
+        Arc::get_mut(&mut self.lifecycle_handlers)
+            .expect("on_lifecycle must be called before the dispatcher is shared")
+            .push(handler);
+        self
+    }
+
+    /// identity / account イベントを登録順に処理し、全ハンドラーが終わるまで待つ
+    ///
+    /// アカウントが無効になったなら、ハンドラーより先に、キューに残っているその DID のイベントを捨てるようにする
+    pub async fn dispatch_lifecycle(&self, event: LifecycleEvent) {
+        self.inactive.update(&event, &self.tracker);
+        let event = Arc::new(event);
+        for handler in self.lifecycle_handlers.iter() {
+            handler(event.clone()).await;
+        }
+    }
+
+    /// 全購読者のキューにイベントを積む。`Block` の購読者のキューが満杯なら空くまで待つ
+    pub async fn dispatch(&self, event: CommitEvent) {
+        let time_us = event_time_us(&event) as i64;
+        self.dispatch_with_cursor(event, time_us).await;
+    }
+
+    /// `dispatch` と同じだが、カーソルとして time_us の代わりに `cursor` を使う（ファイアホースの seq など）
+    pub async fn dispatch_with_cursor(&self, event: CommitEvent, cursor: i64) {
+        let time_us = event_time_us(&event) as i64;
+        let event = Arc::new(event);
+        let seq = self.tracker.begin(cursor, time_us, self.subscribers.len());
+        self.last_dispatched_us.store(time_us, Ordering::Relaxed);
+        self.metrics.count_received(event_collection(&event));
+
+        for subscriber in self.subscribers.iter() {
+            let job = Job {
+                event: event.clone(),
+                time_us,
+                _ack: Ack {
+                    tracker: self.tracker.clone(),
+                    seq,
+                },
+            };
+
+            match subscriber.overflow {
+                OverflowPolicy::Block => {
+                    if subscriber.sender.send(job).await.is_err() {
+                        tracing::error!("Subscriber {} worker has stopped", subscriber.name);
+                    }
+                }
+                OverflowPolicy::Drop => {
+                    if subscriber.sender.try_send(job).is_err() {
+                        subscriber.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+    }
+
+    /// 配信するイベントが無くても、ここまでに配信したイベントが処理し終わったらカーソルを `cursor` まで進める
+    pub fn advance_cursor(&self, cursor: i64, time_us: i64) {
+        self.tracker.begin(cursor, time_us, 0);
+    }
+
+    /// 全購読者が処理し終えた最新イベントのカーソル（通常は time_us、マイクロ秒）
+    pub fn committed_cursor(&self) -> Option<i64> {
+        self.tracker.committed()
+    }
+
+    /// 接続先への再接続を数える
+    pub fn record_reconnect(&self, endpoint: &str) {
+        self.metrics.reconnects.inc(&[("endpoint", endpoint)]);
+    }
+
+    /// 取り込みのメトリクスを Prometheus のテキスト形式で書き出す
+    ///
+    /// 遅れは `now_us` との差で出すので、購読者の処理が詰まると増え続ける
+    pub fn encode_metrics(&self, encoder: &mut TextEncoder, now_us: i64) {
+        let age = |time_us: i64| (now_us - time_us).max(0) as f64 / 1_000_000.0;
+        let stats = self.stats();
+
+        encoder.counter(
+            "ingest_events_received_total",
+            "Commit events received, by collection.",
+            &self.metrics.received,
+        );
+        encoder.counter(
+            "ingest_events_matched_total",
+            "Commit events stored by a feed, by feed.",
+            &self.metrics.matched,
+        );
+        encoder.histogram(
+            "ingest_processing_duration_seconds",
+            "Time a subscriber spent handling one event.",
+            &self.metrics.processing,
+        );
+        encoder.counter(
+            "ingest_reconnects_total",
+            "Reconnections to the ingest source, by endpoint.",
+            &self.metrics.reconnects,
+        );
+        encoder.gauge(
+            "ingest_cursor_age_seconds",
+            "Wall clock time since the event at the committed cursor.",
+            self.tracker
+                .committed_time_us()
+                .map(|time_us| (vec![], age(time_us))),
+        );
+        encoder.gauge(
+            "ingest_subscriber_queued_events",
+            "Events waiting in a subscriber queue.",
+            stats
+                .iter()
+                .map(|s| (vec![("subscriber", s.name.to_string())], s.queued as f64)),
+        );
+        encoder.counter_values(
+            "ingest_subscriber_processed_events_total",
+            "Events a subscriber has finished handling.",
+            stats
+                .iter()
+                .map(|s| (vec![("subscriber", s.name.to_string())], s.processed)),
+        );
+        encoder.counter_values(
+            "ingest_subscriber_dropped_events_total",
+            "Events dropped because a subscriber queue was full.",
+            stats
+                .iter()
+                .map(|s| (vec![("subscriber", s.name.to_string())], s.dropped)),
+        );
+        encoder.gauge(
+            "ingest_subscriber_lag_seconds",
+            "Wall clock time since the last event a subscriber finished handling.",
+            self.subscribers.iter().filter_map(|s| {
+                let last_processed_us = s.metrics.last_processed_us.load(Ordering::Relaxed);
+                (last_processed_us > 0).then(|| {
+                    (
+                        vec![("subscriber", s.name.to_string())],
+                        age(last_processed_us),
+                    )
+                })
+            }),
+        );
+    }
+
+    pub fn stats(&self) -> Vec<SubscriberStats> {
+        let last_dispatched_us = self.last_dispatched_us.load(Ordering::Relaxed);
+        self.subscribers
+            .iter()
+            .map(|s| {
+                let last_processed_us = s.metrics.last_processed_us.load(Ordering::Relaxed);
+                SubscriberStats {
+                    name: s.name,
+                    queued: s.sender.max_capacity() - s.sender.capacity(),
+                    processed: s.metrics.processed.load(Ordering::Relaxed),
+                    dropped: s.metrics.dropped.load(Ordering::Relaxed),
+                    lag_us: if last_processed_us > 0 {
+                        (last_dispatched_us - last_processed_us).max(0)
+                    } else {
+                        0
+                    },
+                }
+            })
+            .collect()
+    }
+}
+
+async fn run_worker(
+    name: &'static str,
+    mut receiver: mpsc::Receiver<Job>,
+    handler: Handler,
+    inactive: Arc<InactiveDids>,
+    concurrency: usize,
+    metrics: Arc<SubscriberMetrics>,
+    ingest_metrics: IngestMetrics,
+) {
+    let processing = ingest_metrics
+        .processing
+        .with_labels(&[("subscriber", name)]);
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+
+    loop {
+        // 空きができてから取り出す（取り出したものは queued に数えられなくなるため）
+        let Ok(permit) = semaphore.clone().acquire_owned().await else {
+            break;
+        };
+        let Some(job) = receiver.recv().await else {
+            break;
+        };
+        let handler = handler.clone();
+        let metrics = metrics.clone();
+        let ingest_metrics = ingest_metrics.clone();
+        let processing = processing.clone();
+        // キューにいる間にアカウントが無効になっていたら、処理せずに ack する
+        let skip = inactive.contains(event_did(&job.event));
+
+        tokio::spawn(async move {
+            let started = std::time::Instant::now();
+            let matched = if skip {
+                None
+            } else {
+                handler(job.event.clone()).await
+            };
+            processing.observe(started.elapsed().as_secs_f64());
+            if let Some(feed) = matched {
+                ingest_metrics.matched.inc(&[("feed", feed)]);
+            }
+            metrics.processed.fetch_add(1, Ordering::Relaxed);
+            metrics
+                .last_processed_us
+                .fetch_max(job.time_us, Ordering::Relaxed);
+            drop(job);
+            drop(permit);
+        });
+    }
+
+    tracing::warn!("Subscriber {} worker exited", name);
+}
+
+pub(crate) fn event_time_us(event: &CommitEvent) -> u64 {
+    match event {
+        CommitEvent::Create { info, .. } => info.time_us,
+        CommitEvent::Delete { info, .. } => info.time_us,
+        CommitEvent::Update { info, .. } => info.time_us,
+    }
+}
+
+fn event_did(event: &CommitEvent) -> &str {
+    match event {
+        CommitEvent::Create { info, .. }
+        | CommitEvent::Delete { info, .. }
+        | CommitEvent::Update { info, .. } => info.did.as_str(),
+    }
+}
+
+fn event_collection(event: &CommitEvent) -> &str {
+    match event {
+        CommitEvent::Create { commit, .. } | CommitEvent::Update { commit, .. } => {
+            commit.info.collection.as_str()
+        }
+        CommitEvent::Delete { commit, .. } => commit.collection.as_str(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::sync::Notify;
+
+    fn delete_event(time_us: u64) -> CommitEvent {
+        serde_json::from_value(serde_json::json!({
+            "did": "did:plc:test",
+            "time_us": time_us,
+            "kind": "commit",
+            "commit": {
+                "operation": "delete",
+                "rev": "rev",
+                "rkey": "rkey",
+                "collection": "app.bsky.feed.post"
+            }
+        }))
+        .unwrap()
+    }
+
+    async fn wait_until(mut condition: impl FnMut() -> bool) {
+        for _ in 0..100 {
+            if condition() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("condition not met in time");
+    }
+
+    /// 遅い購読者が ack するまでカーソルが進まず、速い購読者は先に処理できるか
+    #[tokio::test]
+    async fn test_cursor_waits_for_all_subscribers() {
+        let gate = Arc::new(Notify::new());
+        let gate_for_slow = gate.clone();
+
+        let dispatcher = Dispatcher::new()
+            .subscribe("fast", SubscriberOptions::default(), |_| async {})
+            .subscribe("slow", SubscriberOptions::default(), move |_| {
+                let gate = gate_for_slow.clone();
+                async move { gate.notified().await }
+            });
+
+        dispatcher.dispatch(delete_event(100)).await;
+        dispatcher.dispatch(delete_event(200)).await;
+
+        wait_until(|| dispatcher.stats()[0].processed == 2).await;
+        assert_eq!(dispatcher.committed_cursor(), None);
+
+        gate.notify_one();
+        wait_until(|| dispatcher.committed_cursor() == Some(100)).await;
+
+        gate.notify_one();
+        wait_until(|| dispatcher.committed_cursor() == Some(200)).await;
+        assert_eq!(dispatcher.stats()[1].lag_us, 0);
+    }
+
+    /// 並列処理で順不同に完了しても、カーソルは先頭から連続した分しか進まないか
+    #[tokio::test]
+    async fn test_cursor_advances_in_order_with_concurrency() {
+        let dispatcher = Dispatcher::new().subscribe(
+            "concurrent",
+            SubscriberOptions {
+                concurrency: 2,
+                ..Default::default()
+            },
+            |event| async move {
+                // 先に届いたイベントほど遅く終わる
+                let delay = if event_time_us(&event) == 100 { 100 } else { 0 };
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+            },
+        );
+
+        dispatcher.dispatch(delete_event(100)).await;
+        dispatcher.dispatch(delete_event(200)).await;
+
+        wait_until(|| dispatcher.stats()[0].processed == 1).await;
+        assert_eq!(dispatcher.committed_cursor(), None);
+
+        wait_until(|| dispatcher.committed_cursor() == Some(200)).await;
+    }
+
+    /// `Drop` の購読者はキューが満杯ならイベントを捨て、カーソルを止めないか
+    #[tokio::test]
+    async fn test_drop_policy_does_not_block() {
+        let gate = Arc::new(Notify::new());
+        let gate_for_handler = gate.clone();
+
+        let dispatcher = Dispatcher::new().subscribe(
+            "lossy",
+            SubscriberOptions {
+                queue_capacity: 1,
+                concurrency: 1,
+                overflow: OverflowPolicy::Drop,
+            },
+            move |_| {
+                let gate = gate_for_handler.clone();
+                async move { gate.notified().await }
+            },
+        );
+
+        dispatcher.dispatch(delete_event(100)).await;
+        wait_until(|| dispatcher.stats()[0].queued == 0).await;
+        dispatcher.dispatch(delete_event(200)).await;
+        dispatcher.dispatch(delete_event(300)).await;
+
+        let stats = &dispatcher.stats()[0];
+        assert_eq!(stats.queued, 1);
+        assert_eq!(stats.dropped, 1);
+
+        gate.notify_one();
+        gate.notify_one();
+        wait_until(|| dispatcher.committed_cursor() == Some(300)).await;
+    }
+
+    /// identity / account イベントが登録順にハンドラーへ渡され、カーソルには影響しないか
+    #[tokio::test]
+    async fn test_dispatch_lifecycle() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let first = seen.clone();
+        let second = seen.clone();
+        let dispatcher = Dispatcher::new()
+            .on_lifecycle(move |event| {
+                let seen = first.clone();
+                async move {
+                    seen.lock()
+                        .unwrap()
+                        .push(("first", event.did().to_string()))
+                }
+            })
+            .on_lifecycle(move |event| {
+                let seen = second.clone();
+                async move {
+                    seen.lock()
+                        .unwrap()
+                        .push(("second", event.did().to_string()))
+                }
+            });
+
+        dispatcher
+            .dispatch_lifecycle(LifecycleEvent::Identity {
+                did: "did:plc:alice".to_string(),
+                handle: None,
+            })
+            .await;
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                ("first", "did:plc:alice".to_string()),
+                ("second", "did:plc:alice".to_string())
+            ]
+        );
+        assert_eq!(dispatcher.committed_cursor(), None);
+    }
+
+    /// 無効になったアカウントの、キューに残っていたイベントは処理されず、再開後のイベントは処理されるか
+    #[tokio::test]
+    async fn test_inactive_account_events_are_skipped() {
+        let gate = Arc::new(Notify::new());
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let (gate_for_handler, handled_for_handler) = (gate.clone(), handled.clone());
+        let dispatcher =
+            Dispatcher::new().subscribe("gated", SubscriberOptions::default(), move |event| {
+                let gate = gate_for_handler.clone();
+                let handled = handled_for_handler.clone();
+                async move {
+                    gate.notified().await;
+                    handled.lock().unwrap().push(event_time_us(&event));
+                }
+            });
+        let account = |active| LifecycleEvent::Account {
+            did: "did:plc:test".to_string(),
+            active,
+            status: None,
+        };
+
+        // 100 は処理中、200 はキューで待っている間にアカウントが無効になる
+        dispatcher.dispatch(delete_event(100)).await;
+        wait_until(|| dispatcher.stats()[0].queued == 0).await;
+        dispatcher.dispatch(delete_event(200)).await;
+        dispatcher.dispatch_lifecycle(account(false)).await;
+        gate.notify_one();
+        wait_until(|| dispatcher.committed_cursor() == Some(200)).await;
+        assert_eq!(*handled.lock().unwrap(), vec![100]);
+
+        dispatcher.dispatch_lifecycle(account(true)).await;
+        dispatcher.dispatch(delete_event(300)).await;
+        gate.notify_one();
+        wait_until(|| dispatcher.committed_cursor() == Some(300)).await;
+        assert_eq!(*handled.lock().unwrap(), vec![100, 300]);
+        assert_eq!(dispatcher.stats()[0].processed, 3);
+    }
+
+    /// 購読者がいなければ即座にカーソルが進むか
+    #[tokio::test]
+    async fn test_no_subscribers() {
+        let dispatcher = Dispatcher::new();
+        dispatcher.dispatch(delete_event(100)).await;
+        assert_eq!(dispatcher.committed_cursor(), Some(100));
+    }
+
+    /// 受信件数・採用件数・処理時間・遅れがメトリクスに出力されるか
+    #[tokio::test]
+    async fn test_encode_metrics() {
+        let dispatcher = Dispatcher::new()
+            .subscribe("plain", SubscriberOptions::default(), |_| async {})
+            .subscribe(
+                "matcher",
+                SubscriberOptions::default(),
+                |event| async move { (event_time_us(&event) == 200).then_some("somefeed") },
+            );
+
+        dispatcher.dispatch(delete_event(100)).await;
+        dispatcher.dispatch(delete_event(200)).await;
+        dispatcher.record_reconnect("wss://example.com/subscribe");
+        wait_until(|| dispatcher.committed_cursor() == Some(200)).await;
+
+        let mut encoder = TextEncoder::new();
+        dispatcher.encode_metrics(&mut encoder, 1_500_200);
+        let text = encoder.finish();
+
+        for line in [
+            "ingest_events_received_total{collection=\"app.bsky.feed.post\"} 2",
+            "ingest_events_matched_total{feed=\"somefeed\"} 1",
+            "ingest_processing_duration_seconds_count{subscriber=\"matcher\"} 2",
+            "ingest_reconnects_total{endpoint=\"wss://example.com/subscribe\"} 1",
+            "ingest_cursor_age_seconds 1.5",
+            "ingest_subscriber_processed_events_total{subscriber=\"plain\"} 2",
+            "ingest_subscriber_dropped_events_total{subscriber=\"plain\"} 0",
+            "# TYPE ingest_subscriber_processed_events_total counter",
+            "ingest_subscriber_lag_seconds{subscriber=\"matcher\"} 1.5",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "missing {}:\n{}",
+                line,
+                text
+            );
+        }
+    }
+}
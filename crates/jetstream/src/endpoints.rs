@@ -0,0 +1,210 @@
+//! 複数の Jetstream インスタンスの切り替えと死活管理
+//!
+//! - 接続してもイベントを 1 件も受け取れずに切れたら失敗として数える
+//! - 連続失敗が `failure_threshold` に達したら、別のインスタンスに切り替える
+//! - 切り替え先は、連続失敗が少なく、直近で正常だったものを優先する
+
+use chrono::{DateTime, Utc};
+use std::sync::{Arc, Mutex};
+
+/// 公式の Jetstream インスタンス（先頭から順に使う）
+pub const DEFAULT_ENDPOINTS: &[&str] = &[
+    "wss://jetstream2.us-west.bsky.network/subscribe",
+    "wss://jetstream1.us-west.bsky.network/subscribe",
+    "wss://jetstream2.us-east.bsky.network/subscribe",
+    "wss://jetstream1.us-east.bsky.network/subscribe",
+];
+
+const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointHealth {
+    pub url: String,
+    pub consecutive_failures: u32,
+    /// 最後にイベントを受信できた時刻
+    pub last_healthy: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+struct PoolState {
+    endpoints: Vec<EndpointHealth>,
+    active: usize,
+    failure_threshold: u32,
+}
+
+#[derive(Clone)]
+pub struct EndpointPool {
+    state: Arc<Mutex<PoolState>>,
+}
+
+impl Default for EndpointPool {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_ENDPOINTS
+                .iter()
+                .map(|url| url.to_string())
+                .collect(),
+        )
+    }
+}
+
+impl EndpointPool {
+    /// `urls` が空なら `DEFAULT_ENDPOINTS` を使う
+    pub fn new(urls: Vec<String>) -> Self {
+        if urls.is_empty() {
+            return Self::default();
+        }
+
+        let endpoints = urls
+            .into_iter()
+            .map(|url| EndpointHealth {
+                url,
+                consecutive_failures: 0,
+                last_healthy: None,
+                last_error: None,
+            })
+            .collect();
+
+        Self {
+            state: Arc::new(Mutex::new(PoolState {
+                endpoints,
+                active: 0,
+                failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+            })),
+        }
+    }
+
+    pub fn with_failure_threshold(self, failure_threshold: u32) -> Self {
+        self.state.lock().unwrap().failure_threshold = failure_threshold.max(1);
+        self
+    }
+
+    /// 現在接続先として使っているインスタンスの URL
+    pub fn active(&self) -> String {
+        let state = self.state.lock().unwrap();
+        state.endpoints[state.active].url.clone()
+    }
+
+    pub fn snapshot(&self) -> Vec<EndpointHealth> {
+        self.state.lock().unwrap().endpoints.clone()
+    }
+
+    /// `url` からイベントを受信できた
+    pub fn record_success(&self, url: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(endpoint) = state.endpoints.iter_mut().find(|e| e.url == url) {
+            endpoint.consecutive_failures = 0;
+            endpoint.last_healthy = Some(Utc::now());
+            endpoint.last_error = None;
+        }
+    }
+
+    /// `url` への接続に失敗した。閾値に達したら別のインスタンスに切り替える
+    pub fn record_failure(&self, url: &str, error: impl Into<String>) {
+        let mut state = self.state.lock().unwrap();
+        let threshold = state.failure_threshold;
+        let Some(index) = state.endpoints.iter().position(|e| e.url == url) else {
+            return;
+        };
+
+        let endpoint = &mut state.endpoints[index];
+        endpoint.consecutive_failures += 1;
+        endpoint.last_error = Some(error.into());
+        let failures = endpoint.consecutive_failures;
+
+        if index != state.active || failures < threshold || state.endpoints.len() < 2 {
+            return;
+        }
+
+        let next = state
+            .endpoints
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != index)
+            // 連続失敗が少ないもの → 直近で正常だったもの → 並び順
+            .min_by_key(|(i, e)| {
+                (
+                    e.consecutive_failures,
+                    std::cmp::Reverse(e.last_healthy),
+                    (*i + state.endpoints.len() - index) % state.endpoints.len(),
+                )
+            })
+            .map(|(i, _)| i)
+            .unwrap_or(index);
+
+        tracing::warn!(
+            "Jetstream endpoint {} failed {} times in a row. Switching to {}",
+            url,
+            failures,
+            state.endpoints[next].url
+        );
+        state.active = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool() -> EndpointPool {
+        EndpointPool::new(vec![
+            "wss://a/subscribe".to_string(),
+            "wss://b/subscribe".to_string(),
+            "wss://c/subscribe".to_string(),
+        ])
+        .with_failure_threshold(2)
+    }
+
+    /// 連続失敗が閾値に達するまでは同じインスタンスを使い続けるか
+    #[test]
+    fn test_rotates_after_threshold() {
+        let pool = pool();
+        assert_eq!(pool.active(), "wss://a/subscribe");
+
+        pool.record_failure("wss://a/subscribe", "closed");
+        assert_eq!(pool.active(), "wss://a/subscribe");
+
+        pool.record_failure("wss://a/subscribe", "closed");
+        assert_eq!(pool.active(), "wss://b/subscribe");
+
+        let health = pool.snapshot();
+        assert_eq!(health[0].consecutive_failures, 2);
+        assert_eq!(health[0].last_error.as_deref(), Some("closed"));
+    }
+
+    /// 成功すると連続失敗がリセットされるか
+    #[test]
+    fn test_success_resets_failures() {
+        let pool = pool();
+        pool.record_failure("wss://a/subscribe", "closed");
+        pool.record_success("wss://a/subscribe");
+        pool.record_failure("wss://a/subscribe", "closed");
+
+        assert_eq!(pool.active(), "wss://a/subscribe");
+        assert!(pool.snapshot()[0].last_healthy.is_some());
+    }
+
+    /// 切り替え先は、一度も試していないものより直近で正常だったものが優先されるか
+    #[test]
+    fn test_prefers_last_healthy() {
+        let pool = pool();
+        pool.record_success("wss://c/subscribe");
+
+        pool.record_failure("wss://a/subscribe", "closed");
+        pool.record_failure("wss://a/subscribe", "closed");
+        assert_eq!(pool.active(), "wss://c/subscribe");
+
+        // c も落ちたら、失敗の少ない b へ
+        pool.record_failure("wss://c/subscribe", "closed");
+        pool.record_failure("wss://c/subscribe", "closed");
+        assert_eq!(pool.active(), "wss://b/subscribe");
+    }
+
+    /// 空のリストならデフォルトのインスタンスを使うか
+    #[test]
+    fn test_empty_falls_back_to_defaults() {
+        let pool = EndpointPool::new(vec![]);
+        assert_eq!(pool.active(), DEFAULT_ENDPOINTS[0]);
+        assert_eq!(pool.snapshot().len(), DEFAULT_ENDPOINTS.len());
+    }
+}
@@ -0,0 +1,654 @@
+//! `com.atproto.sync.subscribeRepos`（リポジトリのファイアホース）からの取り込み
+//!
+//! Jetstream の代わりに、リレーや PDS に直接つなぐ。
+//! フレームの DAG-CBOR と CAR ブロックをデコードし、Jetstream と同じ形の `CommitEvent` にして
+//! ディスパッチャーへ流すので、購読者（各フィード）は取り込み元を意識しなくてよい
+//!
+//! - カーソルは time_us ではなく seq。`CursorStore` には Jetstream とは別の名前で保存すること
+//! - 対象の操作を含まないフレームでも、それより前のイベントが処理し終われば seq まで進める
+//! - コレクションの絞り込みはサーバー側ではできないので、受信してから捨てる
+//! - どこまで遡れるかはリレー次第なので、`BackfillConfig` のうち効くのはレート上限だけ
+
+use crate::backfill::CatchUp;
+use crate::lifecycle::{AccountStatus, LifecycleEvent};
+use crate::{ConsumerConfig, CursorStore, Dispatcher, SharedRecorder};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use futures_util::StreamExt;
+use ipld_core::{cid::Cid, ipld::Ipld};
+use jetstream_oxide::events::commit::CommitEvent;
+use std::collections::{BTreeMap, HashMap};
+use tokio_tungstenite::tungstenite::Message;
+
+pub const DEFAULT_FIREHOSE_URL: &str = "wss://bsky.network/xrpc/com.atproto.sync.subscribeRepos";
+
+/// 1 フレームをデコードした結果
+#[derive(Debug)]
+pub(crate) enum Frame {
+    /// `#commit`。対象コレクションの操作だけを含む（空のこともある）
+    Commit {
+        seq: i64,
+        time_us: i64,
+        events: Vec<CommitEvent>,
+    },
+    /// `#identity` / `#account`
+    Lifecycle {
+        seq: Option<i64>,
+        time_us: i64,
+        event: LifecycleEvent,
+    },
+    /// `#info` や `#sync` など、取り込みに関係しないもの
+    Ignored { seq: Option<i64>, time_us: i64 },
+}
+
+/// ファイアホースから取り込み続ける（`start_consumer` から呼ばれる）
+pub(crate) async fn run(
+    url: &str,
+    cursor_store: &CursorStore,
+    initial_cursor: Option<i64>,
+    config: &ConsumerConfig,
+    recorder: Option<SharedRecorder>,
+    dispatcher: Dispatcher,
+) {
+    loop {
+        // 処理し終えた seq から再開する（サーバーはその次から送ってくる）
+        let cursor = dispatcher.committed_cursor().or(initial_cursor);
+        tracing::info!(
+            "Connecting to firehose at {} (cursor {}: {:?})",
+            url,
+            cursor_store.name(),
+            cursor
+        );
+
+        // カーソルから再開するときは、追いつくまでレート上限をかける（時刻はフレームの time で判断する）
+        let now = chrono::Utc::now();
+        let catch_up = match cursor {
+            Some(_) => CatchUp::resuming(config.backfill, now),
+            None => CatchUp::new(config.backfill, None, now),
+        };
+
+        let result = connect_and_run(
+            url,
+            cursor,
+            &config.wanted_collections,
+            catch_up,
+            recorder.as_ref(),
+            &dispatcher,
+        )
+        .await;
+        dispatcher.record_reconnect(url);
+        tracing::warn!(
+            "Firehose disconnected from {}: {:?}. Reconnecting in {:?}...",
+            url,
+            result,
+            config.reconnect_delay
+        );
+        tokio::time::sleep(config.reconnect_delay).await;
+    }
+}
+
+/// 切断されるまでイベントを流し、受信したフレーム数を返す
+async fn connect_and_run(
+    url: &str,
+    cursor: Option<i64>,
+    wanted_collections: &[String],
+    mut catch_up: CatchUp,
+    recorder: Option<&SharedRecorder>,
+    dispatcher: &Dispatcher,
+) -> Result<u64> {
+    let url = match cursor {
+        Some(cursor) => format!("{}?cursor={}", url, cursor),
+        None => url.to_string(),
+    };
+    let (mut socket, _) = tokio_tungstenite::connect_async(&url)
+        .await
+        .with_context(|| format!("Failed to connect to {}", url))?;
+
+    let mut received = 0;
+    while let Some(message) = socket.next().await {
+        let bytes = match message? {
+            Message::Binary(bytes) => bytes,
+            Message::Close(frame) => {
+                tracing::info!("Firehose closed: {:?}", frame);
+                break;
+            }
+            _ => continue,
+        };
+        received += 1;
+
+        match decode_frame(&bytes, wanted_collections) {
+            Ok(Frame::Commit {
+                seq,
+                time_us,
+                events,
+            }) => {
+                let event_time = chrono::DateTime::from_timestamp_micros(time_us);
+                if let Some(wait) =
+                    event_time.and_then(|time| catch_up.on_event(time, chrono::Utc::now()))
+                {
+                    tokio::time::sleep(wait).await;
+                }
+                // 同じコミットの操作がすべて処理されるまでは、カーソルを 1 つ前の seq に留める
+                for event in events {
+                    if let Some(recorder) = recorder {
+                        if let Err(e) = recorder.lock().await.record(&event).await {
+                            tracing::warn!("Failed to record firehose event: {}", e);
+                        }
+                    }
+                    dispatcher.dispatch_with_cursor(event, seq - 1).await;
+                }
+                dispatcher.advance_cursor(seq, time_us);
+            }
+            Ok(Frame::Lifecycle {
+                seq,
+                time_us,
+                event,
+            }) => {
+                if let Some(recorder) = recorder {
+                    if let Err(e) = recorder
+                        .lock()
+                        .await
+                        .record_lifecycle(&event, time_us as u64)
+                        .await
+                    {
+                        tracing::warn!("Failed to record firehose event: {}", e);
+                    }
+                }
+                dispatcher.dispatch_lifecycle(event).await;
+                if let Some(seq) = seq {
+                    dispatcher.advance_cursor(seq, time_us);
+                }
+            }
+            Ok(Frame::Ignored { seq, time_us }) => {
+                if let Some(seq) = seq {
+                    dispatcher.advance_cursor(seq, time_us);
+                }
+            }
+            // エラーフレームを受け取ったら切断される
+            Err(e) if is_error_frame(&bytes) => return Err(e),
+            Err(e) => tracing::warn!("Failed to decode firehose frame: {:#}", e),
+        }
+    }
+
+    Ok(received)
+}
+
+fn is_error_frame(bytes: &[u8]) -> bool {
+    let mut items = serde_ipld_dagcbor::de::iter_from_reader::<Ipld, _>(bytes);
+    matches!(
+        items.next(),
+        Some(Ok(Ipld::Map(header))) if header.get("op") == Some(&Ipld::Integer(-1))
+    )
+}
+
+/// ヘッダーとボディの 2 つの DAG-CBOR からなるフレームをデコードする
+pub(crate) fn decode_frame(bytes: &[u8], wanted_collections: &[String]) -> Result<Frame> {
+    let mut items = serde_ipld_dagcbor::de::iter_from_reader::<Ipld, _>(bytes);
+    let header = as_map(items.next().context("Missing header")??)?;
+    let body = as_map(items.next().context("Missing body")??)?;
+
+    if header.get("op") == Some(&Ipld::Integer(-1)) {
+        bail!(
+            "Firehose error: {} {}",
+            string(&body, "error").unwrap_or_default(),
+            string(&body, "message").unwrap_or_default()
+        );
+    }
+
+    let seq = integer(&body, "seq");
+    let time_us = time_us(&body);
+    match string(&header, "t").as_deref() {
+        Some("#commit") => Ok(Frame::Commit {
+            seq: seq.context("Missing seq")?,
+            time_us,
+            events: decode_commit(&body, time_us, wanted_collections)?,
+        }),
+        Some("#identity") => Ok(Frame::Lifecycle {
+            seq,
+            time_us,
+            event: LifecycleEvent::Identity {
+                did: string(&body, "did").context("Missing did")?,
+                handle: string(&body, "handle"),
+            },
+        }),
+        Some("#account") => {
+            let did = string(&body, "did").context("Missing did")?;
+            let active = matches!(body.get("active"), Some(Ipld::Bool(true)));
+            let status = match string(&body, "status").as_deref() {
+                None => None,
+                Some("deactivated") => Some(AccountStatus::Deactivated),
+                Some("deleted") => Some(AccountStatus::Deleted),
+                Some("suspended") => Some(AccountStatus::Suspended),
+                Some("takendown") => Some(AccountStatus::TakenDown),
+                // desynchronized / throttled などは一時的なものなので、投稿は消さない
+                Some(other) => {
+                    tracing::debug!("Ignoring account status {} for {}", other, did);
+                    return Ok(Frame::Ignored { seq, time_us });
+                }
+            };
+            Ok(Frame::Lifecycle {
+                seq,
+                time_us,
+                event: LifecycleEvent::Account {
+                    did,
+                    active,
+                    status,
+                },
+            })
+        }
+        Some("#info") => {
+            tracing::info!("Firehose info: {:?}", string(&body, "name"));
+            Ok(Frame::Ignored { seq, time_us })
+        }
+        _ => Ok(Frame::Ignored { seq, time_us }),
+    }
+}
+
+/// フレームの `time`。無ければ受信した時刻
+fn time_us(body: &BTreeMap<String, Ipld>) -> i64 {
+    string(body, "time")
+        .and_then(|time| chrono::DateTime::parse_from_rfc3339(&time).ok())
+        .map(|time| time.timestamp_micros())
+        .unwrap_or_else(|| chrono::Utc::now().timestamp_micros())
+}
+
+/// 読めない操作はログに出して飛ばし、同じコミットの他の操作は流す
+fn decode_commit(
+    body: &BTreeMap<String, Ipld>,
+    time_us: i64,
+    wanted_collections: &[String],
+) -> Result<Vec<CommitEvent>> {
+    let repo = string(body, "repo").context("Missing repo")?;
+    let rev = string(body, "rev").unwrap_or_default();
+
+    let blocks = match body.get("blocks") {
+        Some(Ipld::Bytes(car)) => read_car(car)?,
+        _ => HashMap::new(),
+    };
+    let Some(Ipld::List(ops)) = body.get("ops") else {
+        return Ok(vec![]);
+    };
+
+    let mut events = Vec::new();
+    for op in ops {
+        let Ok(op) = as_map(op.clone()) else {
+            tracing::warn!("Skipping malformed op in {}", repo);
+            continue;
+        };
+        let (Some(action), Some(path)) = (string(&op, "action"), string(&op, "path")) else {
+            tracing::warn!("Skipping op without action or path in {}", repo);
+            continue;
+        };
+        let Some((collection, rkey)) = path.split_once('/') else {
+            continue;
+        };
+        if !is_wanted(collection, wanted_collections) {
+            continue;
+        }
+
+        // Jetstream と同じ形にしてから CommitEvent として読む
+        let mut commit = serde_json::json!({
+            "rev": rev,
+            "operation": action,
+            "collection": collection,
+            "rkey": rkey,
+        });
+        if action != "delete" {
+            let Some(Ipld::Link(cid)) = op.get("cid") else {
+                continue;
+            };
+            // tooBig のコミットにはブロックが含まれない
+            let Some(block) = blocks.get(cid) else {
+                tracing::debug!("Missing block for {} in {}", path, repo);
+                continue;
+            };
+            let record: Ipld = match serde_ipld_dagcbor::from_slice(block) {
+                Ok(record) => record,
+                Err(e) => {
+                    tracing::warn!("Skipping undecodable record {} in {}: {}", path, repo, e);
+                    continue;
+                }
+            };
+            commit["cid"] = serde_json::json!(cid.to_string());
+            commit["record"] = ipld_to_json(record);
+        }
+
+        let event = serde_json::json!({
+            "did": repo,
+            "time_us": time_us,
+            "kind": "commit",
+            "commit": commit,
+        });
+        match serde_json::from_value::<CommitEvent>(event) {
+            Ok(event) => events.push(event),
+            Err(e) => tracing::debug!("Skipping unsupported record {} in {}: {}", path, repo, e),
+        }
+    }
+    Ok(events)
+}
+
+/// `app.bsky.feed.*` のようなプレフィックス指定にも対応する。空なら全コレクション
+fn is_wanted(collection: &str, wanted_collections: &[String]) -> bool {
+    wanted_collections.is_empty()
+        || wanted_collections
+            .iter()
+            .any(|wanted| match wanted.strip_suffix('*') {
+                Some(prefix) => collection.starts_with(prefix),
+                None => wanted == collection,
+            })
+}
+
+/// CAR v1 のブロックを CID ごとに取り出す
+fn read_car(mut car: &[u8]) -> Result<HashMap<Cid, Vec<u8>>> {
+    let header_len = read_varint(&mut car)? as usize;
+    car = car.get(header_len..).context("Truncated CAR header")?;
+
+    let mut blocks = HashMap::new();
+    while !car.is_empty() {
+        let section_len = read_varint(&mut car)? as usize;
+        let section = car.get(..section_len).context("Truncated CAR section")?;
+        car = &car[section_len..];
+
+        let cid_len = cid_len(section)?;
+        let cid = Cid::try_from(&section[..cid_len])?;
+        blocks.insert(cid, section[cid_len..].to_vec());
+    }
+    Ok(blocks)
+}
+
+/// バイナリ形式の CID の長さ
+fn cid_len(bytes: &[u8]) -> Result<usize> {
+    // CIDv0 は sha2-256 のマルチハッシュそのもの
+    if bytes.starts_with(&[0x12, 0x20]) {
+        return Ok(34);
+    }
+
+    let mut rest = bytes;
+    let _version = read_varint(&mut rest)?;
+    let _codec = read_varint(&mut rest)?;
+    let _hash_code = read_varint(&mut rest)?;
+    let digest_len = read_varint(&mut rest)? as usize;
+    let len = bytes.len() - rest.len() + digest_len;
+    if len > bytes.len() {
+        bail!("Truncated CID");
+    }
+    Ok(len)
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for (i, byte) in bytes.iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            *bytes = &bytes[i + 1..];
+            return Ok(value);
+        }
+    }
+    Err(anyhow!("Invalid varint"))
+}
+
+/// DAG-CBOR のレコードを atproto の JSON 表現（`$link` / `$bytes`）に変換する
+fn ipld_to_json(ipld: Ipld) -> serde_json::Value {
+    match ipld {
+        Ipld::Null => serde_json::Value::Null,
+        Ipld::Bool(value) => value.into(),
+        Ipld::Integer(value) => i64::try_from(value)
+            .map(Into::into)
+            .unwrap_or(serde_json::Value::Null),
+        Ipld::Float(value) => value.into(),
+        Ipld::String(value) => value.into(),
+        Ipld::Bytes(bytes) => serde_json::json!({
+            "$bytes": base64::engine::general_purpose::STANDARD_NO_PAD.encode(bytes)
+        }),
+        Ipld::List(items) => items.into_iter().map(ipld_to_json).collect(),
+        Ipld::Map(map) => map
+            .into_iter()
+            .map(|(key, value)| (key, ipld_to_json(value)))
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+        Ipld::Link(cid) => serde_json::json!({ "$link": cid.to_string() }),
+    }
+}
+
+fn as_map(ipld: Ipld) -> Result<BTreeMap<String, Ipld>> {
+    match ipld {
+        Ipld::Map(map) => Ok(map),
+        other => bail!("Expected a map, got {:?}", other.kind()),
+    }
+}
+
+fn string(map: &BTreeMap<String, Ipld>, key: &str) -> Option<String> {
+    match map.get(key) {
+        Some(Ipld::String(value)) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+fn integer(map: &BTreeMap<String, Ipld>, key: &str) -> Option<i64> {
+    match map.get(key) {
+        Some(Ipld::Integer(value)) => i64::try_from(*value).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ipld_core::cid::multihash::Multihash;
+    use sha2::{Digest, Sha256};
+
+    const DAG_CBOR: u64 = 0x71;
+    const SHA2_256: u64 = 0x12;
+
+    fn ipld(value: serde_json::Value) -> Ipld {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn encode(value: &Ipld) -> Vec<u8> {
+        serde_ipld_dagcbor::to_vec(value).unwrap()
+    }
+
+    fn cid_of(block: &[u8]) -> Cid {
+        let digest = Sha256::digest(block);
+        Cid::new_v1(DAG_CBOR, Multihash::wrap(SHA2_256, &digest).unwrap())
+    }
+
+    fn varint(mut value: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte);
+                return out;
+            }
+            out.push(byte | 0x80);
+        }
+    }
+
+    fn car(blocks: &[(Cid, Vec<u8>)]) -> Vec<u8> {
+        let header = encode(&Ipld::Map(BTreeMap::from([
+            ("version".to_string(), Ipld::Integer(1)),
+            ("roots".to_string(), Ipld::List(vec![])),
+        ])));
+        let mut out = varint(header.len());
+        out.extend(header);
+        for (cid, block) in blocks {
+            let cid_bytes = cid.to_bytes();
+            out.extend(varint(cid_bytes.len() + block.len()));
+            out.extend(cid_bytes);
+            out.extend(block);
+        }
+        out
+    }
+
+    fn frame(t: &str, body: Ipld) -> Vec<u8> {
+        let mut out = encode(&Ipld::Map(BTreeMap::from([
+            ("op".to_string(), Ipld::Integer(1)),
+            ("t".to_string(), Ipld::String(t.to_string())),
+        ])));
+        out.extend(encode(&body));
+        out
+    }
+
+    fn op(action: &str, path: &str, cid: Option<Cid>) -> Ipld {
+        Ipld::Map(BTreeMap::from([
+            ("action".to_string(), Ipld::String(action.to_string())),
+            ("path".to_string(), Ipld::String(path.to_string())),
+            ("cid".to_string(), cid.map(Ipld::Link).unwrap_or(Ipld::Null)),
+        ]))
+    }
+
+    /// コミットのフレームから、対象コレクションの操作だけが CommitEvent になり、読めないレコードは飛ばされるか
+    #[test]
+    fn test_decode_commit_frame() {
+        let post = encode(&ipld(serde_json::json!({
+            "$type": "app.bsky.feed.post",
+            "createdAt": "2024-09-09T19:46:02.102Z",
+            "langs": ["en"],
+            "text": "Hello, World!"
+        })));
+        let post_cid = cid_of(&post);
+        let like = encode(&ipld(serde_json::json!({
+            "$type": "app.bsky.feed.like",
+            "createdAt": "2024-09-09T19:46:02.102Z",
+            "subject": {"cid": post_cid.to_string(), "uri": "at://did:plc:alice/app.bsky.feed.post/3l3qo2vuowo2b"}
+        })));
+        let like_cid = cid_of(&like);
+        // DAG-CBOR として読めないブロック
+        let broken = vec![0xff, 0x00];
+        let broken_cid = cid_of(&broken);
+
+        let body = Ipld::Map(BTreeMap::from([
+            ("seq".to_string(), Ipld::Integer(42)),
+            (
+                "repo".to_string(),
+                Ipld::String("did:plc:alice".to_string()),
+            ),
+            ("rev".to_string(), Ipld::String("3l3qo2vutsw2b".to_string())),
+            (
+                "time".to_string(),
+                Ipld::String("2024-09-09T19:46:02.329Z".to_string()),
+            ),
+            ("tooBig".to_string(), Ipld::Bool(false)),
+            (
+                "blocks".to_string(),
+                Ipld::Bytes(car(&[
+                    (post_cid, post),
+                    (like_cid, like),
+                    (broken_cid, broken),
+                ])),
+            ),
+            (
+                "ops".to_string(),
+                Ipld::List(vec![
+                    op("create", "app.bsky.feed.post/3l3qo2vuowo2b", Some(post_cid)),
+                    op("create", "app.bsky.feed.like/3l3qo2w8mxc2c", Some(like_cid)),
+                    op(
+                        "create",
+                        "app.bsky.feed.post/3l3qo2wbrkn2a",
+                        Some(broken_cid),
+                    ),
+                    op("delete", "app.bsky.feed.post/3l3qo2wigz22a", None),
+                ]),
+            ),
+        ]));
+
+        let wanted = vec!["app.bsky.feed.post".to_string()];
+        let Frame::Commit {
+            seq,
+            time_us,
+            events,
+        } = decode_frame(&frame("#commit", body), &wanted).unwrap()
+        else {
+            panic!("expected a commit frame");
+        };
+
+        assert_eq!(seq, 42);
+        assert_eq!(time_us, 1725911162329000);
+        assert_eq!(events.len(), 2);
+        let CommitEvent::Create { info, commit } = &events[0] else {
+            panic!("expected a create event");
+        };
+        assert_eq!(info.did.as_str(), "did:plc:alice");
+        assert_eq!(info.time_us, 1725911162329000);
+        assert_eq!(commit.info.rkey, "3l3qo2vuowo2b");
+        assert_eq!(commit.cid.as_ref().to_string(), post_cid.to_string());
+        let atrium_api::record::KnownRecord::AppBskyFeedPost(record) = &commit.record else {
+            panic!("expected a post record");
+        };
+        assert_eq!(record.text, "Hello, World!");
+        assert!(
+            matches!(&events[1], CommitEvent::Delete { commit, .. } if commit.rkey == "3l3qo2wigz22a")
+        );
+    }
+
+    /// identity / account のフレームが LifecycleEvent になり、一時的な状態は無視されるか
+    #[test]
+    fn test_decode_lifecycle_frames() {
+        let identity = Ipld::Map(BTreeMap::from([
+            ("seq".to_string(), Ipld::Integer(43)),
+            ("did".to_string(), Ipld::String("did:plc:alice".to_string())),
+            (
+                "handle".to_string(),
+                Ipld::String("alice.example.com".to_string()),
+            ),
+        ]));
+        let Frame::Lifecycle { seq, event, .. } =
+            decode_frame(&frame("#identity", identity), &[]).unwrap()
+        else {
+            panic!("expected a lifecycle frame");
+        };
+        assert_eq!(seq, Some(43));
+        assert_eq!(
+            event,
+            LifecycleEvent::Identity {
+                did: "did:plc:alice".to_string(),
+                handle: Some("alice.example.com".to_string()),
+            }
+        );
+
+        let account = |status: &str| {
+            Ipld::Map(BTreeMap::from([
+                ("seq".to_string(), Ipld::Integer(44)),
+                ("did".to_string(), Ipld::String("did:plc:alice".to_string())),
+                ("active".to_string(), Ipld::Bool(false)),
+                ("status".to_string(), Ipld::String(status.to_string())),
+            ]))
+        };
+        let Frame::Lifecycle { event, .. } =
+            decode_frame(&frame("#account", account("takendown")), &[]).unwrap()
+        else {
+            panic!("expected a lifecycle frame");
+        };
+        assert!(event.is_account_gone());
+
+        assert!(matches!(
+            decode_frame(&frame("#account", account("throttled")), &[]).unwrap(),
+            Frame::Ignored { seq: Some(44), .. }
+        ));
+    }
+
+    /// エラーフレームはエラーとして返されるか
+    #[test]
+    fn test_decode_error_frame() {
+        let mut bytes = encode(&Ipld::Map(BTreeMap::from([(
+            "op".to_string(),
+            Ipld::Integer(-1),
+        )])));
+        bytes.extend(encode(&Ipld::Map(BTreeMap::from([
+            (
+                "error".to_string(),
+                Ipld::String("FutureCursor".to_string()),
+            ),
+            (
+                "message".to_string(),
+                Ipld::String("Cursor in the future.".to_string()),
+            ),
+        ]))));
+
+        assert!(is_error_frame(&bytes));
+        let error = decode_frame(&bytes, &[]).unwrap_err();
+        assert!(error.to_string().contains("FutureCursor"));
+    }
+}
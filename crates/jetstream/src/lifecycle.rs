@@ -0,0 +1,129 @@
+//! identity / account イベント
+//!
+//! Jetstream は購読するコレクションに関わらず、全アカウントの identity / account イベントを流してくる。
+//! どちらも 1 件の処理が軽いので、購読者のキューは通さずに受信したその場で処理する。
+//! 無効になったアカウントのイベントがキューに残っていれば、`Dispatcher` が処理せずに捨てる
+
+use jetstream_oxide::events::{account, identity};
+
+/// アカウントが無効になった理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountStatus {
+    Deactivated,
+    Deleted,
+    Suspended,
+    TakenDown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LifecycleEvent {
+    /// ハンドルなどの ID 情報が変わった
+    Identity { did: String, handle: Option<String> },
+    /// アカウントの状態が変わった。無効なら `status` に理由が入る
+    Account {
+        did: String,
+        active: bool,
+        status: Option<AccountStatus>,
+    },
+}
+
+impl LifecycleEvent {
+    pub fn did(&self) -> &str {
+        match self {
+            Self::Identity { did, .. } | Self::Account { did, .. } => did,
+        }
+    }
+
+    /// 停止・凍結・削除などで、投稿を配信すべきでなくなったか
+    pub fn is_account_gone(&self) -> bool {
+        matches!(self, Self::Account { active: false, .. })
+    }
+}
+
+impl From<&identity::IdentityEvent> for LifecycleEvent {
+    fn from(event: &identity::IdentityEvent) -> Self {
+        Self::Identity {
+            did: event.identity.did.as_str().to_string(),
+            handle: event
+                .identity
+                .handle
+                .as_ref()
+                .map(|handle| handle.as_str().to_string()),
+        }
+    }
+}
+
+impl From<&account::AccountEvent> for LifecycleEvent {
+    fn from(event: &account::AccountEvent) -> Self {
+        Self::Account {
+            did: event.account.did.as_str().to_string(),
+            active: event.account.active,
+            status: event.account.status.as_ref().map(|status| match status {
+                account::AccountStatus::Deactivated => AccountStatus::Deactivated,
+                account::AccountStatus::Deleted => AccountStatus::Deleted,
+                account::AccountStatus::Suspended => AccountStatus::Suspended,
+                account::AccountStatus::TakenDown => AccountStatus::TakenDown,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jetstream_oxide::events::JetstreamEvent;
+
+    fn parse(value: serde_json::Value) -> LifecycleEvent {
+        match serde_json::from_value(value).unwrap() {
+            JetstreamEvent::Identity(event) => LifecycleEvent::from(&event),
+            JetstreamEvent::Account(event) => LifecycleEvent::from(&event),
+            JetstreamEvent::Commit(_) => panic!("unexpected commit event"),
+        }
+    }
+
+    /// Jetstream の identity / account イベントを読み取れるか
+    #[test]
+    fn test_from_jetstream_events() {
+        let identity = parse(serde_json::json!({
+            "did": "did:plc:alice",
+            "time_us": 1725911164250013u64,
+            "kind": "identity",
+            "identity": {
+                "did": "did:plc:alice",
+                "handle": "alice.example.com",
+                "seq": 1409752997,
+                "time": "2024-09-09T19:46:04.250Z"
+            }
+        }));
+        assert_eq!(
+            identity,
+            LifecycleEvent::Identity {
+                did: "did:plc:alice".to_string(),
+                handle: Some("alice.example.com".to_string()),
+            }
+        );
+        assert!(!identity.is_account_gone());
+
+        let taken_down = parse(serde_json::json!({
+            "did": "did:plc:alice",
+            "time_us": 1725911164250014u64,
+            "kind": "account",
+            "account": {
+                "active": false,
+                "did": "did:plc:alice",
+                "seq": 1409752998,
+                "status": "takendown",
+                "time": "2024-09-09T19:46:04.250Z"
+            }
+        }));
+        assert_eq!(taken_down.did(), "did:plc:alice");
+        assert!(taken_down.is_account_gone());
+        assert!(matches!(
+            taken_down,
+            LifecycleEvent::Account {
+                status: Some(AccountStatus::TakenDown),
+                ..
+            }
+        ));
+    }
+}
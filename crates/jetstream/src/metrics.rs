@@ -0,0 +1,63 @@
+//! 取り込みのメトリクス
+//!
+//! ディスパッチャーが持ち、コンシューマーの再接続もここに数える。`Dispatcher::encode_metrics` で出力する
+//!
+//! 値はプロセスのメモリにだけ持ち、永続化はしない（再起動で 0 に戻るのは Prometheus の `rate()` が扱う）。
+//! 再起動をまたいで残るのは `CursorStore` に保存するカーソルだけで、遅れはそこから計算できる
+
+use bsky_core::metrics::{BoundCounter, Counter, Histogram};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// 購読者のハンドラーの戻り値。採用したフィードを返すと、フィードごとの件数に数える
+pub trait Outcome {
+    fn matched_feed(self) -> Option<&'static str>;
+}
+
+impl Outcome for () {
+    fn matched_feed(self) -> Option<&'static str> {
+        None
+    }
+}
+
+impl Outcome for Option<&'static str> {
+    fn matched_feed(self) -> Option<&'static str> {
+        self
+    }
+}
+
+/// 取り除いただけのイベントは採用件数に数えない
+impl Outcome for bsky_core::FeedChange {
+    fn matched_feed(self) -> Option<&'static str> {
+        self.added
+    }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct IngestMetrics {
+    /// コレクションごとの受信件数
+    pub received: Counter,
+    /// フィードごとの採用件数
+    pub matched: Counter,
+    /// 購読者ごとのハンドラーの処理時間
+    pub processing: Histogram,
+    /// 接続先ごとの再接続回数
+    pub reconnects: Counter,
+    /// `received` をコレクションごとに解決したもの
+    received_by_collection: Arc<Mutex<HashMap<String, BoundCounter>>>,
+}
+
+impl IngestMetrics {
+    /// イベントごとに呼ばれるので、ラベルを解決したカウンターを使い回す
+    pub fn count_received(&self, collection: &str) {
+        let mut cache = self.received_by_collection.lock().unwrap();
+        match cache.get(collection) {
+            Some(counter) => counter.inc(),
+            None => {
+                let counter = self.received.with_labels(&[("collection", collection)]);
+                counter.inc();
+                cache.insert(collection.to_string(), counter);
+            }
+        }
+    }
+}
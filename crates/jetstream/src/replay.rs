@@ -0,0 +1,302 @@
+//! イベントの記録と再生
+//!
+//! Jetstream から受け取ったイベント（commit と identity / account）を 1 行 1 イベントの JSON (NDJSON) に書き出し、
+//! 後から同じコールバックに流し直せるようにする。実データでの再現テストやオフラインでのデバッグ用
+//!
+//! 行の形式は Jetstream のワイヤーフォーマットと同じなので、Jetstream の出力をそのまま保存したファイルも読める
+
+use crate::lifecycle::{AccountStatus, LifecycleEvent};
+use anyhow::{Context, Result};
+use jetstream_oxide::events::commit::{CommitEvent, CommitType};
+use jetstream_oxide::events::JetstreamEvent;
+use serde_json::json;
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+/// 再生速度
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// 待たずに流す
+    Unthrottled,
+    /// イベント間の time_us の差を倍率で割って待つ（1.0 で記録時と同じ速度）
+    Scaled(f64),
+}
+
+/// 記録ファイルから読んだイベント
+#[derive(Debug)]
+pub enum ReplayEvent {
+    Commit(CommitEvent),
+    Lifecycle { event: LifecycleEvent, time_us: u64 },
+}
+
+impl ReplayEvent {
+    pub fn time_us(&self) -> u64 {
+        match self {
+            Self::Commit(event) => crate::dispatcher::event_time_us(event),
+            Self::Lifecycle { time_us, .. } => *time_us,
+        }
+    }
+}
+
+/// Jetstream のワイヤーフォーマットに戻す
+pub fn to_json(event: &CommitEvent) -> Result<serde_json::Value> {
+    let value = match event {
+        CommitEvent::Create { info, commit } | CommitEvent::Update { info, commit } => {
+            // CommitEvent は untagged なので update も Create として読まれる。実際の操作は operation を見る
+            let operation = match commit.info.operation {
+                CommitType::Create => "create",
+                CommitType::Update => "update",
+                CommitType::Delete => "delete",
+            };
+            json!({
+                "did": info.did,
+                "time_us": info.time_us,
+                "kind": "commit",
+                "commit": {
+                    "operation": operation,
+                    "rev": commit.info.rev,
+                    "rkey": commit.info.rkey,
+                    "collection": commit.info.collection,
+                    "cid": commit.cid,
+                    "record": serde_json::to_value(&commit.record)
+                        .context("Failed to serialize record")?,
+                }
+            })
+        }
+        CommitEvent::Delete { info, commit } => json!({
+            "did": info.did,
+            "time_us": info.time_us,
+            "kind": "commit",
+            "commit": {
+                "operation": "delete",
+                "rev": commit.rev,
+                "rkey": commit.rkey,
+                "collection": commit.collection,
+            }
+        }),
+    };
+    Ok(value)
+}
+
+/// identity / account イベントを Jetstream のワイヤーフォーマットに戻す
+///
+/// 元の seq は持っていないので 0 にし、time は time_us から作る
+pub fn lifecycle_to_json(event: &LifecycleEvent, time_us: u64) -> serde_json::Value {
+    let time = chrono::DateTime::from_timestamp_micros(time_us as i64)
+        .unwrap_or_default()
+        .to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+    match event {
+        LifecycleEvent::Identity { did, handle } => json!({
+            "did": did,
+            "time_us": time_us,
+            "kind": "identity",
+            "identity": { "did": did, "handle": handle, "seq": 0, "time": time }
+        }),
+        LifecycleEvent::Account {
+            did,
+            active,
+            status,
+        } => {
+            let status = status.map(|status| match status {
+                AccountStatus::Deactivated => "deactivated",
+                AccountStatus::Deleted => "deleted",
+                AccountStatus::Suspended => "suspended",
+                AccountStatus::TakenDown => "takendown",
+            });
+            json!({
+                "did": did,
+                "time_us": time_us,
+                "kind": "account",
+                "account": { "active": active, "did": did, "seq": 0, "time": time, "status": status }
+            })
+        }
+    }
+}
+
+/// イベントを NDJSON ファイルに追記する
+///
+/// 途中で落ちても読める状態を保つため、1 イベントごとに 1 行まとめて書き込む
+pub struct Recorder {
+    file: tokio::fs::File,
+}
+
+impl Recorder {
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        Ok(Self { file })
+    }
+
+    pub async fn record(&mut self, event: &CommitEvent) -> Result<()> {
+        self.write_line(&to_json(event)?).await
+    }
+
+    /// identity / account イベントを記録する。再生したときにアカウントの削除なども再現できる
+    pub async fn record_lifecycle(&mut self, event: &LifecycleEvent, time_us: u64) -> Result<()> {
+        self.write_line(&lifecycle_to_json(event, time_us)).await
+    }
+
+    async fn write_line(&mut self, value: &serde_json::Value) -> Result<()> {
+        let mut line = serde_json::to_vec(value)?;
+        line.push(b'\n');
+        self.file.write_all(&line).await?;
+        Ok(())
+    }
+}
+
+/// NDJSON ファイルのイベントを順にコールバックへ流し、流した件数を返す
+///
+/// 空行とイベントとして読めない行は読み飛ばす
+pub async fn replay_file<F, Fut>(
+    path: impl AsRef<Path>,
+    speed: ReplaySpeed,
+    mut callback: F,
+) -> Result<u64>
+where
+    F: FnMut(ReplayEvent) -> Fut,
+    Fut: std::future::Future<Output = ()>,
+{
+    let path = path.as_ref();
+    let file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut lines = BufReader::new(file).lines();
+
+    let mut replayed = 0;
+    let mut previous_us: Option<u64> = None;
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = match serde_json::from_str(&line) {
+            Ok(JetstreamEvent::Commit(event)) => ReplayEvent::Commit(event),
+            Ok(JetstreamEvent::Identity(event)) => ReplayEvent::Lifecycle {
+                event: (&event).into(),
+                time_us: event.info.time_us,
+            },
+            Ok(JetstreamEvent::Account(event)) => ReplayEvent::Lifecycle {
+                event: (&event).into(),
+                time_us: event.info.time_us,
+            },
+            Err(e) => {
+                tracing::debug!("Skipping unreadable line in {}: {}", path.display(), e);
+                continue;
+            }
+        };
+
+        let time_us = event.time_us();
+        if let (ReplaySpeed::Scaled(factor), Some(previous)) = (speed, previous_us) {
+            let gap_us = time_us.saturating_sub(previous) as f64 / factor.max(f64::MIN_POSITIVE);
+            if gap_us >= 1.0 {
+                tokio::time::sleep(std::time::Duration::from_micros(gap_us as u64)).await;
+            }
+        }
+        previous_us = Some(time_us);
+
+        callback(event).await;
+        replayed += 1;
+    }
+
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CREATE_LINE: &str = r#"{"did":"did:plc:alice","time_us":1700000000000000,"kind":"commit","commit":{"operation":"create","rev":"3kabc","rkey":"3kpost","collection":"app.bsky.feed.post","cid":"bafyreidfayvfuwqa7qlnopdjiqrxzs6blmoeu4rujcjtnci5beludirz2a","record":{"$type":"app.bsky.feed.post","createdAt":"2023-11-14T22:13:20.000Z","text":"Hello world"}}}"#;
+    const UPDATE_LINE: &str = r#"{"did":"did:plc:alice","time_us":1700000000500000,"kind":"commit","commit":{"operation":"update","rev":"3kabd","rkey":"3kpost","collection":"app.bsky.feed.post","cid":"bafyreidfayvfuwqa7qlnopdjiqrxzs6blmoeu4rujcjtnci5beludirz2a","record":{"$type":"app.bsky.feed.post","createdAt":"2023-11-14T22:13:20.000Z","text":"Hello world!"}}}"#;
+    const DELETE_LINE: &str = r#"{"did":"did:plc:alice","time_us":1700000001000000,"kind":"commit","commit":{"operation":"delete","rev":"3kabd","rkey":"3kpost","collection":"app.bsky.feed.post"}}"#;
+    const IDENTITY_LINE: &str = r#"{"did":"did:plc:alice","time_us":1700000002000000,"kind":"identity","identity":{"did":"did:plc:alice","handle":"alice.test","seq":1,"time":"2023-11-14T22:13:22.000Z"}}"#;
+    const ACCOUNT_LINE: &str = r#"{"did":"did:plc:alice","time_us":1700000003000000,"kind":"account","account":{"active":false,"did":"did:plc:alice","seq":2,"time":"2023-11-14T22:13:23.000Z","status":"takendown"}}"#;
+
+    /// 記録した行を読み直すと同じイベントに戻るか
+    #[tokio::test]
+    async fn test_record_and_replay_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("jetstream-replay-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let source = dir.join("source.ndjson");
+        let recorded = dir.join("recorded.ndjson");
+        let _ = tokio::fs::remove_file(&recorded).await;
+        tokio::fs::write(
+            &source,
+            format!(
+                "{}\n{}\n\n{}\n{}\n{}\nnot json\n",
+                CREATE_LINE, UPDATE_LINE, IDENTITY_LINE, DELETE_LINE, ACCOUNT_LINE
+            ),
+        )
+        .await
+        .unwrap();
+
+        // source を再生しながら recorded に記録する
+        let mut recorder = Recorder::open(&recorded).await.unwrap();
+        let mut events = Vec::new();
+        replay_file(&source, ReplaySpeed::Unthrottled, |event| {
+            events.push(event);
+            async {}
+        })
+        .await
+        .unwrap();
+        for event in &events {
+            match event {
+                ReplayEvent::Commit(event) => recorder.record(event).await.unwrap(),
+                ReplayEvent::Lifecycle { event, time_us } => {
+                    recorder.record_lifecycle(event, *time_us).await.unwrap()
+                }
+            }
+        }
+        assert_eq!(events.len(), 5);
+
+        let mut replayed = Vec::new();
+        let count = replay_file(&recorded, ReplaySpeed::Unthrottled, |event| {
+            replayed.push(match event {
+                ReplayEvent::Commit(event) => to_json(&event).unwrap(),
+                ReplayEvent::Lifecycle { event, time_us } => lifecycle_to_json(&event, time_us),
+            });
+            async {}
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(count, 5);
+        assert_eq!(replayed[0]["commit"]["record"]["text"], "Hello world");
+        assert_eq!(replayed[0]["commit"]["operation"], "create");
+        // update は Create として読まれても operation は保たれる
+        assert_eq!(replayed[1]["commit"]["operation"], "update");
+        assert_eq!(replayed[2]["kind"], "identity");
+        assert_eq!(replayed[2]["identity"]["handle"], "alice.test");
+        assert_eq!(replayed[3]["commit"]["operation"], "delete");
+        assert_eq!(replayed[3]["time_us"], 1700000001000000u64);
+        assert_eq!(replayed[4]["kind"], "account");
+        assert_eq!(replayed[4]["account"]["status"], "takendown");
+        assert_eq!(replayed[4]["time_us"], 1700000003000000u64);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    /// Scaled ではイベント間の時間差に応じて待つか
+    #[tokio::test(start_paused = true)]
+    async fn test_replay_scaled_speed() {
+        let dir = std::env::temp_dir().join(format!("jetstream-speed-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let source = dir.join("source.ndjson");
+        // 1 秒間隔の 2 イベント
+        tokio::fs::write(&source, format!("{}\n{}\n", CREATE_LINE, DELETE_LINE))
+            .await
+            .unwrap();
+
+        let started = tokio::time::Instant::now();
+        replay_file(&source, ReplaySpeed::Scaled(4.0), |_| async {})
+            .await
+            .unwrap();
+        assert_eq!(started.elapsed(), std::time::Duration::from_millis(250));
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -1,6 +1,6 @@
 use crate::timezone;
-use anyhow::{Context, Result};
-use reqwest::Client;
+use bsky_core::appview::AppViewClient;
+use bsky_core::xrpc::XrpcError;
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize, Clone)]
@@ -33,22 +33,22 @@ pub trait PostFetcher {
         until: &str,
         limit: usize,
         cursor: Option<String>,
-    ) -> Result<(Vec<PostView>, Option<String>)>;
+    ) -> Result<(Vec<PostView>, Option<String>), XrpcError>;
 
     async fn determine_timezone(
         &self,
         handle: &str,
         user_token: &str,
-    ) -> Result<chrono::FixedOffset>;
+    ) -> Result<chrono::FixedOffset, XrpcError>;
 }
 
 pub struct BlueskyFetcher {
-    client: Client,
+    appview: AppViewClient,
 }
 
 impl BlueskyFetcher {
-    pub fn new(client: Client) -> Self {
-        Self { client }
+    pub fn new(appview: AppViewClient) -> Self {
+        Self { appview }
     }
 }
 
@@ -62,35 +62,24 @@ impl PostFetcher for BlueskyFetcher {
         until: &str,
         limit: usize,
         cursor: Option<String>,
-    ) -> Result<(Vec<PostView>, Option<String>)> {
-        let url = "https://api.bsky.app/xrpc/app.bsky.feed.searchPosts";
+    ) -> Result<(Vec<PostView>, Option<String>), XrpcError> {
         let q = format!("from:{} since:{} until:{}", author, since, until);
+        let limit = limit.to_string();
 
-        let mut req = self
-            .client
-            .get(url)
-            .header("Authorization", format!("Bearer {}", token))
-            .query(&[
-                ("q", q.as_str()),
-                ("limit", &limit.to_string()),
-                ("author", author),
-                ("sort", "latest"),
-            ]);
-
-        if let Some(c) = cursor {
-            req = req.query(&[("cursor", c)]);
-        }
-
-        let res = req.send().await.context("Search request failed")?;
-
-        if !res.status().is_success() {
-            return Ok((vec![], None));
+        let mut query = vec![
+            ("q", q.as_str()),
+            ("limit", limit.as_str()),
+            ("author", author),
+            ("sort", "latest"),
+        ];
+        if let Some(c) = cursor.as_deref() {
+            query.push(("cursor", c));
         }
 
-        let search_res: SearchResponse = res
-            .json()
-            .await
-            .context("Failed to parse search response")?;
+        let search_res: SearchResponse = self
+            .appview
+            .query("app.bsky.feed.searchPosts", Some(token), &query)
+            .await?;
         Ok((search_res.posts, search_res.cursor))
     }
 
@@ -98,7 +87,7 @@ impl PostFetcher for BlueskyFetcher {
         &self,
         handle: &str,
         user_token: &str,
-    ) -> Result<chrono::FixedOffset> {
-        timezone::determine_timezone(&self.client, handle, user_token).await
+    ) -> Result<chrono::FixedOffset, XrpcError> {
+        timezone::determine_timezone(&self.appview, handle, user_token).await
     }
 }
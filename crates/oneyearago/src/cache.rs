@@ -63,11 +63,28 @@ pub struct FeedCacheValue {
 
 pub struct CacheStore {
     pool: SqlitePool,
+    lookups: Option<bsky_core::metrics::Counter>,
 }
 
 impl CacheStore {
     pub fn new(pool: SqlitePool) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            lookups: None,
+        }
+    }
+
+    /// タイムゾーン・フィード結果の取得ごとに、ヒットしたかを `cache` と `result` のラベルで数える
+    pub fn with_lookup_counter(mut self, counter: bsky_core::metrics::Counter) -> Self {
+        self.lookups = Some(counter);
+        self
+    }
+
+    fn record_lookup(&self, cache: &str, hit: bool) {
+        if let Some(lookups) = &self.lookups {
+            let result = if hit { "hit" } else { "miss" };
+            lookups.inc(&[("cache", cache), ("result", result)]);
+        }
     }
 
     // -----------------------------------------------------------------------
@@ -107,7 +124,9 @@ impl CacheStore {
     /// タイムゾーンのキャッシュを取得する
     pub async fn get_timezone(&self, did: &str) -> Result<Option<chrono::FixedOffset>> {
         let key = format!("tz:{}", did);
-        let Some(raw) = self.get_raw(&key).await? else {
+        let raw = self.get_raw(&key).await?;
+        self.record_lookup("timezone", raw.is_some());
+        let Some(raw) = raw else {
             return Ok(None);
         };
         let cached: TimezoneCacheValue =
@@ -155,7 +174,9 @@ impl CacheStore {
         cursor: Option<&str>,
     ) -> Result<Option<FeedCacheValue>> {
         let key = Self::feed_key(did, date, limit, cursor);
-        let Some(raw) = self.get_raw(&key).await? else {
+        let raw = self.get_raw(&key).await?;
+        self.record_lookup("feed", raw.is_some());
+        let Some(raw) = raw else {
             return Ok(None);
         };
         let cached: FeedCacheValue =
@@ -293,6 +314,24 @@ mod tests {
         assert!(result.is_none());
     }
 
+    #[tokio::test]
+    async fn test_lookup_counter() {
+        let lookups = bsky_core::metrics::Counter::default();
+        let store = in_memory_store().await.with_lookup_counter(lookups.clone());
+        store.set_timezone("did:plc:test", 32400).await.unwrap();
+
+        store.get_timezone("did:plc:test").await.unwrap();
+        store.get_timezone("did:plc:unknown").await.unwrap();
+        store
+            .get_feed("did:plc:test", "240101", 30, None)
+            .await
+            .unwrap();
+
+        assert_eq!(lookups.get(&[("cache", "timezone"), ("result", "hit")]), 1);
+        assert_eq!(lookups.get(&[("cache", "timezone"), ("result", "miss")]), 1);
+        assert_eq!(lookups.get(&[("cache", "feed"), ("result", "miss")]), 1);
+    }
+
     // -- フィード結果 -------------------------------------------------------
 
     #[tokio::test]
@@ -6,30 +6,21 @@ mod timezone;
 use crate::api::BlueskyFetcher;
 use crate::cache::CacheStore;
 use anyhow::Result;
+use bsky_core::appview::AppViewClient;
 use bsky_core::FeedSkeletonResult;
-use reqwest::Client;
 
 pub async fn get_feed_skeleton(
-    client: &Client,
-    #[allow(unused_variables)] auth_header: &str,
+    appview: &AppViewClient,
     service_token: &str,
     actor: &str,
     limit: usize,
     cursor: Option<String>,
     cache: Option<&CacheStore>,
 ) -> Result<FeedSkeletonResult> {
-    let fetcher = BlueskyFetcher::new(client.clone());
-    let (feed_items, next_cursor) = logic::fetch_posts_from_past(
-        &fetcher,
-        service_token,
-        auth_header,
-        actor,
-        limit,
-        cursor,
-        None,
-        cache,
-    )
-    .await?;
+    let fetcher = BlueskyFetcher::new(appview.clone());
+    let (feed_items, next_cursor) =
+        logic::fetch_posts_from_past(&fetcher, service_token, actor, limit, cursor, None, cache)
+            .await?;
 
     Ok(FeedSkeletonResult {
         cursor: next_cursor,
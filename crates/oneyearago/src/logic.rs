@@ -1,6 +1,7 @@
 use crate::api::PostFetcher;
 use crate::cache::CacheStore;
 use anyhow::Result;
+use bsky_core::xrpc::XrpcError;
 use bsky_core::FeedItem;
 use chrono::Utc;
 
@@ -11,7 +12,6 @@ const DEFAULT_LIMIT: usize = 30;
 pub async fn fetch_posts_from_past<F: PostFetcher>(
     fetcher: &F,
     service_token: &str,
-    _user_token: &str,
     actor: &str,
     limit: usize,
     cursor: Option<String>,
@@ -63,11 +63,8 @@ pub async fn fetch_posts_from_past<F: PostFetcher>(
         {
             Ok(Some(cached)) => {
                 tracing::debug!("[cache] Feed hit for {} date={}", actor, date_key);
-                let feed_items: Vec<FeedItem> = cached
-                    .uris
-                    .into_iter()
-                    .map(|u| FeedItem { post: u })
-                    .collect();
+                let feed_items: Vec<FeedItem> =
+                    cached.uris.into_iter().map(FeedItem::new).collect();
                 return Ok((feed_items, cached.next));
             }
             Ok(None) => {
@@ -80,6 +77,8 @@ pub async fn fetch_posts_from_past<F: PostFetcher>(
     }
 
     let mut feed_items = Vec::new();
+    // 取得に失敗した年があれば、結果をキャッシュしない
+    let mut incomplete = false;
 
     // Cursor Parsing
     // Format: v1::{years_ago}::{api_cursor}
@@ -155,7 +154,7 @@ pub async fn fetch_posts_from_past<F: PostFetcher>(
         {
             Ok((posts, new_cursor)) => {
                 for p in posts {
-                    feed_items.push(FeedItem { post: p.uri });
+                    feed_items.push(FeedItem::new(p.uri));
                 }
                 current_api_cursor = new_cursor;
 
@@ -165,17 +164,22 @@ pub async fn fetch_posts_from_past<F: PostFetcher>(
                 }
                 // If cursor is Some, we loop again with same years_ago (and new cursor)
             }
+            // トークンの更新やレート制限の解除を待たないと、残りの年も同じく失敗する
+            Err(e) if e.is_auth_failure() || matches!(e, XrpcError::RateLimited { .. }) => {
+                return Err(e.into());
+            }
             Err(e) => {
                 tracing::error!("Failed to fetch posts for {} years ago: {}", years_ago, e);
                 // On error, skip to next year
                 years_ago += 1;
                 current_api_cursor = None;
+                incomplete = true;
             }
         }
     };
 
     // フィード結果をキャッシュに保存
-    if let Some(store) = cache {
+    if let Some(store) = cache.filter(|_| !incomplete) {
         // TTL: ユーザーの現地の「今日の終わり」まで
         let today_end_utc = {
             let tomorrow = today_naive.succ_opt().unwrap_or(today_naive);
@@ -224,9 +228,9 @@ mod tests {
                 until: &str,
                 limit: usize,
                 cursor: Option<String>,
-            ) -> Result<(Vec<PostView>, Option<String>)>;
+            ) -> Result<(Vec<PostView>, Option<String>), XrpcError>;
 
-            async fn determine_timezone(&self, handle: &str, token: &str) -> Result<chrono::FixedOffset>;
+            async fn determine_timezone(&self, handle: &str, token: &str) -> Result<chrono::FixedOffset, XrpcError>;
         }
     }
 
@@ -264,18 +268,10 @@ mod tests {
         // Loop checks limits. feed_items=30 >= limit 30. Break.
         // Return next cursor: v1::1::cursor_abc
 
-        let (items, cursor) = fetch_posts_from_past(
-            &mock,
-            "token",
-            "user_token",
-            "did:plc:test",
-            30,
-            None,
-            None,
-            None,
-        )
-        .await
-        .unwrap();
+        let (items, cursor) =
+            fetch_posts_from_past(&mock, "token", "did:plc:test", 30, None, None, None)
+                .await
+                .unwrap();
         assert_eq!(items.len(), 30);
         assert_eq!(cursor, Some("v1::1::cursor_abc".to_string()));
     }
@@ -347,18 +343,10 @@ mod tests {
         // Loop start. feed_items(30) >= 30. Break.
         // Resumption logic: current_api_cursor is None. Next cursor = v1::3::
 
-        let (items, cursor) = fetch_posts_from_past(
-            &mock,
-            "token",
-            "user_token",
-            "did:plc:test",
-            30,
-            None,
-            None,
-            None,
-        )
-        .await
-        .unwrap();
+        let (items, cursor) =
+            fetch_posts_from_past(&mock, "token", "did:plc:test", 30, None, None, None)
+                .await
+                .unwrap();
 
         assert_eq!(items.len(), 30);
         assert_eq!(items[0].post, "year1:0");
@@ -382,18 +370,10 @@ mod tests {
             .times(2)
             .returning(|_, _, _, _, _, _| Ok((vec![], None)));
 
-        let (items, cursor) = fetch_posts_from_past(
-            &mock,
-            "token",
-            "user_token",
-            "did:plc:test",
-            30,
-            None,
-            Some(now),
-            None,
-        )
-        .await
-        .unwrap();
+        let (items, cursor) =
+            fetch_posts_from_past(&mock, "token", "did:plc:test", 30, None, Some(now), None)
+                .await
+                .unwrap();
         assert_eq!(items.len(), 0);
         assert!(cursor.is_none());
     }
@@ -430,18 +410,10 @@ mod tests {
                 Ok((posts, Some("cursor_456".to_string())))
             });
 
-        let (items, next_cursor) = fetch_posts_from_past(
-            &mock,
-            "token",
-            "user_token",
-            "did:plc:test",
-            1,
-            input_cursor,
-            None,
-            None,
-        )
-        .await
-        .unwrap();
+        let (items, next_cursor) =
+            fetch_posts_from_past(&mock, "token", "did:plc:test", 1, input_cursor, None, None)
+                .await
+                .unwrap();
 
         assert_eq!(items.len(), 1);
         assert_eq!(items[0].post, "resumed:1");
@@ -479,18 +451,10 @@ mod tests {
                 Ok((posts, None))
             });
 
-        let (items, _) = fetch_posts_from_past(
-            &mock,
-            "token",
-            "user_token",
-            "did:plc:test",
-            1,
-            input_cursor,
-            None,
-            None,
-        )
-        .await
-        .unwrap();
+        let (items, _) =
+            fetch_posts_from_past(&mock, "token", "did:plc:test", 1, input_cursor, None, None)
+                .await
+                .unwrap();
 
         assert_eq!(items.len(), 1);
         assert_eq!(items[0].post, "year2:1");
@@ -534,33 +498,15 @@ mod tests {
         let cache = make_cache_store().await;
 
         // 1回目: APIを叩いてTZを取得・保存
-        fetch_posts_from_past(
-            &mock,
-            "token",
-            "auth",
-            "did:plc:test",
-            30,
-            None,
-            None,
-            Some(&cache),
-        )
-        .await
-        .unwrap();
+        fetch_posts_from_past(&mock, "token", "did:plc:test", 30, None, None, Some(&cache))
+            .await
+            .unwrap();
 
         // 2回目: TZキャッシュがヒットするので determine_timezone は呼ばれない
         // (times(1) の制約により、2回呼ばれるとパニック)
-        fetch_posts_from_past(
-            &mock,
-            "token",
-            "auth",
-            "did:plc:test",
-            30,
-            None,
-            None,
-            Some(&cache),
-        )
-        .await
-        .unwrap();
+        fetch_posts_from_past(&mock, "token", "did:plc:test", 30, None, None, Some(&cache))
+            .await
+            .unwrap();
     }
 
     // 統合テスト2:
@@ -601,7 +547,6 @@ mod tests {
         let (items1, _) = fetch_posts_from_past(
             &mock,
             "token",
-            "auth",
             "did:plc:test",
             1,
             None,
@@ -615,7 +560,6 @@ mod tests {
         let (items2, _) = fetch_posts_from_past(
             &mock,
             "token",
-            "auth",
             "did:plc:test",
             1,
             None,
@@ -668,7 +612,6 @@ mod tests {
         fetch_posts_from_past(
             &mock,
             "token",
-            "auth",
             "did:plc:test",
             1,
             None,
@@ -684,7 +627,6 @@ mod tests {
         let (items, _) = fetch_posts_from_past(
             &mock,
             "token",
-            "auth",
             "did:plc:test",
             1,
             None,
@@ -739,7 +681,6 @@ mod tests {
         let (items_p1, _) = fetch_posts_from_past(
             &mock,
             "token",
-            "auth",
             "did:plc:test",
             1,
             None,
@@ -753,7 +694,6 @@ mod tests {
         let (items_p2, _) = fetch_posts_from_past(
             &mock,
             "token",
-            "auth",
             "did:plc:test",
             1,
             Some("v1::1::some_cursor".to_string()),
@@ -788,18 +728,9 @@ mod tests {
         let cache = make_cache_store().await;
 
         // 1回目: キャッシュなし → API から JST を取得
-        fetch_posts_from_past(
-            &mock,
-            "token",
-            "auth",
-            "did:plc:jst",
-            30,
-            None,
-            None,
-            Some(&cache),
-        )
-        .await
-        .unwrap();
+        fetch_posts_from_past(&mock, "token", "did:plc:jst", 30, None, None, Some(&cache))
+            .await
+            .unwrap();
 
         // キャッシュに保存されているか直接確認
         let tz = cache.get_timezone("did:plc:jst").await.unwrap();
@@ -812,18 +743,9 @@ mod tests {
 
         // 2回目: TZキャッシュヒット → determine_timezone は呼ばれない
         // (times(1) の制約で、2回呼ばれるとパニック)
-        fetch_posts_from_past(
-            &mock,
-            "token",
-            "auth",
-            "did:plc:jst",
-            30,
-            None,
-            None,
-            Some(&cache),
-        )
-        .await
-        .unwrap();
+        fetch_posts_from_past(&mock, "token", "did:plc:jst", 30, None, None, Some(&cache))
+            .await
+            .unwrap();
     }
 
     // 統合テスト6:
@@ -868,7 +790,6 @@ mod tests {
         fetch_posts_from_past(
             &mock,
             "token",
-            "auth",
             "did:plc:user:jst",
             1,
             None,
@@ -883,7 +804,6 @@ mod tests {
         let (items, _) = fetch_posts_from_past(
             &mock,
             "token",
-            "auth",
             "did:plc:user:pst", // 名前を変えて TZ 再取得を誘発
             1,
             None,
@@ -1,8 +1,7 @@
-#[allow(unused_imports)]
-use anyhow::{Context, Result};
+use bsky_core::appview::AppViewClient;
+use bsky_core::xrpc::XrpcError;
 use chrono::FixedOffset;
 use regex::Regex;
-use reqwest::Client;
 use serde::Deserialize;
 
 #[derive(Deserialize)]
@@ -73,35 +72,32 @@ fn parse_timezone_description(description: &str) -> Option<FixedOffset> {
 }
 
 /// タイムゾーンを決定する
-pub async fn determine_timezone(client: &Client, handle: &str, token: &str) -> Result<FixedOffset> {
-    let url = "https://api.bsky.app/xrpc/app.bsky.actor.getProfile";
-
-    let auth_val = if token.starts_with("Bearer ") {
-        token.to_string()
-    } else {
-        format!("Bearer {}", token)
-    };
-
-    let res = client
-        .get(url)
-        .header("Authorization", auth_val)
-        .query(&[("actor", handle)])
-        .send()
+///
+/// プロフィールが見つからない・読めないときは UTC にする。
+/// トークンの期限切れやレート制限、上流の障害はそのまま返す（一時的な失敗で UTC をキャッシュしないように）
+pub async fn determine_timezone(
+    appview: &AppViewClient,
+    handle: &str,
+    token: &str,
+) -> Result<FixedOffset, XrpcError> {
+    let utc = FixedOffset::east_opt(0).unwrap();
+    let profile: ProfileResponse = match appview
+        .query(
+            "app.bsky.actor.getProfile",
+            Some(token),
+            &[("actor", handle)],
+        )
         .await
-        .context("Failed to get profile")?;
-
-    if !res.status().is_success() {
-        if res.status() == reqwest::StatusCode::UNAUTHORIZED {
-            return Err(anyhow::anyhow!("Unauthorized"));
+    {
+        Ok(profile) => profile,
+        Err(XrpcError::NotFound { .. } | XrpcError::Request { .. } | XrpcError::Decode(_)) => {
+            return Ok(utc);
         }
-        // プロフィール取得失敗時はデフォルトUTC
-        return Ok(FixedOffset::east_opt(0).unwrap());
-    }
-
-    let profile: ProfileResponse = res.json().await.context("Failed to parse profile")?;
+        Err(e) => return Err(e),
+    };
     let description = profile.description.unwrap_or_default();
 
-    Ok(parse_timezone_description(&description).unwrap_or(FixedOffset::east_opt(0).unwrap()))
+    Ok(parse_timezone_description(&description).unwrap_or(utc))
 }
 
 #[cfg(test)]
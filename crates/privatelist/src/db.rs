@@ -90,6 +90,15 @@ pub async fn cache_post(
     Ok(())
 }
 
+/// 指定したアカウントのキャッシュ済み投稿をすべて取り除き、取り除いた件数を返す
+pub async fn purge_author_posts(pool: &SqlitePool, author_did: &str) -> Result<u64, Error> {
+    let result = sqlx::query("DELETE FROM private_list_post_cache WHERE author_did = ?")
+        .bind(author_did)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
 pub struct CachedPost {
     pub uri: String,
     pub cid: String,
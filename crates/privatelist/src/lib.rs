@@ -4,9 +4,9 @@ pub mod oauth;
 pub mod structs;
 
 use anyhow::{Context, Result};
+use bsky_core::appview::AppViewClient;
 use bsky_core::{FeedItem, FeedSkeletonResult};
 use chrono::DateTime;
-use reqwest::Client;
 use sqlx::SqlitePool;
 
 pub use db::{
@@ -14,10 +14,16 @@ pub use db::{
     remove_user, update_session,
 };
 
+/// リストが空のときに固定表示する "How to use Private List Feed" の投稿
+pub const HOWTO_POST_URI: &str =
+    "at://did:plc:tsvcmd72oxp47wtixs4qllyi/app.bsky.feed.post/3letuz6sqa22o";
+
+/// 使い方の投稿に付ける feedContext
+pub const HOWTO_CONTEXT: &str = "howto";
+
 pub async fn refresh_list(
     pool: &SqlitePool,
-    client: &Client,
-    base_url: &str,
+    appview: &AppViewClient,
     user_did: &str,
     service_token: &str,
 ) -> Result<()> {
@@ -29,7 +35,7 @@ pub async fn refresh_list(
         // We use "from:DID" query to get posts from specific user.
         // This is much more reliable than "OR" query in search API.
         let query = format!("from:{}", target_did);
-        let posts = api::search_posts(client, base_url, &query, service_token)
+        let posts = api::search_posts(appview, &query, service_token)
             .await
             .context(format!("Failed to search posts for {}", target_did))?;
 
@@ -50,9 +56,7 @@ pub async fn refresh_list(
 
 pub async fn get_feed_skeleton(
     pool: &SqlitePool,
-    _client: &Client, // Client is not used for reading from DB
     user_did: &str,
-    _service_token: &str, // Token is not used for reading from DB
     cursor: Option<String>,
     limit: usize,
 ) -> Result<FeedSkeletonResult> {
@@ -64,11 +68,11 @@ pub async fn get_feed_skeleton(
         // Return a pinned post explaining how to use the feed
         return Ok(FeedSkeletonResult {
             cursor: None,
-            feed: vec![FeedItem {
-                // "How to use Private List Feed" post (placeholder)
-                post: "at://did:plc:tsvcmd72oxp47wtixs4qllyi/app.bsky.feed.post/3letuz6sqa22o"
-                    .to_string(),
-            }],
+            feed: vec![
+                FeedItem::new(HOWTO_POST_URI)
+                    .pinned()
+                    .with_context(HOWTO_CONTEXT),
+            ],
         });
     }
 
@@ -84,7 +88,7 @@ pub async fn get_feed_skeleton(
     }
 
     for post in posts {
-        feed.push(FeedItem { post: post.uri });
+        feed.push(FeedItem::new(post.uri));
     }
 
     Ok(FeedSkeletonResult {
@@ -2,8 +2,9 @@ pub mod image_analyzer;
 
 use anyhow::{Context, Result};
 use atrium_api::record::KnownRecord;
+use bsky_core::FeedChange;
 use image_analyzer::{is_blue_sky_image, BlueDetectionConfig};
-use jetstream_oxide::events::commit::CommitEvent;
+use jetstream_oxide::events::commit::{CommitEvent, CommitType};
 use regex::Regex;
 use serde::Serialize;
 use sqlx::SqlitePool;
@@ -77,19 +78,6 @@ pub async fn migrate(pool: &SqlitePool) -> Result<()> {
     .await
     .context("Failed to create real index")?;
 
-    // Jetstream カーソル永続化テーブル（常に1行のみ）
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS jetstream_cursor (
-            id        INTEGER PRIMARY KEY CHECK (id = 1),
-            cursor_us INTEGER NOT NULL
-        );
-        "#,
-    )
-    .execute(pool)
-    .await
-    .context("Failed to create jetstream_cursor table")?;
-
     // マイグレーション: 古い秒単位のデータ（10桁/11桁: < 10000000000）を新しいマイクロ秒単位（16桁）に変換する
     sqlx::query(
         r#"
@@ -105,99 +93,176 @@ pub async fn migrate(pool: &SqlitePool) -> Result<()> {
     Ok(())
 }
 
+/// 投稿を保存するテーブル
+const POST_TABLES: [&str; 2] = ["fake_bluesky_posts", "real_bluesky_posts"];
+
+/// テーブルに対応するフィード名
+fn feed_name(table_name: &str) -> &'static str {
+    match table_name {
+        "fake_bluesky_posts" => "fakebluesky",
+        _ => "realbluesky",
+    }
+}
+
 /// Process Jetstream event
-pub async fn process_event(pool: &SqlitePool, event: &CommitEvent) {
-    // Only process Create events
-    if let CommitEvent::Create { info, commit } = event {
-        // Only process posts
-        if commit.info.collection.as_str() != "app.bsky.feed.post" {
-            return;
-        }
+///
+/// 作成された投稿は判定して保存し、編集された投稿は判定し直す（対象外になったら取り除く）。
+/// 削除された投稿はどちらのテーブルからも取り除く
+///
+/// 保存・削除したフィード名を返す（取り込みのメトリクスとキャッシュの破棄用）
+pub async fn process_event(pool: &SqlitePool, event: &CommitEvent) -> FeedChange {
+    match event {
+        CommitEvent::Create { info, commit } | CommitEvent::Update { info, commit } => {
+            // Only process posts
+            if commit.info.collection.as_str() != "app.bsky.feed.post" {
+                return FeedChange::default();
+            }
 
-        // Extract post record
-        let post = match &commit.record {
-            KnownRecord::AppBskyFeedPost(post) => post,
-            _ => return,
-        };
+            // Extract post record
+            let post = match &commit.record {
+                KnownRecord::AppBskyFeedPost(post) => post,
+                _ => return FeedChange::default(),
+            };
+
+            // Extract post data
+            let did = info.did.as_str();
+            let rkey = commit.info.rkey.as_str();
+            let collection = commit.info.collection.as_str();
+            let uri = format!("at://{}/{}/{}", did, collection, rkey);
+            let cid = commit.cid.as_ref().to_string();
+
+            let classified = classify_post(post, did, &uri).await;
+
+            // CommitEvent は untagged なので、編集も Create として届く。操作の種類は operation で見分ける
+            let mut change = FeedChange::default();
+            if matches!(commit.info.operation, CommitType::Update) {
+                let keep = classified.as_ref().map(|(table_name, _)| *table_name);
+                change.removed = remove_post(pool, &uri, keep).await;
+            }
 
-        // Filter by text content
-        // 1. Remove all whitespace
-        // 2. Must start with "bluesky" (case-insensitive)
-        // 3. Can be followed only by punctuation and emojis
+            let Some((table_name, t_image)) = classified else {
+                return change;
+            };
+
+            // Store in database
+            let indexed_at = post.created_at.as_ref().timestamp_micros();
+            // 計測: DB書き込み（ディスクI/O）の所要時間
+            let t_db_start = std::time::Instant::now();
+            let query = format!(
+                r#"
+                INSERT OR REPLACE INTO {} (uri, cid, indexed_at)
+                VALUES (?, ?, ?)
+                "#,
+                table_name
+            );
+            match sqlx::query(&query)
+                .bind(&uri)
+                .bind(&cid)
+                .bind(indexed_at)
+                .execute(pool)
+                .await
+            {
+                Ok(_) => {
+                    let t_db = t_db_start.elapsed();
+                    tracing::info!(
+                        "MATCH [{}]: t_image={:.1}ms, t_db={:.1}ms, uri={}",
+                        table_name.split('_').next().unwrap_or("unknown"),
+                        t_image.as_secs_f64() * 1000.0,
+                        t_db.as_secs_f64() * 1000.0,
+                        uri
+                    );
+                    change.added = Some(feed_name(table_name));
+                }
+                Err(e) => {
+                    tracing::error!("Failed to store post in {}: {}", table_name, e);
+                }
+            }
+            change
+        }
+        CommitEvent::Delete { info, commit } => {
+            let collection = commit.collection.as_str();
+            if collection != "app.bsky.feed.post" {
+                return FeedChange::default();
+            }
+            let uri = format!("at://{}/{}/{}", info.did.as_str(), collection, commit.rkey);
+            FeedChange {
+                added: None,
+                removed: remove_post(pool, &uri, None).await,
+            }
+        }
+    }
+}
 
-        // Remove all whitespace
-        let cleaned_text: String = post.text.chars().filter(|c| !c.is_whitespace()).collect();
+/// 投稿を判定し、保存先のテーブルと画像解析にかかった時間を返す。対象外なら None
+async fn classify_post(
+    post: &atrium_api::app::bsky::feed::post::Record,
+    did: &str,
+    uri: &str,
+) -> Option<(&'static str, std::time::Duration)> {
+    // Filter by text content
+    // 1. Remove all whitespace
+    // 2. Must start with "bluesky" (case-insensitive)
+    // 3. Can be followed only by punctuation and emojis
+
+    // Remove all whitespace
+    let cleaned_text: String = post.text.chars().filter(|c| !c.is_whitespace()).collect();
+
+    // Regex: (?i)^bluesky[\p{P}\p{S}]*$
+    static BLUESKY_REGEX: OnceLock<Regex> = OnceLock::new();
+    let regex = BLUESKY_REGEX.get_or_init(|| Regex::new(r"(?i)^bluesky[\p{P}\p{S}]*$").unwrap());
+
+    if !regex.is_match(&cleaned_text) {
+        return None;
+    }
 
-        // Regex: (?i)^bluesky[\p{P}\p{S}]*$
-        static BLUESKY_REGEX: OnceLock<Regex> = OnceLock::new();
-        let regex =
-            BLUESKY_REGEX.get_or_init(|| Regex::new(r"(?i)^bluesky[\p{P}\p{S}]*$").unwrap());
+    // If no images, skip
+    let image_urls = match extract_image_urls(post, did) {
+        Some(urls) if !urls.is_empty() => urls,
+        _ => return None,
+    };
 
-        if !regex.is_match(&cleaned_text) {
-            return;
+    // Check if post has blue sky images
+    // 計測: 画像解析（HTTP通信）の所要時間
+    let t_image_start = std::time::Instant::now();
+    let sky_status = evaluate_sky_status(&image_urls).await;
+    let t_image = t_image_start.elapsed();
+
+    match sky_status {
+        SkyStatus::AllFake => Some(("fake_bluesky_posts", t_image)),
+        SkyStatus::AllBlue => Some(("real_bluesky_posts", t_image)),
+        SkyStatus::Mixed => {
+            tracing::debug!("Excluding post with mixed images: {}", uri);
+            None
         }
+    }
+}
 
-        // Extract post data
-        let did = info.did.as_str();
-        let rkey = commit.info.rkey.as_str();
-        let collection = commit.info.collection.as_str();
-        let uri = format!("at://{}/{}/{}", did, collection, rkey);
-        let cid = commit.cid.as_ref().to_string();
-
-        // If no images, skip
-        let image_urls = match extract_image_urls(post, did) {
-            Some(urls) if !urls.is_empty() => urls,
-            _ => return,
-        };
-
-        // Check if post has blue sky images
-        // 計測: 画像解析（HTTP通信）の所要時間
-        let t_image_start = std::time::Instant::now();
-        let sky_status = evaluate_sky_status(&image_urls).await;
-        let t_image = t_image_start.elapsed();
-
-        let table_name = match sky_status {
-            SkyStatus::AllFake => "fake_bluesky_posts",
-            SkyStatus::AllBlue => "real_bluesky_posts",
-            SkyStatus::Mixed => {
-                tracing::debug!("Excluding post with mixed images: {}", uri);
-                return;
+/// `keep` 以外のテーブルから投稿を取り除き、取り除いたフィード名を返す
+async fn remove_post(pool: &SqlitePool, uri: &str, keep: Option<&str>) -> Vec<&'static str> {
+    let mut removed = Vec::new();
+    for table_name in POST_TABLES.iter().filter(|t| Some(**t) != keep) {
+        let query = format!("DELETE FROM {} WHERE uri = ?", table_name);
+        match sqlx::query(&query).bind(uri).execute(pool).await {
+            Ok(result) if result.rows_affected() > 0 => {
+                tracing::info!("Removed post from {}: {}", table_name, uri);
+                removed.push(feed_name(table_name));
             }
-        };
+            Ok(_) => {}
+            Err(e) => tracing::error!("Failed to remove post from {}: {}", table_name, e),
+        }
+    }
+    removed
+}
 
-        // Store in database
-        let indexed_at = post.created_at.as_ref().timestamp_micros();
-        // 計測: DB書き込み（ディスクI/O）の所要時間
-        let t_db_start = std::time::Instant::now();
-        let query = format!(
-            r#"
-            INSERT OR REPLACE INTO {} (uri, cid, indexed_at)
-            VALUES (?, ?, ?)
-            "#,
-            table_name
-        );
-        match sqlx::query(&query)
-            .bind(&uri)
-            .bind(&cid)
-            .bind(indexed_at)
-            .execute(pool)
+/// 指定したアカウントの投稿を両方のテーブルから取り除き、取り除いた件数を返す
+pub async fn purge_author(pool: &SqlitePool, did: &str) -> Result<u64> {
+    let mut purged = 0;
+    for table_name in POST_TABLES {
+        purged += bsky_core::delete_posts_by_author(pool, table_name, did)
             .await
-        {
-            Ok(_) => {
-                let t_db = t_db_start.elapsed();
-                tracing::info!(
-                    "MATCH [{}]: t_image={:.1}ms, t_db={:.1}ms, uri={}",
-                    table_name.split('_').next().unwrap_or("unknown"),
-                    t_image.as_secs_f64() * 1000.0,
-                    t_db.as_secs_f64() * 1000.0,
-                    uri
-                );
-            }
-            Err(e) => {
-                tracing::error!("Failed to store post in {}: {}", table_name, e);
-            }
-        }
+            .with_context(|| format!("Failed to purge posts from {}", table_name))?;
     }
+    Ok(purged)
 }
 
 /// Get fake feed skeleton
@@ -506,6 +571,142 @@ mod tests {
         assert_eq!(result.feed[0].post, "at://did:example:1/foo/real1");
     }
 
+    /// 記録した Jetstream のイベントを再生し、画像を取得せずに判定できる投稿が保存されないか検証
+    /// （本文がマッチしない・画像がない投稿は、画像の解析に進む前に除外される）
+    #[tokio::test]
+    async fn test_process_event_replay_skips_without_fetching() {
+        use super::*;
+        use sqlx::sqlite::SqlitePoolOptions;
+
+        let pool = SqlitePoolOptions::new()
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        migrate(&pool).await.unwrap();
+
+        let fixture = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../tests/fixtures/jetstream/posts.ndjson"
+        );
+        let replayed =
+            jetstream::replay::replay_file(fixture, jetstream::ReplaySpeed::Unthrottled, |event| {
+                let pool = pool.clone();
+                async move {
+                    if let jetstream::ReplayEvent::Commit(event) = event {
+                        process_event(&pool, &event).await;
+                    }
+                }
+            })
+            .await
+            .unwrap();
+        assert_eq!(replayed, 11);
+
+        for table in ["fake_bluesky_posts", "real_bluesky_posts"] {
+            let count: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table))
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            assert_eq!(count, 0, "{} should be empty", table);
+        }
+    }
+
+    /// 削除された投稿と、対象外になるよう編集された投稿がテーブルから取り除かれ、そのフィードを返すか検証
+    #[tokio::test]
+    async fn test_process_event_delete_and_update() {
+        use super::*;
+        use sqlx::sqlite::SqlitePoolOptions;
+
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        migrate(&pool).await.unwrap();
+
+        let deleted = "at://did:plc:alice/app.bsky.feed.post/3l3qo2vuowo2b";
+        let edited = "at://did:plc:alice/app.bsky.feed.post/3l3qo2w8mxc2c";
+        let untouched = "at://did:plc:bob/app.bsky.feed.post/3l3qo2w8mxc2c";
+        for (table, uri) in [
+            ("fake_bluesky_posts", deleted),
+            ("real_bluesky_posts", edited),
+            ("real_bluesky_posts", untouched),
+        ] {
+            sqlx::query(&format!(
+                "INSERT INTO {} (uri, cid, indexed_at) VALUES (?, 'cid', 1700000000000000)",
+                table
+            ))
+            .bind(uri)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let delete: CommitEvent = serde_json::from_value(serde_json::json!({
+            "did": "did:plc:alice",
+            "time_us": 1725911164021778u64,
+            "kind": "commit",
+            "commit": {
+                "rev": "3l3qo2xnrye2a",
+                "operation": "delete",
+                "collection": "app.bsky.feed.post",
+                "rkey": "3l3qo2vuowo2b"
+            }
+        }))
+        .unwrap();
+        // 画像付きのまま本文だけ対象外に編集された（本文で弾かれるので画像は取得しない）
+        let update: CommitEvent = serde_json::from_value(serde_json::json!({
+            "did": "did:plc:alice",
+            "time_us": 1725911164102345u64,
+            "kind": "commit",
+            "commit": {
+                "rev": "3l3qo2xr3ab2c",
+                "operation": "update",
+                "collection": "app.bsky.feed.post",
+                "rkey": "3l3qo2w8mxc2c",
+                "record": {
+                    "$type": "app.bsky.feed.post",
+                    "createdAt": "2024-09-09T19:46:02.401Z",
+                    "embed": {
+                        "$type": "app.bsky.embed.images",
+                        "images": [{
+                            "alt": "",
+                            "image": {
+                                "$type": "blob",
+                                "ref": {"$link": "bafyreidfayvfuwqa7qlnopdjiqrxzs6blmoeu4rujcjtnci5beludirz2a"},
+                                "mimeType": "image/jpeg",
+                                "size": 412330
+                            }
+                        }]
+                    },
+                    "text": "bluesky is nice today"
+                },
+                "cid": "bafyreidfayvfuwqa7qlnopdjiqrxzs6blmoeu4rujcjtnci5beludirz2a"
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(
+            process_event(&pool, &delete).await,
+            FeedChange::removed("fakebluesky")
+        );
+        assert_eq!(
+            process_event(&pool, &update).await,
+            FeedChange::removed("realbluesky")
+        );
+        // 索引していない投稿の削除では、どのフィードも変わらない
+        assert_eq!(process_event(&pool, &delete).await, FeedChange::default());
+
+        let mut remaining = Vec::new();
+        for table in POST_TABLES {
+            let uris: Vec<String> = sqlx::query_scalar(&format!("SELECT uri FROM {}", table))
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+            remaining.extend(uris);
+        }
+        assert_eq!(remaining, vec![untouched]);
+    }
+
     #[test]
     fn test_determine_sky_status() {
         use super::*;
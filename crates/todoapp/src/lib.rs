@@ -3,23 +3,18 @@ pub mod logic;
 pub mod structs;
 
 use anyhow::{Context, Result};
+use bsky_core::appview::AppViewClient;
 use bsky_core::FeedSkeletonResult;
-use reqwest::Client;
-
-pub use api::authenticate;
 
 pub async fn get_feed_skeleton(
-    client: &Client,
-    user_jwt: &str,
+    appview: &AppViewClient,
+    did: &str,
     service_token: &str,
 ) -> Result<FeedSkeletonResult> {
-    let did = bsky_core::extract_did_from_jwt(Some(user_jwt))
-        .context("Failed to extract DID from auth")?;
-
     // TODOとDONEを並列で取得して、後で紐づける
     let (todos_res, dones_res) = tokio::join!(
-        api::search_posts(client, "TODO", &did, service_token),
-        api::search_posts(client, "DONE", &did, service_token)
+        api::search_posts(appview, "TODO", did, service_token),
+        api::search_posts(appview, "DONE", did, service_token)
     );
 
     let todos = todos_res.context("Failed to fetch TODOs")?;
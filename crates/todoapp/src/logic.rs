@@ -36,7 +36,7 @@ pub fn filter_todos(todos: Vec<PostView>, dones: Vec<PostView>) -> Vec<FeedItem>
 
         if let Ok(record) = serde_json::from_value::<Record>(post.record.clone()) {
             if record.reply.is_none() {
-                feed_items.push(FeedItem { post: post.uri });
+                feed_items.push(FeedItem::new(post.uri));
             }
         }
     }
@@ -27,10 +27,3 @@ pub struct ReplyRef {
 pub struct Link {
     pub uri: String,
 }
-
-#[derive(Deserialize, Debug)]
-pub struct SessionResponse {
-    #[serde(rename = "accessJwt")]
-    pub access_jwt: String,
-    pub did: String,
-}
@@ -1,105 +1,311 @@
-use reqwest::Client;
+//! フィードの利用状況の記録
+//!
+//! リクエストごとのイベントを有界キューに積み、ワーカーがまとめて `AnalyticsSink` に書き出す。
+//! 書き出しが追いつかずキューが満杯になったら、リクエストを待たせずにイベントを捨てる
+//!
+//! DID はプロセス内では生のまま扱い、`PrivateSink` を通して仮名化してから書き出す
+
+pub mod privacy;
+pub mod sqlite;
+pub mod umami;
+
+pub use privacy::{OptOut, PrivateSink, Pseudonymizer};
+pub use sqlite::{FeedUsage, SqliteSink};
+pub use umami::UmamiSink;
+
+use axum::async_trait;
+use bsky_core::metrics::Counter;
 use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
 
-#[derive(Clone, Debug)]
-pub struct UmamiClient {
-    client: Client,
-    host: String,
-    website_id: String,
-    hostname: Option<String>,
+/// getFeedSkeleton 1 回分
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FeedRequestEvent {
+    /// フィードの rkey
+    pub feed: String,
+    /// 未認証なら "anonymous"
+    pub did: String,
+    pub language: String,
+    /// 2 ページ目以降の取得か
+    pub has_cursor: bool,
+    /// マイクロ秒
+    pub time_us: i64,
 }
 
-#[derive(Serialize)]
-struct EventPayload {
-    #[serde(rename = "type")]
-    event_type: String,
-    payload: EventData,
+#[async_trait]
+pub trait AnalyticsSink: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// 失敗してもリトライはしない（ログを出して捨てる）
+    async fn write_batch(&self, events: &[FeedRequestEvent]) -> anyhow::Result<()>;
 }
 
-#[derive(Serialize)]
-struct EventData {
-    website: String,
-    hostname: Option<String>,
-    url: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    name: Option<String>,
-    language: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    id: Option<String>,
-    data: Option<serde_json::Value>,
+/// 何もしない
+pub struct NoopSink;
+
+#[async_trait]
+impl AnalyticsSink for NoopSink {
+    fn name(&self) -> &'static str {
+        "none"
+    }
+
+    async fn write_batch(&self, _events: &[FeedRequestEvent]) -> anyhow::Result<()> {
+        Ok(())
+    }
 }
 
-impl UmamiClient {
-    pub fn new(mut host: String, website_id: String, hostname: Option<String>) -> Self {
-        if !host.starts_with("http://") && !host.starts_with("https://") {
-            host = format!("https://{}", host);
+/// 標準出力に 1 行 1 イベントの JSON で書き出す
+pub struct StdoutSink;
+
+#[async_trait]
+impl AnalyticsSink for StdoutSink {
+    fn name(&self) -> &'static str {
+        "stdout"
+    }
+
+    async fn write_batch(&self, events: &[FeedRequestEvent]) -> anyhow::Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let mut lines = Vec::new();
+        for event in events {
+            serde_json::to_writer(&mut lines, event)?;
+            lines.push(b'\n');
         }
-        // Remove trailing slash if present
-        if host.ends_with('/') {
-            host.pop();
+        let mut stdout = tokio::io::stdout();
+        stdout.write_all(&lines).await?;
+        stdout.flush().await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BatchOptions {
+    pub queue_capacity: usize,
+    pub max_batch_size: usize,
+    /// バッチが埋まらなくても、この間隔で書き出す
+    pub flush_interval: Duration,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            queue_capacity: 1024,
+            max_batch_size: 100,
+            flush_interval: Duration::from_secs(5),
         }
+    }
+}
 
+/// イベントを受け付けるハンドル。clone しても同じワーカーに積まれる
+#[derive(Clone)]
+pub struct Analytics {
+    sender: mpsc::Sender<FeedRequestEvent>,
+    dropped: Counter,
+}
+
+impl Analytics {
+    /// `sink` に書き出すワーカーを起動する
+    pub fn start(sink: Arc<dyn AnalyticsSink>, options: BatchOptions) -> Self {
+        let (sender, receiver) = mpsc::channel(options.queue_capacity.max(1));
+        tracing::info!("Analytics sink: {}", sink.name());
+        tokio::spawn(run_worker(sink, receiver, options));
         Self {
-            client: Client::new(),
-            host,
-            website_id,
-            hostname,
+            sender,
+            dropped: Counter::default(),
+        }
+    }
+
+    /// 捨てたイベントを `/metrics` にも出すためのカウンター
+    pub fn with_drop_counter(mut self, counter: Counter) -> Self {
+        self.dropped = counter;
+        self
+    }
+
+    /// 記録しない
+    pub fn disabled() -> Self {
+        Self::start(Arc::new(NoopSink), BatchOptions::default())
+    }
+
+    /// キューに積む。満杯なら捨てる（待たない）
+    pub fn record(&self, event: FeedRequestEvent) {
+        if self.sender.try_send(event).is_err() {
+            self.dropped.inc(&[]);
+            let dropped = self.dropped();
+            // ログが溢れないよう、100 件ごとにだけ出す
+            if dropped % 100 == 1 {
+                tracing::warn!("Analytics queue is full, dropped {} events so far", dropped);
+            }
         }
     }
 
-    pub fn send_event(
-        &self,
-        url: String,
-        event_name: Option<String>,
-        session_id: Option<String>,
-        language: Option<String>,
-        data: Option<serde_json::Value>,
-    ) {
-        let client = self.client.clone();
-        let host = self.host.clone();
-        let payload = EventPayload {
-            event_type: "event".to_string(),
-            payload: EventData {
-                website: self.website_id.clone(),
-                hostname: self.hostname.clone(),
-                url,
-                name: event_name,
-                language,
-                id: session_id,
-                data,
+    /// キューが満杯で捨てたイベントの数
+    pub fn dropped(&self) -> u64 {
+        self.dropped.get(&[])
+    }
+}
+
+async fn run_worker(
+    sink: Arc<dyn AnalyticsSink>,
+    mut receiver: mpsc::Receiver<FeedRequestEvent>,
+    options: BatchOptions,
+) {
+    let max_batch_size = options.max_batch_size.max(1);
+    let mut batch = Vec::with_capacity(max_batch_size);
+    // 起動直後の 1 回目は待たずに来てしまうので、1 間隔後から始める
+    let mut ticker = tokio::time::interval_at(
+        tokio::time::Instant::now() + options.flush_interval,
+        options.flush_interval,
+    );
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        // バッチが埋まるか、フラッシュ間隔が来たら書き出す
+        let (flush, closed) = tokio::select! {
+            event = receiver.recv() => match event {
+                Some(event) => {
+                    batch.push(event);
+                    (batch.len() >= max_batch_size, false)
+                }
+                None => (true, true),
             },
+            _ = ticker.tick() => (true, false),
         };
 
-        tokio::spawn(async move {
-            let endpoint = format!("{}/api/send", host);
-            match client
-                .post(&endpoint)
-                .json(&payload)
-                // Umami に弾かれないようにするためにUser-Agentを偽装する
-                .header(
-                    "User-Agent",
-                    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36"
-                )
-                .send()
-                .await
-            {
-                Ok(response) => {
-                    if !response.status().is_success() {
-                        let status = response.status();
-                        let text = response.text().await.unwrap_or_default();
-                        tracing::warn!(
-                            "Umami returned error: status={}, body={}",
-                            status,
-                            text
-                        );
-                    } else {
-                        tracing::debug!("Analytics event sent successfully");
-                    }
-                }
-                Err(e) => {
-                    tracing::warn!("Failed to send analytics event: {}", e);
-                }
+        if flush && !batch.is_empty() {
+            if let Err(e) = sink.write_batch(&batch).await {
+                tracing::warn!(
+                    "Failed to write {} analytics events to {}: {:#}",
+                    batch.len(),
+                    sink.name(),
+                    e
+                );
+            }
+            batch.clear();
+        }
+
+        if closed {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// 受け取ったバッチを覚えておく。`gate` の許可が出るまで書き出しを止め、書き出しに入ったら `entered` で知らせる
+    struct RecordingSink {
+        batches: Mutex<Vec<Vec<FeedRequestEvent>>>,
+        gate: tokio::sync::Semaphore,
+        entered: tokio::sync::Notify,
+    }
+
+    impl Default for RecordingSink {
+        fn default() -> Self {
+            Self {
+                batches: Mutex::default(),
+                gate: tokio::sync::Semaphore::new(0),
+                entered: tokio::sync::Notify::new(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl AnalyticsSink for RecordingSink {
+        fn name(&self) -> &'static str {
+            "recording"
+        }
+
+        async fn write_batch(&self, events: &[FeedRequestEvent]) -> anyhow::Result<()> {
+            self.entered.notify_one();
+            self.gate.acquire().await?.forget();
+            self.batches.lock().unwrap().push(events.to_vec());
+            Ok(())
+        }
+    }
+
+    fn event(feed: &str) -> FeedRequestEvent {
+        FeedRequestEvent {
+            feed: feed.to_string(),
+            did: "did:plc:alice".to_string(),
+            language: "ja".to_string(),
+            has_cursor: false,
+            time_us: 0,
+        }
+    }
+
+    /// バッチの上限に達したら、フラッシュ間隔を待たずにまとめて書き出すか
+    #[tokio::test]
+    async fn test_batches_events() {
+        let sink = Arc::new(RecordingSink::default());
+        sink.gate.add_permits(10);
+        let analytics = Analytics::start(
+            sink.clone(),
+            BatchOptions {
+                queue_capacity: 16,
+                max_batch_size: 3,
+                flush_interval: Duration::from_secs(3600),
+            },
+        );
+
+        for feed in ["a", "b", "c", "d"] {
+            analytics.record(event(feed));
+        }
+
+        for _ in 0..100 {
+            if !sink.batches.lock().unwrap().is_empty() {
+                break;
             }
-        });
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let batches = sink.batches.lock().unwrap().clone();
+        assert_eq!(batches.len(), 1);
+        let feeds: Vec<&str> = batches[0].iter().map(|e| e.feed.as_str()).collect();
+        assert_eq!(feeds, vec!["a", "b", "c"]);
+    }
+
+    /// 書き出しが詰まってキューが満杯になったら、待たずに捨てて数えるか
+    #[tokio::test]
+    async fn test_drops_on_overload() {
+        let sink = Arc::new(RecordingSink::default());
+        let dropped = Counter::default();
+        let analytics = Analytics::start(
+            sink.clone(),
+            BatchOptions {
+                queue_capacity: 2,
+                max_batch_size: 1,
+                flush_interval: Duration::from_secs(3600),
+            },
+        )
+        .with_drop_counter(dropped.clone());
+
+        // 1 件目はワーカーが取り出して書き出し待ち、続く 2 件でキューが埋まる
+        analytics.record(event("a"));
+        sink.entered.notified().await;
+        for feed in ["b", "c", "d", "e"] {
+            analytics.record(event(feed));
+        }
+        assert_eq!(analytics.dropped(), 2);
+        assert_eq!(dropped.get(&[]), 2);
+
+        sink.gate.add_permits(3);
+        for _ in 0..100 {
+            if sink.batches.lock().unwrap().len() == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let feeds: Vec<String> = sink
+            .batches
+            .lock()
+            .unwrap()
+            .iter()
+            .flatten()
+            .map(|e| e.feed.clone())
+            .collect();
+        assert_eq!(feeds, vec!["a", "b", "c"]);
     }
 }
@@ -0,0 +1,381 @@
+//! 利用者の DID をそのまま外に出さないためのラッパー
+//!
+//! - 仮名化: DID を鍵付きハッシュ（HMAC-SHA256）に置き換える。鍵は期間ごとに変わるので、期間をまたいで同じ人を追えない
+//! - オプトアウト: 設定に DID を並べた人は記録しない。目印を設定したときは、プロフィールの説明文に目印を書いた人も記録しない
+
+use super::{AnalyticsSink, FeedRequestEvent};
+use axum::async_trait;
+use bsky_core::appview::AppViewClient;
+use bsky_core::xrpc::XrpcError;
+use futures_util::StreamExt;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// 未認証のリクエストの DID（仮名化しない）
+const ANONYMOUS: &str = "anonymous";
+
+/// 仮名化の鍵の最短の長さ（バイト）。短い鍵や例の値だと、候補の DID をハッシュして仮名を戻せる
+pub const MIN_SECRET_LEN: usize = 32;
+
+/// プロフィールを同時に問い合わせる数
+const PROFILE_CONCURRENCY: usize = 8;
+
+/// プロフィールの確認結果を覚えておく件数
+const DEFAULT_CACHE_CAPACITY: usize = 10_000;
+
+/// DID を期間ごとに変わる鍵付きハッシュに置き換える
+///
+/// 期間は UNIX エポックから `rotation` ごとに区切る。1 日なら UTC の日付の切り替わりで変わる
+#[derive(Clone)]
+pub struct Pseudonymizer {
+    secret: Vec<u8>,
+    rotation: Duration,
+}
+
+impl Pseudonymizer {
+    pub fn new(secret: impl Into<Vec<u8>>, rotation: Duration) -> Self {
+        Self {
+            secret: secret.into(),
+            rotation: rotation.max(Duration::from_secs(1)),
+        }
+    }
+
+    /// 秘密鍵をプロセスごとにランダムに作る
+    ///
+    /// 再起動で仮名が変わり、同じ日の利用者が別人として数えられる
+    pub fn random(rotation: Duration) -> Self {
+        use rand::RngCore;
+        let mut secret = vec![0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        Self::new(secret, rotation)
+    }
+
+    /// `time_us`（マイクロ秒）の時点での `did` の仮名
+    pub fn pseudonymize(&self, did: &str, time_us: i64) -> String {
+        if did == ANONYMOUS {
+            return did.to_string();
+        }
+
+        let period = time_us.max(0) as u128 / self.rotation.as_micros();
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC can take a key of any size");
+        mac.update(&(period as u64).to_be_bytes());
+        mac.update(did.as_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        // 衝突しない程度に短くする（128 bit）
+        digest[..16].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+#[derive(Deserialize)]
+struct ProfileResponse {
+    #[serde(default)]
+    description: Option<String>,
+}
+
+/// 記録しない利用者の判定
+pub struct OptOut {
+    dids: HashSet<String>,
+    /// プロフィールの説明文に含まれていたら除外する
+    marker: Option<String>,
+    appview: Option<AppViewClient>,
+    cache_ttl: Duration,
+    cache_capacity: usize,
+    cache: Mutex<HashMap<String, (bool, Instant)>>,
+}
+
+impl OptOut {
+    /// 設定で除外する DID だけを見る
+    pub fn new(dids: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            dids: dids.into_iter().collect(),
+            marker: None,
+            appview: None,
+            cache_ttl: Duration::from_secs(3600),
+            cache_capacity: DEFAULT_CACHE_CAPACITY,
+            cache: Mutex::default(),
+        }
+    }
+
+    /// プロフィールの目印も見る（AppView に問い合わせ、結果は `cache_ttl` の間覚えておく）
+    pub fn with_profile_marker(
+        mut self,
+        marker: impl Into<String>,
+        appview: AppViewClient,
+    ) -> Self {
+        self.marker = Some(marker.into());
+        self.appview = Some(appview);
+        self
+    }
+
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// 覚えておく件数の上限。超えたら期限切れを捨て、それでも足りなければ古いものから捨てる
+    pub fn with_cache_capacity(mut self, capacity: usize) -> Self {
+        self.cache_capacity = capacity;
+        self
+    }
+
+    /// `dids` のうち記録しない DID
+    ///
+    /// キャッシュに無いプロフィールは `PROFILE_CONCURRENCY` 件ずつ並行して問い合わせる。
+    /// 確認できなかった人は除外せず、次のバッチで改めて問い合わせる
+    pub async fn opted_out<'a>(&self, dids: impl IntoIterator<Item = &'a str>) -> HashSet<String> {
+        let mut opted_out = HashSet::new();
+        let mut unknown = HashSet::new();
+        for did in dids {
+            if did == ANONYMOUS {
+                continue;
+            }
+            if self.dids.contains(did) {
+                opted_out.insert(did.to_string());
+                continue;
+            }
+            match self.cached(did) {
+                Some(true) => {
+                    opted_out.insert(did.to_string());
+                }
+                Some(false) => {}
+                None => {
+                    unknown.insert(did.to_string());
+                }
+            }
+        }
+
+        let (Some(marker), Some(appview)) = (&self.marker, &self.appview) else {
+            return opted_out;
+        };
+        let mut lookups = futures_util::stream::iter(unknown)
+            .map(|did: String| async move {
+                let result = fetch_description(appview, &did).await;
+                (did, result)
+            })
+            .buffer_unordered(PROFILE_CONCURRENCY);
+        while let Some((did, result)) = lookups.next().await {
+            match result {
+                Ok(description) => {
+                    let marked = description.is_some_and(|d| d.contains(marker.as_str()));
+                    self.remember(&did, marked);
+                    if marked {
+                        opted_out.insert(did);
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to check analytics opt-out for {}: {:#}", did, e);
+                }
+            }
+        }
+        opted_out
+    }
+
+    fn cached(&self, did: &str) -> Option<bool> {
+        let cache = self.cache.lock().unwrap();
+        let (opted_out, checked_at) = cache.get(did)?;
+        (checked_at.elapsed() < self.cache_ttl).then_some(*opted_out)
+    }
+
+    fn remember(&self, did: &str, opted_out: bool) {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= self.cache_capacity && !cache.contains_key(did) {
+            cache.retain(|_, (_, checked_at)| checked_at.elapsed() < self.cache_ttl);
+            while cache.len() >= self.cache_capacity.max(1) {
+                let Some(oldest) = cache
+                    .iter()
+                    .min_by_key(|(_, (_, checked_at))| *checked_at)
+                    .map(|(did, _)| did.clone())
+                else {
+                    break;
+                };
+                cache.remove(&oldest);
+            }
+        }
+        cache.insert(did.to_string(), (opted_out, Instant::now()));
+    }
+}
+
+async fn fetch_description(
+    appview: &AppViewClient,
+    did: &str,
+) -> Result<Option<String>, XrpcError> {
+    let profile: ProfileResponse = appview
+        .query("app.bsky.actor.getProfile", None, &[("actor", did)])
+        .await?;
+    Ok(profile.description)
+}
+
+/// オプトアウトした人を除き、DID を仮名化してから `inner` に渡す
+pub struct PrivateSink {
+    inner: Arc<dyn AnalyticsSink>,
+    pseudonymizer: Pseudonymizer,
+    opt_out: OptOut,
+}
+
+impl PrivateSink {
+    pub fn new(
+        inner: Arc<dyn AnalyticsSink>,
+        pseudonymizer: Pseudonymizer,
+        opt_out: OptOut,
+    ) -> Self {
+        Self {
+            inner,
+            pseudonymizer,
+            opt_out,
+        }
+    }
+}
+
+#[async_trait]
+impl AnalyticsSink for PrivateSink {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    async fn write_batch(&self, events: &[FeedRequestEvent]) -> anyhow::Result<()> {
+        let opted_out = self
+            .opt_out
+            .opted_out(events.iter().map(|event| event.did.as_str()))
+            .await;
+        let masked: Vec<FeedRequestEvent> = events
+            .iter()
+            .filter(|event| !opted_out.contains(&event.did))
+            .map(|event| FeedRequestEvent {
+                did: self.pseudonymizer.pseudonymize(&event.did, event.time_us),
+                ..event.clone()
+            })
+            .collect();
+
+        if masked.is_empty() {
+            return Ok(());
+        }
+        self.inner.write_batch(&masked).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY_US: i64 = 86_400_000_000;
+
+    /// 同じ期間内では同じ仮名に、期間や鍵が変わると別の仮名になるか
+    #[test]
+    fn test_pseudonymize_rotates() {
+        let day = Duration::from_secs(86_400);
+        let pseudonymizer = Pseudonymizer::new("secret", day);
+        let today = 19_000 * DAY_US;
+
+        let a = pseudonymizer.pseudonymize("did:plc:alice", today);
+        assert_eq!(a.len(), 32);
+        assert!(!a.contains("alice"));
+        assert_eq!(
+            a,
+            pseudonymizer.pseudonymize("did:plc:alice", today + DAY_US - 1)
+        );
+        assert_ne!(a, pseudonymizer.pseudonymize("did:plc:bob", today));
+        assert_ne!(
+            a,
+            pseudonymizer.pseudonymize("did:plc:alice", today + DAY_US)
+        );
+        assert_ne!(
+            a,
+            Pseudonymizer::new("other", day).pseudonymize("did:plc:alice", today)
+        );
+        assert_eq!(pseudonymizer.pseudonymize(ANONYMOUS, today), ANONYMOUS);
+    }
+
+    #[derive(Default)]
+    struct RecordingSink {
+        events: Mutex<Vec<FeedRequestEvent>>,
+    }
+
+    #[async_trait]
+    impl AnalyticsSink for RecordingSink {
+        fn name(&self) -> &'static str {
+            "recording"
+        }
+
+        async fn write_batch(&self, events: &[FeedRequestEvent]) -> anyhow::Result<()> {
+            self.events.lock().unwrap().extend_from_slice(events);
+            Ok(())
+        }
+    }
+
+    /// 設定でオプトアウトした人を除き、生の DID を渡さないか
+    #[tokio::test]
+    async fn test_private_sink() {
+        let inner = Arc::new(RecordingSink::default());
+        let sink = PrivateSink::new(
+            inner.clone(),
+            Pseudonymizer::new("secret", Duration::from_secs(86_400)),
+            OptOut::new(["did:plc:bob".to_string()]),
+        );
+
+        let event = |did: &str| FeedRequestEvent {
+            feed: "helloworld".to_string(),
+            did: did.to_string(),
+            language: "ja".to_string(),
+            has_cursor: false,
+            time_us: 0,
+        };
+        sink.write_batch(&[
+            event("did:plc:alice"),
+            event("did:plc:bob"),
+            event(ANONYMOUS),
+        ])
+        .await
+        .unwrap();
+
+        let dids: Vec<String> = inner
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| e.did.clone())
+            .collect();
+        assert_eq!(dids.len(), 2);
+        assert!(dids.iter().all(|did| !did.starts_with("did:")));
+        assert_eq!(dids[1], ANONYMOUS);
+    }
+
+    /// プロフィールを確認できなかった人は除外せず、次に改めて問い合わせるか
+    #[tokio::test]
+    async fn test_lookup_error_is_not_opt_out() {
+        let http = bsky_core::http::HttpClient::new(reqwest::Client::new()).with_options(
+            bsky_core::http::HttpOptions {
+                max_retries: 0,
+                ..Default::default()
+            },
+        );
+        // 誰も待ち受けていないポート
+        let appview = AppViewClient::new(http, "http://127.0.0.1:1");
+        let opt_out =
+            OptOut::new(["did:plc:bob".to_string()]).with_profile_marker("#noanalytics", appview);
+
+        let opted_out = opt_out
+            .opted_out(["did:plc:alice", "did:plc:bob", ANONYMOUS])
+            .await;
+        assert_eq!(opted_out, HashSet::from(["did:plc:bob".to_string()]));
+        assert_eq!(opt_out.cached("did:plc:alice"), None);
+    }
+
+    /// 覚えておく件数が上限を超えず、古いものから捨てるか
+    #[test]
+    fn test_cache_capacity() {
+        let opt_out = OptOut::new([]).with_cache_capacity(2);
+        opt_out.remember("did:plc:alice", true);
+        opt_out.remember("did:plc:bob", false);
+        opt_out.remember("did:plc:carol", false);
+
+        assert_eq!(opt_out.cache.lock().unwrap().len(), 2);
+        assert_eq!(opt_out.cached("did:plc:alice"), None);
+        assert_eq!(opt_out.cached("did:plc:carol"), Some(false));
+    }
+}
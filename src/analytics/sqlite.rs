@@ -0,0 +1,200 @@
+//! ローカルの SQLite に保存する
+//!
+//! テーブル: `feed_requests`
+//!   - feed       : TEXT NOT NULL    (フィードの rkey)
+//!   - did        : TEXT NOT NULL    (未認証なら "anonymous")
+//!   - language   : TEXT NOT NULL
+//!   - has_cursor : INTEGER NOT NULL (2 ページ目以降なら 1)
+//!   - created_at : INTEGER NOT NULL (マイクロ秒)
+
+use super::{AnalyticsSink, FeedRequestEvent};
+use axum::async_trait;
+use sqlx::SqlitePool;
+use std::time::Duration;
+
+/// これより古いリクエストは消す
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(90 * 24 * 3600);
+
+/// 必要なテーブルを作成する（冪等）
+pub async fn migrate(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS feed_requests (
+            id         INTEGER PRIMARY KEY AUTOINCREMENT,
+            feed       TEXT    NOT NULL,
+            did        TEXT    NOT NULL,
+            language   TEXT    NOT NULL,
+            has_cursor INTEGER NOT NULL,
+            created_at INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_feed_requests_created_at
+            ON feed_requests(created_at);
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// フィードの 1 日（UTC）分の利用状況
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct FeedUsage {
+    pub feed: String,
+    /// `YYYY-MM-DD`
+    pub day: String,
+    pub requests: i64,
+    /// 未認証のリクエストは除く
+    pub unique_dids: i64,
+    /// 1 ページ目（カーソルなし）の取得数
+    pub first_pages: i64,
+    /// 2 ページ目以降（カーソルあり）の取得数
+    pub paged_requests: i64,
+}
+
+impl FeedUsage {
+    /// 1 ページ目を開いてから平均して何ページ先まで読まれたか
+    pub fn average_cursor_depth(&self) -> f64 {
+        if self.first_pages == 0 {
+            return 0.0;
+        }
+        self.paged_requests as f64 / self.first_pages as f64
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteSink {
+    pool: SqlitePool,
+}
+
+impl SqliteSink {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    /// `since_us`（マイクロ秒）以降の、フィードと日ごとの利用状況
+    pub async fn daily_usage(&self, since_us: i64) -> anyhow::Result<Vec<FeedUsage>> {
+        let usage = sqlx::query_as::<_, FeedUsage>(
+            r#"
+            SELECT
+                feed,
+                date(created_at / 1000000, 'unixepoch') AS day,
+                COUNT(*) AS requests,
+                COUNT(DISTINCT CASE WHEN did != 'anonymous' THEN did END) AS unique_dids,
+                SUM(has_cursor = 0) AS first_pages,
+                SUM(has_cursor = 1) AS paged_requests
+            FROM feed_requests
+            WHERE created_at >= ?
+            GROUP BY feed, day
+            ORDER BY day, feed
+            "#,
+        )
+        .bind(since_us)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(usage)
+    }
+
+    /// `retention` より古いリクエストを消し、消した件数を返す
+    pub async fn prune(&self, retention: Duration) -> anyhow::Result<u64> {
+        let cutoff = chrono::Utc::now().timestamp_micros() - retention.as_micros() as i64;
+        self.prune_before(cutoff).await
+    }
+
+    async fn prune_before(&self, cutoff_us: i64) -> anyhow::Result<u64> {
+        let result = sqlx::query("DELETE FROM feed_requests WHERE created_at < ?")
+            .bind(cutoff_us)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl AnalyticsSink for SqliteSink {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn write_batch(&self, events: &[FeedRequestEvent]) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for event in events {
+            sqlx::query(
+                "INSERT INTO feed_requests (feed, did, language, has_cursor, created_at) VALUES (?, ?, ?, ?, ?)",
+            )
+            .bind(&event.feed)
+            .bind(&event.did)
+            .bind(&event.language)
+            .bind(event.has_cursor)
+            .bind(event.time_us)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// フィードと日ごとに、リクエスト数・ユニーク DID 数・ページ送りの深さを集計できるか
+    #[tokio::test]
+    async fn test_daily_usage() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate(&pool).await.unwrap();
+        let sink = SqliteSink::new(pool);
+
+        // 2024-01-01 00:00:00 UTC
+        let day1 = 1_704_067_200_000_000i64;
+        let day2 = day1 + 86_400_000_000;
+        let event = |feed: &str, did: &str, has_cursor: bool, time_us: i64| FeedRequestEvent {
+            feed: feed.to_string(),
+            did: did.to_string(),
+            language: "ja".to_string(),
+            has_cursor,
+            time_us,
+        };
+        sink.write_batch(&[
+            event("helloworld", "did:plc:alice", false, day1),
+            event("helloworld", "did:plc:alice", true, day1 + 1),
+            event("helloworld", "did:plc:alice", true, day1 + 2),
+            event("helloworld", "did:plc:bob", false, day1 + 3),
+            event("helloworld", "anonymous", false, day1 + 4),
+            event("oneyearago", "did:plc:alice", false, day2),
+        ])
+        .await
+        .unwrap();
+
+        let usage = sink.daily_usage(day1).await.unwrap();
+        assert_eq!(
+            usage,
+            vec![
+                FeedUsage {
+                    feed: "helloworld".to_string(),
+                    day: "2024-01-01".to_string(),
+                    requests: 5,
+                    unique_dids: 2,
+                    first_pages: 3,
+                    paged_requests: 2,
+                },
+                FeedUsage {
+                    feed: "oneyearago".to_string(),
+                    day: "2024-01-02".to_string(),
+                    requests: 1,
+                    unique_dids: 1,
+                    first_pages: 1,
+                    paged_requests: 0,
+                },
+            ]
+        );
+        assert!((usage[0].average_cursor_depth() - 2.0 / 3.0).abs() < 1e-9);
+
+        assert_eq!(sink.daily_usage(day2).await.unwrap().len(), 1);
+
+        // 2 日目より前を消すと、1 日目の分だけが消える
+        assert_eq!(sink.prune_before(day2).await.unwrap(), 5);
+        assert_eq!(sink.daily_usage(day1).await.unwrap().len(), 1);
+    }
+}
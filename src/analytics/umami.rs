@@ -0,0 +1,121 @@
+use super::{AnalyticsSink, FeedRequestEvent};
+use axum::async_trait;
+use bsky_core::http::HttpClient;
+use serde::Serialize;
+
+/// Umami に送る（Umami の API は 1 イベントずつなので、バッチ内を順に送る）
+#[derive(Clone)]
+pub struct UmamiSink {
+    client: HttpClient,
+    host: String,
+    website_id: String,
+    hostname: Option<String>,
+}
+
+#[derive(Serialize)]
+struct EventPayload {
+    #[serde(rename = "type")]
+    event_type: String,
+    payload: EventData,
+}
+
+#[derive(Serialize)]
+struct EventData {
+    website: String,
+    hostname: Option<String>,
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    data: Option<serde_json::Value>,
+}
+
+impl UmamiSink {
+    pub fn new(
+        client: HttpClient,
+        mut host: String,
+        website_id: String,
+        hostname: Option<String>,
+    ) -> Self {
+        if !host.starts_with("http://") && !host.starts_with("https://") {
+            host = format!("https://{}", host);
+        }
+        // Remove trailing slash if present
+        if host.ends_with('/') {
+            host.pop();
+        }
+
+        Self {
+            client,
+            host,
+            website_id,
+            hostname,
+        }
+    }
+
+    fn payload(&self, event: &FeedRequestEvent) -> EventPayload {
+        let cursor_state = if event.has_cursor { "exists" } else { "none" };
+        EventPayload {
+            event_type: "event".to_string(),
+            payload: EventData {
+                website: self.website_id.clone(),
+                hostname: self.hostname.clone(),
+                // Construct URL with query parameters for easier filtering in Umami
+                url: format!("/feeds/{}?did={}", event.feed, event.did),
+                name: None,
+                language: Some(event.language.clone()),
+                id: Some(event.did.clone()),
+                data: Some(serde_json::json!({
+                    "did": event.did,
+                    "cursor": cursor_state,
+                    "language": event.language,
+                })),
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl AnalyticsSink for UmamiSink {
+    fn name(&self) -> &'static str {
+        "umami"
+    }
+
+    async fn write_batch(&self, events: &[FeedRequestEvent]) -> anyhow::Result<()> {
+        let endpoint = format!("{}/api/send", self.host);
+        let mut failed = 0;
+
+        for event in events {
+            let request = self
+                .client
+                .post(&endpoint)
+                .json(&self.payload(event))
+                // Umami に弾かれないようにするためにUser-Agentを偽装する
+                .header(
+                    "User-Agent",
+                    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36"
+                );
+            match self.client.send(request).await {
+                Ok(response) if response.status().is_success() => {}
+                Ok(response) => {
+                    let status = response.status();
+                    let text = response.text().await.unwrap_or_default();
+                    tracing::warn!("Umami returned error: status={}, body={}", status, text);
+                    failed += 1;
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to send analytics event: {}", e);
+                    failed += 1;
+                }
+            }
+        }
+
+        if failed > 0 {
+            anyhow::bail!("{} of {} events were not accepted", failed, events.len());
+        }
+        tracing::debug!("Sent {} analytics events", events.len());
+        Ok(())
+    }
+}
@@ -1,4 +1,6 @@
 use anyhow::{Context, Result};
+use bluesky_feeds::config::Config;
+use bluesky_feeds::feed::FeedRegistry;
 use dotenv::dotenv;
 use reqwest::{Client, ClientBuilder};
 use serde::{Deserialize, Serialize};
@@ -37,6 +39,8 @@ struct FeedGeneratorRecord {
     description: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     avatar: Option<BlobRef>,
+    #[serde(rename = "acceptsInteractions")]
+    accepts_interactions: bool,
     #[serde(rename = "createdAt")]
     created_at: String,
 }
@@ -63,48 +67,6 @@ struct UploadBlobResponse {
     blob: BlobRef,
 }
 
-struct FeedServiceConfig {
-    service: &'static str,
-    display_name: &'static str,
-    description: &'static str,
-    avatar: Option<&'static str>,
-}
-
-const SERVICE_DID: &str = "did:web:feeds.bsky.girigiribauer.com";
-
-const AVAILABLE_FEED_SERVICES: &[FeedServiceConfig] = &[
-    FeedServiceConfig {
-        service: "helloworld",
-        display_name: "Helloworld",
-        description: "Healthcheck test feed\n\n死活監視を兼ねたテストフィードです",
-        avatar: Some("assets/helloworld.png"),
-    },
-    FeedServiceConfig {
-        service: "todoapp",
-        display_name: "TODO",
-        description: "Only your posts starting with `TODO` are displayed. Replying with `DONE` will remove them.\n\n`TODO` と頭につけた自分の投稿だけが表示されます。 `DONE` と返信すると消えます。",
-        avatar: Some("assets/todoapp.png"),
-    },
-    FeedServiceConfig {
-        service: "oneyearago",
-        display_name: "OneYearAgo",
-        description: "Shows posts from this day in past years.\nYou can specify timezone in bio (e.g. \"UTC-8\", \"Asia/Tokyo\").\n\n過去の今日（1年前、2年前...）の投稿を表示するフィードです。\n日本語話者と推定されると日本時間で表示されます。\nプロフィール内にタイムゾーンで指定も可能です。(\"UTC-8\", \"Asia/Tokyo\"）\n\nhttps://en.wikipedia.org/wiki/List_of_tz_database_time_zones",
-        avatar: Some("assets/oneyearago.png"),
-    },
-    FeedServiceConfig {
-        service: "fakebluesky",
-        display_name: "Fake Bluesky",
-        description: "Fake Bluesky feed\n\nBluesky と言いながら実は青空の写真ではないフィード",
-        avatar: Some("assets/fakebluesky.png"),
-    },
-    FeedServiceConfig {
-        service: "realbluesky",
-        display_name: "Real Bluesky",
-        description: "Real Bluesky feed\n\n正真正銘の青空の写真だけを集めたフィード",
-        avatar: Some("assets/realbluesky.png"),
-    },
-];
-
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv().ok();
@@ -116,23 +78,23 @@ async fn main() -> Result<()> {
     }
     let target_service = &args[1];
 
-    let handle = env::var("APP_HANDLE")
-        .context("APP_HANDLE not set in .env (checked current and parent directories)")?;
-    let password = env::var("APP_PASSWORD")
-        .context("APP_PASSWORD not set in .env (checked current and parent directories)")?;
+    let config = Config::load()?;
+    let handle = &config.account.handle;
+    let password = &config.account.password;
+    let pds_url = &config.account.pds_url;
 
-    let config = AVAILABLE_FEED_SERVICES
-        .iter()
-        .find(|c| c.service == target_service)
+    let feed = FeedRegistry::builtin()
+        .get(target_service)
         .context(format!("Feed service '{}' not found", target_service))?;
+    let metadata = feed.metadata();
 
     let client = ClientBuilder::new().build()?;
 
     println!("Logging in as {}...", handle);
-    let session = create_session(&client, &handle, &password).await?;
+    let session = create_session(&client, pds_url, handle, password).await?;
     println!("Login successful. DID: {}", session.did);
 
-    let avatar_blob = if let Some(avatar_path) = config.avatar {
+    let avatar_blob = if let Some(avatar_path) = metadata.avatar {
         let path = Path::new(avatar_path);
         let final_path = if path.exists() {
             path.to_path_buf()
@@ -147,7 +109,7 @@ async fn main() -> Result<()> {
 
         if final_path.exists() {
             println!("Uploading avatar: {:?}", final_path);
-            Some(upload_blob(&client, &session.access_jwt, &final_path).await?)
+            Some(upload_blob(&client, pds_url, &session.access_jwt, &final_path).await?)
         } else {
             println!(
                 "Avatar file not found at {:?}, skipping avatar upload.",
@@ -159,35 +121,38 @@ async fn main() -> Result<()> {
         None
     };
 
-    println!("Publishing feed '{}'...", config.display_name);
+    println!("Publishing feed '{}'...", metadata.display_name);
     let record = FeedGeneratorRecord {
-        did: SERVICE_DID.to_string(),
-        display_name: config.display_name.to_string(),
-        description: config.description.to_string(),
+        did: config.service_did(),
+        display_name: metadata.display_name.to_string(),
+        description: metadata.description.to_string(),
         avatar: avatar_blob,
+        accepts_interactions: feed.accepts_interactions(),
         created_at: chrono::Utc::now().to_rfc3339(),
     };
 
     put_record(
         &client,
+        pds_url,
         &session.access_jwt,
         &session.did,
-        config.service,
+        feed.rkey(),
         record,
     )
     .await?;
-    println!("Successfully published {}", config.service);
+    println!("Successfully published {}", feed.rkey());
 
     Ok(())
 }
 
 async fn create_session(
     client: &Client,
+    pds_url: &str,
     identifier: &str,
     password: &str,
 ) -> Result<CreateSessionResponse> {
     let res = client
-        .post("https://bsky.social/xrpc/com.atproto.server.createSession")
+        .post(format!("{}/xrpc/com.atproto.server.createSession", pds_url))
         .json(&CreateSessionRequest {
             identifier,
             password,
@@ -199,10 +164,15 @@ async fn create_session(
     Ok(res.json().await?)
 }
 
-async fn upload_blob(client: &Client, token: &str, path: &PathBuf) -> Result<BlobRef> {
+async fn upload_blob(
+    client: &Client,
+    pds_url: &str,
+    token: &str,
+    path: &PathBuf,
+) -> Result<BlobRef> {
     let bytes = std::fs::read(path).context("Failed to read avatar file")?;
     let res = client
-        .post("https://bsky.social/xrpc/com.atproto.repo.uploadBlob")
+        .post(format!("{}/xrpc/com.atproto.repo.uploadBlob", pds_url))
         .header("Authorization", format!("Bearer {}", token))
         .header("Content-Type", "image/png")
         .body(bytes)
@@ -216,6 +186,7 @@ async fn upload_blob(client: &Client, token: &str, path: &PathBuf) -> Result<Blo
 
 async fn put_record(
     client: &Client,
+    pds_url: &str,
     token: &str,
     repo: &str,
     rkey: &str,
@@ -229,7 +200,7 @@ async fn put_record(
     };
 
     client
-        .post("https://bsky.social/xrpc/com.atproto.repo.putRecord")
+        .post(format!("{}/xrpc/com.atproto.repo.putRecord", pds_url))
         .header("Authorization", format!("Bearer {}", token))
         .json(&req)
         .send()
@@ -1,4 +1,6 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
+use bluesky_feeds::config::Config;
+use bluesky_feeds::feed::FeedRegistry;
 use dotenv::dotenv;
 use reqwest::{Client, ClientBuilder};
 use serde::{Deserialize, Serialize};
@@ -25,9 +27,6 @@ struct DeleteRecordRequest {
     rkey: String,
 }
 
-// Ported from packages/shared/src/index.ts (only needed for validation if STRICT, but simple arg check is enough)
-const AVAILABLE_SERVICES: &[&str] = &["helloworld", "todoapp", "oneyearago"];
-
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv().ok();
@@ -40,26 +39,33 @@ async fn main() -> Result<()> {
     }
     let target_service = &args[1];
 
-    if !AVAILABLE_SERVICES.contains(&target_service.as_str()) {
+    if FeedRegistry::builtin().get(target_service).is_none() {
         eprintln!(
             "Warning: '{}' is not in the known service list. Proceeding anyway...",
             target_service
         );
     }
 
-    let handle = env::var("APP_HANDLE")
-        .context("APP_HANDLE not set in .env (checked current and parent directories)")?;
-    let password = env::var("APP_PASSWORD")
-        .context("APP_PASSWORD not set in .env (checked current and parent directories)")?;
+    let config = Config::load()?;
+    let handle = &config.account.handle;
+    let password = &config.account.password;
+    let pds_url = &config.account.pds_url;
 
     let client = ClientBuilder::new().build()?;
 
     println!("Logging in as {}...", handle);
-    let session = create_session(&client, &handle, &password).await?;
+    let session = create_session(&client, pds_url, handle, password).await?;
     println!("Login successful. DID: {}", session.did);
 
     println!("Unpublishing feed '{}'...", target_service);
-    delete_record(&client, &session.access_jwt, &session.did, target_service).await?;
+    delete_record(
+        &client,
+        pds_url,
+        &session.access_jwt,
+        &session.did,
+        target_service,
+    )
+    .await?;
     println!("Successfully unpublished {}", target_service);
 
     Ok(())
@@ -67,11 +73,12 @@ async fn main() -> Result<()> {
 
 async fn create_session(
     client: &Client,
+    pds_url: &str,
     identifier: &str,
     password: &str,
 ) -> Result<CreateSessionResponse> {
     let res = client
-        .post("https://bsky.social/xrpc/com.atproto.server.createSession")
+        .post(format!("{}/xrpc/com.atproto.server.createSession", pds_url))
         .json(&CreateSessionRequest {
             identifier,
             password,
@@ -83,7 +90,13 @@ async fn create_session(
     Ok(res.json().await?)
 }
 
-async fn delete_record(client: &Client, token: &str, repo: &str, rkey: &str) -> Result<()> {
+async fn delete_record(
+    client: &Client,
+    pds_url: &str,
+    token: &str,
+    repo: &str,
+    rkey: &str,
+) -> Result<()> {
     let req = DeleteRecordRequest {
         repo: repo.to_string(),
         collection: "app.bsky.feed.generator".to_string(),
@@ -91,7 +104,7 @@ async fn delete_record(client: &Client, token: &str, repo: &str, rkey: &str) ->
     };
 
     client
-        .post("https://bsky.social/xrpc/com.atproto.repo.deleteRecord")
+        .post(format!("{}/xrpc/com.atproto.repo.deleteRecord", pds_url))
         .header("Authorization", format!("Bearer {}", token))
         .json(&req)
         .send()
@@ -0,0 +1,787 @@
+//! サーバーと各バイナリで共有する設定
+//!
+//! TOML ファイル（`CONFIG_FILE`、未指定ならあれば `config.toml`）を読み、環境変数で上書きしてから検証する。
+//! どちらにも無い項目は既定値になる。環境変数の名前は `config.example.toml` を参照
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// `CONFIG_FILE` が未指定のときに探すファイル
+pub const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// `ingest.backfill_max_hours` の上限（Jetstream がイベントを残しておく期間の目安）
+pub const MAX_BACKFILL_HOURS: i64 = 72;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub account: AccountConfig,
+    pub services: ServicesConfig,
+    pub database: DatabaseConfig,
+    pub analytics: AnalyticsConfig,
+    pub ingest: IngestConfig,
+    pub cache: CacheConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub port: u16,
+    /// フィードジェネレーターのホスト名。サービスの DID は `did:web:{hostname}`
+    pub hostname: String,
+    /// Cookie の署名・暗号化の鍵（64 バイト以上）
+    pub cookie_secret: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            port: 3000,
+            hostname: "feeds.bsky.girigiribauer.com".to_string(),
+            cookie_secret: "very-secret-key-that-is-at-least-64-bytes-long-for-security-reasons-please-change-me".to_string(),
+        }
+    }
+}
+
+/// フィードを公開し、AppView を呼び出すアカウント
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountConfig {
+    pub handle: String,
+    /// アプリパスワード
+    pub password: String,
+    /// アカウントの PDS（ログインとレコードの書き込み）
+    pub pds_url: String,
+}
+
+impl Default for AccountConfig {
+    fn default() -> Self {
+        Self {
+            handle: String::new(),
+            password: String::new(),
+            pds_url: "https://bsky.social".to_string(),
+        }
+    }
+}
+
+// パスワードをログに出さない
+impl fmt::Debug for AccountConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccountConfig")
+            .field("handle", &self.handle)
+            .field("password", &"<redacted>")
+            .field("pds_url", &self.pds_url)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServicesConfig {
+    pub bsky_api_url: String,
+    pub plc_directory_url: String,
+    /// privatelist の Web UI の URL
+    pub privatelist_url: String,
+}
+
+impl Default for ServicesConfig {
+    fn default() -> Self {
+        Self {
+            bsky_api_url: "https://api.bsky.app".to_string(),
+            plc_directory_url: bsky_core::resolver::DEFAULT_PLC_DIRECTORY_URL.to_string(),
+            privatelist_url: "http://localhost:3000".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub helloworld: String,
+    pub realfakebluesky: String,
+    pub privatelist: String,
+    pub oneyearago: String,
+    pub identity: String,
+    pub interactions: String,
+    pub jetstream: String,
+    pub analytics: String,
+    /// getFeedSkeleton の結果のキャッシュ（`cache.persist = true` のときだけ使う）
+    pub skeleton_cache: String,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            helloworld: "sqlite:data/helloworld.db".to_string(),
+            realfakebluesky: "sqlite:data/fakebluesky.db".to_string(),
+            privatelist: "sqlite:data/privatelist.db".to_string(),
+            oneyearago: "sqlite:data/oneyearago.db".to_string(),
+            identity: "sqlite:data/identity.db".to_string(),
+            interactions: "sqlite:data/interactions.db".to_string(),
+            jetstream: "sqlite:data/jetstream.db".to_string(),
+            analytics: "sqlite:data/analytics.db".to_string(),
+            skeleton_cache: "sqlite:data/skeleton_cache.db".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnalyticsSinkKind {
+    Umami,
+    Sqlite,
+    Stdout,
+    None,
+}
+
+impl FromStr for AnalyticsSinkKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "umami" => Ok(Self::Umami),
+            "sqlite" => Ok(Self::Sqlite),
+            "stdout" => Ok(Self::Stdout),
+            "none" => Ok(Self::None),
+            _ => Err("expected one of umami, sqlite, stdout, none".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnalyticsConfig {
+    /// 未指定なら、Umami の設定が揃っていれば umami、なければ none
+    pub sink: Option<AnalyticsSinkKind>,
+    pub umami_host: Option<String>,
+    pub umami_website_id: Option<String>,
+    /// DID の仮名化の鍵（32 バイト以上）。未指定なら起動ごとにランダム（再起動をまたぐと日ごとの利用者数が重複する）
+    pub hash_secret: Option<String>,
+    /// 日ごとの利用者数を数えられるよう、24 の倍数
+    pub salt_rotation_hours: u64,
+    pub opt_out_dids: Vec<String>,
+    /// プロフィールの説明文にあれば記録しない。空（既定）ならプロフィールは見ない
+    pub opt_out_marker: String,
+}
+
+impl Default for AnalyticsConfig {
+    fn default() -> Self {
+        Self {
+            sink: None,
+            umami_host: None,
+            umami_website_id: None,
+            hash_secret: None,
+            salt_rotation_hours: 24,
+            opt_out_dids: Vec::new(),
+            opt_out_marker: String::new(),
+        }
+    }
+}
+
+impl AnalyticsConfig {
+    pub fn sink(&self) -> AnalyticsSinkKind {
+        self.sink.unwrap_or(
+            if self.umami_host.is_some() && self.umami_website_id.is_some() {
+                AnalyticsSinkKind::Umami
+            } else {
+                AnalyticsSinkKind::None
+            },
+        )
+    }
+
+    pub fn salt_rotation(&self) -> Duration {
+        Duration::from_secs(self.salt_rotation_hours * 3600)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IngestSourceKind {
+    #[default]
+    Jetstream,
+    Firehose,
+}
+
+impl FromStr for IngestSourceKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jetstream" => Ok(Self::Jetstream),
+            "firehose" => Ok(Self::Firehose),
+            _ => Err("expected one of jetstream, firehose".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IngestConfig {
+    pub enabled: bool,
+    pub source: IngestSourceKind,
+    pub firehose_url: String,
+    /// 接続先の候補。先頭から使い、落ちたら次に切り替える。空なら公式のインスタンス
+    pub jetstream_urls: Vec<String>,
+    /// 保存済みカーソルからどこまで遡って追いつくか（Jetstream のみ。ファイアホースはリレー次第）。
+    /// 1 から `MAX_BACKFILL_HOURS` まで
+    pub backfill_max_hours: Option<i64>,
+    pub backfill_max_rate: Option<u32>,
+    /// 受信したイベントを NDJSON に保存する（再現用）
+    pub record_file: Option<PathBuf>,
+    /// 指定されていれば、ライブに繋がずに保存済みのイベントを流す
+    pub replay_file: Option<PathBuf>,
+    /// 1.0 で記録時と同じ速度。未指定か 0 以下なら待たずに流す
+    pub replay_speed: Option<f64>,
+}
+
+impl Default for IngestConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            source: IngestSourceKind::default(),
+            firehose_url: jetstream::firehose::DEFAULT_FIREHOSE_URL.to_string(),
+            jetstream_urls: Vec::new(),
+            backfill_max_hours: None,
+            backfill_max_rate: None,
+            record_file: None,
+            replay_file: None,
+            replay_speed: None,
+        }
+    }
+}
+
+/// フィードごとの TTL（秒）の既定値。載っていないフィードはキャッシュしない
+const DEFAULT_SKELETON_TTL_SECONDS: &[(&str, u64)] = &[
+    ("helloworld", 30),
+    ("todoapp", 60),
+    ("oneyearago", 60),
+    ("fakebluesky", 30),
+    ("realbluesky", 30),
+    ("privatelist", 60),
+];
+
+/// getFeedSkeleton の結果のキャッシュ
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    /// メモリに持つ件数
+    pub capacity: usize,
+    /// `database.skeleton_cache` にも書く（再起動しても残る）
+    pub persist: bool,
+    /// フィードごとの TTL（秒）。指定のないフィードは既定値で、0 ならキャッシュしない
+    pub ttl_seconds: BTreeMap<String, u64>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            capacity: crate::skeleton_cache::DEFAULT_CAPACITY,
+            persist: false,
+            ttl_seconds: BTreeMap::new(),
+        }
+    }
+}
+
+impl CacheConfig {
+    /// 既定値に `ttl_seconds` を重ねた、フィードごとの TTL
+    pub fn ttls(&self) -> BTreeMap<String, Duration> {
+        DEFAULT_SKELETON_TTL_SECONDS
+            .iter()
+            .map(|(feed, seconds)| (feed.to_string(), *seconds))
+            .chain(self.ttl_seconds.clone())
+            .map(|(feed, seconds)| (feed, Duration::from_secs(seconds)))
+            .collect()
+    }
+}
+
+/// 設定の誤り。見つかったものをまとめて返す
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration")?;
+        for problem in &self.0 {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// 設定ファイルと環境変数から読み込み、検証する
+    pub fn load() -> Result<Self, ConfigError> {
+        let path = match std::env::var("CONFIG_FILE") {
+            Ok(path) => Some(PathBuf::from(path)),
+            Err(_) => Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|path| path.exists()),
+        };
+        let toml = match &path {
+            Some(path) => Some(read_file(path)?),
+            None => None,
+        };
+        Self::from_sources(toml.as_deref(), |name| std::env::var(name).ok())
+    }
+
+    /// `toml` の内容を `env` で上書きし、検証する
+    pub fn from_sources<F>(toml: Option<&str>, env: F) -> Result<Self, ConfigError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut config: Config = match toml {
+            Some(toml) => toml::from_str(toml)
+                .map_err(|e| ConfigError(vec![format!("config file: {}", e)]))?,
+            None => Config::default(),
+        };
+
+        let mut overrides = Overrides {
+            env,
+            problems: Vec::new(),
+        };
+        config.apply_env(&mut overrides);
+
+        let mut problems = overrides.problems;
+        problems.extend(config.problems());
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError(problems))
+        }
+    }
+
+    /// `did:web:{hostname}`
+    pub fn service_did(&self) -> String {
+        format!("did:web:{}", self.server.hostname)
+    }
+
+    pub fn app_config(&self) -> crate::state::AppConfig {
+        let privatelist_url = &self.services.privatelist_url;
+        crate::state::AppConfig {
+            service_did: self.service_did(),
+            hostname: self.server.hostname.clone(),
+            privatelist_url: privatelist_url.clone(),
+            bsky_api_url: self.services.bsky_api_url.clone(),
+            client_id: format!("{}/client-metadata.json", privatelist_url),
+            redirect_uri: format!("{}/oauth/callback", privatelist_url),
+        }
+    }
+
+    fn apply_env<F: Fn(&str) -> Option<String>>(&mut self, o: &mut Overrides<F>) {
+        o.parse("PORT", &mut self.server.port);
+        o.string("APP_HOSTNAME", &mut self.server.hostname);
+        o.string("COOKIE_SECRET", &mut self.server.cookie_secret);
+
+        o.string("APP_HANDLE", &mut self.account.handle);
+        o.string("APP_PASSWORD", &mut self.account.password);
+        o.string("PDS_URL", &mut self.account.pds_url);
+
+        o.string("BSKY_API_URL", &mut self.services.bsky_api_url);
+        o.string("PLC_DIRECTORY_URL", &mut self.services.plc_directory_url);
+        o.string("PRIVATELIST_URL", &mut self.services.privatelist_url);
+
+        let db = &mut self.database;
+        o.string("HELLOWORLD_DB_URL", &mut db.helloworld);
+        o.string("REALFAKEBLUESKY_DB_URL", &mut db.realfakebluesky);
+        o.string("PRIVATELIST_DB_URL", &mut db.privatelist);
+        o.string("ONEYEARAGO_DB_URL", &mut db.oneyearago);
+        o.string("IDENTITY_DB_URL", &mut db.identity);
+        o.string("INTERACTIONS_DB_URL", &mut db.interactions);
+        o.string("JETSTREAM_DB_URL", &mut db.jetstream);
+        o.string("ANALYTICS_DB_URL", &mut db.analytics);
+        o.string("SKELETON_CACHE_DB_URL", &mut db.skeleton_cache);
+
+        let analytics = &mut self.analytics;
+        o.parse_optional("ANALYTICS_SINK", &mut analytics.sink);
+        o.optional("UMAMI_HOST", &mut analytics.umami_host);
+        o.optional("UMAMI_WEBSITE_ID", &mut analytics.umami_website_id);
+        o.optional("ANALYTICS_HASH_SECRET", &mut analytics.hash_secret);
+        o.parse(
+            "ANALYTICS_SALT_ROTATION_HOURS",
+            &mut analytics.salt_rotation_hours,
+        );
+        o.list("ANALYTICS_OPT_OUT_DIDS", &mut analytics.opt_out_dids);
+        o.string("ANALYTICS_OPT_OUT_MARKER", &mut analytics.opt_out_marker);
+
+        let ingest = &mut self.ingest;
+        o.parse("ENABLE_JETSTREAM", &mut ingest.enabled);
+        o.parse("INGEST_SOURCE", &mut ingest.source);
+        o.string("FIREHOSE_URL", &mut ingest.firehose_url);
+        o.exclusive("JETSTREAM_URL", "JETSTREAM_URLS");
+        o.list("JETSTREAM_URL", &mut ingest.jetstream_urls);
+        o.list("JETSTREAM_URLS", &mut ingest.jetstream_urls);
+        o.parse_optional(
+            "JETSTREAM_BACKFILL_MAX_HOURS",
+            &mut ingest.backfill_max_hours,
+        );
+        o.parse_optional("JETSTREAM_BACKFILL_MAX_RATE", &mut ingest.backfill_max_rate);
+        o.parse_optional("JETSTREAM_RECORD_FILE", &mut ingest.record_file);
+        o.parse_optional("JETSTREAM_REPLAY_FILE", &mut ingest.replay_file);
+        o.parse_optional("JETSTREAM_REPLAY_SPEED", &mut ingest.replay_speed);
+
+        let cache = &mut self.cache;
+        o.parse("SKELETON_CACHE_ENABLED", &mut cache.enabled);
+        o.parse("SKELETON_CACHE_CAPACITY", &mut cache.capacity);
+        o.parse("SKELETON_CACHE_PERSIST", &mut cache.persist);
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.server.hostname.is_empty() {
+            problems.push("server.hostname (APP_HOSTNAME) must not be empty".to_string());
+        }
+        if self.server.cookie_secret.len() < 64 {
+            problems
+                .push("server.cookie_secret (COOKIE_SECRET) must be at least 64 bytes".to_string());
+        }
+        if self.account.handle.is_empty() {
+            problems.push("account.handle (APP_HANDLE) is not set".to_string());
+        }
+        if self.account.password.is_empty() {
+            problems.push("account.password (APP_PASSWORD) is not set".to_string());
+        }
+
+        let urls = [
+            ("account.pds_url (PDS_URL)", &self.account.pds_url),
+            (
+                "services.bsky_api_url (BSKY_API_URL)",
+                &self.services.bsky_api_url,
+            ),
+            (
+                "services.plc_directory_url (PLC_DIRECTORY_URL)",
+                &self.services.plc_directory_url,
+            ),
+            (
+                "services.privatelist_url (PRIVATELIST_URL)",
+                &self.services.privatelist_url,
+            ),
+        ];
+        for (name, url) in urls {
+            if !(url.starts_with("http://") || url.starts_with("https://")) {
+                problems.push(format!("{} must be an http(s) URL, got {:?}", name, url));
+            }
+        }
+        let ws_urls = std::iter::once(&self.ingest.firehose_url)
+            .chain(&self.ingest.jetstream_urls)
+            .filter(|url| !(url.starts_with("ws://") || url.starts_with("wss://")));
+        for url in ws_urls {
+            problems.push(format!(
+                "ingest endpoints must be ws(s) URLs, got {:?}",
+                url
+            ));
+        }
+
+        // 0 以下だと保存済みカーソルがすべて範囲外になり、遡らずに最新から始めてしまう
+        if let Some(hours) = self.ingest.backfill_max_hours {
+            if !(1..=MAX_BACKFILL_HOURS).contains(&hours) {
+                problems.push(format!(
+                    "ingest.backfill_max_hours (JETSTREAM_BACKFILL_MAX_HOURS) must be between 1 and {}, got {}",
+                    MAX_BACKFILL_HOURS, hours
+                ));
+            }
+        }
+        // ファイアホースのカーソルは seq なので、時間で遡る範囲は指定できない
+        if self.ingest.source == IngestSourceKind::Firehose
+            && self.ingest.backfill_max_hours.is_some()
+        {
+            problems.push(
+                "ingest.backfill_max_hours (JETSTREAM_BACKFILL_MAX_HOURS) cannot be used with source = \"firehose\""
+                    .to_string(),
+            );
+        }
+
+        if self.analytics.sink() == AnalyticsSinkKind::Umami
+            && (self.analytics.umami_host.is_none() || self.analytics.umami_website_id.is_none())
+        {
+            problems.push(
+                "analytics.sink = \"umami\" requires umami_host (UMAMI_HOST) and umami_website_id (UMAMI_WEBSITE_ID)"
+                    .to_string(),
+            );
+        }
+        if let Some(secret) = &self.analytics.hash_secret {
+            if secret.len() < crate::analytics::privacy::MIN_SECRET_LEN
+                || secret.contains("change-me")
+            {
+                problems.push(format!(
+                    "analytics.hash_secret (ANALYTICS_HASH_SECRET) must be a random string of at least {} bytes, not a placeholder",
+                    crate::analytics::privacy::MIN_SECRET_LEN
+                ));
+            }
+        }
+        // 1 日の途中で仮名が変わると、同じ人が別人として数えられる
+        if self.analytics.salt_rotation_hours == 0
+            || !self.analytics.salt_rotation_hours.is_multiple_of(24)
+        {
+            problems.push(
+                "analytics.salt_rotation_hours (ANALYTICS_SALT_ROTATION_HOURS) must be a positive multiple of 24"
+                    .to_string(),
+            );
+        }
+
+        if self.cache.capacity == 0 {
+            problems.push("cache.capacity (SKELETON_CACHE_CAPACITY) must be positive".to_string());
+        }
+        let feeds = crate::feed::FeedRegistry::builtin();
+        for feed in self.cache.ttl_seconds.keys() {
+            if feeds.get(feed).is_none() {
+                problems.push(format!("cache.ttl_seconds: unknown feed {:?}", feed));
+            }
+        }
+
+        problems
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, ConfigError> {
+    std::fs::read_to_string(path).map_err(|e| {
+        ConfigError(vec![format!(
+            "failed to read config file {}: {}",
+            path.display(),
+            e
+        )])
+    })
+}
+
+/// 環境変数での上書き。値が読めなければ、どの変数かを添えて記録する
+struct Overrides<F> {
+    env: F,
+    problems: Vec<String>,
+}
+
+impl<F: Fn(&str) -> Option<String>> Overrides<F> {
+    fn string(&mut self, name: &str, target: &mut String) {
+        if let Some(value) = (self.env)(name) {
+            *target = value;
+        }
+    }
+
+    /// 空文字列なら未指定に戻す
+    fn optional(&mut self, name: &str, target: &mut Option<String>) {
+        if let Some(value) = (self.env)(name) {
+            *target = Some(value).filter(|v| !v.is_empty());
+        }
+    }
+
+    /// 同じ項目を指す 2 つの変数が両方あれば、どちらが効くかわからないので誤りにする
+    fn exclusive(&mut self, a: &str, b: &str) {
+        if (self.env)(a).is_some() && (self.env)(b).is_some() {
+            self.problems
+                .push(format!("{} and {} must not both be set", a, b));
+        }
+    }
+
+    /// カンマ区切り
+    fn list(&mut self, name: &str, target: &mut Vec<String>) {
+        if let Some(value) = (self.env)(name) {
+            *target = value
+                .split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect();
+        }
+    }
+
+    fn parse<T: FromStr>(&mut self, name: &str, target: &mut T)
+    where
+        T::Err: fmt::Display,
+    {
+        if let Some(value) = (self.env)(name) {
+            match value.trim().parse() {
+                Ok(parsed) => *target = parsed,
+                Err(e) => self
+                    .problems
+                    .push(format!("{}: invalid value {:?} ({})", name, value, e)),
+            }
+        }
+    }
+
+    /// 空文字列なら未指定に戻す
+    fn parse_optional<T: FromStr>(&mut self, name: &str, target: &mut Option<T>)
+    where
+        T::Err: fmt::Display,
+    {
+        match (self.env)(name) {
+            Some(value) if value.trim().is_empty() => *target = None,
+            Some(value) => match value.trim().parse() {
+                Ok(parsed) => *target = Some(parsed),
+                Err(e) => self
+                    .problems
+                    .push(format!("{}: invalid value {:?} ({})", name, value, e)),
+            },
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    const CREDENTIALS: &[(&str, &str)] =
+        &[("APP_HANDLE", "feeds.example.com"), ("APP_PASSWORD", "pw")];
+
+    /// TOML の値を読み、環境変数が優先されるか
+    #[test]
+    fn test_toml_with_env_overrides() {
+        let toml = r#"
+            [server]
+            port = 8080
+            hostname = "feeds.example.com"
+
+            [account]
+            handle = "feeds.example.com"
+            password = "from-file"
+
+            [ingest]
+            source = "firehose"
+            jetstream_urls = ["wss://a.example.com/subscribe"]
+
+            [analytics]
+            sink = "sqlite"
+            opt_out_dids = ["did:plc:alice"]
+
+            [cache.ttl_seconds]
+            todoapp = 0
+            privatelist = 120
+        "#;
+        let config = Config::from_sources(
+            Some(toml),
+            env(&[
+                ("PORT", "9090"),
+                ("APP_PASSWORD", "from-env"),
+                (
+                    "JETSTREAM_URLS",
+                    "wss://b.example.com/subscribe, wss://c.example.com/subscribe",
+                ),
+                ("JETSTREAM_REPLAY_SPEED", "2.5"),
+                ("SKELETON_CACHE_PERSIST", "true"),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(config.server.port, 9090);
+        assert_eq!(config.service_did(), "did:web:feeds.example.com");
+        assert_eq!(config.account.password, "from-env");
+        assert_eq!(config.ingest.source, IngestSourceKind::Firehose);
+        assert_eq!(
+            config.ingest.jetstream_urls,
+            vec![
+                "wss://b.example.com/subscribe".to_string(),
+                "wss://c.example.com/subscribe".to_string()
+            ]
+        );
+        assert_eq!(config.ingest.replay_speed, Some(2.5));
+        assert_eq!(config.analytics.sink(), AnalyticsSinkKind::Sqlite);
+        assert_eq!(
+            config.analytics.opt_out_dids,
+            vec!["did:plc:alice".to_string()]
+        );
+        assert!(config.cache.persist);
+        let ttls = config.cache.ttls();
+        assert_eq!(ttls["todoapp"], Duration::ZERO);
+        assert_eq!(ttls["privatelist"], Duration::from_secs(120));
+        assert_eq!(ttls["helloworld"], Duration::from_secs(30));
+        // 指定のない項目は既定値
+        assert_eq!(config.services.bsky_api_url, "https://api.bsky.app");
+        assert!(config.ingest.enabled);
+    }
+
+    /// 同梱の設定ファイルの例がそのまま読めるか
+    #[test]
+    fn test_example_file() {
+        let config =
+            Config::from_sources(Some(include_str!("../config.example.toml")), env(&[])).unwrap();
+        assert_eq!(config.service_did(), "did:web:feeds.bsky.girigiribauer.com");
+    }
+
+    /// Umami の設定が揃っているときだけ、既定の書き出し先が umami になるか
+    #[test]
+    fn test_analytics_sink_default() {
+        let config = Config::from_sources(None, env(CREDENTIALS)).unwrap();
+        assert_eq!(config.analytics.sink(), AnalyticsSinkKind::None);
+
+        let mut vars = CREDENTIALS.to_vec();
+        vars.push(("UMAMI_HOST", "https://umami.example.com"));
+        vars.push(("UMAMI_WEBSITE_ID", "site"));
+        let config = Config::from_sources(None, env(&vars)).unwrap();
+        assert_eq!(config.analytics.sink(), AnalyticsSinkKind::Umami);
+    }
+
+    /// 遡る時間は 1 から上限までの範囲だけ受け付けるか
+    #[test]
+    fn test_backfill_max_hours_range() {
+        for (hours, ok) in [("-1", false), ("0", false), ("24", true), ("100000", false)] {
+            let mut vars = CREDENTIALS.to_vec();
+            vars.push(("JETSTREAM_BACKFILL_MAX_HOURS", hours));
+            let result = Config::from_sources(None, env(&vars));
+            assert_eq!(result.is_ok(), ok, "{}: {:?}", hours, result.err());
+        }
+    }
+
+    /// 誤りをまとめて、どの項目かがわかる形で返すか
+    #[test]
+    fn test_validation_errors() {
+        let err = Config::from_sources(
+            Some("[services]\nbsky_api_url = \"api.bsky.app\"\n[cache.ttl_seconds]\nunknown = 10"),
+            env(&[
+                ("PORT", "http"),
+                ("ANALYTICS_SINK", "umami"),
+                ("INGEST_SOURCE", "firehose"),
+                ("JETSTREAM_BACKFILL_MAX_HOURS", "12"),
+                ("ANALYTICS_SALT_ROTATION_HOURS", "12"),
+                ("ANALYTICS_HASH_SECRET", "change-me"),
+                ("JETSTREAM_URL", "wss://a.example.com/subscribe"),
+                ("JETSTREAM_URLS", "wss://b.example.com/subscribe"),
+            ]),
+        )
+        .unwrap_err();
+        let message = err.to_string();
+
+        assert!(
+            message.contains("PORT: invalid value \"http\""),
+            "{}",
+            message
+        );
+        assert!(message.contains("APP_HANDLE"), "{}", message);
+        assert!(message.contains("APP_PASSWORD"), "{}", message);
+        assert!(message.contains("BSKY_API_URL"), "{}", message);
+        assert!(message.contains("UMAMI_HOST"), "{}", message);
+        assert!(message.contains("unknown feed \"unknown\""), "{}", message);
+        assert!(
+            message.contains("JETSTREAM_BACKFILL_MAX_HOURS"),
+            "{}",
+            message
+        );
+        assert!(
+            message.contains("ANALYTICS_SALT_ROTATION_HOURS"),
+            "{}",
+            message
+        );
+        assert!(message.contains("ANALYTICS_HASH_SECRET"), "{}", message);
+        assert!(
+            message.contains("JETSTREAM_URL and JETSTREAM_URLS must not both be set"),
+            "{}",
+            message
+        );
+
+        let err = Config::from_sources(Some("[server]\nprot = 1"), env(CREDENTIALS)).unwrap_err();
+        assert!(err.to_string().contains("unknown field"), "{}", err);
+    }
+}
@@ -1,55 +1,154 @@
 use axum::{
-    http::StatusCode,
+    extract::rejection::{JsonRejection, QueryRejection},
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
+use bsky_core::xrpc::XrpcError;
 use serde_json::json;
 use tracing::error;
 
+/// ハンドラーのエラー。レスポンスは XRPC の形（`{"error": "<エラー名>", "message": "..."}`）で返す
 #[derive(Debug)]
 pub enum AppError {
+    /// 401 AuthRequired
     Auth(String),
+    /// 500 InternalServerError
     Database(anyhow::Error),
+    /// 400 InvalidRequest
     BadRequest(String),
+    /// 400 UnknownFeed（getFeedSkeleton の lexicon で定義されたエラー）
+    UnknownFeed(String),
+    /// 404 NotFound
     #[allow(dead_code)]
     NotFound(String),
+    /// 500 InternalServerError
     Internal(anyhow::Error),
+    /// AppView / PDS の呼び出しの失敗（429 RateLimitExceeded / 502 UpstreamFailure）
+    Upstream(XrpcError),
+}
+
+/// エラーになったレスポンスに付ける、`AppError` の種類（メトリクス用）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorKind(pub &'static str);
+
+impl AppError {
+    pub fn kind(&self) -> ErrorKind {
+        ErrorKind(match self {
+            AppError::Auth(_) => "Auth",
+            AppError::Database(_) => "Database",
+            AppError::BadRequest(_) => "BadRequest",
+            AppError::UnknownFeed(_) => "UnknownFeed",
+            AppError::NotFound(_) => "NotFound",
+            AppError::Internal(_) => "Internal",
+            AppError::Upstream(_) => "Upstream",
+        })
+    }
+
+    /// レスポンスのステータスと、`error` に入れる XRPC のエラー名
+    ///
+    /// サービスアカウントのトークンが上流で拒否されたのはこちら側の問題なので、クライアントには 502 を返す。
+    /// 上流の 404 も、フィードが無いと読まれないよう 502 にする（フィード自体が無いときは `UnknownFeed`）
+    pub fn status(&self) -> (StatusCode, &'static str) {
+        match self {
+            AppError::Auth(_) => (StatusCode::UNAUTHORIZED, "AuthRequired"),
+            AppError::BadRequest(_) => (StatusCode::BAD_REQUEST, "InvalidRequest"),
+            AppError::UnknownFeed(_) => (StatusCode::BAD_REQUEST, "UnknownFeed"),
+            AppError::NotFound(_) => (StatusCode::NOT_FOUND, "NotFound"),
+            AppError::Database(_) | AppError::Internal(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, "InternalServerError")
+            }
+            AppError::Upstream(err) => match err {
+                XrpcError::RateLimited { .. } => {
+                    (StatusCode::TOO_MANY_REQUESTS, "RateLimitExceeded")
+                }
+                XrpcError::NotFound { .. }
+                | XrpcError::ExpiredToken { .. }
+                | XrpcError::InvalidToken { .. }
+                | XrpcError::Upstream5xx { .. }
+                | XrpcError::Decode(_)
+                | XrpcError::Transport(_)
+                | XrpcError::Request { .. } => (StatusCode::BAD_GATEWAY, "UpstreamFailure"),
+            },
+        }
+    }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            AppError::Auth(msg) => (StatusCode::UNAUTHORIZED, msg),
+        let kind = self.kind();
+        let (status, error_name) = self.status();
+        // 内部や上流の詳細はログにだけ出し、クライアントには決まった文言を返す
+        let mut retry_after = None;
+        let message = match self {
+            AppError::Auth(msg)
+            | AppError::BadRequest(msg)
+            | AppError::UnknownFeed(msg)
+            | AppError::NotFound(msg) => msg,
             AppError::Database(err) => {
                 error!("Database error: {:#}", err);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Database error".to_string(),
-                )
+                "Database error".to_string()
             }
-            AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
-            AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
             AppError::Internal(err) => {
                 error!("Internal error: {:#}", err);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Internal server error".to_string(),
-                )
+                "Internal server error".to_string()
+            }
+            AppError::Upstream(err) => match err {
+                XrpcError::RateLimited { reset, .. } => {
+                    retry_after =
+                        reset.map(|reset| (reset - chrono::Utc::now().timestamp()).max(1));
+                    "Upstream rate limit exceeded, try again later"
+                }
+                err => {
+                    error!("Upstream error: {}", err);
+                    "Upstream request failed"
+                }
             }
+            .to_string(),
         };
 
         let body = Json(json!({
-            "error": error_message,
+            "error": error_name,
+            "message": message,
         }));
 
-        (status, body).into_response()
+        let mut response = (status, body).into_response();
+        if let Some(seconds) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response.extensions_mut().insert(kind);
+        response
     }
 }
 
-// Anyhow conversion
+// Anyhow conversion（AppView の失敗なら種類を残す）
 impl From<anyhow::Error> for AppError {
     fn from(err: anyhow::Error) -> Self {
-        AppError::Internal(err)
+        match err.downcast::<XrpcError>() {
+            Ok(err) => AppError::Upstream(err),
+            Err(err) => AppError::Internal(err),
+        }
+    }
+}
+
+// クエリや JSON ボディが読めなかったときも XRPC の形で返す
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        AppError::BadRequest(rejection.body_text())
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        AppError::BadRequest(rejection.body_text())
+    }
+}
+
+impl From<XrpcError> for AppError {
+    fn from(err: XrpcError) -> Self {
+        AppError::Upstream(err)
     }
 }
 
@@ -59,3 +158,39 @@ impl From<sqlx::Error> for AppError {
         AppError::Database(anyhow::Error::from(err))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 上流のレート制限を 429 にし、解除までの秒数を Retry-After に入れるか
+    #[test]
+    fn test_upstream_rate_limit_response() {
+        let reset = chrono::Utc::now().timestamp() + 30;
+        let err: AppError = anyhow::Error::from(XrpcError::RateLimited {
+            reset: Some(reset),
+            message: "Rate Limit Exceeded".to_string(),
+        })
+        .context("Failed to fetch TODOs")
+        .into();
+        assert_eq!(err.kind(), ErrorKind("Upstream"));
+
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: i64 = response.headers()[header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=30).contains(&retry_after));
+    }
+
+    /// 上流の 404 を、フィードが無いという意味の 404 ではなく 502 にするか
+    #[test]
+    fn test_upstream_not_found_response() {
+        let err = AppError::Upstream(XrpcError::NotFound {
+            message: "Profile not found".to_string(),
+        });
+        assert_eq!(err.status(), (StatusCode::BAD_GATEWAY, "UpstreamFailure"));
+    }
+}
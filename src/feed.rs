@@ -0,0 +1,203 @@
+//! フィードの共通インターフェースとレジストリ
+//!
+//! フィードを追加するときは `Feed` を実装して `FeedRegistry::builtin` に登録するだけでよい。
+//! ルーティング・describeFeedGenerator・publish_feed はすべてこのレジストリを参照する。
+
+use crate::error::AppError;
+use crate::state::SharedState;
+use axum::async_trait;
+use bsky_core::interactions::InteractionStore;
+use std::sync::Arc;
+
+/// フィードジェネレーターレコード (`app.bsky.feed.generator`) に載せる表示用メタデータ
+#[derive(Debug, Clone, Copy)]
+pub struct FeedMetadata {
+    pub display_name: &'static str,
+    pub description: &'static str,
+    /// リポジトリルートからの相対パス
+    pub avatar: Option<&'static str>,
+}
+
+/// getFeedSkeleton の入力
+#[derive(Debug, Clone)]
+pub struct FeedRequest {
+    /// JWT 検証済みのリクエスト元 DID（未認証なら None）
+    pub requester: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl FeedRequest {
+    /// `requires_auth` なフィードから呼ぶ。ディスパッチ時に検証済みなので通常は失敗しない
+    pub fn requester_did(&self) -> Result<&str, AppError> {
+        self.requester
+            .as_deref()
+            .ok_or(AppError::Auth("Authentication required".to_string()))
+    }
+}
+
+#[async_trait]
+pub trait Feed: Send + Sync {
+    /// フィード URI (`at://{did}/app.bsky.feed.generator/{rkey}`) の rkey
+    fn rkey(&self) -> &'static str;
+
+    fn metadata(&self) -> FeedMetadata;
+
+    /// true の場合、検証済みの JWT がないリクエストは 401 になる
+    fn requires_auth(&self) -> bool {
+        true
+    }
+
+    /// true の場合、結果がリクエスト元ごとに異なるので、キャッシュのキーにリクエスト元を含める
+    fn personalized(&self) -> bool {
+        true
+    }
+
+    /// true の場合、publish_feed がフィードジェネレーターレコードに `acceptsInteractions` を載せる
+    ///
+    /// 受け取ったシグナルを使う（`demote_requested_less` を呼ぶ）フィードだけが true にする
+    fn accepts_interactions(&self) -> bool {
+        false
+    }
+
+    async fn skeleton(
+        &self,
+        state: &SharedState,
+        request: FeedRequest,
+    ) -> Result<bsky_core::FeedSkeletonResult, AppError>;
+}
+
+/// "Show less" された投稿をページ末尾に回す
+///
+/// 未認証、または取得に失敗した場合はそのまま返す
+pub async fn demote_requested_less(
+    state: &SharedState,
+    feed: &str,
+    requester: Option<&str>,
+    mut result: bsky_core::FeedSkeletonResult,
+) -> bsky_core::FeedSkeletonResult {
+    let Some(requester) = requester else {
+        return result;
+    };
+
+    let store = InteractionStore::new(state.interactions_db.clone());
+    match store.requested_less(feed, requester).await {
+        Ok(less) if !less.is_empty() => {
+            let (demoted, kept): (Vec<_>, Vec<_>) = result
+                .feed
+                .into_iter()
+                .partition(|item| less.contains(&item.post));
+            result.feed = kept.into_iter().chain(demoted).collect();
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!("Failed to load interactions for {}: {:#}", feed, e),
+    }
+
+    result
+}
+
+/// 停止・凍結・削除されたアカウントの投稿を、投稿を溜めている全フィードのテーブルから取り除く
+pub async fn purge_account(state: &SharedState, did: &str) -> anyhow::Result<u64> {
+    let mut purged = helloworld::purge_author(&state.helloworld_db, did).await?;
+    purged += realfakebluesky::purge_author(&state.realfakebluesky_db, did).await?;
+    purged += privatelist::db::purge_author_posts(&state.privatelist_db, did).await?;
+    Ok(purged)
+}
+
+/// Jetstream の identity / account イベントを反映する
+///
+/// - どちらのイベントでも DID キャッシュを破棄する（ハンドル変更や PDS の移行に追従する）
+/// - アカウントが無効になったら投稿を取り除く（再開されても取り除いた投稿は戻らない）
+pub async fn handle_lifecycle_event(state: &SharedState, event: &jetstream::LifecycleEvent) {
+    let did = event.did();
+
+    if let Err(e) = state.did_resolver.invalidate(did).await {
+        tracing::warn!("Failed to invalidate DID cache for {}: {:#}", did, e);
+    }
+
+    if event.is_account_gone() {
+        match purge_account(state, did).await {
+            Ok(purged) => {
+                tracing::info!("Purged {} posts of inactive account {}", purged, did);
+                // どのフィードのどのページに載っていたかはわからないので、まとめて捨てる
+                if purged > 0 {
+                    state.skeleton_cache.invalidate_all().await;
+                }
+            }
+            Err(e) => tracing::error!("Failed to purge posts of {}: {:#}", did, e),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct FeedRegistry {
+    feeds: Vec<Arc<dyn Feed>>,
+}
+
+impl FeedRegistry {
+    /// このサーバーで提供している全フィード
+    pub fn builtin() -> Self {
+        use crate::handlers::{
+            FakeblueskyFeed, HelloworldFeed, OneyearagoFeed, PrivatelistFeed, RealblueskyFeed,
+            TodoappFeed,
+        };
+
+        Self::default()
+            .register(HelloworldFeed)
+            .register(TodoappFeed)
+            .register(OneyearagoFeed)
+            .register(FakeblueskyFeed)
+            .register(RealblueskyFeed)
+            .register(PrivatelistFeed)
+    }
+
+    /// rkey が重複した場合は後から登録したもので置き換える
+    pub fn register(mut self, feed: impl Feed + 'static) -> Self {
+        self.feeds.retain(|f| f.rkey() != feed.rkey());
+        self.feeds.push(Arc::new(feed));
+        self
+    }
+
+    pub fn get(&self, rkey: &str) -> Option<Arc<dyn Feed>> {
+        self.feeds.iter().find(|f| f.rkey() == rkey).cloned()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<dyn Feed>> {
+        self.feeds.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 組み込みフィードがすべて登録され、rkey で引けるか
+    #[test]
+    fn test_builtin_registry() {
+        let registry = FeedRegistry::builtin();
+        let rkeys: Vec<&str> = registry.iter().map(|f| f.rkey()).collect();
+        assert_eq!(
+            rkeys,
+            vec![
+                "helloworld",
+                "todoapp",
+                "oneyearago",
+                "fakebluesky",
+                "realbluesky",
+                "privatelist"
+            ]
+        );
+
+        assert!(registry.get("helloworld").unwrap().requires_auth());
+        assert!(!registry.get("fakebluesky").unwrap().requires_auth());
+        assert!(registry.get("unknown").is_none());
+    }
+
+    /// 同じ rkey を再登録すると置き換わるか
+    #[test]
+    fn test_register_replaces_same_rkey() {
+        let registry = FeedRegistry::builtin().register(crate::handlers::HelloworldFeed);
+        assert_eq!(registry.iter().count(), 6);
+        assert_eq!(registry.iter().last().unwrap().rkey(), "helloworld");
+    }
+}
@@ -2,6 +2,7 @@ pub mod common;
 pub mod feed_generator;
 pub mod health;
 pub mod helloworld;
+pub mod metrics;
 pub mod oauth;
 pub mod oneyearago;
 pub mod privatelist;
@@ -12,6 +13,7 @@ pub use common::*;
 pub use feed_generator::*;
 pub use health::*;
 pub use helloworld::*;
+pub use metrics::*;
 pub use oauth::*;
 pub use oneyearago::*;
 pub use privatelist::*;
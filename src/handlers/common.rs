@@ -1,3 +1,5 @@
+use crate::error::AppError;
+use crate::state::SharedState;
 use serde::Serialize;
 
 #[derive(Serialize)]
@@ -20,3 +22,66 @@ pub struct DidService {
 pub async fn root() -> &'static str {
     "お試しで Bluesky のフィードを作っています https://github.com/girigiribauer/bluesky-feeds"
 }
+
+/// フィード URI (`at://{did}/app.bsky.feed.generator/{rkey}`) を (did, rkey) に分ける
+pub fn parse_feed_uri(uri: &str) -> Option<(&str, &str)> {
+    let mut parts = uri.strip_prefix("at://")?.split('/');
+    let (did, collection, rkey) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some()
+        || !did.starts_with("did:")
+        || collection != "app.bsky.feed.generator"
+        || rkey.is_empty()
+    {
+        return None;
+    }
+    Some((did, rkey))
+}
+
+/// Authorization ヘッダーのサービス間 JWT を検証し、リクエスト元の DID を返す
+///
+/// ヘッダーが無いのは未ログインの利用者なので、検証もログも省く
+pub async fn verify_requester(
+    state: &SharedState,
+    headers: &axum::http::HeaderMap,
+    lxm: Option<&str>,
+) -> Result<String, AppError> {
+    let Some(auth_header) = headers.get(axum::http::header::AUTHORIZATION) else {
+        return Err(AppError::Auth("Missing Authorization header".to_string()));
+    };
+    let auth_header = auth_header.to_str().ok();
+    state
+        .jwt_verifier
+        .verify(auth_header, lxm)
+        .await
+        .map_err(|e| {
+            tracing::warn!("JWT verification failed: {:#}", e);
+            AppError::Auth(e.to_string())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// フィードジェネレーターの AT-URI だけを受け付けるか
+    #[test]
+    fn test_parse_feed_uri() {
+        assert_eq!(
+            parse_feed_uri("at://did:plc:abc/app.bsky.feed.generator/helloworld"),
+            Some(("did:plc:abc", "helloworld"))
+        );
+        assert_eq!(
+            parse_feed_uri("at://did:plc:abc/app.bsky.feed.post/helloworld"),
+            None
+        );
+        assert_eq!(
+            parse_feed_uri("at://handle.test/app.bsky.feed.generator/helloworld"),
+            None
+        );
+        assert_eq!(
+            parse_feed_uri("at://did:plc:abc/app.bsky.feed.generator/helloworld/extra"),
+            None
+        );
+        assert_eq!(parse_feed_uri("helloworld"), None);
+    }
+}
@@ -1,20 +1,27 @@
+use crate::analytics::FeedRequestEvent;
 use crate::error::AppError;
-use crate::handlers::{
-    handle_fakebluesky, handle_helloworld, handle_oneyearago, handle_privatelist,
-    handle_realbluesky, handle_todoapp, DidResponse, DidService,
-};
-use crate::state::{FeedQuery, SharedState};
+use crate::feed::FeedRequest;
+use crate::handlers::{parse_feed_uri, verify_requester, DidResponse, DidService};
+use crate::skeleton_cache::CacheKey;
+use crate::state::{FeedQuery, SendInteractionsInput, SharedState};
 use axum::{
-    extract::{Query, State},
+    extract::{
+        rejection::{JsonRejection, QueryRejection},
+        Query, State,
+    },
     response::Json,
 };
-use bsky_core::FeedService;
+use bsky_core::interactions::InteractionStore;
+
+const GET_FEED_SKELETON_LXM: &str = "app.bsky.feed.getFeedSkeleton";
+const SEND_INTERACTIONS_LXM: &str = "app.bsky.feed.sendInteractions";
 
 pub async fn get_feed_skeleton(
     State(state): State<SharedState>,
     headers: axum::http::HeaderMap,
-    Query(params): Query<FeedQuery>,
+    query: Result<Query<FeedQuery>, QueryRejection>,
 ) -> Result<Json<bsky_core::FeedSkeletonResult>, AppError> {
+    let Query(params) = query?;
     tracing::info!(
         "Received feed request: {} (cursor={:?}, limit={:?})",
         params.feed,
@@ -23,94 +30,142 @@ pub async fn get_feed_skeleton(
     );
 
     // Analytics
-    let requester_did = match headers.get("authorization").and_then(|h| h.to_str().ok()) {
-        Some(header) => match bsky_core::extract_did_from_jwt(Some(header)) {
-            Ok(did) => did,
-            Err(e) => {
-                tracing::warn!("Failed to extract DID from Authorization header: {}", e);
-                "anonymous".to_string()
-            }
-        },
-        None => "anonymous".to_string(),
-    };
+    let requester = verify_requester(&state, &headers, Some(GET_FEED_SKELETON_LXM)).await;
+    let requester_did = requester
+        .as_ref()
+        .cloned()
+        .unwrap_or_else(|_| "anonymous".to_string());
 
     let language =
         bsky_core::get_user_language(headers.get("accept-language").and_then(|h| h.to_str().ok()))
             .unwrap_or_else(|| "en".to_string());
 
-    let cursor_state = if params.cursor.is_some() {
-        "exists"
-    } else {
-        "none"
-    };
-
     let feed_name = params
         .feed
         .split('/')
         .next_back()
         .ok_or(AppError::BadRequest("Invalid feed URI".to_string()))?;
 
-    // Construct URL with query parameters for easier filtering in Umami
-    let feed_path = format!("/feeds/{}?did={}", feed_name, requester_did);
-
-    let event_data = serde_json::json!({
-        "did": requester_did,
-        "cursor": cursor_state,
-        "language": language,
+    state.analytics.record(FeedRequestEvent {
+        feed: feed_name.to_string(),
+        did: requester_did,
+        language,
+        has_cursor: params.cursor.is_some(),
+        time_us: chrono::Utc::now().timestamp_micros(),
     });
 
-    state.umami.send_event(
-        feed_path,
-        None,
-        Some(requester_did.clone()),
-        Some(language.clone()), // Clone language as it's used above
-        Some(event_data),
-    );
+    let feed = state
+        .feeds
+        .get(feed_name)
+        .ok_or_else(|| AppError::UnknownFeed(format!("Unknown feed: {}", feed_name)))?;
+
+    let requester = match requester {
+        Ok(did) => Some(did),
+        Err(e) if feed.requires_auth() => return Err(e),
+        Err(_) => None,
+    };
+
+    let key = CacheKey {
+        feed: feed.rkey().to_string(),
+        requester: requester.clone().filter(|_| feed.personalized()),
+        cursor: params.cursor.clone(),
+        limit: params.limit,
+    };
+    let request = FeedRequest {
+        requester,
+        cursor: params.cursor,
+        limit: params.limit,
+    };
+
+    state
+        .skeleton_cache
+        .get_or_insert_with(key, || feed.skeleton(&state, request))
+        .await
+        .map(Json)
+}
 
-    let service =
-        FeedService::from_str(feed_name).ok_or(AppError::NotFound("Feed not found".to_string()))?;
+pub async fn send_interactions(
+    State(state): State<SharedState>,
+    headers: axum::http::HeaderMap,
+    input: Result<Json<SendInteractionsInput>, JsonRejection>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let requester_did = verify_requester(&state, &headers, Some(SEND_INTERACTIONS_LXM)).await?;
+    let Json(input) = input?;
+
+    let Some(feed_uri) = input.feed else {
+        tracing::debug!(
+            "Ignoring {} interactions without feed from {}",
+            input.interactions.len(),
+            requester_did
+        );
+        return Ok(Json(serde_json::json!({})));
+    };
+
+    let (publisher, feed_name) = parse_feed_uri(&feed_uri)
+        .ok_or_else(|| AppError::BadRequest(format!("Invalid feed URI: {}", feed_uri)))?;
+    // フィードジェネレーターレコードはサービスアカウントのリポジトリにある
+    let service_account = state
+        .session
+        .did()
+        .await
+        .ok_or(AppError::Internal(anyhow::anyhow!(
+            "Service not authenticated yet"
+        )))?;
+    if publisher != service_account {
+        return Err(AppError::UnknownFeed(format!("Unknown feed: {}", feed_uri)));
+    }
 
-    match service {
-        FeedService::Helloworld => handle_helloworld(state, headers, params).await,
-        FeedService::Todoapp => handle_todoapp(state, headers, params).await,
-        FeedService::Oneyearago => handle_oneyearago(state, headers, params).await,
-        FeedService::Fakebluesky => handle_fakebluesky(state, params).await,
-        FeedService::Realbluesky => handle_realbluesky(state, params).await,
-        FeedService::Privatelist => handle_privatelist(state, headers, params).await,
+    let feed = state
+        .feeds
+        .get(feed_name)
+        .ok_or_else(|| AppError::UnknownFeed(format!("Unknown feed: {}", feed_name)))?;
+
+    if !feed.accepts_interactions() {
+        return Err(AppError::BadRequest(format!(
+            "Feed {} does not accept interactions",
+            feed_name
+        )));
     }
+
+    let stored = InteractionStore::new(state.interactions_db.clone())
+        .record(feed.rkey(), &requester_did, &input.interactions)
+        .await
+        .map_err(AppError::Database)?;
+    // "Show less" を次のページから反映する
+    state
+        .skeleton_cache
+        .invalidate_requester(feed.rkey(), &requester_did)
+        .await;
+
+    tracing::info!(
+        "Stored {}/{} interactions for {} from {}",
+        stored,
+        input.interactions.len(),
+        feed_name,
+        requester_did
+    );
+
+    Ok(Json(serde_json::json!({})))
 }
 
 pub async fn describe_feed_generator(
     State(state): State<SharedState>,
 ) -> Result<Json<bsky_core::DescribeFeedGeneratorResponse>, AppError> {
-    let (did, _service_did) = {
-        let auth = state.service_auth.read().await;
-        let did = auth.did.clone().ok_or(AppError::Internal(anyhow::anyhow!(
+    let did = state
+        .session
+        .did()
+        .await
+        .ok_or(AppError::Internal(anyhow::anyhow!(
             "Service not authenticated yet"
         )))?;
-        (did.clone(), did) // logic::service_did
-    };
 
-    let feeds = vec![
-        bsky_core::FeedUri {
-            uri: format!("at://{}/app.bsky.feed.generator/helloworld", did),
-        },
-        bsky_core::FeedUri {
-            uri: format!("at://{}/app.bsky.feed.generator/todoapp", did),
-        },
-        bsky_core::FeedUri {
-            uri: format!("at://{}/app.bsky.feed.generator/oneyearago", did),
-        },
-        bsky_core::FeedUri {
-            uri: format!("at://{}/app.bsky.feed.generator/fakebluesky", did),
-        },
-        bsky_core::FeedUri {
-            uri: format!("at://{}/app.bsky.feed.generator/realbluesky", did),
-        },
-        bsky_core::FeedUri {
-            uri: format!("at://{}/app.bsky.feed.generator/privatelist", did),
-        },
-    ];
+    let feeds = state
+        .feeds
+        .iter()
+        .map(|feed| bsky_core::FeedUri {
+            uri: format!("at://{}/app.bsky.feed.generator/{}", did, feed.rkey()),
+        })
+        .collect();
 
     Ok(Json(bsky_core::DescribeFeedGeneratorResponse {
         did,
@@ -118,12 +173,10 @@ pub async fn describe_feed_generator(
     }))
 }
 
-pub async fn get_did_json(
-    State(_state): State<SharedState>,
-) -> Result<Json<DidResponse>, AppError> {
-    let hostname = "feeds.bsky.girigiribauer.com";
+pub async fn get_did_json(State(state): State<SharedState>) -> Result<Json<DidResponse>, AppError> {
+    let hostname = &state.config.hostname;
 
-    let did = format!("did:web:{}", hostname);
+    let did = state.config.service_did.clone();
     let service_endpoint = format!("https://{}", hostname);
 
     let response = DidResponse {
@@ -1,20 +1,34 @@
 use crate::error::AppError;
-use crate::state::{FeedQuery, SharedState};
-use axum::response::Json;
+use crate::feed::{Feed, FeedMetadata, FeedRequest};
+use crate::state::SharedState;
+use axum::async_trait;
 
-pub async fn handle_helloworld(
-    state: SharedState,
-    headers: axum::http::HeaderMap,
-    params: FeedQuery,
-) -> Result<Json<bsky_core::FeedSkeletonResult>, AppError> {
-    let _auth_header = headers
-        .get("authorization")
-        .and_then(|h| h.to_str().ok())
-        .ok_or(AppError::Auth(
-            "Missing or invalid authorization header".to_string(),
-        ))?;
+pub struct HelloworldFeed;
 
-    let pool = state.helloworld_db.clone();
-    let skeleton = helloworld::get_feed_skeleton(&pool, params.cursor, params.limit).await;
-    Ok(Json(skeleton))
+#[async_trait]
+impl Feed for HelloworldFeed {
+    fn rkey(&self) -> &'static str {
+        "helloworld"
+    }
+
+    fn metadata(&self) -> FeedMetadata {
+        FeedMetadata {
+            display_name: "Helloworld",
+            description: "Healthcheck test feed\n\n死活監視を兼ねたテストフィードです",
+            avatar: Some("assets/helloworld.png"),
+        }
+    }
+
+    fn personalized(&self) -> bool {
+        false
+    }
+
+    async fn skeleton(
+        &self,
+        state: &SharedState,
+        request: FeedRequest,
+    ) -> Result<bsky_core::FeedSkeletonResult, AppError> {
+        let pool = state.helloworld_db.clone();
+        Ok(helloworld::get_feed_skeleton(&pool, request.cursor, request.limit).await)
+    }
 }
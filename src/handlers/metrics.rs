@@ -0,0 +1,10 @@
+use crate::state::SharedState;
+use axum::{extract::State, http::header, response::IntoResponse};
+
+/// Prometheus のスクレイプ用
+pub async fn metrics(State(state): State<SharedState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, bsky_core::metrics::CONTENT_TYPE)],
+        state.metrics.render(),
+    )
+}
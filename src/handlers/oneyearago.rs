@@ -1,113 +1,71 @@
 use crate::error::AppError;
-use crate::state::{FeedQuery, SharedState};
-use axum::response::Json;
+use crate::feed::{demote_requested_less, Feed, FeedMetadata, FeedRequest};
+use crate::state::SharedState;
+use axum::async_trait;
 use oneyearago::cache::CacheStore;
 
-pub async fn handle_oneyearago(
-    state: SharedState,
-    headers: axum::http::HeaderMap,
-    params: FeedQuery,
-) -> Result<Json<bsky_core::FeedSkeletonResult>, AppError> {
-    let auth_header = headers
-        .get("authorization")
-        .and_then(|h| h.to_str().ok())
-        .ok_or(AppError::Auth(
-            "Missing or invalid authorization header".to_string(),
-        ))?;
+pub struct OneyearagoFeed;
 
-    // Extract DID from JWT
-    let did = bsky_core::extract_did_from_jwt(Some(auth_header))
-        .map_err(|_| AppError::Auth("Invalid JWT".to_string()))?;
-
-    // Read client and current token
-    let (client, current_token) = {
-        let auth = state.service_auth.read().await;
-        (state.http_client.clone(), auth.token.clone())
-    };
-
-    let token = current_token.ok_or(AppError::Internal(anyhow::anyhow!(
-        "Service not authenticated"
-    )))?;
+#[async_trait]
+impl Feed for OneyearagoFeed {
+    fn rkey(&self) -> &'static str {
+        "oneyearago"
+    }
 
-    let cache_store = CacheStore::new(state.oneyearago_db.clone());
-    let cache = Some(&cache_store);
+    fn metadata(&self) -> FeedMetadata {
+        FeedMetadata {
+            display_name: "OneYearAgo",
+            description: "Shows posts from this day in past years.\nYou can specify timezone in bio (e.g. \"UTC-8\", \"Asia/Tokyo\").\n\n過去の今日（1年前、2年前...）の投稿を表示するフィードです。\n日本語話者と推定されると日本時間で表示されます。\nプロフィール内にタイムゾーンで指定も可能です。(\"UTC-8\", \"Asia/Tokyo\"）\n\nhttps://en.wikipedia.org/wiki/List_of_tz_database_time_zones",
+            avatar: Some("assets/oneyearago.png"),
+        }
+    }
 
-    let results = match oneyearago::get_feed_skeleton(
-        &client,
-        auth_header,
-        &token,
-        &did,
-        params.limit.unwrap_or(30),
-        params.cursor.clone(),
-        cache,
-    )
-    .await
-    {
-        Ok(res) => Ok(Json(res)),
-        Err(e) => {
-            let err_msg = format!("{:?}", e);
-            if err_msg.contains("ExpiredToken")
-                || err_msg.contains("401")
-                || err_msg.contains("Unauthorized")
-            {
-                tracing::warn!("Token expired, attempting refresh... ({})", err_msg);
+    fn accepts_interactions(&self) -> bool {
+        true
+    }
 
-                // RE-AUTHENTICATION LOGIC
-                let handle = &state.auth_handle;
-                let password = &state.auth_password;
+    async fn skeleton(
+        &self,
+        state: &SharedState,
+        request: FeedRequest,
+    ) -> Result<bsky_core::FeedSkeletonResult, AppError> {
+        let did = request.requester_did()?.to_string();
+        let result = handle_oneyearago(state, &did, request).await?;
+        Ok(demote_requested_less(state, self.rkey(), Some(&did), result).await)
+    }
+}
 
-                if !handle.is_empty() && !password.is_empty() {
-                    match todoapp::authenticate(&client, handle, password).await {
-                        Ok((new_token, new_did)) => {
-                            tracing::info!("Token refresh successful (DID: {})", new_did);
-                            // Update state with new token
-                            {
-                                let mut auth = state.service_auth.write().await;
-                                auth.token = Some(new_token.clone());
-                                auth.did = Some(new_did);
-                            }
+async fn handle_oneyearago(
+    state: &SharedState,
+    did: &str,
+    params: FeedRequest,
+) -> Result<bsky_core::FeedSkeletonResult, AppError> {
+    let cache_store = CacheStore::new(state.oneyearago_db.clone())
+        .with_lookup_counter(state.metrics.oneyearago_cache.clone());
+    let limit = params.limit.unwrap_or(30);
 
-                            // Retry request with new token
-                            match oneyearago::get_feed_skeleton(
-                                &client,
-                                auth_header,
-                                &new_token,
-                                &did,
-                                params.limit.unwrap_or(30),
-                                params.cursor.clone(),
-                                cache,
-                            )
-                            .await
-                            {
-                                Ok(res) => Ok(Json(res)),
-                                Err(e2) => {
-                                    tracing::error!("Retry failed: {:#}", e2);
-                                    Err(AppError::Internal(anyhow::anyhow!(
-                                        "Retry failed: {:#}",
-                                        e2
-                                    )))
-                                }
-                            }
-                        }
-                        Err(reauth_err) => {
-                            tracing::error!("Re-authentication failed: {}", reauth_err);
-                            Err(AppError::Internal(anyhow::anyhow!(
-                                "Re-authentication failed"
-                            )))
-                        }
-                    }
-                } else {
-                    tracing::error!("Cannot refresh token: credentials missing");
-                    Err(AppError::BadRequest(
-                        "Credentials missing for refresh".to_string(),
-                    ))
-                }
-            } else {
-                tracing::error!("Oneyearago error: {:#}", e);
-                Err(AppError::Internal(e))
+    let results = state
+        .session
+        .with_token(|token| {
+            let cursor = params.cursor.clone();
+            let cache_store = &cache_store;
+            async move {
+                oneyearago::get_feed_skeleton(
+                    &state.appview,
+                    &token,
+                    did,
+                    limit,
+                    cursor,
+                    Some(cache_store),
+                )
+                .await
             }
-        }
-    };
+        })
+        .await
+        .map_err(|e| {
+            tracing::error!("Oneyearago error: {:#}", e);
+            AppError::from(e)
+        });
 
     // 正常終了した場合のみ、非同期でクリーンアップを実行する
     if results.is_ok() {
@@ -1,5 +1,7 @@
 use crate::error::AppError;
-use crate::state::{FeedQuery, SharedState};
+use crate::feed::{Feed, FeedMetadata, FeedRequest};
+use crate::handlers::verify_requester;
+use crate::state::SharedState;
 use axum::{
     async_trait,
     extract::{FromRequestParts, State},
@@ -43,14 +45,9 @@ impl FromRequestParts<SharedState> for AuthenticatedUser {
         }
 
         // 2. Check Header
-        if let Some(auth_header) = parts
-            .headers
-            .get("authorization")
-            .and_then(|h| h.to_str().ok())
-        {
-            if let Ok(did) = bsky_core::extract_did_from_jwt(Some(auth_header)) {
-                return Ok(AuthenticatedUser(did));
-            }
+        if parts.headers.contains_key("authorization") {
+            let did = verify_requester(state, &parts.headers, None).await?;
+            return Ok(AuthenticatedUser(did));
         }
 
         Err(AppError::Auth(
@@ -78,10 +75,8 @@ async fn authenticate_user(
     }
 
     // 2. Try Header (Bearer JWT)
-    if let Some(auth_header) = headers.get("authorization").and_then(|h| h.to_str().ok()) {
-        if let Ok(did) = bsky_core::extract_did_from_jwt(Some(auth_header)) {
-            return Ok(did);
-        }
+    if headers.contains_key("authorization") {
+        return verify_requester(state, headers, None).await;
     }
 
     Err(AppError::Auth(
@@ -99,6 +94,10 @@ pub async fn privatelist_add(
     Json(payload): Json<PrivateListTarget>,
 ) -> Result<StatusCode, AppError> {
     privatelist::add_user(&state.privatelist_db, &user.0, &payload.target).await?;
+    state
+        .skeleton_cache
+        .invalidate_requester("privatelist", &user.0)
+        .await;
 
     Ok(StatusCode::OK)
 }
@@ -109,6 +108,10 @@ pub async fn privatelist_remove(
     Json(payload): Json<PrivateListTarget>,
 ) -> Result<StatusCode, AppError> {
     privatelist::remove_user(&state.privatelist_db, &user.0, &payload.target).await?;
+    state
+        .skeleton_cache
+        .invalidate_requester("privatelist", &user.0)
+        .await;
 
     Ok(StatusCode::OK)
 }
@@ -126,158 +129,120 @@ pub async fn privatelist_refresh(
     user: AuthenticatedUser,
     State(state): State<SharedState>,
 ) -> Result<StatusCode, AppError> {
-    // Read client and current token
-    let (client, current_token) = {
-        let auth = state.service_auth.read().await;
-        (state.http_client.clone(), auth.token.clone())
-    };
-
-    let token = current_token.ok_or(AppError::BadRequest(
-        "Service not authenticated".to_string(),
-    ))?;
-
-    // First attempt
-    match privatelist::refresh_list(
-        &state.privatelist_db,
-        &client,
-        &state.config.bsky_api_url,
-        &user.0,
-        &token,
-    )
-    .await
-    {
-        Ok(_) => Ok(StatusCode::OK),
-        Err(e) => {
-            let err_msg = format!("{:?}", e);
-            if err_msg.contains("ExpiredToken")
-                || err_msg.contains("401")
-                || err_msg.contains("Unauthorized")
-            {
-                tracing::warn!(
-                    "Token expired during refresh, attempting re-auth... ({})",
-                    err_msg
-                );
-
-                // RE-AUTHENTICATION LOGIC
-                let handle = &state.auth_handle;
-                let password = &state.auth_password;
-
-                if !handle.is_empty() && !password.is_empty() {
-                    match todoapp::authenticate(&client, handle, password).await {
-                        Ok((new_token, new_did_service)) => {
-                            tracing::info!(
-                                "Token refresh successful (Service DID: {})",
-                                new_did_service
-                            );
-                            {
-                                let mut auth = state.service_auth.write().await;
-                                auth.token = Some(new_token.clone());
-                                auth.did = Some(new_did_service);
-                            }
-
-                            // Retry with new token
-                            match privatelist::refresh_list(
-                                &state.privatelist_db,
-                                &client,
-                                &state.config.bsky_api_url,
-                                &user.0,
-                                &new_token,
-                            )
-                            .await
-                            {
-                                Ok(_) => Ok(StatusCode::OK),
-                                Err(e2) => {
-                                    tracing::error!("Retry refresh failed: {:#}", e2);
-                                    Err(AppError::Internal(anyhow::anyhow!(
-                                        "Retry refresh failed: {:#}",
-                                        e2
-                                    )))
-                                }
-                            }
-                        }
-                        Err(reauth_err) => {
-                            tracing::error!("Re-authentication failed: {}", reauth_err);
-                            Err(AppError::Internal(anyhow::anyhow!(
-                                "Re-authentication failed"
-                            )))
-                        }
-                    }
-                } else {
-                    tracing::error!("Cannot refresh token: credentials missing");
-                    Err(AppError::BadRequest(
-                        "Credentials missing for refresh".to_string(),
-                    ))
-                }
-            } else {
-                tracing::error!("Privatelist refresh error: {:#}", e);
-                Err(AppError::Internal(e))
+    state
+        .session
+        .with_token(|token| {
+            let user_did = &user.0;
+            let state = &state;
+            async move {
+                privatelist::refresh_list(&state.privatelist_db, &state.appview, user_did, &token)
+                    .await
             }
+        })
+        .await
+        .map_err(|e| {
+            tracing::error!("Privatelist refresh error: {:#}", e);
+            AppError::from(e)
+        })?;
+    state
+        .skeleton_cache
+        .invalidate_requester("privatelist", &user.0)
+        .await;
+
+    Ok(StatusCode::OK)
+}
+
+pub struct PrivatelistFeed;
+
+#[async_trait]
+impl Feed for PrivatelistFeed {
+    fn rkey(&self) -> &'static str {
+        "privatelist"
+    }
+
+    fn metadata(&self) -> FeedMetadata {
+        FeedMetadata {
+            display_name: "Private List",
+            description: "Shows posts only from the users on your private list.\n\n自分だけの非公開リストに追加したユーザーの投稿だけを表示するフィードです。",
+            avatar: Some("assets/privatelist.png"),
         }
     }
-}
 
-pub async fn handle_privatelist(
-    state: SharedState,
-    headers: axum::http::HeaderMap,
-    params: FeedQuery,
-) -> Result<Json<bsky_core::FeedSkeletonResult>, AppError> {
-    let auth_header = headers
-        .get("authorization")
-        .and_then(|h| h.to_str().ok())
-        .ok_or(AppError::Auth(
-            "Missing or invalid authorization header".to_string(),
-        ))?;
-
-    // Extract DID from JWT
-    let did = bsky_core::extract_did_from_jwt(Some(auth_header))
-        .map_err(|_| AppError::Auth("Invalid JWT".to_string()))?;
-
-    let res = privatelist::get_feed_skeleton(
-        &state.privatelist_db,
-        &state.http_client, // Passed but unused
-        &did,               // user_did (requester)
-        "",                 // service_token unused
-        params.cursor.clone(),
-        params.limit.unwrap_or(30),
-    )
-    .await?;
-
-    Ok(Json(res))
+    async fn skeleton(
+        &self,
+        state: &SharedState,
+        request: FeedRequest,
+    ) -> Result<bsky_core::FeedSkeletonResult, AppError> {
+        let did = request.requester_did()?;
+
+        let res = privatelist::get_feed_skeleton(
+            &state.privatelist_db,
+            did, // user_did (requester)
+            request.cursor.clone(),
+            request.limit.unwrap_or(30),
+        )
+        .await?;
+
+        Ok(res)
+    }
 }
 
 #[cfg(test)]
+#[allow(clippy::items_after_test_module)]
 mod tests {
     use super::*;
-    use crate::analytics::UmamiClient;
-    use crate::state::{AppState, ServiceAuth};
+    use crate::state::AppState;
+    use bsky_core::appview::{AppViewClient, Session};
+    use bsky_core::jwt::JwtVerifier;
+    use bsky_core::resolver::DidResolver;
+    use bsky_core::session::ServiceSession;
     use std::sync::Arc;
-    use tokio::sync::RwLock;
 
     async fn create_test_state() -> SharedState {
         use crate::state::AppConfig;
         let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
         privatelist::migrate(&pool).await.unwrap();
+        bsky_core::resolver::migrate(&pool).await.unwrap();
+        bsky_core::interactions::migrate(&pool).await.unwrap();
+        let did_resolver = DidResolver::new(reqwest::Client::new(), pool.clone());
+        let appview = AppViewClient::new(reqwest::Client::new(), "https://api.bsky.app");
 
         AppState {
             config: AppConfig {
+                service_did: "did:web:feeds.bsky.girigiribauer.com".to_string(),
+                hostname: "feeds.bsky.girigiribauer.com".to_string(),
                 privatelist_url: "http://localhost:3000".to_string(),
                 bsky_api_url: "https://api.bsky.app".to_string(),
                 client_id: "http://localhost:3000/client-metadata.json".to_string(),
                 redirect_uri: "http://localhost:3000/oauth/callback".to_string(),
             },
+            feeds: crate::feed::FeedRegistry::builtin(),
             helloworld: helloworld::State::default(),
-            http_client: reqwest::Client::new(),
-            service_auth: Arc::new(RwLock::new(ServiceAuth {
-                token: Some("test_token".to_string()),
-                did: Some("did:plc:test".to_string()),
-            })),
-            auth_handle: "test_handle".to_string(),
-            auth_password: "test_password".to_string(),
+            http_client: reqwest::Client::new().into(),
+            appview: appview.clone(),
+            did_resolver: did_resolver.clone(),
+            jwt_verifier: JwtVerifier::new(
+                "did:web:feeds.bsky.girigiribauer.com".to_string(),
+                Arc::new(did_resolver),
+            ),
+            session: Arc::new(
+                ServiceSession::new(appview.clone(), "test_handle", "test_password").with_session(
+                    Session {
+                        did: "did:plc:test".to_string(),
+                        access_jwt: "test_token".to_string(),
+                        refresh_jwt: "test_refresh_token".to_string(),
+                    },
+                ),
+            ),
             helloworld_db: pool.clone(),
             realfakebluesky_db: pool.clone(),
             privatelist_db: pool.clone(),
-            oneyearago_db: pool,
-            umami: UmamiClient::new("http://localhost".to_string(), "site_id".to_string(), None),
+            oneyearago_db: pool.clone(),
+            identity_db: pool.clone(),
+            interactions_db: pool,
+            analytics: crate::analytics::Analytics::disabled(),
+            skeleton_cache: Arc::new(crate::skeleton_cache::SkeletonCache::disabled()),
+            metrics: crate::metrics::Metrics::new(),
             key: axum_extra::extract::cookie::Key::generate(),
         }
     }
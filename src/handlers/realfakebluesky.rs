@@ -1,45 +1,101 @@
 use crate::error::AppError;
-use crate::state::{FeedQuery, SharedState};
-use axum::response::Json;
-
-pub async fn handle_fakebluesky(
-    state: SharedState,
-    params: FeedQuery,
-) -> Result<Json<bsky_core::FeedSkeletonResult>, AppError> {
-    let skeleton = realfakebluesky::get_fake_feed_skeleton(
-        &state.realfakebluesky_db,
-        params.limit.unwrap_or(30),
-        params.cursor.clone(),
-    )
-    .await?;
-
-    Ok(Json(bsky_core::FeedSkeletonResult {
-        feed: skeleton
-            .feed
-            .into_iter()
-            .map(|item| bsky_core::FeedItem { post: item.post })
-            .collect(),
-        cursor: skeleton.cursor,
-    }))
+use crate::feed::{demote_requested_less, Feed, FeedMetadata, FeedRequest};
+use crate::state::SharedState;
+use axum::async_trait;
+
+pub struct FakeblueskyFeed;
+
+#[async_trait]
+impl Feed for FakeblueskyFeed {
+    fn rkey(&self) -> &'static str {
+        "fakebluesky"
+    }
+
+    fn metadata(&self) -> FeedMetadata {
+        FeedMetadata {
+            display_name: "Fake Bluesky",
+            description: "Fake Bluesky feed\n\nBluesky と言いながら実は青空の写真ではないフィード",
+            avatar: Some("assets/fakebluesky.png"),
+        }
+    }
+
+    fn requires_auth(&self) -> bool {
+        false
+    }
+
+    fn accepts_interactions(&self) -> bool {
+        true
+    }
+
+    async fn skeleton(
+        &self,
+        state: &SharedState,
+        request: FeedRequest,
+    ) -> Result<bsky_core::FeedSkeletonResult, AppError> {
+        let skeleton = realfakebluesky::get_fake_feed_skeleton(
+            &state.realfakebluesky_db,
+            request.limit.unwrap_or(30),
+            request.cursor,
+        )
+        .await?;
+
+        Ok(demote_requested_less(
+            state,
+            self.rkey(),
+            request.requester.as_deref(),
+            into_feed_skeleton(skeleton),
+        )
+        .await)
+    }
+}
+
+pub struct RealblueskyFeed;
+
+#[async_trait]
+impl Feed for RealblueskyFeed {
+    fn rkey(&self) -> &'static str {
+        "realbluesky"
+    }
+
+    fn metadata(&self) -> FeedMetadata {
+        FeedMetadata {
+            display_name: "Real Bluesky",
+            description: "Real Bluesky feed\n\n正真正銘の青空の写真だけを集めたフィード",
+            avatar: Some("assets/realbluesky.png"),
+        }
+    }
+
+    fn requires_auth(&self) -> bool {
+        false
+    }
+
+    fn personalized(&self) -> bool {
+        false
+    }
+
+    async fn skeleton(
+        &self,
+        state: &SharedState,
+        request: FeedRequest,
+    ) -> Result<bsky_core::FeedSkeletonResult, AppError> {
+        let skeleton = realfakebluesky::get_real_feed_skeleton(
+            &state.realfakebluesky_db,
+            request.limit.unwrap_or(30),
+            request.cursor,
+        )
+        .await?;
+
+        Ok(into_feed_skeleton(skeleton))
+    }
 }
 
-pub async fn handle_realbluesky(
-    state: SharedState,
-    params: FeedQuery,
-) -> Result<Json<bsky_core::FeedSkeletonResult>, AppError> {
-    let skeleton = realfakebluesky::get_real_feed_skeleton(
-        &state.realfakebluesky_db,
-        params.limit.unwrap_or(30),
-        params.cursor.clone(),
-    )
-    .await?;
-
-    Ok(Json(bsky_core::FeedSkeletonResult {
+fn into_feed_skeleton(skeleton: realfakebluesky::FeedSkeleton) -> bsky_core::FeedSkeletonResult {
+    bsky_core::FeedSkeletonResult {
         feed: skeleton
             .feed
             .into_iter()
-            .map(|item| bsky_core::FeedItem { post: item.post })
+            .map(|item| bsky_core::FeedItem::new(item.post))
             .collect(),
         cursor: skeleton.cursor,
-    }))
+    }
 }
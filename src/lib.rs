@@ -1,12 +1,17 @@
 pub mod analytics;
+pub mod config;
 pub mod error;
+pub mod feed;
 pub mod handlers;
+pub mod metrics;
+pub mod skeleton_cache;
 pub mod state;
 
 use axum::{
     body::Body,
     extract::Host,
     http::{Request, StatusCode},
+    middleware,
     response::IntoResponse,
     routing::{get, post},
     Router,
@@ -55,15 +60,24 @@ fn create_feed_router(state: SharedState) -> Router {
     Router::new()
         .route("/", get(handlers::root))
         .route("/health", get(handlers::health))
+        .route("/metrics", get(handlers::metrics))
         .route(
             "/xrpc/app.bsky.feed.getFeedSkeleton",
             get(handlers::get_feed_skeleton),
         )
+        .route(
+            "/xrpc/app.bsky.feed.sendInteractions",
+            post(handlers::send_interactions),
+        )
         .route(
             "/xrpc/app.bsky.feed.describeFeedGenerator",
             get(handlers::describe_feed_generator),
         )
         .route("/.well-known/did.json", get(handlers::get_did_json))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            metrics::track,
+        ))
         .layer(TraceLayer::new_for_http())
         .with_state(state)
 }
@@ -83,6 +97,10 @@ fn create_webui_router(state: SharedState) -> Router {
         .route("/oauth/login", get(handlers::login))
         .route("/oauth/callback", get(handlers::callback))
         .route("/oauth/logout", get(handlers::logout))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            metrics::track,
+        ))
         .route_service("/", ServeFile::new("webui/dist/index.html"))
         // Static files with Fallback for SPA (History API Fallback)
         .fallback_service(
@@ -108,3 +126,23 @@ pub async fn connect_database(url: &str) -> anyhow::Result<SqlitePool> {
 
     Ok(pool)
 }
+
+/// `task` を `period` ごとに実行し続ける（古い行の削除など）。消した件数をログに出す
+pub fn spawn_periodic<F, Fut>(name: &'static str, period: std::time::Duration, task: F)
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = anyhow::Result<u64>> + Send,
+{
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            match task().await {
+                Ok(n) if n > 0 => tracing::info!("[{}] Cleaned up {} rows", name, n),
+                Ok(_) => {}
+                Err(e) => tracing::warn!("[{}] Cleanup error: {:#}", name, e),
+            }
+        }
+    });
+}
@@ -2,7 +2,6 @@ use bluesky_feeds::app;
 use bluesky_feeds::state::AppState;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::RwLock;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[tokio::main]
@@ -18,144 +17,359 @@ async fn main() -> anyhow::Result<()> {
 
     tracing::info!("Log initialized");
 
-    // Authenticate with Bluesky (Service Auth)
-    let handle = std::env::var("APP_HANDLE").unwrap_or_default();
-    let password = std::env::var("APP_PASSWORD").unwrap_or_default();
-
-    if password.is_empty() {
-        println!("Error: APP_PASSWORD environment variable is not set.");
-        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-        panic!("APP_PASSWORD is missing");
-    }
+    // Load configuration (config file + environment variables)
+    let config = match bluesky_feeds::config::Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            println!("Error: {}", e);
+            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+            return Err(e.into());
+        }
+    };
+    let handle = config.account.handle.clone();
+    let password = config.account.password.clone();
 
     // Initialize Database
-    let database_url = std::env::var("HELLOWORLD_DB_URL")
-        .unwrap_or_else(|_| "sqlite:data/helloworld.db".to_string());
+    let database_url = &config.database.helloworld;
     tracing::info!("Connecting to database: {}", database_url);
 
-    let helloworld_db = bluesky_feeds::connect_database(&database_url).await?;
+    let helloworld_db = bluesky_feeds::connect_database(database_url).await?;
     helloworld::migrate(&helloworld_db).await?;
 
     // Initialize Real Fake Bluesky Database
-    let realfakebluesky_db_url = std::env::var("REALFAKEBLUESKY_DB_URL")
-        .unwrap_or_else(|_| "sqlite:data/fakebluesky.db".to_string());
+    let realfakebluesky_db_url = &config.database.realfakebluesky;
     tracing::info!(
         "Connecting to realfakebluesky database: {}",
         realfakebluesky_db_url
     );
-    let realfakebluesky_db = bluesky_feeds::connect_database(&realfakebluesky_db_url).await?;
+    let realfakebluesky_db = bluesky_feeds::connect_database(realfakebluesky_db_url).await?;
     realfakebluesky::migrate(&realfakebluesky_db).await?;
 
     // Initialize Private List Database
-    let privatelist_db_url = std::env::var("PRIVATELIST_DB_URL")
-        .unwrap_or_else(|_| "sqlite:data/privatelist.db".to_string());
+    let privatelist_db_url = &config.database.privatelist;
     tracing::info!("Connecting to privatelist database: {}", privatelist_db_url);
-    let privatelist_db = bluesky_feeds::connect_database(&privatelist_db_url).await?;
+    let privatelist_db = bluesky_feeds::connect_database(privatelist_db_url).await?;
     privatelist::migrate(&privatelist_db).await?;
 
     // Initialize OneYearAgo Database
-    let oneyearago_db_url = std::env::var("ONEYEARAGO_DB_URL")
-        .unwrap_or_else(|_| "sqlite:data/oneyearago.db".to_string());
+    let oneyearago_db_url = &config.database.oneyearago;
     tracing::info!(
         "Connecting to oneyearago cache database: {}",
         oneyearago_db_url
     );
-    let oneyearago_db = bluesky_feeds::connect_database(&oneyearago_db_url).await?;
+    let oneyearago_db = bluesky_feeds::connect_database(oneyearago_db_url).await?;
     oneyearago::cache::migrate(&oneyearago_db).await?;
 
+    // Initialize Identity (DID cache) Database
+    let identity_db_url = &config.database.identity;
+    tracing::info!("Connecting to identity database: {}", identity_db_url);
+    let identity_db = bluesky_feeds::connect_database(identity_db_url).await?;
+    bsky_core::resolver::migrate(&identity_db).await?;
+
+    // Initialize Interactions Database
+    let interactions_db_url = &config.database.interactions;
+    tracing::info!(
+        "Connecting to interactions database: {}",
+        interactions_db_url
+    );
+    let interactions_db = bluesky_feeds::connect_database(interactions_db_url).await?;
+    bsky_core::interactions::migrate(&interactions_db).await?;
+    let interaction_store = bsky_core::interactions::InteractionStore::new(interactions_db.clone());
+    bluesky_feeds::spawn_periodic(
+        "interactions",
+        std::time::Duration::from_secs(3600),
+        move || {
+            let store = interaction_store.clone();
+            async move {
+                store
+                    .prune(bsky_core::interactions::DEFAULT_RETENTION)
+                    .await
+            }
+        },
+    );
+
     // Initialize HTTP Client
-    let http_client = reqwest::Client::builder()
-        .user_agent("BlueskyFeedGenerator/1.0 (girigiribauer.com)")
-        .build()
-        .expect("Failed to build HTTP client");
+    let http_client = bsky_core::http::HttpClient::new(
+        reqwest::Client::builder()
+            .user_agent("BlueskyFeedGenerator/1.0 (girigiribauer.com)")
+            .build()
+            .expect("Failed to build HTTP client"),
+    );
+
+    let appview =
+        bsky_core::appview::AppViewClient::new(http_client.clone(), &config.services.bsky_api_url)
+            .with_pds_url(&config.account.pds_url);
 
     // Perform initial authentication
-    let (initial_token, initial_did) = if !handle.is_empty() && !password.is_empty() {
-        match todoapp::authenticate(&http_client, &handle, &password).await {
-            Ok((token, did)) => {
-                tracing::info!("Initial authentication successful (DID: {})", did);
-                (Some(token), Some(did))
-            }
-            Err(e) => {
-                tracing::warn!("Initial authentication failed: {}. Feeds requiring auth will fail until first request triggers re-auth.", e);
-                (None, None)
-            }
+    let session = bsky_core::session::ServiceSession::new(appview.clone(), handle, password);
+    match session.login().await {
+        Ok(did) => tracing::info!("Initial authentication successful (DID: {})", did),
+        Err(e) => tracing::warn!(
+            "Initial authentication failed: {:#}. Feeds requiring auth will log in again on first request.",
+            e
+        ),
+    }
+
+    let did_resolver =
+        bsky_core::resolver::DidResolver::new(http_client.clone(), identity_db.clone())
+            .with_plc_directory_url(config.services.plc_directory_url.clone());
+    let jwt_verifier =
+        bsky_core::jwt::JwtVerifier::new(config.service_did(), Arc::new(did_resolver.clone()));
+
+    // Initialize Analytics
+    use bluesky_feeds::config::AnalyticsSinkKind;
+    let analytics_config = &config.analytics;
+    let sink: Arc<dyn bluesky_feeds::analytics::AnalyticsSink> = match analytics_config.sink() {
+        AnalyticsSinkKind::Umami => Arc::new(bluesky_feeds::analytics::UmamiSink::new(
+            http_client.clone(),
+            analytics_config.umami_host.clone().unwrap_or_default(),
+            analytics_config
+                .umami_website_id
+                .clone()
+                .unwrap_or_default(),
+            Some(config.server.hostname.clone()),
+        )),
+        AnalyticsSinkKind::Sqlite => {
+            let analytics_db_url = &config.database.analytics;
+            tracing::info!("Connecting to analytics database: {}", analytics_db_url);
+            let analytics_db = bluesky_feeds::connect_database(analytics_db_url).await?;
+            bluesky_feeds::analytics::sqlite::migrate(&analytics_db).await?;
+            let sqlite_sink = bluesky_feeds::analytics::SqliteSink::new(analytics_db);
+            let retention_sink = sqlite_sink.clone();
+            bluesky_feeds::spawn_periodic(
+                "analytics",
+                std::time::Duration::from_secs(3600),
+                move || {
+                    let sink = retention_sink.clone();
+                    async move {
+                        sink.prune(bluesky_feeds::analytics::sqlite::DEFAULT_RETENTION)
+                            .await
+                    }
+                },
+            );
+            Arc::new(sqlite_sink)
         }
-    } else {
-        tracing::warn!("No credentials provided. Feeds requiring auth will fail.");
-        (None, None)
+        AnalyticsSinkKind::Stdout => Arc::new(bluesky_feeds::analytics::StdoutSink),
+        AnalyticsSinkKind::None => Arc::new(bluesky_feeds::analytics::NoopSink),
     };
+    let sink: Arc<dyn bluesky_feeds::analytics::AnalyticsSink> = if analytics_config.sink()
+        == AnalyticsSinkKind::None
+    {
+        sink
+    } else {
+        // DID は仮名化し、オプトアウトした人は除いてから書き出す
+        let rotation = analytics_config.salt_rotation();
+        let pseudonymizer = match &analytics_config.hash_secret {
+            Some(secret) => {
+                bluesky_feeds::analytics::Pseudonymizer::new(secret.as_bytes(), rotation)
+            }
+            None => {
+                tracing::warn!(
+                        "analytics.hash_secret (ANALYTICS_HASH_SECRET) is not set; pseudonyms change on every restart"
+                    );
+                bluesky_feeds::analytics::Pseudonymizer::random(rotation)
+            }
+        };
 
-    let privatelist_url =
-        std::env::var("PRIVATELIST_URL").unwrap_or_else(|_| "http://localhost:3000".to_string());
-    let bsky_api_url =
-        std::env::var("BSKY_API_URL").unwrap_or_else(|_| "https://api.bsky.app".to_string());
+        let mut opt_out =
+            bluesky_feeds::analytics::OptOut::new(analytics_config.opt_out_dids.clone());
+        if !analytics_config.opt_out_marker.is_empty() {
+            opt_out = opt_out
+                .with_profile_marker(analytics_config.opt_out_marker.clone(), appview.clone());
+        }
 
-    let config = bluesky_feeds::state::AppConfig {
-        privatelist_url: privatelist_url.clone(),
-        bsky_api_url: bsky_api_url.clone(),
-        client_id: format!("{}/client-metadata.json", privatelist_url),
-        redirect_uri: format!("{}/oauth/callback", privatelist_url),
+        Arc::new(bluesky_feeds::analytics::PrivateSink::new(
+            sink,
+            pseudonymizer,
+            opt_out,
+        ))
     };
+    let metrics =
+        bluesky_feeds::metrics::Metrics::new().with_outbound(http_client.metrics().clone());
+    let analytics = bluesky_feeds::analytics::Analytics::start(
+        sink,
+        bluesky_feeds::analytics::BatchOptions::default(),
+    )
+    .with_drop_counter(metrics.analytics_dropped.clone());
+
+    // Initialize Skeleton Cache
+    let cache_config = &config.cache;
+    let mut skeleton_cache = bluesky_feeds::skeleton_cache::SkeletonCache::disabled();
+    if cache_config.enabled {
+        skeleton_cache = bluesky_feeds::skeleton_cache::SkeletonCache::new(cache_config.capacity);
+        for (feed, ttl) in cache_config.ttls() {
+            skeleton_cache = skeleton_cache.with_ttl(feed, ttl);
+        }
+        if cache_config.persist {
+            let skeleton_cache_db_url = &config.database.skeleton_cache;
+            tracing::info!(
+                "Connecting to skeleton cache database: {}",
+                skeleton_cache_db_url
+            );
+            let skeleton_cache_db = bluesky_feeds::connect_database(skeleton_cache_db_url).await?;
+            bluesky_feeds::skeleton_cache::migrate(&skeleton_cache_db).await?;
+            skeleton_cache = skeleton_cache.with_store(skeleton_cache_db);
+        }
+    }
+    let skeleton_cache =
+        Arc::new(skeleton_cache.with_lookup_counter(metrics.skeleton_cache.clone()));
+    if cache_config.enabled && cache_config.persist {
+        let cleanup_cache = skeleton_cache.clone();
+        bluesky_feeds::spawn_periodic(
+            "skeleton_cache",
+            std::time::Duration::from_secs(600),
+            move || {
+                let cache = cleanup_cache.clone();
+                async move { cache.cleanup().await.map_err(Into::into) }
+            },
+        );
+    }
 
     let app_state = AppState {
-        config,
+        config: config.app_config(),
+        feeds: bluesky_feeds::feed::FeedRegistry::builtin(),
         helloworld: helloworld::State::default(),
         http_client,
-        service_auth: Arc::new(RwLock::new(bluesky_feeds::state::ServiceAuth {
-            token: initial_token,
-            did: initial_did,
-        })),
-        auth_handle: handle,
-        auth_password: password,
+        appview,
+        did_resolver,
+        jwt_verifier,
+        session: Arc::new(session),
         helloworld_db,
         realfakebluesky_db,
         privatelist_db,
         oneyearago_db,
-        umami: bluesky_feeds::analytics::UmamiClient::new(
-            std::env::var("UMAMI_HOST").expect("UMAMI_HOST must be set"),
-            std::env::var("UMAMI_WEBSITE_ID").expect("UMAMI_WEBSITE_ID must be set"),
-            Some(
-                std::env::var("APP_HOSTNAME")
-                    .unwrap_or_else(|_| "feeds.bsky.girigiribauer.com".to_string()),
-            ),
-        ),
-        key: axum_extra::extract::cookie::Key::from(
-             &std::env::var("COOKIE_SECRET")
-                .unwrap_or_else(|_| "very-secret-key-that-is-at-least-64-bytes-long-for-security-reasons-please-change-me".to_string())
-                .into_bytes()
-        ),
+        identity_db,
+        interactions_db,
+        analytics,
+        skeleton_cache,
+        metrics,
+        key: axum_extra::extract::cookie::Key::from(config.server.cookie_secret.as_bytes()),
     };
 
     // Start Jetstream consumer in background
-    let enable_jetstream = std::env::var("ENABLE_JETSTREAM").unwrap_or_else(|_| "true".to_string());
-    if enable_jetstream == "true" {
-        let state_for_consumer = app_state.clone();
-        tokio::spawn(async move {
-            jetstream::start_consumer(
-                state_for_consumer.realfakebluesky_db.clone(),
+    let ingest = &config.ingest;
+    if ingest.enabled {
+        let helloworld_db = app_state.helloworld_db.clone();
+        let realfakebluesky_db = app_state.realfakebluesky_db.clone();
+        let lifecycle_state = app_state.clone();
+        // 投稿が入った・消えたフィードは、キャッシュした結果を捨てる
+        let helloworld_cache = app_state.skeleton_cache.clone();
+        let realfakebluesky_cache = app_state.skeleton_cache.clone();
+        let dispatcher = jetstream::Dispatcher::new()
+            .subscribe(
+                "helloworld",
+                jetstream::SubscriberOptions::default(),
                 move |event| {
-                    let state = state_for_consumer.clone();
+                    let pool = helloworld_db.clone();
+                    let cache = helloworld_cache.clone();
                     async move {
-                        // Process event for helloworld
-                        helloworld::process_event(&state.helloworld_db, &event).await;
-
-                        // Process event for realfakebluesky
-                        realfakebluesky::process_event(&state.realfakebluesky_db, &event).await;
+                        let change = helloworld::process_event(&pool, &event).await;
+                        for feed in change.affected() {
+                            cache.invalidate_feed(feed).await;
+                        }
+                        change
+                    }
+                },
+            )
+            // 画像のダウンロードを伴うので、並列に処理してキューも深めに取る
+            .subscribe(
+                "realfakebluesky",
+                jetstream::SubscriberOptions {
+                    queue_capacity: 1024,
+                    concurrency: 4,
+                    ..Default::default()
+                },
+                move |event| {
+                    let pool = realfakebluesky_db.clone();
+                    let cache = realfakebluesky_cache.clone();
+                    async move {
+                        let change = realfakebluesky::process_event(&pool, &event).await;
+                        for feed in change.affected() {
+                            cache.invalidate_feed(feed).await;
+                        }
+                        change
                     }
                 },
             )
-            .await;
-        });
+            // ハンドル変更・アカウント停止などを各フィードと DID キャッシュに反映する
+            .on_lifecycle(move |event| {
+                let state = lifecycle_state.clone();
+                async move { bluesky_feeds::feed::handle_lifecycle_event(&state, &event).await }
+            });
+        app_state.metrics.attach_ingest(dispatcher.clone());
+
+        let jetstream_db_url = &config.database.jetstream;
+        tracing::info!("Connecting to jetstream database: {}", jetstream_db_url);
+        let jetstream_db = bluesky_feeds::connect_database(jetstream_db_url).await?;
+        jetstream::cursor::migrate(&jetstream_db).await?;
+
+        // source = "firehose" なら Jetstream を使わず、リレーや PDS の subscribeRepos に直接つなぐ
+        let source = match ingest.source {
+            bluesky_feeds::config::IngestSourceKind::Firehose => {
+                jetstream::IngestSource::Firehose {
+                    url: ingest.firehose_url.clone(),
+                }
+            }
+            bluesky_feeds::config::IngestSourceKind::Jetstream => {
+                jetstream::IngestSource::Jetstream
+            }
+        };
+
+        // ファイアホースのカーソルは seq なので、time_us のカーソルとは別に保存する
+        let cursor_store = match source {
+            jetstream::IngestSource::Jetstream => {
+                let cursor_store = jetstream::CursorStore::new(jetstream_db, "feeds");
+                if cursor_store
+                    .import_legacy(&app_state.realfakebluesky_db)
+                    .await?
+                {
+                    tracing::info!(
+                        "Imported legacy Jetstream cursor from realfakebluesky database"
+                    );
+                }
+                cursor_store
+            }
+            jetstream::IngestSource::Firehose { .. } => {
+                jetstream::CursorStore::new(jetstream_db, "feeds-firehose")
+            }
+        };
+
+        // 保存済みカーソルからどこまで遡って追いつくか
+        let mut consumer_config = jetstream::ConsumerConfig {
+            source,
+            endpoints: jetstream::EndpointPool::new(ingest.jetstream_urls.clone()),
+            record_to: ingest.record_file.clone(),
+            ..Default::default()
+        };
+        if let Some(hours) = ingest.backfill_max_hours {
+            consumer_config.backfill.max_window = chrono::Duration::hours(hours);
+        }
+        if let Some(rate) = ingest.backfill_max_rate {
+            consumer_config.backfill.max_events_per_sec = rate;
+        }
+
+        // 指定されていれば、ライブに繋がずに保存済みのイベントを流す
+        if let Some(replay_file) = ingest.replay_file.clone() {
+            // 1.0 で記録時と同じ速度。未指定か 0 以下なら待たずに流す
+            let speed = ingest.replay_speed.filter(|factor| *factor > 0.0).map_or(
+                jetstream::ReplaySpeed::Unthrottled,
+                jetstream::ReplaySpeed::Scaled,
+            );
+            tokio::spawn(async move {
+                if let Err(e) = jetstream::start_replay(&replay_file, speed, dispatcher).await {
+                    tracing::error!("Jetstream replay failed: {}", e);
+                }
+            });
+        } else {
+            tokio::spawn(async move {
+                jetstream::start_consumer(cursor_store, consumer_config, dispatcher).await;
+            });
+        }
     } else {
-        tracing::info!("Jetstream consumer is disabled (ENABLE_JETSTREAM != true)");
+        tracing::info!("Jetstream consumer is disabled (ingest.enabled = false)");
     }
 
-    let port = std::env::var("PORT")
-        .ok()
-        .and_then(|p| p.parse().ok())
-        .unwrap_or(3000);
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let addr = SocketAddr::from(([0, 0, 0, 0], config.server.port));
 
     println!("Attempting to bind/listen on {}", addr);
     tracing::info!("Rust feed server listening on {}", addr);
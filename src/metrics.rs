@@ -0,0 +1,208 @@
+//! `/metrics` で公開するメトリクス
+//!
+//! clone しても同じ値を共有するので、`AppState` に持たせておけばどこからでも記録できる
+
+use crate::error::ErrorKind;
+use crate::state::SharedState;
+use axum::{
+    extract::{MatchedPath, Query, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use bsky_core::metrics::{Counter, Histogram, TextEncoder};
+use std::sync::{Arc, OnceLock};
+
+const GET_FEED_SKELETON_PATH: &str = "/xrpc/app.bsky.feed.getFeedSkeleton";
+
+#[derive(Clone, Default)]
+pub struct Metrics {
+    /// ルートとステータスコードごとのリクエスト数
+    http_requests: Counter,
+    /// ルートと `AppError` の種類ごとのエラー数
+    http_errors: Counter,
+    /// フィードとステータスコードごとの getFeedSkeleton の数
+    feed_requests: Counter,
+    feed_duration: Histogram,
+    /// oneyearago の `CacheStore` のヒット・ミス
+    pub oneyearago_cache: Counter,
+    /// `SkeletonCache` のヒット・ミス
+    pub skeleton_cache: Counter,
+    /// `Analytics` のキューが満杯で捨てたイベント
+    pub analytics_dropped: Counter,
+    /// `AppState::http_client` のやり直し・待ち
+    outbound: bsky_core::http::HttpMetrics,
+    /// 取り込みのディスパッチャー（Jetstream を無効にしていれば空）
+    ingest: Arc<OnceLock<jetstream::Dispatcher>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 外部 API への呼び出しのやり直し・待ちも出力する
+    pub fn with_outbound(mut self, outbound: bsky_core::http::HttpMetrics) -> Self {
+        self.outbound = outbound;
+        self
+    }
+
+    /// 取り込みのメトリクスも出力する。`AppState` より後に作るディスパッチャーを後から渡すためのもの
+    pub fn attach_ingest(&self, dispatcher: jetstream::Dispatcher) {
+        if self.ingest.set(dispatcher).is_err() {
+            tracing::warn!("Ingest metrics are already attached");
+        }
+    }
+
+    /// Prometheus のテキスト形式で書き出す
+    pub fn render(&self) -> String {
+        let mut encoder = TextEncoder::new();
+        encoder.counter(
+            "http_requests_total",
+            "HTTP requests, by route and status code.",
+            &self.http_requests,
+        );
+        encoder.counter(
+            "http_errors_total",
+            "HTTP requests that failed, by route and AppError variant.",
+            &self.http_errors,
+        );
+        encoder.counter(
+            "feed_requests_total",
+            "getFeedSkeleton requests, by feed and status code.",
+            &self.feed_requests,
+        );
+        encoder.histogram(
+            "feed_request_duration_seconds",
+            "getFeedSkeleton latency, by feed.",
+            &self.feed_duration,
+        );
+        encoder.counter(
+            "oneyearago_cache_lookups_total",
+            "oneyearago cache lookups, by cache and result.",
+            &self.oneyearago_cache,
+        );
+        encoder.counter(
+            "skeleton_cache_lookups_total",
+            "getFeedSkeleton response cache lookups, by feed and result.",
+            &self.skeleton_cache,
+        );
+        encoder.counter(
+            "analytics_events_dropped_total",
+            "Analytics events dropped because the sink queue was full.",
+            &self.analytics_dropped,
+        );
+
+        let appview = bsky_core::metrics::appview();
+        encoder.counter(
+            "appview_requests_total",
+            "Outbound AppView calls, by method.",
+            &appview.requests,
+        );
+        encoder.counter(
+            "appview_failures_total",
+            "Outbound AppView calls that failed or returned a non-2xx status, by method.",
+            &appview.failures,
+        );
+        encoder.histogram(
+            "appview_request_duration_seconds",
+            "Outbound AppView latency, by method.",
+            &appview.duration,
+        );
+        encoder.counter(
+            "outbound_retries_total",
+            "Outbound requests that were retried, by host and reason.",
+            &self.outbound.retries,
+        );
+        encoder.counter(
+            "outbound_rate_limit_waits_total",
+            "Outbound requests that waited for a rate limit to reset, by host.",
+            &self.outbound.rate_limit_waits,
+        );
+        encoder.counter(
+            "outbound_concurrency_waits_total",
+            "Outbound requests that waited for the per-host concurrency cap, by host.",
+            &self.outbound.concurrency_waits,
+        );
+        encoder.counter(
+            "outbound_deadline_exceeded_total",
+            "Outbound requests that gave up at their deadline, by host.",
+            &self.outbound.deadline_exceeded,
+        );
+
+        if let Some(dispatcher) = self.ingest.get() {
+            dispatcher.encode_metrics(&mut encoder, chrono::Utc::now().timestamp_micros());
+        }
+        encoder.finish()
+    }
+}
+
+/// ルートごとのリクエスト数・エラー、フィードごとのリクエスト数・処理時間を記録するミドルウェア
+///
+/// `route_layer` で使う（マッチしたルートのパスをラベルにするので、ラベルの種類が増えすぎない）
+pub async fn track(State(state): State<SharedState>, request: Request, next: Next) -> Response {
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unknown", |path| path.as_str())
+        .to_string();
+    // 未登録のフィード名はまとめて数える
+    let feed = (path == GET_FEED_SKELETON_PATH).then(|| {
+        requested_feed(request.uri())
+            .and_then(|name| state.feeds.get(&name))
+            .map_or("unknown", |feed| feed.rkey())
+    });
+
+    let started = std::time::Instant::now();
+    let response = next.run(request).await;
+    let elapsed = started.elapsed().as_secs_f64();
+
+    let metrics = &state.metrics;
+    let status = response.status();
+    metrics
+        .http_requests
+        .inc(&[("path", &path), ("status", status.as_str())]);
+    if let Some(ErrorKind(kind)) = response.extensions().get::<ErrorKind>() {
+        metrics.http_errors.inc(&[("path", &path), ("error", kind)]);
+    }
+    if let Some(feed) = feed {
+        metrics
+            .feed_requests
+            .inc(&[("feed", feed), ("status", status.as_str())]);
+        metrics.feed_duration.observe(&[("feed", feed)], elapsed);
+    }
+
+    response
+}
+
+#[derive(serde::Deserialize)]
+struct FeedParam {
+    feed: Option<String>,
+}
+
+/// クエリの `feed`（フィード URI）の rkey
+fn requested_feed(uri: &axum::http::Uri) -> Option<String> {
+    let Query(FeedParam { feed }) = Query::try_from_uri(uri).ok()?;
+    feed?.rsplit('/').next().map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// クエリの feed が URL エンコードを解いてから読まれるか
+    #[test]
+    fn test_requested_feed() {
+        let feed = |uri: &str| requested_feed(&uri.parse().unwrap());
+        assert_eq!(
+            feed("/xrpc/app.bsky.feed.getFeedSkeleton?limit=10&feed=at%3A%2F%2Fdid%3Aplc%3Aabc%2Fapp.bsky.feed.generator%2Fhelloworld"),
+            Some("helloworld".to_string())
+        );
+        // `+` は空白
+        assert_eq!(
+            feed("/xrpc/app.bsky.feed.getFeedSkeleton?feed=at://did:plc:abc/app.bsky.feed.generator/hello+world"),
+            Some("hello world".to_string())
+        );
+        assert_eq!(feed("/xrpc/app.bsky.feed.getFeedSkeleton?limit=10"), None);
+        assert_eq!(feed("/xrpc/app.bsky.feed.getFeedSkeleton"), None);
+    }
+}
@@ -0,0 +1,568 @@
+//! getFeedSkeleton の結果のキャッシュ
+//!
+//! - キーはフィード・リクエスト元（個人向けのフィードのみ）・カーソル・件数
+//! - フィードごとに TTL を決める。TTL のないフィードはキャッシュしない
+//! - メモリ上の LRU に持つ。SQLite を渡せばそこにも書き、再起動後やメモリから追い出された後も使う
+//! - 取り込みで新しい投稿が入ったときなどに、フィード単位・リクエスト元単位で破棄する
+//!
+//! テーブル: `skeleton_cache`
+//!   - key        : TEXT PRIMARY KEY  (`CacheKey` の JSON)
+//!   - feed       : TEXT NOT NULL
+//!   - requester  : TEXT NOT NULL     (個人向けでなければ空文字列)
+//!   - value      : TEXT NOT NULL     (`FeedSkeletonResult` の JSON)
+//!   - expires_at : INTEGER NOT NULL  (UNIX タイムスタンプミリ秒)
+
+use bsky_core::metrics::Counter;
+use bsky_core::FeedSkeletonResult;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub const DEFAULT_CAPACITY: usize = 1000;
+
+/// 必要なテーブルを作成する（冪等）
+pub async fn migrate(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS skeleton_cache (
+            key        TEXT    PRIMARY KEY,
+            feed       TEXT    NOT NULL,
+            requester  TEXT    NOT NULL,
+            value      TEXT    NOT NULL,
+            expires_at INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_skeleton_cache_feed
+            ON skeleton_cache(feed, requester);
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct CacheKey {
+    pub feed: String,
+    /// 個人向けのフィードのみ。誰に対しても同じ結果なら None
+    pub requester: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl CacheKey {
+    fn encode(&self) -> String {
+        serde_json::to_string(self).expect("CacheKey is always serializable")
+    }
+}
+
+struct Entry {
+    value: FeedSkeletonResult,
+    expires_at: i64,
+    last_used: u64,
+}
+
+/// 最後に使った順を `order` で持つ LRU
+struct Lru {
+    capacity: usize,
+    entries: HashMap<CacheKey, Entry>,
+    order: BTreeMap<u64, CacheKey>,
+    tick: u64,
+}
+
+impl Lru {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn get(&mut self, key: &CacheKey, now: i64) -> Option<FeedSkeletonResult> {
+        let entry = self.entries.get_mut(key)?;
+        if entry.expires_at <= now {
+            self.remove(key);
+            return None;
+        }
+        self.tick += 1;
+        self.order.remove(&entry.last_used);
+        self.order.insert(self.tick, key.clone());
+        entry.last_used = self.tick;
+        Some(entry.value.clone())
+    }
+
+    fn insert(&mut self, key: CacheKey, value: FeedSkeletonResult, expires_at: i64) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(&key);
+        while self.entries.len() >= self.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                expires_at,
+                last_used: self.tick,
+            },
+        );
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.last_used);
+        }
+    }
+
+    fn retain(&mut self, keep: impl Fn(&CacheKey) -> bool) {
+        let removed: Vec<CacheKey> = self.entries.keys().filter(|k| !keep(k)).cloned().collect();
+        for key in removed {
+            self.remove(&key);
+        }
+    }
+}
+
+/// メモリ上の結果と、フィードごとの世代（同じロックの下で見る）
+struct Memory {
+    lru: Lru,
+    /// フィードの結果を（リクエスト元単位でも）破棄するたびに増やす。計算している間に変わったら、その結果は入れない
+    generations: HashMap<String, u64>,
+}
+
+impl Memory {
+    fn generation(&self, feed: &str) -> u64 {
+        self.generations.get(feed).copied().unwrap_or(0)
+    }
+
+    fn bump(&mut self, feed: &str) {
+        *self.generations.entry(feed.to_string()).or_default() += 1;
+    }
+}
+
+pub struct SkeletonCache {
+    ttls: HashMap<String, Duration>,
+    memory: Mutex<Memory>,
+    store: Option<SqlitePool>,
+    lookups: Option<Counter>,
+}
+
+impl SkeletonCache {
+    /// メモリに `capacity` 件まで持つ。フィードごとの TTL は `with_ttl` で決める
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            ttls: HashMap::new(),
+            memory: Mutex::new(Memory {
+                lru: Lru::new(capacity),
+                generations: HashMap::new(),
+            }),
+            store: None,
+            lookups: None,
+        }
+    }
+
+    /// 何もキャッシュしない
+    pub fn disabled() -> Self {
+        Self::new(0)
+    }
+
+    /// `feed` の結果を `ttl` の間使い回す。0 ならキャッシュしない
+    pub fn with_ttl(mut self, feed: impl Into<String>, ttl: Duration) -> Self {
+        let feed = feed.into();
+        if ttl.is_zero() {
+            self.ttls.remove(&feed);
+        } else {
+            self.ttls.insert(feed, ttl);
+        }
+        self
+    }
+
+    /// SQLite にも書く（`migrate` 済みのプール）
+    pub fn with_store(mut self, pool: SqlitePool) -> Self {
+        self.store = Some(pool);
+        self
+    }
+
+    /// 取得ごとに、どこで見つかったか（`memory` / `sqlite` / `miss`）を `feed` と `result` のラベルで数える
+    pub fn with_lookup_counter(mut self, counter: Counter) -> Self {
+        self.lookups = Some(counter);
+        self
+    }
+
+    pub fn ttl(&self, feed: &str) -> Option<Duration> {
+        self.ttls.get(feed).copied()
+    }
+
+    /// キャッシュにあればそれを、なければ `compute` の結果を返す（成功したときだけ入れる）
+    pub async fn get_or_insert_with<F, Fut, E>(
+        &self,
+        key: CacheKey,
+        compute: F,
+    ) -> Result<FeedSkeletonResult, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<FeedSkeletonResult, E>>,
+    {
+        let Some(ttl) = self.ttl(&key.feed) else {
+            return compute().await;
+        };
+
+        if let Some(value) = self.get(&key).await {
+            return Ok(value);
+        }
+
+        let generation = self.generation(&key.feed);
+        let value = compute().await?;
+        self.insert(key, value.clone(), ttl, generation).await;
+        Ok(value)
+    }
+
+    fn generation(&self, feed: &str) -> u64 {
+        self.memory.lock().unwrap().generation(feed)
+    }
+
+    async fn get(&self, key: &CacheKey) -> Option<FeedSkeletonResult> {
+        let now = now_ms();
+        if let Some(value) = self.memory.lock().unwrap().lru.get(key, now) {
+            self.count(&key.feed, "memory");
+            return Some(value);
+        }
+
+        if let Some(pool) = &self.store {
+            let generation = self.generation(&key.feed);
+            let row: Result<Option<(String, i64)>, _> = sqlx::query_as(
+                "SELECT value, expires_at FROM skeleton_cache WHERE key = ? AND expires_at > ?",
+            )
+            .bind(key.encode())
+            .bind(now)
+            .fetch_optional(pool)
+            .await;
+            match row {
+                Ok(Some((value, expires_at))) => {
+                    match serde_json::from_str::<FeedSkeletonResult>(&value) {
+                        Ok(value) => {
+                            // 読んでいる間に破棄されていたら、メモリには載せない
+                            let mut memory = self.memory.lock().unwrap();
+                            if memory.generation(&key.feed) == generation {
+                                memory.lru.insert(key.clone(), value.clone(), expires_at);
+                            }
+                            drop(memory);
+                            self.count(&key.feed, "sqlite");
+                            return Some(value);
+                        }
+                        Err(e) => {
+                            tracing::warn!("[skeleton_cache] Broken entry for {}: {}", key.feed, e)
+                        }
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => tracing::warn!("[skeleton_cache] Failed to read: {}", e),
+            }
+        }
+
+        self.count(&key.feed, "miss");
+        None
+    }
+
+    /// `generation` は計算を始めたときの世代。変わっていたら入れない
+    async fn insert(
+        &self,
+        key: CacheKey,
+        value: FeedSkeletonResult,
+        ttl: Duration,
+        generation: u64,
+    ) {
+        if self.generation(&key.feed) != generation {
+            return;
+        }
+        let expires_at = now_ms() + ttl.as_millis() as i64;
+
+        if let Some(pool) = &self.store {
+            let result = sqlx::query(
+                "INSERT OR REPLACE INTO skeleton_cache (key, feed, requester, value, expires_at) VALUES (?, ?, ?, ?, ?)",
+            )
+            .bind(key.encode())
+            .bind(&key.feed)
+            .bind(key.requester.as_deref().unwrap_or_default())
+            .bind(serde_json::to_string(&value).unwrap_or_default())
+            .bind(expires_at)
+            .execute(pool)
+            .await;
+            if let Err(e) = result {
+                tracing::warn!("[skeleton_cache] Failed to write: {}", e);
+            }
+        }
+
+        // 書いている間に破棄されたら、書いた行も消す
+        let encoded = key.encode();
+        let fresh = {
+            let mut memory = self.memory.lock().unwrap();
+            let fresh = memory.generation(&key.feed) == generation;
+            if fresh {
+                memory.lru.insert(key, value, expires_at);
+            }
+            fresh
+        };
+        if !fresh {
+            self.delete("DELETE FROM skeleton_cache WHERE key = ?", &[&encoded])
+                .await;
+        }
+    }
+
+    /// `feed` の結果をすべて破棄する（新しい投稿が入ったときなど）
+    pub async fn invalidate_feed(&self, feed: &str) {
+        {
+            let mut memory = self.memory.lock().unwrap();
+            memory.bump(feed);
+            memory.lru.retain(|key| key.feed != feed);
+        }
+        self.delete("DELETE FROM skeleton_cache WHERE feed = ?", &[feed])
+            .await;
+    }
+
+    /// `requester` に向けた `feed` の結果を破棄する（リストの変更や "Show less" のとき）
+    pub async fn invalidate_requester(&self, feed: &str, requester: &str) {
+        {
+            let mut memory = self.memory.lock().unwrap();
+            memory.bump(feed);
+            memory
+                .lru
+                .retain(|key| key.feed != feed || key.requester.as_deref() != Some(requester));
+        }
+        self.delete(
+            "DELETE FROM skeleton_cache WHERE feed = ? AND requester = ?",
+            &[feed, requester],
+        )
+        .await;
+    }
+
+    /// すべて破棄する（アカウントの投稿を取り除いたときなど）
+    pub async fn invalidate_all(&self) {
+        {
+            let mut memory = self.memory.lock().unwrap();
+            for feed in self.ttls.keys() {
+                memory.bump(feed);
+            }
+            memory.lru.retain(|_| false);
+        }
+        self.delete("DELETE FROM skeleton_cache", &[]).await;
+    }
+
+    /// 期限切れの行を消す
+    pub async fn cleanup(&self) -> Result<u64, sqlx::Error> {
+        let Some(pool) = &self.store else {
+            return Ok(0);
+        };
+        let result = sqlx::query("DELETE FROM skeleton_cache WHERE expires_at <= ?")
+            .bind(now_ms())
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn delete(&self, sql: &str, binds: &[&str]) {
+        let Some(pool) = &self.store else {
+            return;
+        };
+        let mut query = sqlx::query(sql);
+        for bind in binds {
+            query = query.bind(*bind);
+        }
+        if let Err(e) = query.execute(pool).await {
+            tracing::warn!("[skeleton_cache] Failed to invalidate: {}", e);
+        }
+    }
+
+    fn count(&self, feed: &str, result: &str) {
+        if let Some(lookups) = &self.lookups {
+            lookups.inc(&[("feed", feed), ("result", result)]);
+        }
+    }
+}
+
+fn now_ms() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bsky_core::FeedItem;
+
+    fn key(feed: &str, requester: Option<&str>) -> CacheKey {
+        CacheKey {
+            feed: feed.to_string(),
+            requester: requester.map(str::to_string),
+            cursor: None,
+            limit: Some(30),
+        }
+    }
+
+    fn skeleton(post: &str) -> FeedSkeletonResult {
+        FeedSkeletonResult {
+            cursor: None,
+            feed: vec![FeedItem::new(post)],
+        }
+    }
+
+    async fn fetch(cache: &SkeletonCache, key: CacheKey, post: &str) -> FeedSkeletonResult {
+        let post = post.to_string();
+        cache
+            .get_or_insert_with(key, || async move { Ok::<_, ()>(skeleton(&post)) })
+            .await
+            .unwrap()
+    }
+
+    /// TTL の間は使い回し、切れたら計算し直すか。TTL のないフィードはキャッシュしないか
+    #[tokio::test]
+    async fn test_ttl() {
+        let cache = SkeletonCache::new(10).with_ttl("helloworld", Duration::from_millis(50));
+
+        assert_eq!(
+            fetch(&cache, key("helloworld", None), "a").await,
+            skeleton("a")
+        );
+        assert_eq!(
+            fetch(&cache, key("helloworld", None), "b").await,
+            skeleton("a")
+        );
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        assert_eq!(
+            fetch(&cache, key("helloworld", None), "c").await,
+            skeleton("c")
+        );
+
+        assert_eq!(
+            fetch(&cache, key("todoapp", None), "a").await,
+            skeleton("a")
+        );
+        assert_eq!(
+            fetch(&cache, key("todoapp", None), "b").await,
+            skeleton("b")
+        );
+    }
+
+    /// 上限を超えたら、最後に使ったのが最も古いものから追い出すか
+    #[tokio::test]
+    async fn test_lru_eviction() {
+        let cache = SkeletonCache::new(2).with_ttl("privatelist", Duration::from_secs(60));
+        let alice = || key("privatelist", Some("did:plc:alice"));
+        let bob = || key("privatelist", Some("did:plc:bob"));
+        let carol = || key("privatelist", Some("did:plc:carol"));
+
+        fetch(&cache, alice(), "alice").await;
+        fetch(&cache, bob(), "bob").await;
+        // alice を使ったので、追い出されるのは bob
+        fetch(&cache, alice(), "-").await;
+        fetch(&cache, carol(), "carol").await;
+
+        assert_eq!(fetch(&cache, alice(), "-").await, skeleton("alice"));
+        assert_eq!(fetch(&cache, carol(), "-").await, skeleton("carol"));
+        assert_eq!(fetch(&cache, bob(), "bob2").await, skeleton("bob2"));
+    }
+
+    /// フィード単位・リクエスト元単位で破棄できるか。SQLite からも消えるか
+    #[tokio::test]
+    async fn test_invalidation_with_store() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate(&pool).await.unwrap();
+        let lookups = Counter::default();
+        let cache = SkeletonCache::new(10)
+            .with_ttl("privatelist", Duration::from_secs(60))
+            .with_ttl("helloworld", Duration::from_secs(60))
+            .with_store(pool.clone())
+            .with_lookup_counter(lookups.clone());
+        let alice = || key("privatelist", Some("did:plc:alice"));
+        let bob = || key("privatelist", Some("did:plc:bob"));
+
+        fetch(&cache, alice(), "alice").await;
+        fetch(&cache, bob(), "bob").await;
+        fetch(&cache, key("helloworld", None), "hello").await;
+
+        // メモリになくても、SQLite にあれば使う（再起動を想定）
+        let restarted = SkeletonCache::new(10)
+            .with_ttl("privatelist", Duration::from_secs(60))
+            .with_store(pool.clone())
+            .with_lookup_counter(lookups.clone());
+        assert_eq!(fetch(&restarted, alice(), "-").await, skeleton("alice"));
+        assert_eq!(
+            lookups.get(&[("feed", "privatelist"), ("result", "sqlite")]),
+            1
+        );
+
+        cache
+            .invalidate_requester("privatelist", "did:plc:alice")
+            .await;
+        restarted
+            .invalidate_requester("privatelist", "did:plc:alice")
+            .await;
+        assert_eq!(fetch(&cache, alice(), "alice2").await, skeleton("alice2"));
+        assert_eq!(fetch(&cache, bob(), "-").await, skeleton("bob"));
+
+        cache.invalidate_feed("privatelist").await;
+        assert_eq!(fetch(&cache, bob(), "bob2").await, skeleton("bob2"));
+        assert_eq!(
+            fetch(&cache, key("helloworld", None), "-").await,
+            skeleton("hello")
+        );
+
+        // 残るのは helloworld と、破棄した後に入れた bob の分
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM skeleton_cache")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(rows, 2);
+    }
+
+    /// 計算している間に同じフィードが破棄されたら、古いかもしれない結果はメモリにも SQLite にも入れないか。
+    /// 他のフィードの破棄では捨てないか
+    #[tokio::test]
+    async fn test_invalidation_during_compute() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate(&pool).await.unwrap();
+        let cache = SkeletonCache::new(10)
+            .with_ttl("helloworld", Duration::from_secs(60))
+            .with_ttl("todoapp", Duration::from_secs(60))
+            .with_store(pool.clone());
+
+        let result = cache
+            .get_or_insert_with(key("helloworld", None), || async {
+                cache.invalidate_feed("helloworld").await;
+                Ok::<_, ()>(skeleton("stale"))
+            })
+            .await
+            .unwrap();
+        assert_eq!(result, skeleton("stale"));
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM skeleton_cache")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(rows, 0);
+        assert_eq!(
+            fetch(&cache, key("helloworld", None), "fresh").await,
+            skeleton("fresh")
+        );
+
+        cache
+            .get_or_insert_with(key("todoapp", None), || async {
+                cache.invalidate_feed("helloworld").await;
+                Ok::<_, ()>(skeleton("todo"))
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            fetch(&cache, key("todoapp", None), "-").await,
+            skeleton("todo")
+        );
+    }
+}
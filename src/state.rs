@@ -1,7 +1,6 @@
 use serde::Deserialize;
 use sqlx::SqlitePool;
 use std::sync::Arc;
-use tokio::sync::RwLock;
 
 #[derive(Debug, Deserialize)]
 pub struct FeedQuery {
@@ -10,10 +9,21 @@ pub struct FeedQuery {
     pub limit: Option<usize>,
 }
 
+/// `app.bsky.feed.sendInteractions` の入力
+#[derive(Debug, Deserialize)]
+pub struct SendInteractionsInput {
+    /// 古いクライアントは送ってこない
+    pub feed: Option<String>,
+    pub interactions: Vec<bsky_core::interactions::Interaction>,
+}
+
 pub type SharedState = AppState;
 
 #[derive(Clone)]
 pub struct AppConfig {
+    pub service_did: String,
+    /// did.json で公開するホスト名
+    pub hostname: String,
     pub privatelist_url: String,
     pub bsky_api_url: String,
     pub client_id: String,
@@ -23,16 +33,26 @@ pub struct AppConfig {
 #[derive(Clone)]
 pub struct AppState {
     pub config: AppConfig,
+    pub feeds: crate::feed::FeedRegistry,
     pub helloworld: helloworld::State,
-    pub http_client: reqwest::Client,
-    pub service_auth: Arc<RwLock<ServiceAuth>>,
-    pub auth_handle: String,
-    pub auth_password: String,
+    /// 外部 API への呼び出し（ホストごとの同時実行数・レート制限・やり直し）
+    pub http_client: bsky_core::http::HttpClient,
+    /// AppView と PDS への XRPC 呼び出し（接続先は `config.bsky_api_url`）
+    pub appview: bsky_core::appview::AppViewClient,
+    pub did_resolver: bsky_core::resolver::DidResolver,
+    pub jwt_verifier: bsky_core::jwt::JwtVerifier,
+    /// フィードが AppView を呼ぶときのサービスアカウントのセッション
+    pub session: Arc<bsky_core::session::ServiceSession>,
     pub helloworld_db: SqlitePool,
     pub realfakebluesky_db: SqlitePool,
     pub privatelist_db: SqlitePool,
     pub oneyearago_db: SqlitePool,
-    pub umami: crate::analytics::UmamiClient,
+    pub identity_db: SqlitePool,
+    pub interactions_db: SqlitePool,
+    pub analytics: crate::analytics::Analytics,
+    /// getFeedSkeleton の結果のキャッシュ
+    pub skeleton_cache: Arc<crate::skeleton_cache::SkeletonCache>,
+    pub metrics: crate::metrics::Metrics,
     pub key: axum_extra::extract::cookie::Key,
 }
 
@@ -41,9 +61,3 @@ impl axum::extract::FromRef<AppState> for axum_extra::extract::cookie::Key {
         state.key.clone()
     }
 }
-
-#[derive(Clone, Debug)]
-pub struct ServiceAuth {
-    pub token: Option<String>,
-    pub did: Option<String>,
-}
@@ -1,18 +1,49 @@
+use axum::async_trait;
 use base64::{engine::general_purpose, Engine as _};
+use bsky_core::jwt::{PublicKey, SigningKeyResolver};
+use p256::ecdsa::{signature::Signer, Signature, SigningKey};
 use serde_json::json;
+use sha2::{Digest, Sha256};
+
+pub const TEST_SERVICE_DID: &str = "did:web:feeds.bsky.girigiribauer.com";
+
+/// DID ごとに決定論的な P-256 鍵を導出する（テスト専用）
+fn signing_key_for(did: &str) -> SigningKey {
+    let seed = Sha256::digest(did.as_bytes());
+    SigningKey::from_slice(&seed).expect("valid test key")
+}
+
+/// `TestAuth` と同じ鍵を返す DID リゾルバー
+pub struct TestKeyResolver;
+
+#[async_trait]
+impl SigningKeyResolver for TestKeyResolver {
+    async fn resolve_signing_key(&self, did: &str) -> anyhow::Result<PublicKey> {
+        Ok(PublicKey::P256(*signing_key_for(did).verifying_key()))
+    }
+}
 
 pub struct TestAuth {
     pub did: String,
+    pub aud: String,
+    pub exp: i64,
 }
 
 impl TestAuth {
     pub fn new(did: &str) -> Self {
         Self {
             did: did.to_string(),
+            aud: TEST_SERVICE_DID.to_string(),
+            exp: 1999999999, // far future
         }
     }
 
     pub fn generate_token(&self) -> String {
+        self.generate_token_signed_by(&self.did)
+    }
+
+    /// 別の DID の鍵で署名したトークン（なりすましの再現用）
+    pub fn generate_token_signed_by(&self, signer_did: &str) -> String {
         let header = json!({
             "alg": "ES256",
             "typ": "JWT"
@@ -20,16 +51,19 @@ impl TestAuth {
 
         let payload = json!({
             "iss": self.did,
-            "aud": "did:web:feeds.bsky.girigiribauer.com",
-            "exp": 1999999999, // far future
+            "aud": self.aud,
+            "exp": self.exp,
             "iat": 1700000000
         });
 
         let header_part = general_purpose::URL_SAFE_NO_PAD.encode(header.to_string());
         let payload_part = general_purpose::URL_SAFE_NO_PAD.encode(payload.to_string());
-        let signature_part = "dummy_signature"; // App doesn't verify signature yet
+        let signed_part = format!("{}.{}", header_part, payload_part);
+
+        let signature: Signature = signing_key_for(signer_did).sign(signed_part.as_bytes());
+        let signature_part = general_purpose::URL_SAFE_NO_PAD.encode(signature.to_bytes());
 
-        format!("{}.{}.{}", header_part, payload_part, signature_part)
+        format!("{}.{}", signed_part, signature_part)
     }
 
     pub fn header_value(&self) -> String {
@@ -1,14 +1,17 @@
+use crate::helpers::auth::{TestKeyResolver, TEST_SERVICE_DID};
 use axum::{
     body::Body,
     http::{Request, StatusCode},
     Router,
 };
+use bluesky_feeds::skeleton_cache::SkeletonCache;
 use bluesky_feeds::{
     app,
     state::{AppState, SharedState},
 };
+use bsky_core::appview::Session;
+use bsky_core::session::ServiceSession;
 use std::sync::Arc;
-use tokio::sync::RwLock;
 use tower::ServiceExt; // for oneshot
                        // use tower::Service; // removed unused import
 
@@ -28,6 +31,26 @@ impl TestClient {
         Self { router, state }
     }
 
+    /// サービスアカウントのセッションを `session` から始める
+    pub async fn new_with_service_session(bsky_api_url: String, session: Session) -> Self {
+        let mut state = create_test_state(Some(bsky_api_url)).await;
+        state.session = Arc::new(
+            ServiceSession::new(state.appview.clone(), "test.example.com", "dummy")
+                .with_session(session),
+        );
+        let router = app(state.clone());
+        Self { router, state }
+    }
+
+    /// getFeedSkeleton の結果を `cache` でキャッシュする（既定ではキャッシュしない）
+    pub async fn new_with_skeleton_cache(cache: SkeletonCache) -> Self {
+        let mut state = create_test_state(None).await;
+        state.skeleton_cache =
+            Arc::new(cache.with_lookup_counter(state.metrics.skeleton_cache.clone()));
+        let router = app(state.clone());
+        Self { router, state }
+    }
+
     pub async fn get_feed_skeleton(
         &self,
         feed_uri: &str,
@@ -73,6 +96,48 @@ impl TestClient {
         (status, body_json)
     }
 
+    pub async fn send_interactions(
+        &self,
+        payload: serde_json::Value,
+        auth_header: Option<&str>,
+    ) -> (StatusCode, serde_json::Value) {
+        let mut req_builder = Request::builder()
+            .uri("/xrpc/app.bsky.feed.sendInteractions")
+            .header("Host", "feeds.localhost")
+            .header("Content-Type", "application/json")
+            .method("POST");
+
+        if let Some(token) = auth_header {
+            req_builder = req_builder.header("Authorization", token);
+        }
+
+        let request = req_builder
+            .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+            .unwrap();
+
+        let response = self
+            .router
+            .clone()
+            .oneshot(request)
+            .await
+            .expect("Request failed");
+
+        let status = response.status();
+        let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        let body_json: serde_json::Value = if body_bytes.is_empty() {
+            serde_json::json!(null)
+        } else {
+            serde_json::from_slice(&body_bytes).unwrap_or_else(
+                |_| serde_json::json!({ "raw": String::from_utf8_lossy(&body_bytes) }),
+            )
+        };
+
+        (status, body_json)
+    }
+
     pub async fn get_health(&self) -> (StatusCode, String) {
         let request = Request::builder()
             .uri("/health")
@@ -95,6 +160,39 @@ impl TestClient {
         (status, String::from_utf8_lossy(&body_bytes).to_string())
     }
 
+    /// (ステータス, Content-Type, 本文)
+    pub async fn get_metrics(&self) -> (StatusCode, String, String) {
+        let request = Request::builder()
+            .uri("/metrics")
+            .method("GET")
+            .header("Host", "feeds.localhost")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = self
+            .router
+            .clone()
+            .oneshot(request)
+            .await
+            .expect("Request failed");
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (
+            status,
+            content_type,
+            String::from_utf8_lossy(&body_bytes).to_string(),
+        )
+    }
+
     pub async fn get_did_json(&self) -> (StatusCode, serde_json::Value) {
         let request = Request::builder()
             .uri("/.well-known/did.json")
@@ -219,36 +317,71 @@ async fn create_test_state(bsky_api_url: Option<String>) -> SharedState {
             expires_at INTEGER NOT NULL
         );
         CREATE INDEX IF NOT EXISTS idx_cache_expires_at ON cache(expires_at);
+
+        CREATE TABLE IF NOT EXISTS did_cache (
+            did        TEXT    PRIMARY KEY,
+            document   TEXT    NOT NULL,
+            expires_at INTEGER NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS feed_interactions (
+            id            INTEGER PRIMARY KEY AUTOINCREMENT,
+            feed          TEXT    NOT NULL,
+            requester_did TEXT    NOT NULL,
+            item          TEXT    NOT NULL,
+            event         TEXT    NOT NULL,
+            feed_context  TEXT,
+            created_at    INTEGER NOT NULL
+        );
         "#,
     )
     .execute(&db)
     .await
     .unwrap();
 
+    // AppView と PDS は同じ接続先（統合テストではモックサーバー）に向ける
+    let bsky_api_url = bsky_api_url.unwrap_or_else(|| "https://api.bsky.app".to_string());
+    let http_client = bsky_core::http::HttpClient::new(reqwest::Client::new());
+    let appview = bsky_core::appview::AppViewClient::new(http_client.clone(), &bsky_api_url)
+        .with_pds_url(&bsky_api_url);
+
     AppState {
         config: bluesky_feeds::state::AppConfig {
+            service_did: TEST_SERVICE_DID.to_string(),
+            hostname: TEST_SERVICE_DID.trim_start_matches("did:web:").to_string(),
             privatelist_url: "http://localhost:3000".to_string(),
-            bsky_api_url: bsky_api_url.unwrap_or_else(|| "https://api.bsky.app".to_string()),
+            bsky_api_url,
             client_id: "http://localhost:3000/client-metadata.json".to_string(),
             redirect_uri: "http://localhost:3000/oauth/callback".to_string(),
         },
+        feeds: bluesky_feeds::feed::FeedRegistry::builtin(),
         helloworld: helloworld::State::default(),
-        http_client: reqwest::Client::new(),
-        service_auth: Arc::new(RwLock::new(bluesky_feeds::state::ServiceAuth {
-            token: Some("mock_service_token_for_testing".to_string()),
-            did: Some("did:plc:test123456789".to_string()),
-        })),
-        auth_handle: "test.example.com".to_string(),
-        auth_password: "dummy".to_string(),
+        http_client: http_client.clone(),
+        appview: appview.clone(),
+        did_resolver: bsky_core::resolver::DidResolver::new(http_client.clone(), db.clone()),
+        jwt_verifier: bsky_core::jwt::JwtVerifier::new(
+            TEST_SERVICE_DID.to_string(),
+            Arc::new(TestKeyResolver),
+        ),
+        session: Arc::new(
+            ServiceSession::new(appview.clone(), "test.example.com", "dummy").with_session(
+                Session {
+                    did: "did:plc:test123456789".to_string(),
+                    access_jwt: "mock_service_token_for_testing".to_string(),
+                    refresh_jwt: "mock_refresh_token_for_testing".to_string(),
+                },
+            ),
+        ),
         helloworld_db: db.clone(),
         realfakebluesky_db: db.clone(),
         privatelist_db: db.clone(),
-        oneyearago_db: db,
-        umami: bluesky_feeds::analytics::UmamiClient::new(
-            "http://localhost:3000".to_string(),
-            "dummy_website_id".to_string(),
-            Some("localhost".to_string()),
-        ),
+        oneyearago_db: db.clone(),
+        identity_db: db.clone(),
+        interactions_db: db,
+        analytics: bluesky_feeds::analytics::Analytics::disabled(),
+        skeleton_cache: Arc::new(SkeletonCache::disabled()),
+        metrics: bluesky_feeds::metrics::Metrics::new()
+            .with_outbound(http_client.metrics().clone()),
         key: axum_extra::extract::cookie::Key::generate(),
     }
 }
@@ -0,0 +1,313 @@
+//! `com.atproto.sync.subscribeRepos` を話すローカルの WebSocket サーバー（リレーの代わり）
+//!
+//! - フレームはヘッダーとボディの 2 つの DAG-CBOR を連結したバイナリで送る
+//! - `cursor` より後（seq > cursor）のフレームだけを流す。seq の無いフレーム（`#info`）は常に流す
+//! - 送り終えたら接続を保ったまま、後から `push` されたフレームも流す
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        Query, State, WebSocketUpgrade,
+    },
+    response::Response,
+    routing::get,
+    Router,
+};
+use ipld_core::cid::{multihash::Multihash, Cid};
+use ipld_core::ipld::Ipld;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+const DAG_CBOR: u64 = 0x71;
+const SHA2_256: u64 = 0x12;
+
+/// 送るフレームと、その seq
+#[derive(Clone)]
+pub struct RelayFrame {
+    seq: Option<i64>,
+    bytes: Vec<u8>,
+}
+
+#[derive(Default)]
+struct RelayState {
+    frames: Mutex<Vec<RelayFrame>>,
+    /// 接続ごとに、何フレーム送ったら切断するか（先頭から順に使う）
+    disconnect_after: Mutex<VecDeque<usize>>,
+    /// これまでの接続で指定された cursor（接続順）
+    cursors: Mutex<Vec<Option<i64>>>,
+}
+
+pub struct MockFirehose {
+    pub port: u16,
+    state: Arc<RelayState>,
+    shutdown_tx: Option<oneshot::Sender<()>>,
+}
+
+impl MockFirehose {
+    pub async fn start() -> Self {
+        let state = Arc::new(RelayState::default());
+        let app = Router::new()
+            .route(
+                "/xrpc/com.atproto.sync.subscribeRepos",
+                get(handle_subscribe),
+            )
+            .with_state(state.clone());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = oneshot::channel();
+
+        tokio::spawn(async move {
+            axum::serve(listener, app)
+                .with_graceful_shutdown(async {
+                    rx.await.ok();
+                })
+                .await
+                .unwrap();
+        });
+
+        MockFirehose {
+            port,
+            state,
+            shutdown_tx: Some(tx),
+        }
+    }
+
+    pub fn url(&self) -> String {
+        format!(
+            "ws://127.0.0.1:{}/xrpc/com.atproto.sync.subscribeRepos",
+            self.port
+        )
+    }
+
+    /// 流すフレームを追加する（接続中のクライアントにも届く）
+    pub fn push(&self, frame: RelayFrame) {
+        self.state.frames.lock().unwrap().push(frame);
+    }
+
+    /// 次の接続は `count` フレーム送ったところで切断する
+    pub fn disconnect_after(&self, count: usize) {
+        self.state.disconnect_after.lock().unwrap().push_back(count);
+    }
+
+    /// これまでの接続で指定された cursor（接続順）
+    pub fn cursors(&self) -> Vec<Option<i64>> {
+        self.state.cursors.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockFirehose {
+    fn drop(&mut self) {
+        if let Some(tx) = self.shutdown_tx.take() {
+            let _ = tx.send(());
+        }
+    }
+}
+
+/// `#commit` フレームの 1 操作（いずれもレコードの作成）
+pub enum Op<'a> {
+    Post {
+        rkey: &'a str,
+        text: &'a str,
+    },
+    /// 購読していないコレクション
+    Like {
+        rkey: &'a str,
+    },
+    /// DAG-CBOR として読めないレコード
+    Broken {
+        rkey: &'a str,
+    },
+}
+
+/// `repo` のコミットのフレーム。`ops` の順に操作が並ぶ
+pub fn commit_frame(seq: i64, repo: &str, ops: &[Op]) -> RelayFrame {
+    let mut blocks = Vec::new();
+    let mut op_items = Vec::new();
+    for op in ops {
+        let (path, block) = match op {
+            Op::Post { rkey, text } => (
+                format!("app.bsky.feed.post/{}", rkey),
+                encode(&json_ipld(serde_json::json!({
+                    "$type": "app.bsky.feed.post",
+                    "createdAt": "2024-09-09T19:46:02.102Z",
+                    "langs": ["en"],
+                    "text": text
+                }))),
+            ),
+            Op::Like { rkey } => (
+                format!("app.bsky.feed.like/{}", rkey),
+                encode(&json_ipld(serde_json::json!({
+                    "$type": "app.bsky.feed.like",
+                    "createdAt": "2024-09-09T19:46:02.102Z",
+                    "subject": {
+                        "cid": "bafyreidfayvfuwqa7qlnopdjiqrxzs6blmoeu4rujcjtnci5beludirz2a",
+                        "uri": format!("at://{}/app.bsky.feed.post/{}", repo, rkey)
+                    }
+                }))),
+            ),
+            Op::Broken { rkey } => (format!("app.bsky.feed.post/{}", rkey), vec![0xff, 0x00]),
+        };
+
+        let cid = cid_of(&block);
+        blocks.push((cid, block));
+        op_items.push(Ipld::Map(BTreeMap::from([
+            ("action".to_string(), Ipld::String("create".to_string())),
+            ("path".to_string(), Ipld::String(path)),
+            ("cid".to_string(), Ipld::Link(cid)),
+        ])));
+    }
+
+    let body = BTreeMap::from([
+        ("seq".to_string(), Ipld::Integer(seq.into())),
+        ("repo".to_string(), Ipld::String(repo.to_string())),
+        ("rev".to_string(), Ipld::String("3l3qo2vutsw2b".to_string())),
+        ("time".to_string(), Ipld::String(now())),
+        ("tooBig".to_string(), Ipld::Bool(false)),
+        ("blocks".to_string(), Ipld::Bytes(car(&blocks))),
+        ("ops".to_string(), Ipld::List(op_items)),
+    ]);
+    frame(Some(seq), "#commit", body)
+}
+
+/// `#account` フレーム（`status` を指定すると無効なアカウントになる）
+pub fn account_frame(seq: i64, did: &str, status: Option<&str>) -> RelayFrame {
+    let mut body = BTreeMap::from([
+        ("seq".to_string(), Ipld::Integer(seq.into())),
+        ("did".to_string(), Ipld::String(did.to_string())),
+        ("active".to_string(), Ipld::Bool(status.is_none())),
+        ("time".to_string(), Ipld::String(now())),
+    ]);
+    if let Some(status) = status {
+        body.insert("status".to_string(), Ipld::String(status.to_string()));
+    }
+    frame(Some(seq), "#account", body)
+}
+
+/// `#info` フレーム（seq を持たない）
+pub fn info_frame(name: &str) -> RelayFrame {
+    let body = BTreeMap::from([("name".to_string(), Ipld::String(name.to_string()))]);
+    frame(None, "#info", body)
+}
+
+fn frame(seq: Option<i64>, t: &str, body: BTreeMap<String, Ipld>) -> RelayFrame {
+    let mut bytes = encode(&Ipld::Map(BTreeMap::from([
+        ("op".to_string(), Ipld::Integer(1)),
+        ("t".to_string(), Ipld::String(t.to_string())),
+    ])));
+    bytes.extend(encode(&Ipld::Map(body)));
+    RelayFrame { seq, bytes }
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+fn json_ipld(value: serde_json::Value) -> Ipld {
+    serde_json::from_value(value).unwrap()
+}
+
+fn encode(value: &Ipld) -> Vec<u8> {
+    serde_ipld_dagcbor::to_vec(value).unwrap()
+}
+
+fn cid_of(block: &[u8]) -> Cid {
+    let digest = Sha256::digest(block);
+    Cid::new_v1(DAG_CBOR, Multihash::wrap(SHA2_256, &digest).unwrap())
+}
+
+fn varint(mut value: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return out;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// ブロックを CAR v1 にまとめる
+fn car(blocks: &[(Cid, Vec<u8>)]) -> Vec<u8> {
+    let header = encode(&Ipld::Map(BTreeMap::from([
+        ("version".to_string(), Ipld::Integer(1)),
+        ("roots".to_string(), Ipld::List(vec![])),
+    ])));
+    let mut out = varint(header.len());
+    out.extend(header);
+    for (cid, block) in blocks {
+        let cid_bytes = cid.to_bytes();
+        out.extend(varint(cid_bytes.len() + block.len()));
+        out.extend(cid_bytes);
+        out.extend(block);
+    }
+    out
+}
+
+async fn handle_subscribe(
+    State(state): State<Arc<RelayState>>,
+    Query(params): Query<HashMap<String, String>>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let cursor = params.get("cursor").and_then(|cursor| cursor.parse().ok());
+    state.cursors.lock().unwrap().push(cursor);
+    let disconnect_after = state.disconnect_after.lock().unwrap().pop_front();
+
+    ws.on_upgrade(move |socket| stream_frames(socket, state, cursor, disconnect_after))
+}
+
+async fn stream_frames(
+    mut socket: WebSocket,
+    state: Arc<RelayState>,
+    cursor: Option<i64>,
+    disconnect_after: Option<usize>,
+) {
+    let mut next_index = 0;
+    let mut sent = 0;
+
+    loop {
+        let pending: Vec<RelayFrame> = {
+            let frames = state.frames.lock().unwrap();
+            let pending = frames[next_index..].to_vec();
+            next_index = frames.len();
+            pending
+        };
+
+        let after_cursor = |frame: &&RelayFrame| match (frame.seq, cursor) {
+            (Some(seq), Some(cursor)) => seq > cursor,
+            _ => true,
+        };
+        for frame in pending.iter().filter(after_cursor) {
+            if disconnect_after == Some(sent) {
+                let _ = socket.send(Message::Close(None)).await;
+                return;
+            }
+            if socket
+                .send(Message::Binary(frame.bytes.clone()))
+                .await
+                .is_err()
+            {
+                return;
+            }
+            sent += 1;
+        }
+
+        if disconnect_after == Some(sent) {
+            let _ = socket.send(Message::Close(None)).await;
+            return;
+        }
+
+        // クライアントが切断するまで、追加されたフレームを待つ
+        tokio::select! {
+            _ = tokio::time::sleep(std::time::Duration::from_millis(20)) => {}
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
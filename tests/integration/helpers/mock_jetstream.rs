@@ -0,0 +1,259 @@
+//! Jetstream の subscribe プロトコルを話すローカルの WebSocket サーバー
+//!
+//! - `wantedCollections` で commit イベントを絞り込む（identity / account は常に流す。`*` のプレフィックス指定も可）
+//! - `cursor` 以降（time_us >= cursor）のイベントだけを流す
+//! - `compress=true` なら公式の zstd 辞書で圧縮してバイナリフレームで送る
+//! - 送り終えたら接続を保ったまま、後から `push` されたイベントも流す
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        RawQuery, State, WebSocketUpgrade,
+    },
+    response::Response,
+    routing::get,
+    Router,
+};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+/// Jetstream 公式の zstd 辞書
+const ZSTD_DICTIONARY: &[u8] = include_bytes!("../../fixtures/jetstream/zstd_dictionary");
+
+/// 接続時に指定されたパラメータ
+#[derive(Debug, Clone, PartialEq)]
+pub struct Connection {
+    pub wanted_collections: Vec<String>,
+    pub cursor: Option<u64>,
+    pub compress: bool,
+}
+
+#[derive(Default)]
+struct JetstreamState {
+    events: Mutex<Vec<serde_json::Value>>,
+    /// 接続ごとに、何件送ったら切断するか（先頭から順に使う）
+    disconnect_after: Mutex<VecDeque<usize>>,
+    connections: Mutex<Vec<Connection>>,
+}
+
+pub struct MockJetstream {
+    pub port: u16,
+    state: Arc<JetstreamState>,
+    shutdown_tx: Option<oneshot::Sender<()>>,
+}
+
+impl MockJetstream {
+    pub async fn start() -> Self {
+        let state = Arc::new(JetstreamState::default());
+        let app = Router::new()
+            .route("/subscribe", get(handle_subscribe))
+            .with_state(state.clone());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = oneshot::channel();
+
+        tokio::spawn(async move {
+            axum::serve(listener, app)
+                .with_graceful_shutdown(async {
+                    rx.await.ok();
+                })
+                .await
+                .unwrap();
+        });
+
+        MockJetstream {
+            port,
+            state,
+            shutdown_tx: Some(tx),
+        }
+    }
+
+    pub fn url(&self) -> String {
+        format!("ws://127.0.0.1:{}/subscribe", self.port)
+    }
+
+    /// 流すイベントを追加する（接続中のクライアントにも届く）
+    pub fn push(&self, event: serde_json::Value) {
+        self.state.events.lock().unwrap().push(event);
+    }
+
+    /// 次の接続は `count` 件送ったところで切断する
+    pub fn disconnect_after(&self, count: usize) {
+        self.state.disconnect_after.lock().unwrap().push_back(count);
+    }
+
+    /// これまでの接続のパラメータ（接続順）
+    pub fn connections(&self) -> Vec<Connection> {
+        self.state.connections.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockJetstream {
+    fn drop(&mut self) {
+        if let Some(tx) = self.shutdown_tx.take() {
+            let _ = tx.send(());
+        }
+    }
+}
+
+/// Jetstream の形式の投稿作成イベント
+pub fn post_event(did: &str, rkey: &str, text: &str, time_us: u64) -> serde_json::Value {
+    serde_json::json!({
+        "did": did,
+        "time_us": time_us,
+        "kind": "commit",
+        "commit": {
+            "rev": "3l3qo2vutsw2b",
+            "operation": "create",
+            "collection": "app.bsky.feed.post",
+            "rkey": rkey,
+            "record": {
+                "$type": "app.bsky.feed.post",
+                "createdAt": "2024-09-09T19:46:02.102Z",
+                "langs": ["en"],
+                "text": text
+            },
+            "cid": "bafyreidfayvfuwqa7qlnopdjiqrxzs6blmoeu4rujcjtnci5beludirz2a"
+        }
+    })
+}
+
+/// Jetstream の形式の identity イベント
+pub fn identity_event(did: &str, handle: &str, time_us: u64) -> serde_json::Value {
+    serde_json::json!({
+        "did": did,
+        "time_us": time_us,
+        "kind": "identity",
+        "identity": {
+            "did": did,
+            "handle": handle,
+            "seq": 1409752997,
+            "time": "2024-09-09T19:46:04.250Z"
+        }
+    })
+}
+
+/// Jetstream の形式の account イベント（`status` を指定すると無効なアカウントになる）
+pub fn account_event(did: &str, status: Option<&str>, time_us: u64) -> serde_json::Value {
+    let mut account = serde_json::json!({
+        "active": status.is_none(),
+        "did": did,
+        "seq": 1409752998,
+        "time": "2024-09-09T19:46:04.250Z"
+    });
+    if let Some(status) = status {
+        account["status"] = serde_json::json!(status);
+    }
+    serde_json::json!({
+        "did": did,
+        "time_us": time_us,
+        "kind": "account",
+        "account": account
+    })
+}
+
+async fn handle_subscribe(
+    State(state): State<Arc<JetstreamState>>,
+    RawQuery(query): RawQuery,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let mut connection = Connection {
+        wanted_collections: vec![],
+        cursor: None,
+        compress: false,
+    };
+    for pair in query.unwrap_or_default().split('&') {
+        let Some((key, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let value = urlencoding::decode(value).unwrap().into_owned();
+        match key {
+            "wantedCollections" => connection.wanted_collections.push(value),
+            "cursor" => connection.cursor = value.parse().ok(),
+            "compress" => connection.compress = value == "true",
+            _ => {}
+        }
+    }
+
+    state.connections.lock().unwrap().push(connection.clone());
+    let disconnect_after = state.disconnect_after.lock().unwrap().pop_front();
+
+    ws.on_upgrade(move |socket| stream_events(socket, state, connection, disconnect_after))
+}
+
+async fn stream_events(
+    mut socket: WebSocket,
+    state: Arc<JetstreamState>,
+    connection: Connection,
+    disconnect_after: Option<usize>,
+) {
+    let mut next_index = 0;
+    let mut sent = 0;
+
+    loop {
+        let pending: Vec<serde_json::Value> = {
+            let events = state.events.lock().unwrap();
+            let pending = events[next_index..].to_vec();
+            next_index = events.len();
+            pending
+        };
+
+        for event in pending.iter().filter(|e| should_send(&connection, e)) {
+            if disconnect_after == Some(sent) {
+                let _ = socket.send(Message::Close(None)).await;
+                return;
+            }
+            if socket.send(encode(&connection, event)).await.is_err() {
+                return;
+            }
+            sent += 1;
+        }
+
+        if disconnect_after == Some(sent) {
+            let _ = socket.send(Message::Close(None)).await;
+            return;
+        }
+
+        // クライアントが切断するまで、追加されたイベントを待つ
+        tokio::select! {
+            _ = tokio::time::sleep(std::time::Duration::from_millis(20)) => {}
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+fn should_send(connection: &Connection, event: &serde_json::Value) -> bool {
+    if let Some(cursor) = connection.cursor {
+        if event["time_us"].as_u64().unwrap_or(0) < cursor {
+            return false;
+        }
+    }
+
+    if event["kind"] != "commit" || connection.wanted_collections.is_empty() {
+        return true;
+    }
+    let collection = event["commit"]["collection"].as_str().unwrap_or("");
+    // `app.bsky.feed.*` のようなプレフィックス指定にも対応する
+    connection
+        .wanted_collections
+        .iter()
+        .any(|wanted| match wanted.strip_suffix('*') {
+            Some(prefix) => collection.starts_with(prefix),
+            None => wanted == collection,
+        })
+}
+
+fn encode(connection: &Connection, event: &serde_json::Value) -> Message {
+    let json = serde_json::to_string(event).unwrap();
+    if !connection.compress {
+        return Message::Text(json);
+    }
+
+    let mut compressor = zstd::bulk::Compressor::with_dictionary(3, ZSTD_DICTIONARY).unwrap();
+    Message::Binary(compressor.compress(json.as_bytes()).unwrap())
+}
@@ -1,15 +1,77 @@
-use axum::{extract::Query, routing::get, Json, Router};
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use tokio::sync::oneshot;
 
+/// このアクセストークンで呼ばれたら ExpiredToken で拒否する
+pub const EXPIRED_ACCESS_TOKEN: &str = "expired-access-token";
+/// refreshSession で発行するアクセストークン
+pub const REFRESHED_ACCESS_TOKEN: &str = "refreshed-access-token";
+
+/// todoapp がこの DID の投稿を検索すると 429 で断る
+pub const RATE_LIMITED_DID: &str = "did:plc:ratelimited";
+/// todoapp がこの DID の投稿を検索すると 503 を返す
+pub const UNAVAILABLE_DID: &str = "did:plc:unavailable";
+/// todoapp がこの DID の投稿を検索すると 404 を返す
+pub const MISSING_DID: &str = "did:plc:missing";
+
+/// todoapp がこの DID の投稿を検索すると、最初の 2 回だけ 503 を返す
+pub const UNSTABLE_DID: &str = "did:plc:unstable";
+/// この DID のプロフィールを返すときに、レート制限を使い切ったと伝える（2 秒後に解除）
+pub const EXHAUSTED_DID: &str = "did:plc:exhausted";
+
+/// 受けたセッション操作の回数
+#[derive(Clone, Default)]
+pub struct SessionCalls {
+    pub create: Arc<AtomicUsize>,
+    pub refresh: Arc<AtomicUsize>,
+}
+
+impl SessionCalls {
+    pub fn create(&self) -> usize {
+        self.create.load(Ordering::SeqCst)
+    }
+
+    pub fn refresh(&self) -> usize {
+        self.refresh.load(Ordering::SeqCst)
+    }
+}
+
+#[derive(Clone, Default)]
+struct MockState {
+    session_calls: SessionCalls,
+    unstable_failures: Arc<AtomicUsize>,
+}
+
 pub struct MockServer {
     pub port: u16,
+    pub session_calls: SessionCalls,
     shutdown_tx: Option<oneshot::Sender<()>>,
 }
 
 impl MockServer {
     pub async fn start() -> Self {
-        let app = Router::new().route("/xrpc/app.bsky.feed.searchPosts", get(handle_search));
+        let state = MockState::default();
+        let session_calls = state.session_calls.clone();
+        let app = Router::new()
+            .route("/xrpc/app.bsky.feed.searchPosts", get(handle_search))
+            .route("/xrpc/app.bsky.actor.getProfile", get(handle_get_profile))
+            .route(
+                "/xrpc/com.atproto.server.createSession",
+                post(handle_create_session),
+            )
+            .route(
+                "/xrpc/com.atproto.server.refreshSession",
+                post(handle_refresh_session),
+            )
+            .with_state(state);
 
         let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
         let port = listener.local_addr().unwrap().port();
@@ -26,6 +88,7 @@ impl MockServer {
 
         MockServer {
             port,
+            session_calls,
             shutdown_tx: Some(tx),
         }
     }
@@ -43,41 +106,165 @@ impl Drop for MockServer {
     }
 }
 
-async fn handle_search(Query(params): Query<HashMap<String, String>>) -> Json<serde_json::Value> {
+async fn handle_search(
+    State(state): State<MockState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    if let Some(rejected) = reject_expired(&headers) {
+        return rejected;
+    }
     let q = params.get("q").map(|s| s.as_str()).unwrap_or("");
 
+    // todoapp: author を指定して "TODO" / "DONE" で検索する。TODO の投稿を 1 件だけ返す
+    if q == "TODO" || q == "DONE" {
+        let did = params
+            .get("author")
+            .map(|s| s.as_str())
+            .unwrap_or("unknown");
+        if did == RATE_LIMITED_DID {
+            let reset = chrono::Utc::now().timestamp() + 60;
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [("ratelimit-reset", reset.to_string())],
+                Json(serde_json::json!({
+                    "error": "RateLimitExceeded",
+                    "message": "Rate Limit Exceeded",
+                })),
+            )
+                .into_response();
+        }
+        if did == UNSTABLE_DID && state.unstable_failures.fetch_add(1, Ordering::SeqCst) < 2 {
+            return (StatusCode::SERVICE_UNAVAILABLE, "upstream connect error").into_response();
+        }
+        if did == UNAVAILABLE_DID {
+            return (StatusCode::SERVICE_UNAVAILABLE, "upstream connect error").into_response();
+        }
+        if did == MISSING_DID {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({
+                    "error": "NotFound",
+                    "message": "Actor not found",
+                })),
+            )
+                .into_response();
+        }
+        let posts = if q == "TODO" {
+            vec![post_view(
+                &format!("at://{}/app.bsky.feed.post/todo", did),
+                did,
+                "TODO write tests",
+            )]
+        } else {
+            vec![]
+        };
+        return Json(serde_json::json!({ "posts": posts })).into_response();
+    }
+
     // Simple mock logic: return a post for any "from:DID" query
-    let did = if q.starts_with("from:") {
-        q.strip_prefix("from:").unwrap()
-    } else {
-        "unknown"
-    };
+    // oneyearago は "from:DID since:... until:..." の形で検索する
+    let did = q
+        .split_whitespace()
+        .next()
+        .and_then(|term| term.strip_prefix("from:"))
+        .unwrap_or("unknown");
 
     Json(serde_json::json!({
-        "posts": [
-            {
-                "uri": format!("at://{}/app.bsky.feed.post/1", did),
-                "cid": "bafyreicid",
-                "record": {
-                    "text": format!("Post from {}", did),
-                    "createdAt": "2023-01-01T00:00:00Z"
-                },
-                "indexedAt": "2023-01-01T00:00:00Z",
-                "author": {
-                    "did": did,
-                    "handle": format!("{}.test", did),
-                    "displayName": format!("User {}", did),
-                    "avatar": "https://example.com/avatar.png",
-                    "labels": [],
-                    "viewer": {
-                        "muted": false,
-                        "blockedBy": false
-                    }
-                },
-                "replyCount": 0,
-                "repostCount": 0,
-                "likeCount": 0
-            }
-        ]
+        "posts": [post_view(
+            &format!("at://{}/app.bsky.feed.post/1", did),
+            did,
+            &format!("Post from {}", did),
+        )]
+    }))
+    .into_response()
+}
+
+/// PDS / AppView と同じく、期限切れのアクセストークンは 400 ExpiredToken で拒否する
+fn reject_expired(headers: &HeaderMap) -> Option<Response> {
+    let token = headers.get("authorization")?.to_str().ok()?;
+    if token != format!("Bearer {}", EXPIRED_ACCESS_TOKEN) {
+        return None;
+    }
+    Some(
+        (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": "ExpiredToken",
+                "message": "Token has expired",
+            })),
+        )
+            .into_response(),
+    )
+}
+
+async fn handle_create_session(State(state): State<MockState>) -> Json<serde_json::Value> {
+    state.session_calls.create.fetch_add(1, Ordering::SeqCst);
+    Json(serde_json::json!({
+        "did": "did:plc:test123456789",
+        "handle": "test.example.com",
+        "accessJwt": "created-access-token",
+        "refreshJwt": "created-refresh-token",
     }))
 }
+
+/// 同時に来た更新がまとめられているか確かめられるよう、少し待ってから返す
+async fn handle_refresh_session(State(state): State<MockState>) -> Json<serde_json::Value> {
+    state.session_calls.refresh.fetch_add(1, Ordering::SeqCst);
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    Json(serde_json::json!({
+        "did": "did:plc:test123456789",
+        "handle": "test.example.com",
+        "accessJwt": REFRESHED_ACCESS_TOKEN,
+        "refreshJwt": "refreshed-refresh-token",
+    }))
+}
+
+/// プロフィールの説明文にタイムゾーンを書いている体にする
+async fn handle_get_profile(Query(params): Query<HashMap<String, String>>) -> Response {
+    let actor = params.get("actor").map(|s| s.as_str()).unwrap_or("unknown");
+
+    let profile = Json(serde_json::json!({
+        "did": actor,
+        "handle": format!("{}.test", actor),
+        "description": "Living in Asia/Tokyo",
+    }));
+    if actor == EXHAUSTED_DID {
+        let reset = chrono::Utc::now().timestamp() + 2;
+        return (
+            [
+                ("ratelimit-remaining", "0".to_string()),
+                ("ratelimit-reset", reset.to_string()),
+            ],
+            profile,
+        )
+            .into_response();
+    }
+    profile.into_response()
+}
+
+fn post_view(uri: &str, did: &str, text: &str) -> serde_json::Value {
+    serde_json::json!({
+        "uri": uri,
+        "cid": "bafyreicid",
+        "record": {
+            "text": text,
+            "createdAt": "2023-01-01T00:00:00Z"
+        },
+        "indexedAt": "2023-01-01T00:00:00Z",
+        "author": {
+            "did": did,
+            "handle": format!("{}.test", did),
+            "displayName": format!("User {}", did),
+            "avatar": "https://example.com/avatar.png",
+            "labels": [],
+            "viewer": {
+                "muted": false,
+                "blockedBy": false
+            }
+        },
+        "replyCount": 0,
+        "repostCount": 0,
+        "likeCount": 0
+    })
+}
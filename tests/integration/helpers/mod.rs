@@ -1,3 +1,23 @@
 pub mod auth;
 pub mod client;
+pub mod mock_firehose;
+pub mod mock_jetstream;
 pub mod mock_server;
+
+use std::time::Duration;
+
+/// 条件を満たすまで待つ（5秒でタイムアウト）
+pub async fn wait_until<F, Fut>(mut condition: F)
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = bool>,
+{
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    while !condition().await {
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "condition not met in time"
+        );
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
@@ -0,0 +1,70 @@
+use crate::helpers::{auth::TestAuth, client::TestClient};
+use axum::http::StatusCode;
+
+/// XRPC のエラーボディ（`error` と `message` だけ）になっているか
+fn assert_xrpc_error(body: &serde_json::Value, error: &str) {
+    let object = body.as_object().unwrap_or_else(|| panic!("{:?}", body));
+    assert_eq!(object.len(), 2, "{:?}", body);
+    assert_eq!(body["error"], error, "{:?}", body);
+    assert!(body["message"].is_string(), "{:?}", body);
+}
+
+/// 観点: 認証が必要なフィードはすべて、認証なし・不正なトークンで 401 AuthRequired を返すか
+#[tokio::test]
+async fn test_auth_errors_for_every_feed() {
+    let client = TestClient::new().await;
+    let feeds: Vec<_> = client.state.feeds.iter().cloned().collect();
+    assert!(!feeds.is_empty());
+
+    for feed in feeds {
+        let uri = format!(
+            "at://did:example:123/app.bsky.feed.generator/{}",
+            feed.rkey()
+        );
+
+        for header in [None, Some("Bearer invalid.token.structure")] {
+            let (status, body) = client.get_feed_skeleton(&uri, header).await;
+            if feed.requires_auth() {
+                assert_eq!(status, StatusCode::UNAUTHORIZED, "{}: {:?}", uri, body);
+                assert_xrpc_error(&body, "AuthRequired");
+            } else {
+                assert_eq!(status, StatusCode::OK, "{}: {:?}", uri, body);
+                assert!(body["feed"].is_array());
+            }
+        }
+    }
+}
+
+/// 観点: 存在しないフィードは lexicon どおり 400 UnknownFeed を返すか
+#[tokio::test]
+async fn test_unknown_feed_error_body() {
+    let client = TestClient::new().await;
+    let auth = TestAuth::new("did:plc:alice");
+
+    let (status, body) = client
+        .get_feed_skeleton(
+            "at://did:example:123/app.bsky.feed.generator/nosuchfeed",
+            Some(&auth.header_value()),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_xrpc_error(&body, "UnknownFeed");
+}
+
+/// 観点: パラメータが読めないときも 400 InvalidRequest を XRPC の形で返すか
+#[tokio::test]
+async fn test_invalid_params_error_body() {
+    let client = TestClient::new().await;
+    let auth = TestAuth::new("did:plc:alice");
+
+    let (status, body) = client
+        .get_feed_skeleton(
+            "at://did:example:123/app.bsky.feed.generator/helloworld&limit=many",
+            Some(&auth.header_value()),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_xrpc_error(&body, "InvalidRequest");
+}
@@ -0,0 +1,212 @@
+use crate::helpers::mock_firehose::{account_frame, commit_frame, info_frame, MockFirehose, Op};
+use crate::helpers::wait_until;
+use jetstream::{
+    ConsumerConfig, CursorStore, Dispatcher, IngestSource, ReplayEvent, ReplaySpeed,
+    SubscriberOptions,
+};
+use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 接続ごとに別の DB にならないよう、1 接続だけのインメモリ DB
+async fn memory_pool() -> SqlitePool {
+    SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap()
+}
+
+struct Harness {
+    helloworld_db: SqlitePool,
+    cursor_store: CursorStore,
+    /// 購読者に届いたイベントの数
+    received: Arc<AtomicU64>,
+}
+
+impl Harness {
+    async fn new() -> Self {
+        let helloworld_db = memory_pool().await;
+        helloworld::migrate(&helloworld_db).await.unwrap();
+
+        let jetstream_db = memory_pool().await;
+        jetstream::cursor::migrate(&jetstream_db).await.unwrap();
+
+        Self {
+            helloworld_db,
+            cursor_store: CursorStore::new(jetstream_db, "feeds-firehose"),
+            received: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// main と同じように購読者をつないで、`server` に向けてファイアホースのコンシューマーを起動する
+    fn start(
+        &self,
+        server: &MockFirehose,
+        record_to: Option<PathBuf>,
+    ) -> tokio::task::JoinHandle<()> {
+        let helloworld_db = self.helloworld_db.clone();
+        let purge_db = self.helloworld_db.clone();
+        let received = self.received.clone();
+        let dispatcher = Dispatcher::new()
+            .subscribe("helloworld", SubscriberOptions::default(), move |event| {
+                let pool = helloworld_db.clone();
+                async move { helloworld::process_event(&pool, &event).await }
+            })
+            .subscribe("counter", SubscriberOptions::default(), move |_| {
+                let received = received.clone();
+                async move {
+                    received.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+            .on_lifecycle(move |event| {
+                let pool = purge_db.clone();
+                async move {
+                    if event.is_account_gone() {
+                        helloworld::purge_author(&pool, event.did()).await.unwrap();
+                    }
+                }
+            });
+
+        let config = ConsumerConfig {
+            source: IngestSource::Firehose { url: server.url() },
+            record_to,
+            reconnect_delay: Duration::from_millis(100),
+            cursor_save_interval: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let cursor_store = self.cursor_store.clone();
+        tokio::spawn(async move {
+            jetstream::start_consumer(cursor_store, config, dispatcher).await;
+        })
+    }
+
+    async fn indexed_uris(&self) -> Vec<String> {
+        sqlx::query_scalar("SELECT uri FROM helloworld_posts ORDER BY uri")
+            .fetch_all(&self.helloworld_db)
+            .await
+            .unwrap()
+    }
+
+    async fn saved_cursor(&self) -> Option<i64> {
+        self.cursor_store.load().await.unwrap()
+    }
+}
+
+/// 記録ファイルを再生して、commit と identity / account の件数を数える
+async fn count_recorded(path: &PathBuf) -> (usize, usize) {
+    let (mut commits, mut lifecycles) = (0, 0);
+    jetstream::replay::replay_file(path, ReplaySpeed::Unthrottled, |event| {
+        match event {
+            ReplayEvent::Commit(_) => commits += 1,
+            ReplayEvent::Lifecycle { .. } => lifecycles += 1,
+        }
+        async {}
+    })
+    .await
+    .unwrap();
+    (commits, lifecycles)
+}
+
+/// 観点: リレーのフレームが helloworld まで届き、読めないレコードは同じコミットの他の操作を止めず、対象の操作が無いフレームでもカーソルが進み、記録ファイルに残るか
+#[tokio::test]
+async fn test_firehose_events_are_indexed_end_to_end() {
+    let server = MockFirehose::start().await;
+    server.push(info_frame("OutdatedCursor"));
+    server.push(commit_frame(
+        1,
+        "did:plc:alice",
+        &[
+            Op::Broken {
+                rkey: "3l3qo2vuowo2a",
+            },
+            Op::Post {
+                rkey: "3l3qo2vuowo2b",
+                text: "Hello, World!",
+            },
+            Op::Like {
+                rkey: "3l3qo2w8mxc2c",
+            },
+        ],
+    ));
+    server.push(commit_frame(
+        2,
+        "did:plc:carol",
+        &[Op::Post {
+            rkey: "3l3qo2wigz22a",
+            text: "good morning",
+        }],
+    ));
+    // 購読していないコレクションだけのコミットが続く
+    server.push(commit_frame(
+        3,
+        "did:plc:bob",
+        &[Op::Like {
+            rkey: "3l3qo2x6lbs2d",
+        }],
+    ));
+    server.push(commit_frame(
+        4,
+        "did:plc:bob",
+        &[Op::Like {
+            rkey: "3l3qo2x6lbs2e",
+        }],
+    ));
+
+    let record_to = std::env::temp_dir().join(format!(
+        "firehose-record-{}-{}.ndjson",
+        std::process::id(),
+        chrono::Utc::now().timestamp_micros()
+    ));
+    let harness = Harness::new().await;
+    let handle = harness.start(&server, Some(record_to.clone()));
+
+    wait_until(|| async { harness.received.load(Ordering::SeqCst) == 2 }).await;
+    assert_eq!(
+        harness.indexed_uris().await,
+        vec!["at://did:plc:alice/app.bsky.feed.post/3l3qo2vuowo2b"]
+    );
+    wait_until(|| async { harness.saved_cursor().await == Some(4) }).await;
+    assert_eq!(server.cursors(), vec![None]);
+
+    // 凍結されたアカウントの投稿が消え、その account イベントも記録される
+    server.push(account_frame(5, "did:plc:alice", Some("takendown")));
+    wait_until(|| async { harness.indexed_uris().await.is_empty() }).await;
+    wait_until(|| async { harness.saved_cursor().await == Some(5) }).await;
+    assert_eq!(count_recorded(&record_to).await, (2, 1));
+
+    handle.abort();
+    tokio::fs::remove_file(&record_to).await.unwrap();
+}
+
+/// 観点: 切断されたら処理済みの seq から再接続し、取りこぼしなく索引されるか
+#[tokio::test]
+async fn test_firehose_reconnects_from_committed_seq() {
+    let server = MockFirehose::start().await;
+    for seq in 1..=4 {
+        server.push(commit_frame(
+            seq,
+            "did:plc:alice",
+            &[Op::Post {
+                rkey: &format!("3l3qo2vuowo2{}", seq),
+                text: "hello world",
+            }],
+        ));
+    }
+    // 最初の接続は 2 フレーム送ったところで切れる
+    server.disconnect_after(2);
+
+    let harness = Harness::new().await;
+    let handle = harness.start(&server, None);
+
+    wait_until(|| async { harness.indexed_uris().await.len() == 4 }).await;
+    wait_until(|| async { harness.saved_cursor().await == Some(4) }).await;
+
+    // 2 回目は処理し終えた seq の次から（再配信はされない）
+    assert_eq!(server.cursors(), vec![None, Some(2)]);
+    assert_eq!(harness.received.load(Ordering::SeqCst), 4);
+
+    handle.abort();
+}
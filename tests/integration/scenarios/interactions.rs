@@ -0,0 +1,143 @@
+use crate::helpers::auth::TestAuth;
+use crate::helpers::client::TestClient;
+use axum::http::StatusCode;
+use serde_json::json;
+
+/// フィードジェネレーターレコードはサービスアカウント（テストでは did:plc:test123456789）のリポジトリにある
+const FAKEBLUESKY_URI: &str = "at://did:plc:test123456789/app.bsky.feed.generator/fakebluesky";
+
+async fn seed_fakebluesky(client: &TestClient) {
+    for (uri, indexed_at) in [
+        ("at://did:example:1/app.bsky.feed.post/1", 300),
+        ("at://did:example:1/app.bsky.feed.post/2", 200),
+        ("at://did:example:1/app.bsky.feed.post/3", 100),
+    ] {
+        sqlx::query("INSERT INTO fake_bluesky_posts (uri, cid, indexed_at) VALUES (?, 'cid', ?)")
+            .bind(uri)
+            .bind(indexed_at)
+            .execute(&client.state.realfakebluesky_db)
+            .await
+            .unwrap();
+    }
+}
+
+/// 観点: 認証なしの sendInteractions は 401 Unauthorized を返すか
+#[tokio::test]
+async fn test_send_interactions_unauthorized() {
+    let client = TestClient::new().await;
+
+    let (status, _body) = client
+        .send_interactions(json!({ "feed": FAKEBLUESKY_URI, "interactions": [] }), None)
+        .await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+/// 観点: 存在しないフィードへの sendInteractions は 400 UnknownFeed を返すか
+#[tokio::test]
+async fn test_send_interactions_unknown_feed() {
+    let client = TestClient::new().await;
+    let auth = TestAuth::new("did:plc:alice");
+
+    let (status, body) = client
+        .send_interactions(
+            json!({
+                "feed": "at://did:plc:test123456789/app.bsky.feed.generator/unknown_feed",
+                "interactions": []
+            }),
+            Some(&auth.header_value()),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "UnknownFeed");
+}
+
+/// 観点: 他のアカウントが公開したフィードや、シグナルを使わないフィードへの sendInteractions は 400 を返すか
+#[tokio::test]
+async fn test_send_interactions_rejects_other_feeds() {
+    let client = TestClient::new().await;
+    let auth = TestAuth::new("did:plc:alice");
+
+    for (feed, error) in [
+        (
+            "at://did:plc:someoneelse/app.bsky.feed.generator/fakebluesky",
+            "UnknownFeed",
+        ),
+        (
+            "at://did:plc:test123456789/app.bsky.feed.post/fakebluesky",
+            "InvalidRequest",
+        ),
+        (
+            "at://did:plc:test123456789/app.bsky.feed.generator/helloworld",
+            "InvalidRequest",
+        ),
+    ] {
+        let (status, body) = client
+            .send_interactions(
+                json!({ "feed": feed, "interactions": [] }),
+                Some(&auth.header_value()),
+            )
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", feed);
+        assert_eq!(body["error"], error, "{}", feed);
+    }
+}
+
+/// 観点: "Show less" した投稿が、そのユーザーのフィードでだけページ末尾に回されるか
+#[tokio::test]
+async fn test_send_interactions_demotes_requested_less() {
+    let client = TestClient::new().await;
+    seed_fakebluesky(&client).await;
+    let alice = TestAuth::new("did:plc:alice");
+    let bob = TestAuth::new("did:plc:bob");
+
+    let (status, body) = client
+        .send_interactions(
+            json!({
+                "feed": FAKEBLUESKY_URI,
+                "interactions": [
+                    {
+                        "item": "at://did:example:1/app.bsky.feed.post/1",
+                        "event": "app.bsky.feed.defs#requestLess",
+                        "feedContext": "ctx"
+                    },
+                    {
+                        "item": "at://did:example:1/app.bsky.feed.post/2",
+                        "event": "app.bsky.feed.defs#clickthroughItem"
+                    }
+                ]
+            }),
+            Some(&alice.header_value()),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({}));
+
+    let (status, body) = client
+        .get_feed_skeleton(FAKEBLUESKY_URI, Some(&alice.header_value()))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let posts: Vec<&str> = body["feed"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["post"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        posts,
+        vec![
+            "at://did:example:1/app.bsky.feed.post/2",
+            "at://did:example:1/app.bsky.feed.post/3",
+            "at://did:example:1/app.bsky.feed.post/1",
+        ]
+    );
+
+    let (_status, body) = client
+        .get_feed_skeleton(FAKEBLUESKY_URI, Some(&bob.header_value()))
+        .await;
+    assert_eq!(
+        body["feed"][0]["post"],
+        "at://did:example:1/app.bsky.feed.post/1"
+    );
+}
@@ -0,0 +1,378 @@
+use crate::helpers::client::TestClient;
+use crate::helpers::mock_jetstream::{account_event, identity_event, post_event, MockJetstream};
+use crate::helpers::wait_until;
+use axum::http::StatusCode;
+use jetstream::{ConsumerConfig, CursorStore, Dispatcher, EndpointPool, SubscriberOptions};
+use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 接続ごとに別の DB にならないよう、1 接続だけのインメモリ DB
+async fn memory_pool() -> SqlitePool {
+    SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap()
+}
+
+struct Harness {
+    helloworld_db: SqlitePool,
+    cursor_store: CursorStore,
+    /// 購読者に届いたイベントの数
+    received: Arc<AtomicU64>,
+}
+
+impl Harness {
+    async fn new() -> Self {
+        let helloworld_db = memory_pool().await;
+        helloworld::migrate(&helloworld_db).await.unwrap();
+
+        let jetstream_db = memory_pool().await;
+        jetstream::cursor::migrate(&jetstream_db).await.unwrap();
+
+        Self {
+            helloworld_db,
+            cursor_store: CursorStore::new(jetstream_db, "feeds"),
+            received: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// main と同じように購読者をつないで、`server` に向けてコンシューマーを起動する
+    fn start(&self, server: &MockJetstream) -> tokio::task::JoinHandle<()> {
+        let helloworld_db = self.helloworld_db.clone();
+        let received = self.received.clone();
+        let dispatcher = Dispatcher::new()
+            .subscribe("helloworld", SubscriberOptions::default(), move |event| {
+                let pool = helloworld_db.clone();
+                async move { helloworld::process_event(&pool, &event).await }
+            })
+            .subscribe("counter", SubscriberOptions::default(), move |_| {
+                let received = received.clone();
+                async move {
+                    received.fetch_add(1, Ordering::SeqCst);
+                }
+            });
+
+        let config = consumer_config(server);
+        let cursor_store = self.cursor_store.clone();
+        tokio::spawn(async move {
+            jetstream::start_consumer(cursor_store, config, dispatcher).await;
+        })
+    }
+
+    async fn indexed_uris(&self) -> Vec<String> {
+        sqlx::query_scalar("SELECT uri FROM helloworld_posts ORDER BY uri")
+            .fetch_all(&self.helloworld_db)
+            .await
+            .unwrap()
+    }
+}
+
+/// `server` に繋ぎ、再接続・カーソル保存の間隔をテスト用に短くした設定
+fn consumer_config(server: &MockJetstream) -> ConsumerConfig {
+    ConsumerConfig {
+        endpoints: EndpointPool::new(vec![server.url()]),
+        reconnect_delay: Duration::from_millis(100),
+        cursor_save_interval: Duration::from_millis(100),
+        ..Default::default()
+    }
+}
+
+fn now_us() -> u64 {
+    chrono::Utc::now().timestamp_micros() as u64
+}
+
+/// 観点: 圧縮されたイベントが start_consumer から helloworld まで届き、購読していないコレクションは流れてこないか
+#[tokio::test]
+async fn test_jetstream_events_are_indexed_end_to_end() {
+    let server = MockJetstream::start().await;
+    let base = now_us();
+    server.push(post_event(
+        "did:plc:alice",
+        "3l3qo2vuowo2b",
+        "Hello, World!",
+        base,
+    ));
+    server.push(serde_json::json!({
+        "did": "did:plc:bob",
+        "time_us": base + 1,
+        "kind": "commit",
+        "commit": {
+            "rev": "3l3qo2wadrs2c",
+            "operation": "create",
+            "collection": "app.bsky.feed.like",
+            "rkey": "3l3qo2w8mxc2c",
+            "record": {
+                "$type": "app.bsky.feed.like",
+                "createdAt": "2024-09-09T19:46:02.401Z",
+                "subject": {
+                    "cid": "bafyreidfayvfuwqa7qlnopdjiqrxzs6blmoeu4rujcjtnci5beludirz2a",
+                    "uri": "at://did:plc:alice/app.bsky.feed.post/3l3qo2vuowo2b"
+                }
+            },
+            "cid": "bafyreidfayvfuwqa7qlnopdjiqrxzs6blmoeu4rujcjtnci5beludirz2a"
+        }
+    }));
+    server.push(post_event(
+        "did:plc:carol",
+        "3l3qo2wigz22a",
+        "good morning",
+        base + 2,
+    ));
+
+    let harness = Harness::new().await;
+    let handle = harness.start(&server);
+
+    wait_until(|| async { harness.received.load(Ordering::SeqCst) == 2 }).await;
+    assert_eq!(
+        harness.indexed_uris().await,
+        vec!["at://did:plc:alice/app.bsky.feed.post/3l3qo2vuowo2b"]
+    );
+
+    let connections = server.connections();
+    assert_eq!(
+        connections[0].wanted_collections,
+        vec!["app.bsky.feed.post"]
+    );
+    assert!(connections[0].compress);
+    assert_eq!(connections[0].cursor, None);
+
+    // 接続中に追加されたイベントも届く
+    server.push(post_event(
+        "did:plc:dave",
+        "3l3qo2x6lbs2d",
+        "hello world",
+        base + 3,
+    ));
+    wait_until(|| async { harness.indexed_uris().await.len() == 2 }).await;
+
+    handle.abort();
+}
+
+/// 観点: 切断されたら処理済みのカーソルから再接続し、取りこぼしなく索引され、カーソルが保存されるか
+#[tokio::test]
+async fn test_jetstream_reconnects_from_committed_cursor() {
+    let server = MockJetstream::start().await;
+    let base = now_us();
+    let times: Vec<u64> = (0..4).map(|i| base + i * 1_000).collect();
+    for (i, time_us) in times.iter().enumerate() {
+        server.push(post_event(
+            "did:plc:alice",
+            &format!("3l3qo2vuowo2{}", i),
+            "hello world",
+            *time_us,
+        ));
+    }
+    // 最初の接続は 2 件送ったところで切れる
+    server.disconnect_after(2);
+
+    let harness = Harness::new().await;
+    let handle = harness.start(&server);
+
+    wait_until(|| async { harness.indexed_uris().await.len() == 4 }).await;
+    wait_until(|| async { harness.cursor_store.load().await.unwrap() == Some(times[3] as i64) })
+        .await;
+
+    // 2 回目は最後に処理し終えたイベントから（そのイベントは再配信される）
+    let connections = server.connections();
+    assert_eq!(connections.len(), 2);
+    assert_eq!(connections[0].cursor, None);
+    assert_eq!(connections[1].cursor, Some(times[1]));
+    assert_eq!(harness.received.load(Ordering::SeqCst), 5);
+
+    handle.abort();
+}
+
+/// 観点: 保存済みのカーソルがあれば、起動直後からそこを指定して接続するか
+#[tokio::test]
+async fn test_jetstream_resumes_from_saved_cursor() {
+    let server = MockJetstream::start().await;
+    let base = now_us();
+    server.push(post_event(
+        "did:plc:alice",
+        "3l3qo2vuowo2a",
+        "hello world",
+        base,
+    ));
+    server.push(post_event(
+        "did:plc:alice",
+        "3l3qo2vuowo2b",
+        "hello world",
+        base + 1_000,
+    ));
+
+    let harness = Harness::new().await;
+    harness
+        .cursor_store
+        .save((base + 1_000) as i64)
+        .await
+        .unwrap();
+    let handle = harness.start(&server);
+
+    wait_until(|| async { harness.received.load(Ordering::SeqCst) == 1 }).await;
+    assert_eq!(server.connections()[0].cursor, Some(base + 1_000));
+    assert_eq!(
+        harness.indexed_uris().await,
+        vec!["at://did:plc:alice/app.bsky.feed.post/3l3qo2vuowo2b"]
+    );
+
+    handle.abort();
+}
+
+/// 観点: 凍結されたアカウントの投稿が全フィードから消え、identity イベントで DID キャッシュが破棄されるか
+#[tokio::test]
+async fn test_jetstream_account_events_purge_posts() {
+    let client = TestClient::new().await;
+    let state = client.state.clone();
+    let db = state.helloworld_db.clone();
+
+    for (table, uri) in [
+        (
+            "helloworld_posts",
+            "at://did:plc:alice/app.bsky.feed.post/1",
+        ),
+        (
+            "fake_bluesky_posts",
+            "at://did:plc:alice/app.bsky.feed.post/2",
+        ),
+        (
+            "real_bluesky_posts",
+            "at://did:plc:alice/app.bsky.feed.post/3",
+        ),
+        ("helloworld_posts", "at://did:plc:bob/app.bsky.feed.post/1"),
+    ] {
+        sqlx::query(&format!(
+            "INSERT INTO {} (uri, cid, indexed_at) VALUES (?, 'cid', 1)",
+            table
+        ))
+        .bind(uri)
+        .execute(&db)
+        .await
+        .unwrap();
+    }
+    sqlx::query(
+        "INSERT INTO private_list_post_cache (uri, cid, author_did, indexed_at) VALUES (?, 'cid', ?, 1)",
+    )
+    .bind("at://did:plc:alice/app.bsky.feed.post/4")
+    .bind("did:plc:alice")
+    .execute(&db)
+    .await
+    .unwrap();
+    sqlx::query("INSERT INTO did_cache (did, document, expires_at) VALUES (?, '{}', ?)")
+        .bind("did:plc:bob")
+        .bind(i64::MAX)
+        .execute(&db)
+        .await
+        .unwrap();
+
+    let server = MockJetstream::start().await;
+    let base = now_us();
+    server.push(account_event("did:plc:alice", Some("takendown"), base));
+    server.push(identity_event("did:plc:bob", "bob.example.com", base + 1));
+
+    let jetstream_db = memory_pool().await;
+    jetstream::cursor::migrate(&jetstream_db).await.unwrap();
+    let dispatcher = Dispatcher::new().on_lifecycle(move |event| {
+        let state = state.clone();
+        async move { bluesky_feeds::feed::handle_lifecycle_event(&state, &event).await }
+    });
+    let config = consumer_config(&server);
+    let handle = tokio::spawn(async move {
+        jetstream::start_consumer(CursorStore::new(jetstream_db, "feeds"), config, dispatcher)
+            .await;
+    });
+
+    let count = |query: &'static str| {
+        let db = db.clone();
+        async move {
+            sqlx::query_scalar::<_, i64>(query)
+                .fetch_one(&db)
+                .await
+                .unwrap()
+        }
+    };
+    wait_until(|| async { count("SELECT COUNT(*) FROM did_cache").await == 0 }).await;
+
+    assert_eq!(count("SELECT COUNT(*) FROM fake_bluesky_posts").await, 0);
+    assert_eq!(count("SELECT COUNT(*) FROM real_bluesky_posts").await, 0);
+    assert_eq!(
+        count("SELECT COUNT(*) FROM private_list_post_cache").await,
+        0
+    );
+    // 無効になったアカウント以外の投稿は残る
+    let remaining: Vec<String> = sqlx::query_scalar("SELECT uri FROM helloworld_posts")
+        .fetch_all(&db)
+        .await
+        .unwrap();
+    assert_eq!(remaining, vec!["at://did:plc:bob/app.bsky.feed.post/1"]);
+
+    handle.abort();
+}
+
+/// 観点: 取り込んだ件数・採用件数・再接続回数・カーソルの遅れが /metrics に出力されるか
+#[tokio::test]
+async fn test_jetstream_ingest_metrics_endpoint() {
+    let client = TestClient::new().await;
+    let db = client.state.helloworld_db.clone();
+
+    let server = MockJetstream::start().await;
+    let base = now_us();
+    server.push(post_event(
+        "did:plc:alice",
+        "3l3qo2vuowo2a",
+        "Hello, World!",
+        base,
+    ));
+    server.push(post_event(
+        "did:plc:bob",
+        "3l3qo2vuowo2b",
+        "good morning",
+        base + 1_000,
+    ));
+    // 1 件送ったところで切れて、再接続する
+    server.disconnect_after(1);
+
+    let dispatcher =
+        Dispatcher::new().subscribe("helloworld", SubscriberOptions::default(), move |event| {
+            let pool = db.clone();
+            async move { helloworld::process_event(&pool, &event).await }
+        });
+    client.state.metrics.attach_ingest(dispatcher.clone());
+
+    let jetstream_db = memory_pool().await;
+    jetstream::cursor::migrate(&jetstream_db).await.unwrap();
+    let config = consumer_config(&server);
+    let consumer = dispatcher.clone();
+    let handle = tokio::spawn(async move {
+        jetstream::start_consumer(CursorStore::new(jetstream_db, "feeds"), config, consumer).await;
+    });
+    wait_until(|| async { dispatcher.committed_cursor() == Some((base + 1_000) as i64) }).await;
+
+    let (status, content_type, body) = client.get_metrics().await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(content_type.starts_with("text/plain; version=0.0.4"));
+
+    let lines: Vec<&str> = body.lines().collect();
+    for line in [
+        // 切断後は処理済みのイベントから再開するので、1 件目は 2 回届く
+        "ingest_events_received_total{collection=\"app.bsky.feed.post\"} 3",
+        "ingest_events_matched_total{feed=\"helloworld\"} 2",
+        "ingest_processing_duration_seconds_count{subscriber=\"helloworld\"} 3",
+    ] {
+        assert!(lines.contains(&line), "missing {}:\n{}", line, body);
+    }
+    assert!(body.contains(&format!(
+        "ingest_reconnects_total{{endpoint=\"{}\"}} 1",
+        server.url()
+    )));
+    assert!(lines
+        .iter()
+        .any(|l| l.starts_with("ingest_cursor_age_seconds ")));
+    assert!(lines
+        .iter()
+        .any(|l| l.starts_with("ingest_subscriber_lag_seconds{subscriber=\"helloworld\"} ")));
+
+    handle.abort();
+}
@@ -0,0 +1,84 @@
+use crate::helpers::{auth::TestAuth, client::TestClient, mock_server::MockServer};
+use axum::http::StatusCode;
+
+/// `name{labels} value` の value（なければ 0）
+fn sample(body: &str, series: &str) -> f64 {
+    body.lines()
+        .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+        .map_or(0.0, |value| value.parse().unwrap())
+}
+
+/// 観点: getFeedSkeleton のリクエストがフィード・ステータス・AppError の種類ごとに数えられるか
+#[tokio::test]
+async fn test_metrics_count_feed_requests() {
+    let client = TestClient::new().await;
+    let auth = TestAuth::new("did:plc:alice");
+
+    let (status, _) = client
+        .get_feed_skeleton(
+            "at://did:example:123/app.bsky.feed.generator/helloworld",
+            Some(&auth.header_value()),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = client
+        .get_feed_skeleton(
+            "at://did:example:123/app.bsky.feed.generator/nosuchfeed",
+            Some(&auth.header_value()),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, content_type, body) = client.get_metrics().await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(content_type.starts_with("text/plain; version=0.0.4"));
+
+    for (series, expected) in [
+        (
+            r#"feed_requests_total{feed="helloworld",status="200"}"#,
+            1.0,
+        ),
+        (r#"feed_requests_total{feed="unknown",status="400"}"#, 1.0),
+        (
+            r#"feed_request_duration_seconds_count{feed="helloworld"}"#,
+            1.0,
+        ),
+        (
+            r#"http_requests_total{path="/xrpc/app.bsky.feed.getFeedSkeleton",status="400"}"#,
+            1.0,
+        ),
+        (
+            r#"http_errors_total{path="/xrpc/app.bsky.feed.getFeedSkeleton",error="UnknownFeed"}"#,
+            1.0,
+        ),
+    ] {
+        assert_eq!(sample(&body, series), expected, "{}:\n{}", series, body);
+    }
+}
+
+/// 観点: AppView への呼び出しがメソッドごとに数えられるか
+#[tokio::test]
+async fn test_metrics_count_appview_calls() {
+    let mock_server = MockServer::start().await;
+    let client = TestClient::new_with_bsky_url(Some(mock_server.base_url())).await;
+    let token = TestAuth::new("did:plc:test_user").header_value();
+
+    let series = r#"appview_requests_total{method="app.bsky.feed.searchPosts"}"#;
+    // AppView のメトリクスはプロセス全体で共有されるので、差分で見る
+    let (_, _, before) = client.get_metrics().await;
+
+    let status = client
+        .privatelist_add("did:plc:target_user", Some(&token))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let status = client.privatelist_refresh(Some(&token)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, _, after) = client.get_metrics().await;
+    assert!(
+        sample(&after, series) > sample(&before, series),
+        "{}",
+        after
+    );
+    assert!(after.contains("# TYPE appview_failures_total counter"));
+}
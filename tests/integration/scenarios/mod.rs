@@ -1,3 +1,12 @@
 pub mod common_endpoints;
+pub mod error_responses;
 pub mod feed_skeleton;
+pub mod firehose_consumer;
+pub mod interactions;
+pub mod jetstream_consumer;
+pub mod metrics;
+pub mod outbound_http;
 pub mod private_list_refresh;
+pub mod service_session;
+pub mod skeleton_cache;
+pub mod upstream_errors;
@@ -0,0 +1,107 @@
+use crate::helpers::{
+    auth::TestAuth,
+    client::TestClient,
+    mock_server::{MockServer, EXHAUSTED_DID, UNSTABLE_DID},
+};
+use axum::http::StatusCode;
+use bsky_core::http::{HttpClient, HttpOptions};
+use bsky_core::xrpc::XrpcError;
+use std::time::Duration;
+
+const HOST: &[(&str, &str)] = &[("host", "127.0.0.1")];
+
+fn get_profile(
+    mock_server: &MockServer,
+    http: &HttpClient,
+    actor: &str,
+) -> reqwest::RequestBuilder {
+    http.get(format!(
+        "{}/xrpc/app.bsky.actor.getProfile",
+        mock_server.base_url()
+    ))
+    .query(&[("actor", actor)])
+}
+
+/// 観点: AppView の一時的な 503 はやり直して成功し、やり直した回数が /metrics に出るか
+#[tokio::test]
+async fn test_retries_transient_upstream_failure() {
+    let mock_server = MockServer::start().await;
+    let client = TestClient::new_with_bsky_url(Some(mock_server.base_url())).await;
+    let auth = TestAuth::new(UNSTABLE_DID);
+
+    let (status, body) = client
+        .get_feed_skeleton(
+            "at://did:example:123/app.bsky.feed.generator/todoapp",
+            Some(&auth.header_value()),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::OK, "{:?}", body);
+    assert_eq!(body["feed"].as_array().unwrap().len(), 1);
+    let retries = client
+        .state
+        .http_client
+        .metrics()
+        .retries
+        .get(&[("host", "127.0.0.1"), ("reason", "status")]);
+    assert_eq!(retries, 2);
+
+    let (_, _, metrics) = client.get_metrics().await;
+    assert!(
+        metrics.contains(r#"outbound_retries_total{host="127.0.0.1",reason="status"} 2"#),
+        "{}",
+        metrics
+    );
+}
+
+/// 観点: レート制限を使い切ったら、リセットまで待ってから次を送るか。締め切りに間に合わなければ送らずに諦めるか
+#[tokio::test]
+async fn test_waits_for_rate_limit_reset() {
+    let mock_server = MockServer::start().await;
+
+    let http = HttpClient::new(reqwest::Client::new());
+    for _ in 0..2 {
+        let res = http
+            .send(get_profile(&mock_server, &http, EXHAUSTED_DID))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+    assert_eq!(http.metrics().rate_limit_waits.get(HOST), 1);
+
+    let impatient = HttpClient::new(reqwest::Client::new()).with_options(HttpOptions {
+        deadline: Duration::from_millis(200),
+        ..Default::default()
+    });
+    impatient
+        .send(get_profile(&mock_server, &impatient, EXHAUSTED_DID))
+        .await
+        .unwrap();
+    let err = impatient
+        .send(get_profile(&mock_server, &impatient, EXHAUSTED_DID))
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(err, XrpcError::RateLimited { reset: Some(_), .. }),
+        "{:?}",
+        err
+    );
+    assert_eq!(impatient.metrics().deadline_exceeded.get(HOST), 1);
+    assert_eq!(impatient.metrics().rate_limit_waits.get(HOST), 0);
+}
+
+/// 観点: 同じホストへの同時リクエストが上限で待たされ、待った回数が数えられるか
+#[tokio::test]
+async fn test_per_host_concurrency_cap() {
+    let mock_server = MockServer::start().await;
+    let http = HttpClient::new(reqwest::Client::new()).with_options(HttpOptions {
+        per_host_concurrency: 1,
+        ..Default::default()
+    });
+
+    let requests = (0..3).map(|_| http.send(get_profile(&mock_server, &http, "did:plc:alice")));
+    for res in futures_util::future::join_all(requests).await {
+        assert_eq!(res.unwrap().status(), reqwest::StatusCode::OK);
+    }
+    assert_eq!(http.metrics().concurrency_waits.get(HOST), 2);
+}
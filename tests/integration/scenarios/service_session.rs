@@ -0,0 +1,58 @@
+use crate::helpers::{
+    auth::TestAuth,
+    client::TestClient,
+    mock_server::{MockServer, EXPIRED_ACCESS_TOKEN, REFRESHED_ACCESS_TOKEN},
+};
+use axum::http::StatusCode;
+use bsky_core::appview::Session;
+
+const TODOAPP_FEED: &str = "at://did:example:123/app.bsky.feed.generator/todoapp";
+
+fn expired_session() -> Session {
+    Session {
+        did: "did:plc:test123456789".to_string(),
+        access_jwt: EXPIRED_ACCESS_TOKEN.to_string(),
+        refresh_jwt: "refresh-token".to_string(),
+    }
+}
+
+/// 観点: AppView に期限切れで拒否されたら、refreshSession で更新してやり直すか（createSession はしない）
+#[tokio::test]
+async fn test_service_session_refreshes_rejected_token() {
+    let mock_server = MockServer::start().await;
+    let client =
+        TestClient::new_with_service_session(mock_server.base_url(), expired_session()).await;
+    let auth = TestAuth::new("did:plc:todo");
+
+    let (status, body) = client
+        .get_feed_skeleton(TODOAPP_FEED, Some(&auth.header_value()))
+        .await;
+
+    assert_eq!(status, StatusCode::OK, "{:?}", body);
+    assert_eq!(body["feed"].as_array().unwrap().len(), 1);
+    assert_eq!(mock_server.session_calls.refresh(), 1);
+    assert_eq!(mock_server.session_calls.create(), 0);
+    assert_eq!(
+        client.state.session.access_token().await.unwrap(),
+        REFRESHED_ACCESS_TOKEN
+    );
+}
+
+/// 観点: 同時に何件拒否されても、更新は 1 回にまとめられるか
+#[tokio::test]
+async fn test_service_session_coalesces_refreshes() {
+    let mock_server = MockServer::start().await;
+    let client =
+        TestClient::new_with_service_session(mock_server.base_url(), expired_session()).await;
+    let auth = TestAuth::new("did:plc:todo");
+    let header = auth.header_value();
+
+    let requests = (0..5).map(|_| client.get_feed_skeleton(TODOAPP_FEED, Some(&header)));
+    let responses = futures_util::future::join_all(requests).await;
+
+    for (status, body) in responses {
+        assert_eq!(status, StatusCode::OK, "{:?}", body);
+    }
+    assert_eq!(mock_server.session_calls.refresh(), 1);
+    assert_eq!(mock_server.session_calls.create(), 0);
+}
@@ -0,0 +1,151 @@
+use crate::helpers::auth::TestAuth;
+use crate::helpers::client::TestClient;
+use axum::http::StatusCode;
+use bluesky_feeds::skeleton_cache::SkeletonCache;
+use serde_json::json;
+use std::time::Duration;
+
+const FAKEBLUESKY_URI: &str = "at://did:plc:test123456789/app.bsky.feed.generator/fakebluesky";
+const REALBLUESKY_URI: &str = "at://did:example:123/app.bsky.feed.generator/realbluesky";
+
+async fn insert_post(client: &TestClient, table: &str, uri: &str, indexed_at: i64) {
+    sqlx::query(&format!(
+        "INSERT INTO {} (uri, cid, indexed_at) VALUES (?, 'cid', ?)",
+        table
+    ))
+    .bind(uri)
+    .bind(indexed_at)
+    .execute(&client.state.realfakebluesky_db)
+    .await
+    .unwrap();
+}
+
+async fn posts(client: &TestClient, feed_uri: &str, auth: Option<&TestAuth>) -> Vec<String> {
+    let header = auth.map(|auth| auth.header_value());
+    let (status, body) = client.get_feed_skeleton(feed_uri, header.as_deref()).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body["feed"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["post"].as_str().unwrap().to_string())
+        .collect()
+}
+
+/// 観点: 誰に対しても同じフィードは、新しい投稿が入っても破棄されるまでキャッシュした結果を返すか
+#[tokio::test]
+async fn test_skeleton_cache_hit_and_invalidate_feed() {
+    let client = TestClient::new_with_skeleton_cache(
+        SkeletonCache::new(100).with_ttl("realbluesky", Duration::from_secs(60)),
+    )
+    .await;
+    insert_post(
+        &client,
+        "real_bluesky_posts",
+        "at://did:example:1/app.bsky.feed.post/1",
+        100,
+    )
+    .await;
+
+    assert_eq!(posts(&client, REALBLUESKY_URI, None).await.len(), 1);
+    insert_post(
+        &client,
+        "real_bluesky_posts",
+        "at://did:example:1/app.bsky.feed.post/2",
+        200,
+    )
+    .await;
+    // リクエスト元が違っても同じ結果を使う
+    let alice = TestAuth::new("did:plc:alice");
+    assert_eq!(posts(&client, REALBLUESKY_URI, Some(&alice)).await.len(), 1);
+
+    client
+        .state
+        .skeleton_cache
+        .invalidate_feed("realbluesky")
+        .await;
+    assert_eq!(
+        posts(&client, REALBLUESKY_URI, None).await,
+        vec![
+            "at://did:example:1/app.bsky.feed.post/2",
+            "at://did:example:1/app.bsky.feed.post/1",
+        ]
+    );
+
+    let (_, _, body) = client.get_metrics().await;
+    assert!(
+        body.contains(r#"skeleton_cache_lookups_total{feed="realbluesky",result="memory"} 1"#),
+        "{}",
+        body
+    );
+    assert!(
+        body.contains(r#"skeleton_cache_lookups_total{feed="realbluesky",result="miss"} 2"#),
+        "{}",
+        body
+    );
+}
+
+/// 観点: 個人向けのフィードはリクエスト元ごとにキャッシュし、sendInteractions でその人の分だけ破棄するか
+#[tokio::test]
+async fn test_skeleton_cache_per_requester() {
+    let client = TestClient::new_with_skeleton_cache(
+        SkeletonCache::new(100).with_ttl("fakebluesky", Duration::from_secs(60)),
+    )
+    .await;
+    for (uri, indexed_at) in [
+        ("at://did:example:1/app.bsky.feed.post/1", 200),
+        ("at://did:example:1/app.bsky.feed.post/2", 100),
+    ] {
+        insert_post(&client, "fake_bluesky_posts", uri, indexed_at).await;
+    }
+    let alice = TestAuth::new("did:plc:alice");
+    let bob = TestAuth::new("did:plc:bob");
+    let first_post = |posts: Vec<String>| posts[0].clone();
+
+    assert_eq!(
+        first_post(posts(&client, FAKEBLUESKY_URI, Some(&alice)).await),
+        "at://did:example:1/app.bsky.feed.post/1"
+    );
+    assert_eq!(
+        first_post(posts(&client, FAKEBLUESKY_URI, Some(&bob)).await),
+        "at://did:example:1/app.bsky.feed.post/1"
+    );
+
+    let (status, _) = client
+        .send_interactions(
+            json!({
+                "feed": FAKEBLUESKY_URI,
+                "interactions": [{
+                    "item": "at://did:example:1/app.bsky.feed.post/1",
+                    "event": "app.bsky.feed.defs#requestLess"
+                }]
+            }),
+            Some(&alice.header_value()),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    insert_post(
+        &client,
+        "fake_bluesky_posts",
+        "at://did:example:1/app.bsky.feed.post/3",
+        300,
+    )
+    .await;
+
+    // alice は計算し直され、bob はキャッシュのまま
+    assert_eq!(
+        posts(&client, FAKEBLUESKY_URI, Some(&alice)).await,
+        vec![
+            "at://did:example:1/app.bsky.feed.post/3",
+            "at://did:example:1/app.bsky.feed.post/2",
+            "at://did:example:1/app.bsky.feed.post/1",
+        ]
+    );
+    assert_eq!(
+        posts(&client, FAKEBLUESKY_URI, Some(&bob)).await,
+        vec![
+            "at://did:example:1/app.bsky.feed.post/1",
+            "at://did:example:1/app.bsky.feed.post/2",
+        ]
+    );
+}
@@ -0,0 +1,58 @@
+use crate::helpers::{
+    auth::TestAuth,
+    client::TestClient,
+    mock_server::{MockServer, MISSING_DID, RATE_LIMITED_DID, UNAVAILABLE_DID},
+};
+use axum::http::StatusCode;
+
+const TODOAPP_FEED: &str = "at://did:example:123/app.bsky.feed.generator/todoapp";
+
+/// 観点: AppView のレート制限は 429 RateLimitExceeded として返すか
+#[tokio::test]
+async fn test_upstream_rate_limit_returns_429() {
+    let mock_server = MockServer::start().await;
+    let client = TestClient::new_with_bsky_url(Some(mock_server.base_url())).await;
+    let auth = TestAuth::new(RATE_LIMITED_DID);
+
+    let (status, body) = client
+        .get_feed_skeleton(TODOAPP_FEED, Some(&auth.header_value()))
+        .await;
+
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS, "{:?}", body);
+    assert_eq!(body["error"], "RateLimitExceeded");
+    assert!(body["message"].is_string());
+}
+
+/// 観点: AppView の 5xx は 502 UpstreamFailure として返し、中身は漏らさないか
+#[tokio::test]
+async fn test_upstream_unavailable_returns_502() {
+    let mock_server = MockServer::start().await;
+    let client = TestClient::new_with_bsky_url(Some(mock_server.base_url())).await;
+    let auth = TestAuth::new(UNAVAILABLE_DID);
+
+    let (status, body) = client
+        .get_feed_skeleton(TODOAPP_FEED, Some(&auth.header_value()))
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_GATEWAY, "{:?}", body);
+    assert_eq!(body["error"], "UpstreamFailure");
+    assert!(!body["message"]
+        .as_str()
+        .unwrap()
+        .contains("upstream connect error"));
+}
+
+/// 観点: AppView の 404 はフィードが無いという意味にならないよう、502 UpstreamFailure として返すか
+#[tokio::test]
+async fn test_upstream_not_found_returns_502() {
+    let mock_server = MockServer::start().await;
+    let client = TestClient::new_with_bsky_url(Some(mock_server.base_url())).await;
+    let auth = TestAuth::new(MISSING_DID);
+
+    let (status, body) = client
+        .get_feed_skeleton(TODOAPP_FEED, Some(&auth.header_value()))
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_GATEWAY, "{:?}", body);
+    assert_eq!(body["error"], "UpstreamFailure");
+}